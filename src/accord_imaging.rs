@@ -10,10 +10,9 @@ impl DetectionUtility {
         image: &[u8],
         width: usize,
         height: usize,
-        max_width: usize,
         resize_factor: f64,
     ) -> (Vec<u8>, usize, usize) {
-        if width <= max_width {
+        if resize_factor >= 1.0 {
             // No resizing needed
             return (image.to_vec(), width, height);
         }
@@ -366,12 +365,17 @@ impl FastGaussianBlur {
     }
 }
 
-/// Median filter implementation
+/// Median filter implementation (3x3, edges use the in-bounds neighbours)
 pub struct Median;
 
 impl Median {
-    pub fn apply(&self, image: &[u8], width: usize, height: usize) -> Vec<u8> {
-        let mut result = vec![0u8; width * height];
+    pub fn apply<T: Copy + Ord + Default>(
+        &self,
+        image: &[T],
+        width: usize,
+        height: usize,
+    ) -> Vec<T> {
+        let mut result = vec![T::default(); width * height];
 
         for y in 0..height {
             for x in 0..width {
@@ -415,7 +419,7 @@ fn gaussian_blur(
     convolve_vertical(&temp, width, height, &kernel)
 }
 
-fn create_gaussian_kernel(size: usize, sigma: f64) -> Vec<f64> {
+pub(crate) fn create_gaussian_kernel(size: usize, sigma: f64) -> Vec<f64> {
     let mut kernel = vec![0.0; size];
     let center = size as f64 / 2.0 - 0.5;
     let mut sum = 0.0;
@@ -478,6 +482,66 @@ fn convolve_vertical(image: &[u8], width: usize, height: usize, kernel: &[f64])
     result
 }
 
+/// Horizontal pass of a separable convolution, keeping full precision
+///
+/// Accepts any pixel type that widens to f64 (u16 frames or f64 maps).
+/// Pixels past the image edge count as zero, as in the u8 version.
+pub(crate) fn convolve_horizontal_f64<T: Copy + Into<f64>>(
+    image: &[T],
+    width: usize,
+    height: usize,
+    kernel: &[f64],
+) -> Vec<f64> {
+    let mut result = vec![0.0; width * height];
+    let half_size = kernel.len() / 2;
+
+    for y in 0..height {
+        let row = &image[y * width..(y + 1) * width];
+        for x in 0..width {
+            // Clip the kernel to the taps that land inside the row
+            let first_tap = half_size.saturating_sub(x);
+            let last_tap = kernel.len().min(width + half_size - x);
+            let start = x + first_tap - half_size;
+
+            result[y * width + x] = kernel[first_tap..last_tap]
+                .iter()
+                .zip(&row[start..])
+                .map(|(&k, &v)| v.into() * k)
+                .sum();
+        }
+    }
+
+    result
+}
+
+/// Vertical pass of a separable convolution, keeping full precision
+pub(crate) fn convolve_vertical_f64<T: Copy + Into<f64>>(
+    image: &[T],
+    width: usize,
+    height: usize,
+    kernel: &[f64],
+) -> Vec<f64> {
+    let mut result = vec![0.0; width * height];
+    let half_size = kernel.len() / 2;
+
+    for (i, &k) in kernel.iter().enumerate() {
+        // Accumulate whole rows at a time so memory is walked sequentially
+        for y in 0..height {
+            let sy = y as i32 + i as i32 - half_size as i32;
+            if sy < 0 || sy >= height as i32 {
+                continue;
+            }
+            let src = &image[sy as usize * width..(sy as usize + 1) * width];
+            let dst = &mut result[y * width..(y + 1) * width];
+            for (d, &v) in dst.iter_mut().zip(src) {
+                *d += v.into() * k;
+            }
+        }
+    }
+
+    result
+}
+
 fn calculate_gradients(image: &[u8], width: usize, height: usize) -> (Vec<f64>, Vec<f64>) {
     let mut magnitudes = vec![0.0; width * height];
     let mut orientations = vec![0.0; width * height];
@@ -0,0 +1,331 @@
+//! Star analysis of a frame held in memory
+//!
+//! [`analyze_frame`] runs the statistics, stretch and detection steps of
+//! `analyze-fits` on a caller's 16-bit buffer, for applications that get pixels
+//! from a camera driver or their own decoder rather than from a FITS file.
+
+use crate::field_analysis::{ensemble_psf, EnsemblePSF};
+use crate::hocus_focus_star_detection::{
+    detect_stars_hocus_focus, HocusFocusParams, HocusFocusStar,
+};
+use crate::image_analysis::{pixel_statistics, FitsReadError, ImageStatistics};
+use crate::mtf_stretch::{stretch_image, StretchParameters};
+use crate::nina_star_detection::{
+    detect_stars_fast, detect_stars_with_original, DetectedStar, StarDetectionParams,
+    StarSensitivity,
+};
+use crate::psf_fitting::{PSFModel, PSFType, DEFAULT_MIN_R_SQUARED};
+use crate::utils::OnlineStats;
+
+/// Star detection algorithm run by [`analyze_frame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Detector {
+    #[default]
+    HocusFocus,
+    Nina,
+    Fast,
+}
+
+impl std::str::FromStr for Detector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hocusfocus" => Ok(Detector::HocusFocus),
+            "nina" => Ok(Detector::Nina),
+            "fast" => Ok(Detector::Fast),
+            _ => Err(format!("Unknown detector: {}", s)),
+        }
+    }
+}
+
+/// Detector choice and settings for [`analyze_frame`], defaulting to the
+/// `analyze-fits` defaults
+#[derive(Debug, Clone, Copy)]
+pub struct AnalyzeOptions {
+    pub detector: Detector,
+    /// NINA detection sensitivity
+    pub sensitivity: StarSensitivity,
+    /// PSF model fitted to each star, `PSFType::None` to skip fitting
+    pub psf_type: PSFType,
+    /// MTF stretch before HocusFocus detection; NINA and Fast always stretch
+    pub apply_stretch: bool,
+    /// PSF fits below this R² are left out of the FWHM and eccentricity aggregates
+    pub min_r_squared: f64,
+    /// Resize factor in (0, 1] for the NINA and Fast detection image
+    pub downsample: Option<f64>,
+    /// Pixel size in µm and focal length in mm, giving NINA the image scale
+    pub pixel_size_um: Option<f64>,
+    pub focal_length_mm: Option<f64>,
+    /// Camera saturation level in ADU for HocusFocus, ignored with `apply_stretch`
+    pub saturation_level: Option<f64>,
+    /// Print detector diagnostics to stderr
+    pub verbose: bool,
+}
+
+impl Default for AnalyzeOptions {
+    fn default() -> Self {
+        Self {
+            detector: Detector::HocusFocus,
+            sensitivity: StarSensitivity::Normal,
+            psf_type: PSFType::None,
+            apply_stretch: false,
+            min_r_squared: DEFAULT_MIN_R_SQUARED,
+            downsample: None,
+            pixel_size_um: None,
+            focal_length_mm: None,
+            saturation_level: None,
+            verbose: false,
+        }
+    }
+}
+
+/// Per-star measurements common to all detectors
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStar {
+    pub position: (f64, f64),
+    pub hfr: f64,
+    /// Fitted PSF FWHM, None when no fit was run or it failed
+    pub fwhm: Option<f64>,
+    /// Peak for HocusFocus, background-subtracted mean for NINA and Fast
+    pub brightness: f64,
+    pub eccentricity: Option<f64>,
+    /// Only reported by HocusFocus
+    pub snr: Option<f64>,
+    /// Goodness of the PSF fit, None when no fit was run or it failed
+    pub r_squared: Option<f64>,
+}
+
+impl FrameStar {
+    /// The PSF fit's R² is below `min_r_squared`, so its FWHM and eccentricity
+    /// are left out of the aggregates
+    pub fn is_poor_fit(&self, min_r_squared: f64) -> bool {
+        self.r_squared.is_some_and(|r2| r2 < min_r_squared)
+    }
+}
+
+impl From<&HocusFocusStar> for FrameStar {
+    fn from(star: &HocusFocusStar) -> Self {
+        Self {
+            position: star.position,
+            hfr: star.hfr,
+            fwhm: star.psf_model.as_ref().map(|m| m.fwhm),
+            brightness: star.brightness,
+            eccentricity: star.psf_model.as_ref().map(|m| m.eccentricity),
+            snr: Some(star.snr),
+            r_squared: star.psf_model.as_ref().map(|m| m.r_squared),
+        }
+    }
+}
+
+impl From<&DetectedStar> for FrameStar {
+    fn from(star: &DetectedStar) -> Self {
+        Self {
+            position: star.position,
+            hfr: star.hfr,
+            fwhm: star.psf_model.as_ref().map(|m| m.fwhm),
+            brightness: star.average_brightness,
+            eccentricity: star.psf_model.as_ref().map(|m| m.eccentricity),
+            snr: None,
+            r_squared: star.psf_model.as_ref().map(|m| m.r_squared),
+        }
+    }
+}
+
+/// FWHM, SNR and eccentricity aggregates over a frame's stars
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StarMetrics {
+    /// Average fitted PSF FWHM over the fits at or above the R² threshold.
+    /// It comes from the fitted models rather than HocusFocusStar::fwhm, which
+    /// falls back to the HFR estimate when a fit fails
+    pub psf_fwhm: Option<f64>,
+    /// Fits behind `psf_fwhm`
+    pub psf_fitted: usize,
+    /// `psf_fwhm`, or the HFR-derived estimate when nothing was fitted
+    pub average_fwhm: Option<f64>,
+    /// Only reported by HocusFocus
+    pub average_snr: Option<f64>,
+    /// Median eccentricity of the stars with a PSF fit above the R² threshold
+    pub median_eccentricity: Option<f64>,
+    pub max_eccentricity: Option<f64>,
+    /// Fits left out for an R² below the threshold
+    pub poor_fits: usize,
+}
+
+impl StarMetrics {
+    /// Aggregate `stars`, leaving out PSF fits with an R² below `min_r_squared`
+    pub fn from_stars(stars: &[FrameStar], average_hfr: f64, min_r_squared: f64) -> Self {
+        let mean = |values: &[f64]| {
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        };
+        let reliable = || stars.iter().filter(|s| !s.is_poor_fit(min_r_squared));
+
+        let fitted_fwhms: Vec<f64> = reliable().filter_map(|s| s.fwhm).collect();
+        let psf_fwhm = mean(&fitted_fwhms);
+        let snrs: Vec<f64> = stars.iter().filter_map(|s| s.snr).collect();
+
+        let mut eccentricities: Vec<f64> = reliable().filter_map(|s| s.eccentricity).collect();
+        eccentricities.sort_by(|a, b| a.total_cmp(b));
+        let mid = eccentricities.len() / 2;
+        let median_eccentricity = match eccentricities.len() {
+            0 => None,
+            n if n % 2 == 0 => Some((eccentricities[mid - 1] + eccentricities[mid]) / 2.0),
+            _ => Some(eccentricities[mid]),
+        };
+
+        Self {
+            psf_fwhm,
+            psf_fitted: fitted_fwhms.len(),
+            average_fwhm: psf_fwhm
+                .or_else(|| (!stars.is_empty()).then_some(average_hfr * HFR_TO_FWHM)),
+            average_snr: mean(&snrs),
+            median_eccentricity,
+            max_eccentricity: eccentricities.last().copied(),
+            poor_fits: stars
+                .iter()
+                .filter(|s| s.is_poor_fit(min_r_squared))
+                .count(),
+        }
+    }
+}
+
+/// Statistics, detected stars and their aggregates for one frame
+#[derive(Debug, Clone)]
+pub struct FrameAnalysis {
+    pub statistics: ImageStatistics,
+    pub stars: Vec<FrameStar>,
+    pub average_hfr: f64,
+    pub hfr_std_dev: f64,
+    pub metrics: StarMetrics,
+    /// Frame PSF from the fits above the R² threshold, None without PSF fitting
+    /// or with too few fits
+    pub psf_summary: Option<EnsemblePSF>,
+}
+
+impl FrameAnalysis {
+    pub fn star_count(&self) -> usize {
+        self.stars.len()
+    }
+}
+
+/// HFR to FWHM conversion assuming a Gaussian profile, as used by HocusFocus
+pub const HFR_TO_FWHM: f64 = 2.0 * 1.177;
+
+/// Detect and measure the stars of a `width` x `height` 16-bit frame
+///
+/// # Example
+///
+/// ```
+/// use psf_guard::analysis::{analyze_frame, AnalyzeOptions};
+///
+/// // A 128x128 frame with a noisy sky and four Gaussian stars
+/// let (width, height) = (128, 128);
+/// let mut data: Vec<u16> = (0..width * height)
+///     .map(|i| 1000 + ((i * 7919) % 61) as u16)
+///     .collect();
+/// for &(cx, cy) in &[(30.0, 30.0), (90.0, 40.0), (40.0, 95.0), (100.0, 100.0)] {
+///     for y in 0..height {
+///         for x in 0..width {
+///             let r2 = (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2);
+///             data[y * width + x] += (20000.0 * (-r2 / (2.0 * 1.5 * 1.5)).exp()) as u16;
+///         }
+///     }
+/// }
+///
+/// let analysis = analyze_frame(&data, width, height, &AnalyzeOptions::default()).unwrap();
+/// assert_eq!(analysis.star_count(), 4);
+/// assert!(analysis.average_hfr > 1.0 && analysis.average_hfr < 3.0);
+/// assert!(analysis.statistics.median >= 1000.0);
+/// ```
+pub fn analyze_frame(
+    data: &[u16],
+    width: usize,
+    height: usize,
+    options: &AnalyzeOptions,
+) -> Result<FrameAnalysis, FitsReadError> {
+    if data.len() != width * height {
+        return Err(FitsReadError::SizeMismatch {
+            expected: width * height,
+            got: data.len(),
+        });
+    }
+
+    let statistics = pixel_statistics(data, width, height);
+    let stretched = || {
+        let stretch_params = StretchParameters::default();
+        stretch_image(
+            data,
+            &statistics,
+            stretch_params.factor,
+            stretch_params.black_clipping,
+        )
+    };
+    let fit_psf = Some(options.psf_type).filter(|&psf| psf != PSFType::None);
+
+    // Fits below the R² threshold are left out of the frame PSF
+    let reliable_model = |model: &Option<PSFModel>| {
+        model
+            .as_ref()
+            .filter(|m| m.r_squared >= options.min_r_squared)
+            .cloned()
+    };
+
+    let (stars, models, average_hfr, hfr_std_dev): (Vec<FrameStar>, Vec<PSFModel>, f64, f64) =
+        match options.detector {
+            Detector::HocusFocus => {
+                // The saturation level is on the unstretched scale
+                let params = HocusFocusParams {
+                    psf_type: options.psf_type,
+                    verbose: options.verbose,
+                    ..Default::default()
+                }
+                .with_saturation_level(options.saturation_level.filter(|_| !options.apply_stretch));
+                let result = if options.apply_stretch {
+                    detect_stars_hocus_focus(&stretched(), width, height, &params)
+                } else {
+                    detect_stars_hocus_focus(data, width, height, &params)
+                };
+                let stars: Vec<FrameStar> = result.stars.iter().map(FrameStar::from).collect();
+                let models = result
+                    .stars
+                    .iter()
+                    .filter_map(|star| reliable_model(&star.psf_model))
+                    .collect();
+                let hfr_stats: OnlineStats = stars.iter().map(|s| s.hfr).collect();
+                (stars, models, hfr_stats.mean(), hfr_stats.std_dev())
+            }
+            Detector::Nina | Detector::Fast => {
+                let result = if options.detector == Detector::Nina {
+                    let params = StarDetectionParams {
+                        sensitivity: options.sensitivity,
+                        fit_psf,
+                        force_downsample: options.downsample,
+                        pixel_size_um: options.pixel_size_um,
+                        focal_length_mm: options.focal_length_mm,
+                        verbose: options.verbose,
+                        ..StarDetectionParams::default()
+                    };
+                    detect_stars_with_original(&stretched(), data, width, height, &params)
+                } else {
+                    detect_stars_fast(&stretched(), data, width, height, options.downsample)
+                };
+                let stars = result.star_list.iter().map(FrameStar::from).collect();
+                let models = result
+                    .star_list
+                    .iter()
+                    .filter_map(|star| reliable_model(&star.psf_model))
+                    .collect();
+                (stars, models, result.average_hfr, result.hfr_std_dev)
+            }
+        };
+
+    let metrics = StarMetrics::from_stars(&stars, average_hfr, options.min_r_squared);
+    Ok(FrameAnalysis {
+        statistics,
+        stars,
+        average_hfr,
+        hfr_std_dev,
+        metrics,
+        psf_summary: ensemble_psf(&models),
+    })
+}
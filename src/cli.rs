@@ -1,4 +1,9 @@
+use crate::field_analysis::DEFAULT_ENSEMBLE_STARS;
+use crate::grading::{PercentileMetric, RobustMethod, StatisticalGradingConfig};
+use crate::psf_fitting::DEFAULT_MIN_R_SQUARED;
+use anyhow::Result;
 use clap::{Parser, Subcommand};
+use std::path::Path;
 
 #[derive(Parser)]
 #[command(name = "psf-guard")]
@@ -61,14 +66,51 @@ pub enum Commands {
         #[arg(short, long)]
         target: Option<String>,
 
+        /// Filter by filter name (exact, case-insensitive)
+        #[arg(long)]
+        filter: Option<String>,
+
         /// Enable verbose output for debugging path issues
         #[arg(short, long)]
         verbose: bool,
 
+        /// Output format (text, json); json lists every file with the action taken
+        #[arg(short, long, default_value = "text")]
+        format: String,
+
         #[command(flatten)]
         stat_options: StatisticalOptions,
     },
 
+    /// Move files from LIGHT_REJECT folders back to their original location
+    RestoreRejected {
+        /// Database file to use
+        database: String,
+
+        /// Base directory containing the image files
+        base_dir: String,
+
+        /// Perform a dry run (show what would be moved without actually moving)
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Filter by project name
+        #[arg(short, long)]
+        project: Option<String>,
+
+        /// Filter by target name
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Reset the grading status of restored images to pending
+        #[arg(long)]
+        reset_status: bool,
+
+        /// Enable verbose output (lists files with no matching image)
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
     /// Regrade images in the database based on statistical analysis
     Regrade {
         /// Database file to use
@@ -86,6 +128,10 @@ pub enum Commands {
         #[arg(short, long)]
         project: Option<String>,
 
+        /// Filter by filter name (exact, case-insensitive)
+        #[arg(long)]
+        filter: Option<String>,
+
         /// Number of days to look back (default: 90)
         #[arg(long, default_value = "90")]
         days: u32,
@@ -94,16 +140,104 @@ pub enum Commands {
         #[arg(long, default_value = "none")]
         reset: String,
 
+        /// Comma-separated reject reasons to leave untouched (case-insensitive substring match)
+        #[arg(long, value_delimiter = ',')]
+        protect_reasons: Vec<String>,
+
+        /// Grade only images added since the last regrade, against the stored baselines (every image is still read to find them)
+        #[arg(long, conflicts_with_all = ["limit", "sample"])]
+        incremental: bool,
+
+        /// Analyze only the N most recently acquired images
+        #[arg(long, conflicts_with = "sample")]
+        limit: Option<usize>,
+
+        /// Analyze N images picked at random
+        #[arg(long)]
+        sample: Option<usize>,
+
+        /// Random seed for --sample, so the same images are picked each run
+        #[arg(long, requires = "sample")]
+        seed: Option<u64>,
+
         #[command(flatten)]
         stat_options: StatisticalOptions,
     },
 
+    /// Validate the database schema and report missing tables or columns
+    CheckDb {
+        /// Database to check (overrides the global --database)
+        #[arg(short, long)]
+        database: Option<String>,
+    },
+
+    /// Import N.I.N.A. image metadata (.json/.csv sidecars) into the database
+    ImportMetadata {
+        /// Directory to scan for metadata files
+        dir: String,
+
+        /// Project to import the images into (created if missing)
+        #[arg(short, long, default_value = "Imported")]
+        project: String,
+
+        /// Target name for all images (default: the directory holding each metadata file)
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Show what would be imported without writing to the database
+        #[arg(long)]
+        dry_run: bool,
+    },
+
     /// Show details for specific images by ID
     ShowImages {
         /// Comma-separated list of image IDs
         ids: String,
     },
 
+    /// Show HFR and star count over time (focus drift, clouds)
+    Trend {
+        /// Filter by project name
+        #[arg(short, long)]
+        project: Option<String>,
+
+        /// Filter by target name
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Output format (sparkline, csv)
+        #[arg(short, long, default_value = "sparkline", value_parser = ["sparkline", "csv"])]
+        format: String,
+    },
+
+    /// Export every image with its database and, optionally, recomputed metrics as CSV
+    Export {
+        /// Filter by project name
+        #[arg(short, long)]
+        project: Option<String>,
+
+        /// Filter by target name
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Write the CSV to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Base directory containing the image files, needed by --recompute
+        #[arg(long)]
+        base_dir: Option<String>,
+
+        /// Run star detection on each image file for fresh HFR, star count and
+        /// eccentricity (slow); otherwise only database metadata is exported
+        #[arg(long)]
+        recompute: bool,
+
+        /// Number of files analyzed in parallel with --recompute [default: number of CPUs]
+        #[arg(long, short = 'j')]
+        jobs: Option<usize>,
+    },
+
     /// Manually update the grading status of an image
     UpdateGrade {
         /// Image ID to update
@@ -129,6 +263,14 @@ pub enum Commands {
         /// Output format (table, json, csv)
         #[arg(short, long, default_value = "table")]
         format: String,
+
+        /// Descend into symlinked directories when scanning a directory
+        #[arg(long)]
+        follow_links: bool,
+
+        /// Maximum subdirectory depth when scanning a directory (0 = only the given directory)
+        #[arg(long)]
+        max_depth: Option<usize>,
     },
 
     /// Analyze FITS images and compare computed statistics with database values
@@ -144,11 +286,15 @@ pub enum Commands {
         #[arg(short, long)]
         target: Option<String>,
 
+        /// Only analyze files whose FILTER header matches (case-insensitive)
+        #[arg(long)]
+        filter: Option<String>,
+
         /// Output format (table, json, csv)
         #[arg(short, long, default_value = "table")]
         format: String,
 
-        /// Star detection algorithm to use (nina, hocusfocus)
+        /// Star detection algorithm to use (nina, hocusfocus, fast)
         #[arg(long, default_value = "hocusfocus")]
         detector: String,
 
@@ -164,10 +310,78 @@ pub enum Commands {
         #[arg(long)]
         compare_all: bool,
 
-        /// PSF fitting type (none, gaussian, moffat4)
+        /// PSF fitting type (none, gaussian, moffat4, or moffat<beta> e.g. moffat2.5)
         #[arg(long, default_value = "none")]
         psf_type: String,
 
+        /// Omit the CSV header line (useful when appending to an existing file)
+        #[arg(long)]
+        no_header: bool,
+
+        /// Key naming style for JSON output (snake_case, camelCase, nina)
+        #[arg(long, default_value = "snake_case", value_parser = ["snake_case", "camelCase", "nina"])]
+        field_style: String,
+
+        /// HDU to analyze in multi-extension files (science, error, or an HDU index)
+        #[arg(long, default_value = "science")]
+        plane: String,
+
+        /// Debayer one-shot-color frames before detection (auto, none, rggb, bggr, grbg, gbrg)
+        #[arg(long, default_value = "none")]
+        debayer: String,
+
+        /// Hot pixel map (from build-hotpixel-map) applied before detection
+        #[arg(long)]
+        hotpixel_map: Option<String>,
+
+        /// Resize factor in (0, 1] for the detection image, for oversampled frames
+        /// (nina and fast detectors; HFR is still measured at full resolution)
+        #[arg(long)]
+        downsample: Option<f64>,
+
+        /// PSF fits with an R² below this are flagged and left out of the
+        /// FWHM and eccentricity aggregates
+        #[arg(long, default_value_t = DEFAULT_MIN_R_SQUARED)]
+        min_r_squared: f64,
+
+        /// Saturation level in ADU for the hocusfocus detector, e.g. 16383 for a
+        /// 14-bit camera [default: DATAMAX header, else the frame maximum]
+        #[arg(long)]
+        max_adu: Option<f64>,
+
+        /// Load files whose pixel data ends early (e.g. truncated downloads), padding
+        /// the missing pixels, instead of skipping them
+        #[arg(long)]
+        tolerant: bool,
+
+        /// Write one CSV row per detected star (all files) to this path
+        #[arg(long, conflicts_with = "compare_all")]
+        stars_csv: Option<String>,
+
+        /// Number of files analyzed in parallel in directory mode [default: number of CPUs]
+        #[arg(long, short = 'j')]
+        jobs: Option<usize>,
+
+        /// Descend into symlinked directories when scanning a directory
+        #[arg(long)]
+        follow_links: bool,
+
+        /// Maximum subdirectory depth when scanning a directory (0 = only the given directory)
+        #[arg(long)]
+        max_depth: Option<usize>,
+
+        /// Analyze only the first N files found in directory mode
+        #[arg(long, conflicts_with = "sample")]
+        limit: Option<usize>,
+
+        /// Analyze N files picked at random in directory mode
+        #[arg(long)]
+        sample: Option<usize>,
+
+        /// Random seed for --sample, so the same files are picked each run
+        #[arg(long, requires = "sample")]
+        seed: Option<u64>,
+
         /// Enable verbose debug output
         #[arg(long, short)]
         verbose: bool,
@@ -190,13 +404,29 @@ pub enum Commands {
         #[arg(long, default_value = "-2.8")]
         shadow_clipping: f64,
 
-        /// Apply logarithmic scaling instead of MTF stretch
+        /// Apply logarithmic scaling instead of MTF stretch (same as --algorithm logarithmic)
         #[arg(long)]
         logarithmic: bool,
 
+        /// Stretch algorithm: mtf, logarithmic, asinh
+        #[arg(long, default_value = "mtf", value_parser = ["mtf", "logarithmic", "asinh"])]
+        algorithm: String,
+
+        /// Asinh softening; smaller values brighten faint signal more
+        #[arg(long, default_value = "0.1")]
+        softening: f64,
+
         /// Invert the image (black stars on white background)
         #[arg(long)]
         invert: bool,
+
+        /// PNG bit depth (8 or 16)
+        #[arg(long, default_value = "8", value_parser = ["8", "16"])]
+        bit_depth: String,
+
+        /// Also write the stretched 16-bit data as FITS, keeping acquisition headers
+        #[arg(long)]
+        output_fits: Option<String>,
     },
 
     /// Create annotated PNG with detected stars marked
@@ -232,15 +462,67 @@ pub enum Commands {
         #[arg(long, default_value = "red")]
         annotation_color: String,
 
-        /// PSF fitting type (none, gaussian, moffat4)
+        /// PSF fitting type (none, gaussian, moffat4, or moffat<beta> e.g. moffat2.5)
         #[arg(long, default_value = "none")]
         psf_type: String,
 
+        /// Debayer one-shot-color frames before detection (auto, none, rggb, bggr, grbg, gbrg)
+        #[arg(long, default_value = "none")]
+        debayer: String,
+
+        /// Hot pixel map (from build-hotpixel-map) applied before detection
+        #[arg(long)]
+        hotpixel_map: Option<String>,
+
+        /// Saturation level in ADU for the hocusfocus detector, e.g. 16383 for a
+        /// 14-bit camera [default: DATAMAX header, else the frame maximum]
+        #[arg(long)]
+        max_adu: Option<f64>,
+
+        /// Also mark candidates that failed validation, in red with a letter for
+        /// the reason (hocusfocus detector only)
+        #[arg(long)]
+        show_rejected: bool,
+
         /// Enable verbose debug output
         #[arg(long, short)]
         verbose: bool,
     },
 
+    /// Build a hot pixel map from dark frames
+    BuildHotpixelMap {
+        /// Dark frames to stack
+        #[arg(required = true)]
+        darks: Vec<String>,
+
+        /// Flag pixels above median + sigma * stddev of the stacked darks
+        #[arg(long, default_value = "5.0")]
+        sigma: f64,
+
+        /// Output path for the map (JSON)
+        #[arg(short, long, default_value = "hotpixels.json")]
+        output: String,
+    },
+
+    /// Combine dark, flat or bias frames into a master frame
+    Stack {
+        /// Frames to stack, or directories of frames
+        #[arg(required = true)]
+        inputs: Vec<String>,
+
+        /// Combination method (median, mean, sigma-clip)
+        #[arg(long, default_value = "median")]
+        method: String,
+
+        /// Rejection threshold in standard deviations for sigma-clip
+        #[arg(long, default_value = "3.0")]
+        kappa: f64,
+
+        /// Output path for the master frame (FITS)
+        #[arg(short, long, default_value = "master.fits")]
+        output: String,
+    },
+
     /// Visualize PSF fit residuals for detected stars
     VisualizePsf {
         /// Path to FITS file
@@ -254,7 +536,7 @@ pub enum Commands {
         #[arg(long)]
         star_index: Option<usize>,
 
-        /// PSF fitting type (gaussian or moffat4)
+        /// PSF fitting type (gaussian, moffat4, or moffat<beta> e.g. moffat2.5)
         #[arg(long, default_value = "moffat4")]
         psf_type: String,
 
@@ -262,7 +544,7 @@ pub enum Commands {
         #[arg(long, default_value = "9")]
         max_stars: usize,
 
-        /// Star selection mode (top, regions, quality, corners)
+        /// Star selection mode (top, regions, quality, corners, spread)
         #[arg(long, default_value = "top")]
         selection_mode: String,
 
@@ -288,7 +570,7 @@ pub enum Commands {
         #[arg(long, default_value = "15")]
         num_stars: usize,
 
-        /// PSF fitting type (gaussian or moffat4)
+        /// PSF fitting type (gaussian, moffat4, or moffat<beta> e.g. moffat2.5)
         #[arg(long, default_value = "moffat4")]
         psf_type: String,
 
@@ -300,7 +582,7 @@ pub enum Commands {
         #[arg(long, default_value = "5")]
         grid_cols: usize,
 
-        /// Star selection mode (top, regions, quality, corners)
+        /// Star selection mode (top, regions, quality, corners, spread)
         #[arg(long, default_value = "corners")]
         selection_mode: String,
 
@@ -309,6 +591,68 @@ pub enum Commands {
         verbose: bool,
     },
 
+    /// Fit a plane to corner and center HFR to measure sensor tilt
+    AnalyzeTilt {
+        /// Path to FITS file
+        fits_path: String,
+
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+
+        /// Enable verbose debug output
+        #[arg(long, short)]
+        verbose: bool,
+    },
+
+    /// Report one representative PSF for a frame: median fit over the brightest stars
+    PsfSummary {
+        /// Path to FITS file
+        fits_path: String,
+
+        /// PSF model to fit (gaussian, moffat4, or moffat<beta> e.g. moffat2.5)
+        #[arg(long, default_value = "gaussian")]
+        psf_type: String,
+
+        /// Number of stars fitted, brightest first
+        #[arg(long, default_value_t = DEFAULT_ENSEMBLE_STARS)]
+        max_stars: usize,
+
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+
+        /// Enable verbose debug output
+        #[arg(long, short)]
+        verbose: bool,
+    },
+
+    /// Heatmap of median HFR, eccentricity or FWHM across the frame (tilt/curvature check)
+    VisualizeField {
+        /// Path to FITS file
+        fits_path: String,
+
+        /// Output PNG path (if not provided, uses FITS filename with _field.png suffix)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Number of cells per side of the grid
+        #[arg(long, default_value = "8")]
+        grid: usize,
+
+        /// Metric to map (hfr, eccentricity, fwhm)
+        #[arg(long, default_value = "hfr")]
+        metric: String,
+
+        /// PSF fitting type for the eccentricity and fwhm metrics (gaussian, moffat4, or moffat<beta>)
+        #[arg(long, default_value = "moffat4")]
+        psf_type: String,
+
+        /// Enable verbose debug output
+        #[arg(long, short)]
+        verbose: bool,
+    },
+
     /// Benchmark PSF fitting performance
     BenchmarkPsf {
         /// Path to FITS file
@@ -325,65 +669,173 @@ pub enum Commands {
 }
 
 #[derive(Parser, Debug, Clone)]
+#[command(group(
+    clap::ArgGroup::new("statistical_source")
+        .args(["enable_statistical", "config"])
+        .multiple(true)
+))]
 pub struct StatisticalOptions {
     /// Enable statistical analysis
     #[arg(long)]
     pub enable_statistical: bool,
 
+    /// JSON file with statistical grading settings (explicit flags take precedence)
+    #[arg(long)]
+    pub config: Option<String>,
+
     /// Enable HFR outlier detection
-    #[arg(long, requires = "enable_statistical")]
+    #[arg(long, requires = "statistical_source")]
     pub stat_hfr: bool,
 
-    /// Standard deviations for HFR outlier detection
-    #[arg(long, default_value = "2.0", requires = "stat_hfr")]
-    pub hfr_stddev: f64,
+    /// Standard deviations for HFR outlier detection [default: 2.0]
+    #[arg(long)]
+    pub hfr_stddev: Option<f64>,
 
     /// Enable star count outlier detection
-    #[arg(long, requires = "enable_statistical")]
+    #[arg(long, requires = "statistical_source")]
     pub stat_stars: bool,
 
-    /// Standard deviations for star count outlier detection
-    #[arg(long, default_value = "2.0", requires = "stat_stars")]
-    pub star_stddev: f64,
+    /// Standard deviations for star count outlier detection [default: 2.0]
+    #[arg(long)]
+    pub star_stddev: Option<f64>,
+
+    /// Spread for HFR and star count outliers: stddev (mean ± k·σ) or mad (median ± k·MAD) [default: stddev]
+    #[arg(long, value_parser = ["stddev", "mad"])]
+    pub outlier_method: Option<String>,
 
     /// Enable distribution analysis (median/mean shift detection)
-    #[arg(long, requires = "enable_statistical")]
+    #[arg(long, requires = "statistical_source")]
     pub stat_distribution: bool,
 
-    /// Percentage threshold for median shift from mean (0.0-1.0)
-    #[arg(long, default_value = "0.1", requires = "stat_distribution")]
-    pub median_shift_threshold: f64,
+    /// Percentage threshold for median shift from mean (0.0-1.0) [default: 0.1]
+    #[arg(long)]
+    pub median_shift_threshold: Option<f64>,
 
     /// Enable cloud detection (sudden rises in median HFR or drops in star count)
-    #[arg(long, requires = "enable_statistical")]
+    #[arg(long, requires = "statistical_source")]
     pub stat_clouds: bool,
 
-    /// Percentage threshold for cloud detection (0.0-1.0, e.g. 0.2 = 20% change)
-    #[arg(long, default_value = "0.2", requires = "stat_clouds")]
-    pub cloud_threshold: f64,
+    /// Percentage threshold for cloud detection (0.0-1.0, e.g. 0.2 = 20% change) [default: 0.2]
+    #[arg(long)]
+    pub cloud_threshold: Option<f64>,
+
+    /// Number of images needed to establish baseline after cloud event [default: 5]
+    #[arg(long)]
+    pub cloud_baseline_count: Option<usize>,
+
+    /// Enable background gradient detection (reads the image files; filter-rejected only)
+    #[arg(long, requires = "statistical_source")]
+    pub stat_gradient: bool,
+
+    /// Max quadrant background difference as a fraction of the median (0.0-1.0) [default: 0.2]
+    #[arg(long)]
+    pub gradient_threshold: Option<f64>,
+
+    /// Enable satellite/airplane trail detection (reads the image files; filter-rejected only)
+    #[arg(long, requires = "statistical_source")]
+    pub stat_trails: bool,
+
+    /// Shortest trail in pixels that rejects a frame [default: 100]
+    #[arg(long)]
+    pub min_trail_length: Option<f64>,
+
+    /// Enable guiding RMS checks (absolute threshold and group outliers)
+    #[arg(long, requires = "statistical_source")]
+    pub stat_guiding: bool,
 
-    /// Number of images needed to establish baseline after cloud event
-    #[arg(long, default_value = "5", requires = "stat_clouds")]
-    pub cloud_baseline_count: usize,
+    /// Total guiding RMS in arcseconds above which a frame is rejected [default: 2.0]
+    #[arg(long)]
+    pub rms_threshold: Option<f64>,
+
+    /// Keep only the best N percent of images per target/filter group (0-100)
+    #[arg(long)]
+    pub keep_percentile: Option<f64>,
+
+    /// Metric used to rank images for --keep-percentile (hfr, stars) [default: hfr]
+    #[arg(long, value_parser = ["hfr", "stars"])]
+    pub metric: Option<String>,
 }
 
 impl StatisticalOptions {
-    pub fn to_grading_config(&self) -> Option<crate::grading::StatisticalGradingConfig> {
-        if self.enable_statistical {
-            Some(crate::grading::StatisticalGradingConfig {
-                enable_hfr_analysis: self.stat_hfr,
-                hfr_stddev_threshold: self.hfr_stddev,
-                enable_star_count_analysis: self.stat_stars,
-                star_count_stddev_threshold: self.star_stddev,
-                enable_distribution_analysis: self.stat_distribution,
-                median_shift_threshold: self.median_shift_threshold,
-                enable_cloud_detection: self.stat_clouds,
-                cloud_threshold: self.cloud_threshold,
-                cloud_baseline_count: self.cloud_baseline_count,
-            })
-        } else {
-            None
+    /// Build the grading config: defaults, then the --config file, then explicit flags
+    pub fn to_grading_config(&self) -> Result<Option<StatisticalGradingConfig>> {
+        let mut config = match &self.config {
+            Some(path) => StatisticalGradingConfig::from_json_file(Path::new(path))?,
+            None if self.enable_statistical || self.keep_percentile.is_some() => {
+                // Without a config file each analysis is opt-in via its flag
+                StatisticalGradingConfig {
+                    enable_hfr_analysis: false,
+                    enable_star_count_analysis: false,
+                    enable_distribution_analysis: false,
+                    enable_cloud_detection: false,
+                    ..StatisticalGradingConfig::default()
+                }
+            }
+            None => return Ok(None),
+        };
+
+        if self.stat_hfr {
+            config.enable_hfr_analysis = true;
+        }
+        if self.stat_stars {
+            config.enable_star_count_analysis = true;
+        }
+        if self.stat_distribution {
+            config.enable_distribution_analysis = true;
+        }
+        if self.stat_clouds {
+            config.enable_cloud_detection = true;
+        }
+        if self.stat_gradient {
+            config.enable_gradient_analysis = true;
+        }
+        if self.stat_trails {
+            config.enable_trail_detection = true;
+        }
+        if self.stat_guiding {
+            config.enable_guiding_analysis = true;
+        }
+        if let Some(value) = self.hfr_stddev {
+            config.hfr_stddev_threshold = value;
+        }
+        if let Some(value) = self.star_stddev {
+            config.star_count_stddev_threshold = value;
         }
+        if let Some(method) = &self.outlier_method {
+            config.outlier_method = match method.as_str() {
+                "mad" => RobustMethod::Mad,
+                _ => RobustMethod::StdDev,
+            };
+        }
+        if let Some(value) = self.median_shift_threshold {
+            config.median_shift_threshold = value;
+        }
+        if let Some(value) = self.cloud_threshold {
+            config.cloud_threshold = value;
+        }
+        if let Some(value) = self.cloud_baseline_count {
+            config.cloud_baseline_count = value;
+        }
+        if let Some(value) = self.gradient_threshold {
+            config.gradient_threshold = value;
+        }
+        if let Some(value) = self.min_trail_length {
+            config.min_trail_length = value;
+        }
+        if let Some(value) = self.rms_threshold {
+            config.rms_threshold = value;
+        }
+        if self.keep_percentile.is_some() {
+            config.keep_percentile = self.keep_percentile;
+        }
+        if let Some(metric) = &self.metric {
+            config.percentile_metric = match metric.as_str() {
+                "stars" => PercentileMetric::StarCount,
+                _ => PercentileMetric::Hfr,
+            };
+        }
+
+        Ok(Some(config))
     }
 }
 
@@ -391,22 +843,48 @@ impl StatisticalOptions {
 mod tests {
     use super::*;
 
+    fn empty_options() -> StatisticalOptions {
+        StatisticalOptions {
+            enable_statistical: false,
+            config: None,
+            stat_hfr: false,
+            hfr_stddev: None,
+            stat_stars: false,
+            star_stddev: None,
+            outlier_method: None,
+            stat_distribution: false,
+            median_shift_threshold: None,
+            stat_clouds: false,
+            cloud_threshold: None,
+            cloud_baseline_count: None,
+            stat_gradient: false,
+            gradient_threshold: None,
+            stat_trails: false,
+            min_trail_length: None,
+            stat_guiding: false,
+            rms_threshold: None,
+            keep_percentile: None,
+            metric: None,
+        }
+    }
+
     #[test]
     fn test_statistical_options_to_grading_config_disabled() {
         let options = StatisticalOptions {
             enable_statistical: false,
             stat_hfr: true,
-            hfr_stddev: 2.0,
+            hfr_stddev: Some(2.0),
             stat_stars: true,
-            star_stddev: 2.0,
+            star_stddev: Some(2.0),
             stat_distribution: true,
-            median_shift_threshold: 0.1,
+            median_shift_threshold: Some(0.1),
             stat_clouds: true,
-            cloud_threshold: 0.2,
-            cloud_baseline_count: 5,
+            cloud_threshold: Some(0.2),
+            cloud_baseline_count: Some(5),
+            ..empty_options()
         };
 
-        assert!(options.to_grading_config().is_none());
+        assert!(options.to_grading_config().unwrap().is_none());
     }
 
     #[test]
@@ -414,25 +892,78 @@ mod tests {
         let options = StatisticalOptions {
             enable_statistical: true,
             stat_hfr: true,
-            hfr_stddev: 1.5,
+            hfr_stddev: Some(1.5),
             stat_stars: false,
-            star_stddev: 2.5,
+            star_stddev: Some(2.5),
             stat_distribution: true,
-            median_shift_threshold: 0.15,
+            median_shift_threshold: Some(0.15),
             stat_clouds: false,
-            cloud_threshold: 0.25,
-            cloud_baseline_count: 10,
+            cloud_threshold: Some(0.25),
+            cloud_baseline_count: Some(10),
+            outlier_method: Some("mad".to_string()),
+            keep_percentile: Some(70.0),
+            metric: Some("stars".to_string()),
+            ..empty_options()
         };
 
-        let config = options.to_grading_config().unwrap();
+        let config = options.to_grading_config().unwrap().unwrap();
         assert!(config.enable_hfr_analysis);
         assert_eq!(config.hfr_stddev_threshold, 1.5);
         assert!(!config.enable_star_count_analysis);
         assert_eq!(config.star_count_stddev_threshold, 2.5);
+        assert_eq!(config.outlier_method, RobustMethod::Mad);
         assert!(config.enable_distribution_analysis);
         assert_eq!(config.median_shift_threshold, 0.15);
         assert!(!config.enable_cloud_detection);
         assert_eq!(config.cloud_threshold, 0.25);
         assert_eq!(config.cloud_baseline_count, 10);
+        assert_eq!(config.keep_percentile, Some(70.0));
+        assert_eq!(config.percentile_metric, PercentileMetric::StarCount);
+    }
+
+    #[test]
+    fn test_statistical_options_cli_overrides_config_file() {
+        let path = std::env::temp_dir().join(format!(
+            "psf_guard_cli_grading_config_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{ "enable_star_count_analysis": false, "hfr_stddev_threshold": 3.0, "cloud_threshold": 0.3 }"#,
+        )
+        .unwrap();
+
+        let options = StatisticalOptions {
+            config: Some(path.to_string_lossy().into_owned()),
+            cloud_threshold: Some(0.1),
+            ..empty_options()
+        };
+        let config = options.to_grading_config();
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap().unwrap();
+
+        // File values win over defaults, explicit flags win over the file
+        assert_eq!(config.hfr_stddev_threshold, 3.0);
+        assert!(!config.enable_star_count_analysis);
+        assert_eq!(config.cloud_threshold, 0.1);
+        // Untouched by either keeps the default
+        assert!(config.enable_hfr_analysis);
+        assert_eq!(config.cloud_baseline_count, 5);
+    }
+
+    #[test]
+    fn test_stat_flags_accept_config_instead_of_enable_statistical() {
+        let parsed = Cli::try_parse_from([
+            "psf-guard",
+            "regrade",
+            "db.sqlite",
+            "--config",
+            "grading.json",
+            "--stat-clouds",
+        ]);
+        assert!(parsed.is_ok());
+
+        let parsed = Cli::try_parse_from(["psf-guard", "regrade", "db.sqlite", "--stat-clouds"]);
+        assert!(parsed.is_err());
     }
 }
@@ -1,15 +1,38 @@
-use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
-use crate::image_analysis::{FitsImage, ImageStatistics as ComputedStats};
-use crate::mtf_stretch::{stretch_image, StretchParameters};
-use crate::nina_star_detection::{
-    detect_stars_with_original, NoiseReduction, StarDetectionParams, StarSensitivity,
+use crate::analysis::{
+    analyze_frame, AnalyzeOptions, Detector, FrameAnalysis, FrameStar, StarMetrics, HFR_TO_FWHM,
 };
-use crate::psf_fitting::PSFType;
+use crate::debug::is_debug_enabled;
+use crate::field_style::FieldStyle;
+use crate::hotpixel_map::HotPixelMap;
+use crate::image_analysis::{
+    header_number, read_header_cards, saturation_adu, DebayerMode, FitsImage, FitsPlane,
+    FitsReadError, FitsReadOptions, ImageStatistics as ComputedStats,
+};
+use crate::nina_star_detection::StarSensitivity;
+use crate::psf_fitting::{PSFType, DEFAULT_MIN_R_SQUARED};
+use crate::utils::{find_files, is_image_file, Subset, TraversalOptions};
 use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
 use rusqlite::Connection;
 use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 
+/// Header line for the per-file CSV output
+const CSV_HEADER: &str =
+    "Filename,Min,Max,Mean,Median,MAD,DetectedStars,AvgHFR,HFRStdDev,DBStars,DBHFR";
+
+/// Extra CSV columns emitted when HocusFocus runs with PSF fitting
+const CSV_FWHM_COLUMNS: &str = "AvgHFRFWHM,AvgPSFFWHM,PSFFittedStars";
+
+/// Star aggregate columns, always last so earlier columns keep their positions
+const CSV_STAR_METRIC_COLUMNS: &str = "AvgFWHM,AvgSNR,MedianEccentricity,MaxEccentricity";
+
+/// Header line for the per-star CSV written by --stars-csv
+const STARS_CSV_HEADER: &str = "Filename,X,Y,HFR,FWHM,Brightness,Eccentricity,SNR,RSquared,PoorFit";
+
 #[derive(Debug, Deserialize)]
 struct ImageMetadata {
     #[serde(rename = "FileName")]
@@ -20,6 +43,96 @@ struct ImageMetadata {
     detected_stars: Option<i32>,
 }
 
+/// Aggregated star detection results for one image
+#[derive(Debug, Clone)]
+struct DetectionSummary {
+    star_count: usize,
+    avg_hfr: f64,
+    hfr_std: f64,
+    /// Report the HFR-derived and fitted PSF FWHM separately, as HocusFocus
+    /// does with PSF fitting
+    reports_fwhm: bool,
+    metrics: StarMetrics,
+    info: String,
+    /// Extra lines for the table output, e.g. blank frame or eccentricity remarks
+    notes: Vec<String>,
+    stars: Vec<FrameStar>,
+}
+
+/// How each file is loaded and which detector runs on it
+#[derive(Debug, Clone, Copy)]
+struct DetectionSettings<'a> {
+    detector: &'a str,
+    sensitivity: &'a str,
+    apply_stretch: bool,
+    psf_type: &'a str,
+    plane: FitsPlane,
+    debayer: DebayerMode,
+    hotpixel_map: Option<&'a HotPixelMap>,
+    /// Forced detection resize factor for the nina and fast detectors
+    downsample: Option<f64>,
+    /// PSF fits below this R² are left out of the FWHM and eccentricity aggregates
+    min_r_squared: f64,
+    /// Saturation level in ADU, overriding the DATAMAX header
+    max_adu: Option<f64>,
+    /// Pad files whose data ends early instead of skipping them
+    tolerant: bool,
+}
+
+/// Statistics and detection results for one file
+#[derive(Debug, Clone)]
+struct FileAnalysis {
+    filename: String,
+    stats: ComputedStats,
+    detection: DetectionSummary,
+}
+
+/// Header values that tune detection to the camera and optics
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameHeader {
+    /// Saturation level on the 0-65535 scale of the loaded image
+    saturation_level: Option<f64>,
+    /// XPIXSZ, doubled when debayering halved the frame
+    pixel_size_um: Option<f64>,
+    focal_length_mm: Option<f64>,
+}
+
+impl FrameHeader {
+    fn read(path: &Path, fits: &FitsImage, max_adu: Option<f64>) -> Self {
+        let cards = read_header_cards(path, &["XPIXSZ", "FOCALLEN", "NAXIS1"]).unwrap_or_default();
+        let number = |keyword: &str| {
+            cards
+                .iter()
+                .find(|(k, _)| k == keyword)
+                .and_then(|(_, value)| header_number(value))
+                .filter(|&value| value > 0.0)
+        };
+        let binning = number("NAXIS1").map_or(1.0, |naxis1| {
+            (naxis1 / fits.width.max(1) as f64).round().max(1.0)
+        });
+
+        Self {
+            saturation_level: saturation_adu(path, max_adu).and_then(|adu| fits.scaled_level(adu)),
+            pixel_size_um: number("XPIXSZ").map(|size| size * binning),
+            focal_length_mm: number("FOCALLEN"),
+        }
+    }
+}
+
+/// N.I.N.A. metadata for every acquired image, loaded once so that many
+/// files can be matched without a query each
+struct DatabaseIndex {
+    entries: Vec<DatabaseEntry>,
+}
+
+struct DatabaseEntry {
+    /// Lowercased raw metadata JSON, matched like the SQL `LIKE` it replaces
+    metadata_lower: String,
+    filename: String,
+    stars: i32,
+    hfr: f64,
+}
+
 #[derive(Debug, Clone)]
 struct DetectorConfig {
     name: String,
@@ -33,26 +146,105 @@ pub fn analyze_fits_and_compare(
     fits_path: &str,
     _project_filter: Option<String>,
     _target_filter: Option<String>,
+    filter_name: Option<String>,
     format: &str,
     detector: &str,
     sensitivity: &str,
     apply_stretch: bool,
     compare_all: bool,
     psf_type: &str,
-    _verbose: bool,
+    no_header: bool,
+    field_style: &str,
+    plane: &str,
+    debayer: &str,
+    hotpixel_map: Option<&str>,
+    downsample: Option<f64>,
+    min_r_squared: f64,
+    max_adu: Option<f64>,
+    tolerant: bool,
+    stars_csv: Option<String>,
+    jobs: Option<usize>,
+    traversal: &TraversalOptions,
+    subset: Subset,
+    verbose: bool,
 ) -> Result<()> {
+    crate::debug::init_debug(verbose);
     let fits_path = Path::new(fits_path);
+    let field_style: FieldStyle = field_style.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let plane: FitsPlane = plane.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let debayer: DebayerMode = debayer.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let hotpixel_map = hotpixel_map
+        .map(|path| HotPixelMap::load(Path::new(path)))
+        .transpose()?;
+    if let Some(factor) = downsample {
+        if !(factor > 0.0 && factor <= 1.0) {
+            anyhow::bail!(
+                "Downsample factor must be greater than 0 and at most 1, got {}",
+                factor
+            );
+        }
+    }
+    if !(0.0..=1.0).contains(&min_r_squared) {
+        anyhow::bail!("Minimum R² must be between 0 and 1, got {}", min_r_squared);
+    }
+    if let Some(max_adu) = max_adu {
+        if max_adu <= 0.0 {
+            anyhow::bail!("Maximum ADU must be greater than 0, got {}", max_adu);
+        }
+    }
+    let settings = DetectionSettings {
+        detector,
+        sensitivity,
+        apply_stretch,
+        psf_type,
+        plane,
+        debayer,
+        hotpixel_map: hotpixel_map.as_ref(),
+        downsample,
+        min_r_squared,
+        max_adu,
+        tolerant,
+    };
+
+    let mut stars_out = match &stars_csv {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path, e))?;
+            let mut writer = BufWriter::new(file);
+            writeln!(writer, "{}", STARS_CSV_HEADER)?;
+            Some(writer)
+        }
+        None => None,
+    };
 
     if compare_all {
         // Generate all combinations of detector configurations
         let configs = generate_detector_configs();
 
-        if fits_path.is_file() {
-            compare_single_fits_all_detectors(conn, fits_path, format, apply_stretch, &configs)?;
+        let files = if fits_path.is_file() {
+            vec![fits_path.to_path_buf()]
         } else if fits_path.is_dir() {
-            println!("Comparison mode for directories not yet implemented");
-            return Ok(());
-        }
+            let files = find_fits_files(fits_path, traversal, filter_name.as_deref(), subset)?;
+            if files.is_empty() {
+                eprintln!("No FITS files found in directory: {}", fits_path.display());
+                return Ok(());
+            }
+            files
+        } else {
+            return Err(anyhow::anyhow!(
+                "Path does not exist or is not accessible: {}",
+                fits_path.display()
+            ));
+        };
+        compare_all_detectors(
+            conn,
+            &files,
+            format,
+            &configs,
+            no_header,
+            field_style,
+            &settings,
+        )?;
     } else {
         // Single detector mode
         if fits_path.is_file() {
@@ -60,20 +252,26 @@ pub fn analyze_fits_and_compare(
                 conn,
                 fits_path,
                 format,
-                detector,
-                sensitivity,
-                apply_stretch,
-                psf_type,
+                &settings,
+                !no_header,
+                field_style,
+                stars_out.as_mut().map(|w| w as &mut dyn Write),
+                &mut std::io::stdout(),
             )?;
         } else if fits_path.is_dir() {
             analyze_fits_directory(
                 conn,
                 fits_path,
                 format,
-                detector,
-                sensitivity,
-                apply_stretch,
-                psf_type,
+                &settings,
+                no_header,
+                field_style,
+                jobs,
+                traversal,
+                filter_name.as_deref(),
+                subset,
+                stars_out.as_mut().map(|w| w as &mut dyn Write),
+                &mut std::io::stdout(),
             )?;
         } else {
             return Err(anyhow::anyhow!(
@@ -83,9 +281,33 @@ pub fn analyze_fits_and_compare(
         }
     }
 
+    if let Some(mut writer) = stars_out {
+        writer.flush()?;
+        if let Some(path) = stars_csv {
+            eprintln!("Per-star measurements written to {}", path);
+        }
+    }
+
     Ok(())
 }
 
+fn csv_header(with_fwhm: bool) -> String {
+    if with_fwhm {
+        format!(
+            "{},{},{}",
+            CSV_HEADER, CSV_FWHM_COLUMNS, CSV_STAR_METRIC_COLUMNS
+        )
+    } else {
+        format!("{},{}", CSV_HEADER, CSV_STAR_METRIC_COLUMNS)
+    }
+}
+
+/// Whether the FWHM columns are produced for this detector configuration
+fn reports_fwhm(detector: &str, psf_type: &str) -> bool {
+    detector.eq_ignore_ascii_case("hocusfocus")
+        && psf_type.parse().unwrap_or(PSFType::None) != PSFType::None
+}
+
 fn generate_detector_configs() -> Vec<DetectorConfig> {
     let mut configs = vec![];
 
@@ -105,468 +327,788 @@ fn generate_detector_configs() -> Vec<DetectorConfig> {
         sensitivity: "normal".to_string(),
     });
 
+    configs.push(DetectorConfig {
+        name: "Fast".to_string(),
+        detector: "fast".to_string(),
+        sensitivity: "normal".to_string(),
+    });
+
     configs
 }
 
-fn compare_single_fits_all_detectors(
+/// Star count, average HFR and HFR standard deviation of one detector run
+type DetectorResult = Result<(usize, f64, f64)>;
+
+/// Results of every detector configuration on one file
+struct DetectorComparison {
+    filename: String,
+    width: usize,
+    height: usize,
+    stats: ComputedStats,
+    /// Configuration name with its result
+    results: Vec<(String, DetectorResult)>,
+}
+
+impl DetectorComparison {
+    /// Index of the result whose average HFR is nearest the N.I.N.A. HFR,
+    /// ignoring failed runs and runs that found no stars
+    fn closest_to(&self, db_hfr: f64) -> Option<usize> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, result))| match result {
+                Ok((stars, hfr, _)) if *stars > 0 => Some((i, (hfr - db_hfr).abs())),
+                _ => None,
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+}
+
+const COMPARE_CSV_HEADER: &str = "File,Detector,Stars,AvgHFR,HFRStdDev,DBStars,DBHFR,ClosestToDB";
+
+/// Run every detector configuration on each file and report them side by side
+/// with the N.I.N.A. values. A file is printed only after all its detectors
+/// have run, so verbose detector output never lands inside its table.
+fn compare_all_detectors(
     conn: &Connection,
-    fits_path: &Path,
+    files: &[PathBuf],
     format: &str,
-    apply_stretch: bool,
     configs: &[DetectorConfig],
+    no_header: bool,
+    field_style: FieldStyle,
+    settings: &DetectionSettings,
 ) -> Result<()> {
-    let filename = fits_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown");
-
-    println!("Analyzing FITS file: {}", fits_path.display());
+    let db_index = DatabaseIndex::load(conn)?;
 
-    // Load the FITS file once
-    let fits = FitsImage::from_file(fits_path)?;
-    let computed_stats = fits.calculate_basic_statistics();
-
-    // Get database info if available
-    let db_info = get_database_info(conn, filename)?;
+    if format == "csv" && !no_header {
+        println!("{}", COMPARE_CSV_HEADER);
+    }
 
-    match format {
-        "csv" => {
-            println!("Detector,Stars,AvgHFR,HFRStdDev");
+    for fits_path in files {
+        if format != "csv" && format != "json" {
+            println!("Analyzing FITS file: {}", fits_path.display());
         }
-        "json" => {
-            let mut results = vec![];
-            for config in configs {
-                let result = run_detector_config(&fits, &computed_stats, config, apply_stretch);
-                if let Ok((star_count, avg_hfr, hfr_std)) = result {
-                    results.push(serde_json::json!({
-                        "detector": config.name,
-                        "stars": star_count,
-                        "avg_hfr": avg_hfr,
-                        "hfr_std_dev": hfr_std,
-                    }));
-                }
-            }
-            let output = serde_json::json!({
-                "file": filename,
-                "dimensions": format!("{}x{}", fits.width, fits.height),
-                "statistics": {
-                    "min": computed_stats.min,
-                    "max": computed_stats.max,
-                    "mean": computed_stats.mean,
-                    "median": computed_stats.median,
-                    "mad": computed_stats.mad.unwrap_or(0.0),
-                },
-                "database": db_info,
-                "detectors": results,
-            });
-            println!("{}", serde_json::to_string_pretty(&output)?);
-            return Ok(());
-        }
-        _ => {
-            println!("\n=== Detector Comparison Results ===\n");
-            println!("File: {}", filename);
-            println!("Dimensions: {}x{}", fits.width, fits.height);
-            println!(
-                "Statistics: Min={}, Max={}, Mean={:.2}, Median={:.2}, MAD={:.2}",
-                computed_stats.min,
-                computed_stats.max,
-                computed_stats.mean,
-                computed_stats.median,
-                computed_stats.mad.unwrap_or(0.0)
-            );
 
-            if let Some((nina_stars, nina_hfr)) = db_info {
-                println!(
-                    "N.I.N.A. Database: {} stars, HFR={:.3}",
-                    nina_stars, nina_hfr
-                );
+        let comparison = match compare_file(fits_path, configs, settings) {
+            Ok(comparison) => comparison,
+            // A bad file only ends the run when it is the only one
+            Err(e) if files.len() > 1 => {
+                eprintln!("Error analyzing {}: {}", fits_path.display(), e);
+                continue;
             }
-
-            println!(
-                "\n{:<30} | {:>8} | {:>10} | {:>10}",
-                "Detector", "Stars", "Avg HFR", "HFR StdDev"
-            );
-            println!("{:-<30}-+-{:-<8}-+-{:-<10}-+-{:-<10}", "", "", "", "");
-        }
-    }
-
-    // Run each detector configuration
-    for config in configs {
-        let result = run_detector_config(&fits, &computed_stats, config, apply_stretch);
+            Err(e) => return Err(e),
+        };
+        let db_info = db_index.lookup(&comparison.filename);
 
         match format {
-            "csv" => {
-                if let Ok((star_count, avg_hfr, hfr_std)) = result {
-                    println!(
-                        "{},{},{:.3},{:.3}",
-                        config.name, star_count, avg_hfr, hfr_std
-                    );
-                }
-            }
-            _ => match result {
-                Ok((star_count, avg_hfr, hfr_std)) => {
-                    println!(
-                        "{:<30} | {:>8} | {:>10.3} | {:>10.3}",
-                        config.name, star_count, avg_hfr, hfr_std
-                    );
-                }
-                Err(e) => {
-                    println!("{:<30} | ERROR: {}", config.name, e);
-                }
-            },
+            "csv" => write_comparison_csv(&mut std::io::stdout().lock(), &comparison, db_info)?,
+            "json" => println!(
+                "{}",
+                serde_json::to_string_pretty(
+                    &field_style.apply(comparison_json(&comparison, db_info))
+                )?
+            ),
+            _ => print_comparison_table(&comparison, db_info),
         }
     }
 
     Ok(())
 }
 
-fn run_detector_config(
-    fits: &FitsImage,
-    computed_stats: &ComputedStats,
-    config: &DetectorConfig,
-    apply_stretch: bool,
-) -> Result<(usize, f64, f64)> {
-    match config.detector.as_str() {
-        "nina" => {
-            let star_sensitivity = match config.sensitivity.as_str() {
-                "high" => StarSensitivity::High,
-                "highest" => StarSensitivity::Highest,
-                _ => StarSensitivity::Normal,
-            };
+fn compare_file(
+    fits_path: &Path,
+    configs: &[DetectorConfig],
+    settings: &DetectionSettings,
+) -> Result<DetectorComparison> {
+    let filename = fits_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string();
 
-            let params = StarDetectionParams {
-                sensitivity: star_sensitivity,
-                noise_reduction: NoiseReduction::None,
-                use_roi: false,
-            };
+    // Load the FITS file once
+    let fits = load_image(fits_path, settings)?;
+    let stats = fits.calculate_basic_statistics();
 
-            // NINA always uses MTF stretch
-            let stretch_params = StretchParameters::default();
-            let stretched = stretch_image(
-                &fits.data,
-                computed_stats,
-                stretch_params.factor,
-                stretch_params.black_clipping,
-            );
+    let results = configs
+        .iter()
+        .map(|config| {
+            let result =
+                run_detector_config(&fits, config, settings.apply_stretch, settings.downsample);
+            (config.name.clone(), result)
+        })
+        .collect();
 
-            let result = detect_stars_with_original(
-                &stretched,
-                &fits.data,
-                fits.width,
-                fits.height,
-                &params,
-            );
+    Ok(DetectorComparison {
+        filename,
+        width: fits.width,
+        height: fits.height,
+        stats,
+        results,
+    })
+}
 
-            Ok((
-                result.star_list.len(),
-                result.average_hfr,
-                result.hfr_std_dev,
-            ))
+fn write_comparison_csv(
+    out: &mut dyn Write,
+    comparison: &DetectorComparison,
+    db_info: Option<(i32, f64)>,
+) -> Result<()> {
+    let closest = db_info.and_then(|(_, db_hfr)| comparison.closest_to(db_hfr));
+    let (db_stars, db_hfr) = match db_info {
+        Some((stars, hfr)) => (stars.to_string(), format!("{:.3}", hfr)),
+        None => (String::new(), String::new()),
+    };
+
+    for (i, (name, result)) in comparison.results.iter().enumerate() {
+        if let Ok((star_count, avg_hfr, hfr_std)) = result {
+            writeln!(
+                out,
+                "{},{},{},{:.3},{:.3},{},{},{}",
+                comparison.filename,
+                name,
+                star_count,
+                avg_hfr,
+                hfr_std,
+                db_stars,
+                db_hfr,
+                closest == Some(i)
+            )?;
         }
-        "hocusfocus" => {
-            let params = HocusFocusParams::default();
-
-            let detection_data = if apply_stretch {
-                let stretch_params = StretchParameters::default();
-                stretch_image(
-                    &fits.data,
-                    computed_stats,
-                    stretch_params.factor,
-                    stretch_params.black_clipping,
-                )
-            } else {
-                fits.data.clone()
-            };
+    }
+    Ok(())
+}
 
-            let result =
-                detect_stars_hocus_focus(&detection_data, fits.width, fits.height, &params);
-
-            if result.stars.is_empty() {
-                Ok((0, 0.0, 0.0))
-            } else {
-                let hfr_values: Vec<f64> = result.stars.iter().map(|s| s.hfr).collect();
-                let avg_hfr = hfr_values.iter().sum::<f64>() / hfr_values.len() as f64;
-                let variance = hfr_values
-                    .iter()
-                    .map(|&hfr| (hfr - avg_hfr).powi(2))
-                    .sum::<f64>()
-                    / hfr_values.len() as f64;
-                let std_dev = variance.sqrt();
+fn comparison_json(
+    comparison: &DetectorComparison,
+    db_info: Option<(i32, f64)>,
+) -> serde_json::Value {
+    let closest = db_info.and_then(|(_, db_hfr)| comparison.closest_to(db_hfr));
+    let detectors: Vec<serde_json::Value> = comparison
+        .results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, result))| {
+            let (star_count, avg_hfr, hfr_std) = result.as_ref().ok()?;
+            Some(serde_json::json!({
+                "detector": name,
+                "stars": star_count,
+                "avg_hfr": avg_hfr,
+                "hfr_std_dev": hfr_std,
+                "closest_to_db": closest == Some(i),
+            }))
+        })
+        .collect();
+    let stats = &comparison.stats;
+
+    serde_json::json!({
+        "file": comparison.filename,
+        "dimensions": format!("{}x{}", comparison.width, comparison.height),
+        "statistics": {
+            "min": stats.min,
+            "max": stats.max,
+            "mean": stats.mean,
+            "median": stats.median,
+            "mad": stats.mad.unwrap_or(0.0),
+        },
+        "database": db_info,
+        "detectors": detectors,
+        "closest_to_db": closest.map(|i| comparison.results[i].0.clone()),
+    })
+}
+
+fn print_comparison_table(comparison: &DetectorComparison, db_info: Option<(i32, f64)>) {
+    let stats = &comparison.stats;
+    println!("\n=== Detector Comparison Results ===\n");
+    println!("File: {}", comparison.filename);
+    println!("Dimensions: {}x{}", comparison.width, comparison.height);
+    println!(
+        "Statistics: Min={}, Max={}, Mean={:.2}, Median={:.2}, MAD={:.2}",
+        stats.min,
+        stats.max,
+        stats.mean,
+        stats.median,
+        stats.mad.unwrap_or(0.0)
+    );
 
-                Ok((result.stars.len(), avg_hfr, std_dev))
+    if let Some((nina_stars, nina_hfr)) = db_info {
+        println!(
+            "N.I.N.A. Database: {} stars, HFR={:.3}",
+            nina_stars, nina_hfr
+        );
+    }
+    let closest = db_info.and_then(|(_, db_hfr)| comparison.closest_to(db_hfr));
+
+    println!(
+        "\n{:<30} | {:>8} | {:>10} | {:>10} | {:>10}",
+        "Detector", "Stars", "Avg HFR", "HFR StdDev", "vs DB HFR"
+    );
+    println!(
+        "{:-<30}-+-{:-<8}-+-{:-<10}-+-{:-<10}-+-{:-<10}",
+        "", "", "", "", ""
+    );
+
+    for (i, (name, result)) in comparison.results.iter().enumerate() {
+        match result {
+            Ok((star_count, avg_hfr, hfr_std)) => {
+                let diff = db_info
+                    .map(|(_, db_hfr)| format!("{:+.3}", avg_hfr - db_hfr))
+                    .unwrap_or_default();
+                println!(
+                    "{:<30} | {:>8} | {:>10.3} | {:>10.3} | {:>10}{}",
+                    name,
+                    star_count,
+                    avg_hfr,
+                    hfr_std,
+                    diff,
+                    if closest == Some(i) {
+                        "  <- closest to N.I.N.A."
+                    } else {
+                        ""
+                    }
+                );
+            }
+            Err(e) => {
+                println!("{:<30} | ERROR: {}", name, e);
             }
         }
-        _ => Err(anyhow::anyhow!("Unknown detector: {}", config.detector)),
     }
 }
 
+/// One detector configuration of `--compare-all`, without PSF fitting or header values
+fn run_detector_config(
+    fits: &FitsImage,
+    config: &DetectorConfig,
+    apply_stretch: bool,
+    downsample: Option<f64>,
+) -> DetectorResult {
+    let options = AnalyzeOptions {
+        detector: config
+            .detector
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{}", e))?,
+        sensitivity: parse_sensitivity(&config.sensitivity),
+        apply_stretch,
+        downsample,
+        verbose: is_debug_enabled(),
+        ..AnalyzeOptions::default()
+    };
+    let frame = analyze_frame(&fits.data, fits.width, fits.height, &options)?;
+    Ok((frame.star_count(), frame.average_hfr, frame.hfr_std_dev))
+}
+
+/// Analyze one file; CSV output goes to `out`, table and JSON output to stdout
+#[allow(clippy::too_many_arguments)]
 fn analyze_single_fits(
     conn: &Connection,
     fits_path: &Path,
     format: &str,
-    detector: &str,
-    sensitivity: &str,
-    apply_stretch: bool,
-    psf_type: &str,
+    settings: &DetectionSettings,
+    include_csv_header: bool,
+    field_style: FieldStyle,
+    stars_csv: Option<&mut (dyn Write + '_)>,
+    out: &mut dyn Write,
 ) -> Result<()> {
-    let filename = fits_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown");
-
-    println!("Analyzing FITS file: {}", fits_path.display());
-
-    // Load the FITS file
-    let fits = FitsImage::from_file(fits_path)?;
-    let computed_stats = fits.calculate_basic_statistics();
-
-    // Perform star detection
-    let (star_count, avg_hfr, hfr_std, detection_info) = detect_stars(
-        &fits,
-        &computed_stats,
-        detector,
-        sensitivity,
-        apply_stretch,
-        psf_type,
-    )?;
+    // CSV and JSON output must stay machine-readable, so only tables get a preamble
+    if format != "csv" && format != "json" {
+        println!("Analyzing FITS file: {}", fits_path.display());
+        print_detection_settings(settings);
+    }
 
-    // Look for matching database entries
-    let db_info = get_database_info(conn, filename)?;
+    let analysis = analyze_file(fits_path, settings)?;
 
-    // Output results based on format
-    match format {
-        "json" => output_json(
-            &computed_stats,
-            star_count,
-            avg_hfr,
-            hfr_std,
-            db_info,
-            &detection_info,
-            filename,
-        ),
-        "csv" => output_csv(
-            filename,
-            &computed_stats,
-            star_count,
-            avg_hfr,
-            hfr_std,
-            db_info,
-        ),
-        _ => output_table(
-            filename,
-            &computed_stats,
-            star_count,
-            avg_hfr,
-            hfr_std,
-            db_info,
-            &detection_info,
-        ),
+    if let Some(out) = stars_csv {
+        write_stars_csv(
+            out,
+            &analysis.filename,
+            &analysis.detection.stars,
+            settings.min_r_squared,
+        )?;
     }
 
-    Ok(())
+    // Look for matching database entries
+    let db_info =
+        DatabaseIndex::load_for_file(conn, &analysis.filename)?.lookup(&analysis.filename);
+
+    output_analysis(
+        out,
+        format,
+        settings,
+        &analysis,
+        db_info,
+        include_csv_header,
+        field_style,
+    )
 }
 
+/// Analyze every file in a directory; CSV output goes to `out`, table and JSON
+/// output to stdout
+#[allow(clippy::too_many_arguments)]
 fn analyze_fits_directory(
     conn: &Connection,
     dir_path: &Path,
     format: &str,
-    detector: &str,
-    sensitivity: &str,
-    apply_stretch: bool,
-    psf_type: &str,
+    settings: &DetectionSettings,
+    no_header: bool,
+    field_style: FieldStyle,
+    jobs: Option<usize>,
+    traversal: &TraversalOptions,
+    filter_name: Option<&str>,
+    subset: Subset,
+    mut stars_csv: Option<&mut (dyn Write + '_)>,
+    out: &mut dyn Write,
 ) -> Result<()> {
-    let mut fits_files = Vec::new();
-
-    // Recursively find all FITS files
-    fn find_fits_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                find_fits_files(&path, files)?;
-            } else if let Some(ext) = path.extension() {
-                if ext == "fits" || ext == "fit" || ext == "FIT" || ext == "FITS" {
-                    files.push(path);
-                }
-            }
-        }
-        Ok(())
-    }
-
-    find_fits_files(dir_path, &mut fits_files)?;
-
+    let fits_files = find_fits_files(dir_path, traversal, filter_name, subset)?;
     if fits_files.is_empty() {
-        println!("No FITS files found in directory: {}", dir_path.display());
+        eprintln!("No FITS files found in directory: {}", dir_path.display());
         return Ok(());
     }
 
-    println!("Found {} FITS files to analyze", fits_files.len());
+    let db_index = DatabaseIndex::load(conn)?;
+    let is_table = format != "csv" && format != "json";
+    if is_table {
+        println!("Found {} FITS files to analyze", fits_files.len());
+        print_detection_settings(settings);
+    }
+
+    let results = analyze_files(&fits_files, settings, jobs, is_table)?;
 
-    // CSV header for CSV format
-    if format == "csv" {
-        println!("Filename,Min,Max,Mean,Median,MAD,DetectedStars,AvgHFR,HFRStdDev,DBStars,DBHFR");
+    // CSV header is printed once here; the per-file output below never prints it
+    if format == "csv" && !no_header {
+        writeln!(
+            out,
+            "{}",
+            csv_header(reports_fwhm(settings.detector, settings.psf_type))
+        )?;
     }
 
-    for fits_path in fits_files {
-        if let Err(e) = analyze_single_fits(
-            conn,
-            &fits_path,
-            format,
-            detector,
-            sensitivity,
-            apply_stretch,
-            psf_type,
-        ) {
-            eprintln!("Error analyzing {}: {}", fits_path.display(), e);
+    for (fits_path, result) in fits_files.iter().zip(results) {
+        let analysis = match result {
+            Ok(analysis) => analysis,
+            Err(e) if e.downcast_ref::<FitsReadError>().is_some() => {
+                eprintln!(
+                    "Skipping {}: {} (--tolerant pads the missing pixels)",
+                    fits_path.display(),
+                    e
+                );
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error analyzing {}: {}", fits_path.display(), e);
+                continue;
+            }
+        };
+
+        if let Some(out) = stars_csv.as_deref_mut() {
+            write_stars_csv(
+                out,
+                &analysis.filename,
+                &analysis.detection.stars,
+                settings.min_r_squared,
+            )?;
         }
+
+        let db_info = db_index.lookup(&analysis.filename);
+        output_analysis(
+            out,
+            format,
+            settings,
+            &analysis,
+            db_info,
+            false,
+            field_style,
+        )?;
     }
 
     Ok(())
 }
 
-fn detect_stars(
-    fits: &FitsImage,
-    computed_stats: &ComputedStats,
-    detector: &str,
-    sensitivity: &str,
-    apply_stretch: bool,
-    psf_type: &str,
-) -> Result<(usize, f64, f64, String)> {
-    let detection_info;
+/// FITS files under `dir_path` in sorted order, optionally only those whose
+/// FILTER header matches, narrowed to `subset` after filtering
+fn find_fits_files(
+    dir_path: &Path,
+    traversal: &TraversalOptions,
+    filter_name: Option<&str>,
+    subset: Subset,
+) -> Result<Vec<PathBuf>> {
+    let mut fits_files = find_files(dir_path, traversal, is_image_file)?;
+
+    if let Some(filter) = filter_name {
+        fits_files.retain(|path| matches_filter(path, filter));
+    }
 
-    println!("\nStar Detection:");
-    println!("  Algorithm: {}", detector);
-    println!("  Sensitivity: {}", sensitivity);
-    println!("  Apply MTF Stretch: {}", apply_stretch);
-
-    match detector.to_lowercase().as_str() {
-        "nina" => {
-            // Parse sensitivity
-            let star_sensitivity = match sensitivity.to_lowercase().as_str() {
-                "high" => StarSensitivity::High,
-                "highest" => StarSensitivity::Highest,
-                _ => StarSensitivity::Normal,
-            };
-            println!("  Forcing stretch for NINA");
+    // Directory listing order is platform dependent; sort so output is reproducible
+    fits_files.sort();
+    Ok(subset.apply(fits_files))
+}
 
-            let params = StarDetectionParams {
-                sensitivity: star_sensitivity,
-                noise_reduction: NoiseReduction::None,
-                use_roi: false,
-            };
+/// Analyze files on `jobs` threads (all CPUs when None), returning results in input order
+fn analyze_files(
+    files: &[PathBuf],
+    settings: &DetectionSettings,
+    jobs: Option<usize>,
+    show_progress: bool,
+) -> Result<Vec<Result<FileAnalysis>>> {
+    let progress = if show_progress {
+        let bar = ProgressBar::new(files.len() as u64);
+        bar.set_style(ProgressStyle::with_template(
+            "{bar:40} {pos}/{len} files ({eta} remaining)",
+        )?);
+        bar
+    } else {
+        ProgressBar::hidden()
+    };
 
-            // NINA always uses MTF stretch
-            let stretch_params = StretchParameters::default();
-            let stretched = stretch_image(
-                &fits.data,
-                computed_stats,
-                stretch_params.factor,
-                stretch_params.black_clipping,
-            );
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = jobs {
+        pool = pool.num_threads(jobs);
+    }
+    let pool = pool.build()?;
+
+    // Each file is independent; collect() keeps the input order
+    let results = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| {
+                let result = analyze_file(path, settings);
+                progress.inc(1);
+                result
+            })
+            .collect()
+    });
 
-            let result = detect_stars_with_original(
-                &stretched,
-                &fits.data,
-                fits.width,
-                fits.height,
-                &params,
-            );
+    progress.finish_and_clear();
+    Ok(results)
+}
 
-            detection_info = format!("NINA {} sensitivity", sensitivity);
+/// Headline metrics of one frame, for commands that reuse this pipeline
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameMetrics {
+    pub star_count: usize,
+    pub avg_hfr: f64,
+    pub median_eccentricity: Option<f64>,
+}
 
-            Ok((
-                result.star_list.len(),
-                result.average_hfr,
-                result.hfr_std_dev,
-                detection_info,
-            ))
-        }
-        "hocusfocus" => {
-            println!("  Using OpenCV with automatic fallback");
+/// Analyze frames with the default HocusFocus settings plus a Gaussian PSF fit,
+/// in parallel and in input order
+pub(crate) fn measure_frames(
+    files: &[PathBuf],
+    jobs: Option<usize>,
+) -> Result<Vec<Result<FrameMetrics>>> {
+    let settings = DetectionSettings {
+        detector: "hocusfocus",
+        sensitivity: "normal",
+        apply_stretch: false,
+        psf_type: "gaussian",
+        plane: FitsPlane::Science,
+        debayer: DebayerMode::None,
+        hotpixel_map: None,
+        downsample: None,
+        min_r_squared: DEFAULT_MIN_R_SQUARED,
+        max_adu: None,
+        tolerant: false,
+    };
+
+    let results = analyze_files(files, &settings, jobs, true)?;
+    Ok(results
+        .into_iter()
+        .map(|result| {
+            result.map(|analysis| FrameMetrics {
+                star_count: analysis.detection.star_count,
+                avg_hfr: analysis.detection.avg_hfr,
+                median_eccentricity: analysis.detection.metrics.median_eccentricity,
+            })
+        })
+        .collect())
+}
 
-            // Parse PSF type
-            let params = HocusFocusParams {
-                psf_type: psf_type.parse().unwrap_or(PSFType::None),
-                ..Default::default()
-            };
-            if params.psf_type != PSFType::None {
-                println!("  PSF Fitting: {:?}", params.psf_type);
-            }
+/// Load the configured plane, replace mapped hot pixels on the raw frame, then debayer
+fn load_image(fits_path: &Path, settings: &DetectionSettings) -> Result<FitsImage> {
+    let options = FitsReadOptions {
+        tolerant: settings.tolerant,
+        ..Default::default()
+    };
+    let mut fits = FitsImage::from_file_plane_with_options(fits_path, settings.plane, &options)?;
+    if let Some(map) = settings.hotpixel_map {
+        fits.apply_hotpixel_map(map)?;
+    }
+    settings.debayer.apply(fits, fits_path)
+}
 
-            let detection_data = if apply_stretch {
-                let stretch_params = StretchParameters::default();
-                stretch_image(
-                    &fits.data,
-                    computed_stats,
-                    stretch_params.factor,
-                    stretch_params.black_clipping,
-                )
-            } else {
-                fits.data.clone()
-            };
+/// Load one file and run star detection on it
+fn analyze_file(fits_path: &Path, settings: &DetectionSettings) -> Result<FileAnalysis> {
+    let filename = fits_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string();
+
+    let fits = load_image(fits_path, settings)?;
+    let header = FrameHeader::read(fits_path, &fits, settings.max_adu);
+
+    let (stats, mut detection) = detect_stars(&fits, settings, &header)?;
+    if fits.is_blank() {
+        detection.notes.insert(
+            0,
+            format!(
+                "Blank frame: every pixel is {}, no stars to detect",
+                stats.min
+            ),
+        );
+    }
 
-            let result =
-                detect_stars_hocus_focus(&detection_data, fits.width, fits.height, &params);
+    Ok(FileAnalysis {
+        filename,
+        stats,
+        detection,
+    })
+}
 
-            detection_info = "HocusFocus".to_string();
+fn output_analysis(
+    out: &mut dyn Write,
+    format: &str,
+    settings: &DetectionSettings,
+    analysis: &FileAnalysis,
+    db_info: Option<(i32, f64)>,
+    include_csv_header: bool,
+    field_style: FieldStyle,
+) -> Result<()> {
+    let FileAnalysis {
+        filename,
+        stats,
+        detection,
+    } = analysis;
 
-            if result.stars.is_empty() {
-                Ok((0, 0.0, 0.0, detection_info))
-            } else {
-                // Calculate statistics
-                let hfr_values: Vec<f64> = result.stars.iter().map(|s| s.hfr).collect();
-                let avg_hfr = hfr_values.iter().sum::<f64>() / hfr_values.len() as f64;
-                let variance = hfr_values
-                    .iter()
-                    .map(|&hfr| (hfr - avg_hfr).powi(2))
-                    .sum::<f64>()
-                    / hfr_values.len() as f64;
-                let std_dev = variance.sqrt();
+    match format {
+        "json" => output_json(stats, detection, db_info, filename, field_style),
+        "csv" => write_csv(
+            out,
+            include_csv_header,
+            reports_fwhm(settings.detector, settings.psf_type),
+            filename,
+            stats,
+            detection,
+            db_info,
+        )?,
+        _ => output_table(filename, stats, detection, db_info),
+    }
+
+    Ok(())
+}
 
-                Ok((result.stars.len(), avg_hfr, std_dev, detection_info))
+fn print_detection_settings(settings: &DetectionSettings) {
+    println!("\nStar Detection:");
+    println!("  Algorithm: {}", settings.detector);
+    println!("  Sensitivity: {}", settings.sensitivity);
+    println!("  Apply MTF Stretch: {}", settings.apply_stretch);
+
+    match settings.detector.to_lowercase().as_str() {
+        "nina" => println!("  Forcing stretch for NINA"),
+        "hocusfocus" => println!("  Using OpenCV with automatic fallback"),
+        "fast" => println!("  Approximate counts for triage, no per-star validation"),
+        _ => {}
+    }
+    let psf_type = settings.psf_type.parse().unwrap_or(PSFType::None);
+    if psf_type != PSFType::None {
+        println!("  PSF Fitting: {:?}", psf_type);
+        println!("  Minimum PSF R²: {}", settings.min_r_squared);
+    }
+    if let Some(map) = settings.hotpixel_map {
+        println!("  Hot Pixel Map: {} pixels", map.len());
+    }
+    if let Some(factor) = settings.downsample {
+        println!("  Downsample: {}", factor);
+    }
+    if let Some(max_adu) = settings.max_adu {
+        println!("  Saturation Level: {} ADU", max_adu);
+    }
+}
+
+/// The `analyze_frame` options for these settings and the frame's headers
+fn analyze_options(settings: &DetectionSettings, header: &FrameHeader) -> Result<AnalyzeOptions> {
+    Ok(AnalyzeOptions {
+        detector: settings
+            .detector
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{}", e))?,
+        sensitivity: parse_sensitivity(settings.sensitivity),
+        psf_type: settings.psf_type.parse().unwrap_or(PSFType::None),
+        apply_stretch: settings.apply_stretch,
+        min_r_squared: settings.min_r_squared,
+        downsample: settings.downsample,
+        pixel_size_um: header.pixel_size_um,
+        focal_length_mm: header.focal_length_mm,
+        saturation_level: header.saturation_level,
+        verbose: is_debug_enabled(),
+    })
+}
+
+fn parse_sensitivity(sensitivity: &str) -> StarSensitivity {
+    match sensitivity.to_lowercase().as_str() {
+        "high" => StarSensitivity::High,
+        "highest" => StarSensitivity::Highest,
+        _ => StarSensitivity::Normal,
+    }
+}
+
+/// Statistics and star detection for a loaded frame, through `analyze_frame`
+fn detect_stars(
+    fits: &FitsImage,
+    settings: &DetectionSettings,
+    header: &FrameHeader,
+) -> Result<(ComputedStats, DetectionSummary)> {
+    let options = analyze_options(settings, header)?;
+    let frame = analyze_frame(&fits.data, fits.width, fits.height, &options)?;
+    let stats = frame.statistics.clone();
+
+    let summary = match options.detector {
+        Detector::Nina => {
+            let mut summary = DetectionSummary::from_frame(
+                frame,
+                format!("NINA {} sensitivity", settings.sensitivity),
+                false,
+                options.min_r_squared,
+            );
+            if options.psf_type != PSFType::None {
+                let eccentricities: Vec<f64> = summary
+                    .stars
+                    .iter()
+                    .filter(|s| !s.is_poor_fit(options.min_r_squared))
+                    .filter_map(|s| s.eccentricity)
+                    .collect();
+                summary.notes.insert(
+                    0,
+                    if eccentricities.is_empty() {
+                        "Eccentricity: N/A (no successful PSF fits)".to_string()
+                    } else {
+                        format!(
+                            "Eccentricity: {:.3} (from {} PSF fits)",
+                            eccentricities.iter().sum::<f64>() / eccentricities.len() as f64,
+                            eccentricities.len()
+                        )
+                    },
+                );
             }
+            summary
+        }
+        Detector::Fast => {
+            DetectionSummary::from_frame(frame, "Fast".to_string(), false, options.min_r_squared)
+        }
+        Detector::HocusFocus => DetectionSummary::from_frame(
+            frame,
+            "HocusFocus".to_string(),
+            options.psf_type != PSFType::None,
+            options.min_r_squared,
+        ),
+    };
+    Ok((stats, summary))
+}
+
+impl DetectionSummary {
+    /// Summarize `frame`, noting PSF fits left out for an R² below `min_r_squared`
+    fn from_frame(
+        frame: FrameAnalysis,
+        info: String,
+        reports_fwhm: bool,
+        min_r_squared: f64,
+    ) -> Self {
+        let mut notes = Vec::new();
+        if frame.metrics.poor_fits > 0 {
+            notes.push(format!(
+                "Poor PSF fits: {} with R² below {} excluded from FWHM and eccentricity",
+                frame.metrics.poor_fits, min_r_squared
+            ));
+        }
+
+        Self {
+            star_count: frame.star_count(),
+            avg_hfr: frame.average_hfr,
+            hfr_std: frame.hfr_std_dev,
+            reports_fwhm: reports_fwhm && !frame.stars.is_empty(),
+            metrics: frame.metrics,
+            info,
+            notes,
+            stars: frame.stars,
         }
-        _ => Err(anyhow::anyhow!("Unknown detector: {}", detector)),
     }
 }
 
-fn get_database_info(conn: &Connection, filename: &str) -> Result<Option<(i32, f64)>> {
-    // Simple query to find images by filename pattern
-    let query = "SELECT metadata FROM acquiredimage WHERE metadata LIKE ?";
-    let pattern = format!("%{}%", filename);
+/// Whether the FILTER (or FILTERNAME) header of the primary HDU matches, ignoring case
+fn matches_filter(path: &Path, filter: &str) -> bool {
+    let Ok(fits) = fitrs::Fits::open(path) else {
+        return false;
+    };
+    let Some(hdu) = fits.get(0) else {
+        return false;
+    };
+    ["FILTER", "FILTERNAME"]
+        .iter()
+        .find_map(|keyword| match hdu.value(keyword) {
+            Some(fitrs::HeaderValue::CharacterString(value)) => Some(value.trim().to_string()),
+            _ => None,
+        })
+        .is_some_and(|value| value.eq_ignore_ascii_case(filter))
+}
 
-    let mut stmt = conn.prepare(query)?;
-    let mut rows = stmt.query([&pattern])?;
+impl DatabaseIndex {
+    fn load(conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare("SELECT metadata FROM acquiredimage")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        Self::from_rows(rows)
+    }
 
-    while let Some(row) = rows.next()? {
-        let metadata_json: String = row.get(0)?;
+    /// Only the rows whose metadata mentions `filename`, so a single file does
+    /// not read the whole table
+    fn load_for_file(conn: &Connection, filename: &str) -> Result<Self> {
+        let mut stmt = conn.prepare("SELECT metadata FROM acquiredimage WHERE metadata LIKE ?")?;
+        let rows = stmt.query_map([format!("%{}%", filename)], |row| row.get::<_, String>(0))?;
+        Self::from_rows(rows)
+    }
 
-        // Try to parse the metadata
-        if let Ok(metadata) = serde_json::from_str::<ImageMetadata>(&metadata_json) {
-            // Check if filename matches
-            if let Some(meta_filename) = metadata.filename {
-                if meta_filename.contains(filename) || filename.contains(&meta_filename) {
-                    if let (Some(stars), Some(hfr)) = (metadata.detected_stars, metadata.hfr) {
-                        return Ok(Some((stars, hfr)));
-                    }
-                }
+    fn from_rows(rows: impl Iterator<Item = rusqlite::Result<String>>) -> Result<Self> {
+        let mut entries = Vec::new();
+        for metadata_json in rows {
+            let metadata_json = metadata_json?;
+            let Ok(metadata) = serde_json::from_str::<ImageMetadata>(&metadata_json) else {
+                continue;
+            };
+            if let (Some(filename), Some(stars), Some(hfr)) =
+                (metadata.filename, metadata.detected_stars, metadata.hfr)
+            {
+                entries.push(DatabaseEntry {
+                    metadata_lower: metadata_json.to_lowercase(),
+                    filename,
+                    stars,
+                    hfr,
+                });
             }
         }
+
+        Ok(Self { entries })
     }
 
-    Ok(None)
+    /// N.I.N.A. star count and HFR for the first image whose file name matches
+    fn lookup(&self, filename: &str) -> Option<(i32, f64)> {
+        let filename_lower = filename.to_lowercase();
+        self.entries
+            .iter()
+            .find(|entry| {
+                entry.metadata_lower.contains(&filename_lower)
+                    && (entry.filename.contains(filename) || filename.contains(&entry.filename))
+            })
+            .map(|entry| (entry.stars, entry.hfr))
+    }
 }
 
 fn output_table(
     filename: &str,
     computed_stats: &ComputedStats,
-    star_count: usize,
-    avg_hfr: f64,
-    hfr_std: f64,
+    detection: &DetectionSummary,
     db_info: Option<(i32, f64)>,
-    detection_info: &str,
 ) {
+    let star_count = detection.star_count;
+    let avg_hfr = detection.avg_hfr;
+
     println!("\n=== FITS Analysis Results ===");
     println!("File: {}", filename);
     println!("\nImage Statistics:");
@@ -576,10 +1118,35 @@ fn output_table(
     println!("  Median: {:.2}", computed_stats.median);
     println!("  MAD: {:.2}", computed_stats.mad.unwrap_or(0.0));
 
-    println!("\nDetection Results ({}):", detection_info);
+    println!("\nDetection Results ({}):", detection.info);
     println!("  Detected Stars: {}", star_count);
     println!("  Average HFR: {:.3}", avg_hfr);
-    println!("  HFR Std Dev: {:.3}", hfr_std);
+    println!("  HFR Std Dev: {:.3}", detection.hfr_std);
+    if let Some(fwhm) = detection.metrics.average_fwhm {
+        println!("  Average FWHM: {:.3}", fwhm);
+    }
+    if let Some(snr) = detection.metrics.average_snr {
+        println!("  Average SNR: {:.1}", snr);
+    }
+    if let Some(eccentricity) = detection.metrics.median_eccentricity {
+        println!("  Median Eccentricity: {:.3}", eccentricity);
+    }
+    if let Some(eccentricity) = detection.metrics.max_eccentricity {
+        println!("  Max Eccentricity: {:.3}", eccentricity);
+    }
+    for note in &detection.notes {
+        println!("  {}", note);
+    }
+    if detection.reports_fwhm {
+        println!("  HFR-derived FWHM: {:.3}", detection.avg_hfr * HFR_TO_FWHM);
+        match detection.metrics.psf_fwhm {
+            Some(psf_fwhm) => println!(
+                "  PSF FWHM: {:.3} ({} stars fitted)",
+                psf_fwhm, detection.metrics.psf_fitted
+            ),
+            None => println!("  PSF FWHM: n/a (no successful fits)"),
+        }
+    }
 
     if let Some((nina_stars, nina_hfr)) = db_info {
         println!("\nDatabase Comparison:");
@@ -598,14 +1165,41 @@ fn output_table(
 
 fn output_json(
     computed_stats: &ComputedStats,
-    star_count: usize,
-    avg_hfr: f64,
-    hfr_std: f64,
+    detection: &DetectionSummary,
     db_info: Option<(i32, f64)>,
-    detection_info: &str,
     filename: &str,
+    field_style: FieldStyle,
 ) {
-    let result = serde_json::json!({
+    let result = analysis_json(computed_stats, detection, db_info, filename);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&field_style.apply(result)).unwrap()
+    );
+}
+
+fn analysis_json(
+    computed_stats: &ComputedStats,
+    detection: &DetectionSummary,
+    db_info: Option<(i32, f64)>,
+    filename: &str,
+) -> serde_json::Value {
+    let mut detection_json = serde_json::json!({
+        "algorithm": detection.info,
+        "stars": detection.star_count,
+        "average_hfr": detection.avg_hfr,
+        "hfr_std_dev": detection.hfr_std,
+        "average_fwhm": detection.metrics.average_fwhm,
+        "average_snr": detection.metrics.average_snr,
+        "median_eccentricity": detection.metrics.median_eccentricity,
+        "max_eccentricity": detection.metrics.max_eccentricity,
+    });
+    if detection.reports_fwhm {
+        detection_json["hfr_fwhm"] = serde_json::json!(detection.avg_hfr * HFR_TO_FWHM);
+        detection_json["psf_fwhm"] = serde_json::json!(detection.metrics.psf_fwhm);
+        detection_json["psf_fitted_stars"] = serde_json::json!(detection.metrics.psf_fitted);
+    }
+
+    serde_json::json!({
         "file": filename,
         "computed": {
             "statistics": {
@@ -615,12 +1209,7 @@ fn output_json(
                 "median": computed_stats.median,
                 "mad": computed_stats.mad.unwrap_or(0.0),
             },
-            "detection": {
-                "algorithm": detection_info,
-                "stars": star_count,
-                "average_hfr": avg_hfr,
-                "hfr_std_dev": hfr_std,
-            }
+            "detection": detection_json,
         },
         "database": db_info.map(|(stars, hfr)| {
             serde_json::json!({
@@ -628,22 +1217,26 @@ fn output_json(
                 "hfr": hfr,
             })
         }),
-    });
-
-    println!("{}", serde_json::to_string_pretty(&result).unwrap());
+    })
 }
 
-fn output_csv(
+fn write_csv<W: Write + ?Sized>(
+    out: &mut W,
+    include_header: bool,
+    with_fwhm: bool,
     filename: &str,
     computed_stats: &ComputedStats,
-    star_count: usize,
-    avg_hfr: f64,
-    hfr_std: f64,
+    detection: &DetectionSummary,
     db_info: Option<(i32, f64)>,
-) {
+) -> std::io::Result<()> {
     let (db_stars, db_hfr) = db_info.unwrap_or((0, 0.0));
 
-    println!(
+    if include_header {
+        writeln!(out, "{}", csv_header(with_fwhm))?;
+    }
+
+    write!(
+        out,
         "{},{},{},{:.2},{:.2},{:.2},{},{:.3},{:.3},{},{:.3}",
         filename,
         computed_stats.min,
@@ -651,10 +1244,669 @@ fn output_csv(
         computed_stats.mean,
         computed_stats.median,
         computed_stats.mad.unwrap_or(0.0),
-        star_count,
-        avg_hfr,
-        hfr_std,
+        detection.star_count,
+        detection.avg_hfr,
+        detection.hfr_std,
         db_stars,
         db_hfr
-    );
+    )?;
+
+    if with_fwhm {
+        // Empty cells when no FWHM data is available keep the columns aligned
+        if detection.reports_fwhm {
+            write!(
+                out,
+                ",{:.3},{},{}",
+                detection.avg_hfr * HFR_TO_FWHM,
+                detection
+                    .metrics
+                    .psf_fwhm
+                    .map(|f| format!("{:.3}", f))
+                    .unwrap_or_default(),
+                detection.metrics.psf_fitted
+            )?;
+        } else {
+            write!(out, ",,,")?;
+        }
+    }
+
+    let optional = |value: Option<f64>, precision: usize| {
+        value
+            .map(|v| format!("{:.*}", precision, v))
+            .unwrap_or_default()
+    };
+    writeln!(
+        out,
+        ",{},{},{},{}",
+        optional(detection.metrics.average_fwhm, 3),
+        optional(detection.metrics.average_snr, 1),
+        optional(detection.metrics.median_eccentricity, 3),
+        optional(detection.metrics.max_eccentricity, 3)
+    )
+}
+
+/// Write one CSV row per star; the header is written once by the caller
+fn write_stars_csv(
+    out: &mut dyn Write,
+    filename: &str,
+    stars: &[FrameStar],
+    min_r_squared: f64,
+) -> Result<()> {
+    let optional = |value: Option<f64>| value.map(|v| format!("{:.3}", v)).unwrap_or_default();
+
+    for star in stars {
+        writeln!(
+            out,
+            "{},{:.2},{:.2},{:.3},{},{:.1},{},{},{},{}",
+            filename,
+            star.position.0,
+            star.position.1,
+            star.hfr,
+            optional(star.fwhm),
+            star.brightness,
+            optional(star.eccentricity),
+            star.snr.map(|v| format!("{:.1}", v)).unwrap_or_default(),
+            optional(star.r_squared),
+            star.is_poor_fit(min_r_squared)
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hocus_focus_star_detection::HocusFocusStar;
+    use crate::psf_fitting::PSFModel;
+    use crate::utils::OnlineStats;
+
+    /// The analyze-fits defaults: HocusFocus without stretch or PSF fitting
+    fn test_settings() -> DetectionSettings<'static> {
+        DetectionSettings {
+            detector: "hocusfocus",
+            sensitivity: "normal",
+            apply_stretch: false,
+            psf_type: "none",
+            plane: FitsPlane::Science,
+            debayer: DebayerMode::None,
+            hotpixel_map: None,
+            downsample: None,
+            min_r_squared: DEFAULT_MIN_R_SQUARED,
+            max_adu: None,
+            tolerant: false,
+        }
+    }
+
+    fn test_stats() -> ComputedStats {
+        ComputedStats {
+            width: 100,
+            height: 100,
+            mean: 1000.0,
+            median: 990.0,
+            std_dev: 25.0,
+            min: 900.0,
+            max: 4000.0,
+            star_count: None,
+            hfr: None,
+            fwhm: None,
+            mad: Some(12.0),
+        }
+    }
+
+    fn test_detection() -> DetectionSummary {
+        DetectionSummary {
+            star_count: 10,
+            avg_hfr: 2.5,
+            hfr_std: 0.3,
+            reports_fwhm: false,
+            metrics: StarMetrics::default(),
+            info: "NINA normal sensitivity".to_string(),
+            notes: Vec::new(),
+            stars: Vec::new(),
+        }
+    }
+
+    /// HocusFocus summary of `stars`, as `detect_stars` builds it with PSF fitting
+    fn summarize_stars(stars: &[HocusFocusStar], min_r_squared: f64) -> DetectionSummary {
+        let stars: Vec<FrameStar> = stars.iter().map(FrameStar::from).collect();
+        let hfr: OnlineStats = stars.iter().map(|s| s.hfr).collect();
+        let frame = FrameAnalysis {
+            statistics: test_stats(),
+            metrics: StarMetrics::from_stars(&stars, hfr.mean(), min_r_squared),
+            average_hfr: hfr.mean(),
+            hfr_std_dev: hfr.std_dev(),
+            stars,
+            psf_summary: None,
+        };
+        DetectionSummary::from_frame(frame, "HocusFocus".to_string(), true, min_r_squared)
+    }
+
+    fn test_star(hfr: f64, psf_fwhm: Option<f64>) -> HocusFocusStar {
+        HocusFocusStar {
+            position: (50.0, 50.0),
+            hfr,
+            fwhm: psf_fwhm.unwrap_or(hfr * HFR_TO_FWHM),
+            brightness: 5000.0,
+            background: 1000.0,
+            snr: 50.0,
+            flux: 100000.0,
+            pixel_count: 40,
+            psf_model: psf_fwhm.map(|fwhm| PSFModel {
+                psf_type: PSFType::Moffat4,
+                amplitude: 4000.0,
+                background: 1000.0,
+                x0: 0.0,
+                y0: 0.0,
+                sigma_x: 2.0,
+                sigma_y: 2.0,
+                theta: 0.0,
+                r_squared: 0.95,
+                rmse: 10.0,
+                fwhm,
+                eccentricity: 0.1,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_single_file_lookup_reads_matching_rows_only() {
+        let conn = crate::db::fixture_db();
+        conn.execute_batch(
+            "INSERT INTO acquiredimage (Id, metadata) VALUES
+                 (1, '{\"FileName\": \"C:\\\\data\\\\M31_L_0001.fits\", \"HFR\": 2.5, \"DetectedStars\": 420}'),
+                 (2, '{\"FileName\": \"M31_L_0002.fits\", \"HFR\": 3.0, \"DetectedStars\": 300}'),
+                 (3, 'not json');",
+        )
+        .unwrap();
+
+        let index = DatabaseIndex::load_for_file(&conn, "M31_L_0001.fits").unwrap();
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(index.lookup("M31_L_0001.fits"), Some((420, 2.5)));
+        assert_eq!(
+            DatabaseIndex::load(&conn)
+                .unwrap()
+                .lookup("M31_L_0002.fits"),
+            Some((300, 3.0))
+        );
+    }
+
+    #[test]
+    fn test_csv_no_header() {
+        let stats = test_stats();
+        let mut out = Vec::new();
+        write_csv(
+            &mut out,
+            false,
+            false,
+            "a.fits",
+            &stats,
+            &test_detection(),
+            Some((12, 2.4)),
+        )
+        .unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.starts_with("a.fits,"));
+        assert!(!text.contains("Filename"));
+    }
+
+    #[test]
+    fn test_csv_single_file_includes_header() {
+        let stats = test_stats();
+        let mut out = Vec::new();
+        write_csv(
+            &mut out,
+            true,
+            false,
+            "a.fits",
+            &stats,
+            &test_detection(),
+            None,
+        )
+        .unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], csv_header(false));
+        // Existing columns keep their positions; new ones are appended
+        assert!(lines[0].starts_with(CSV_HEADER));
+        assert_eq!(lines[1].split(',').count(), lines[0].split(',').count());
+    }
+
+    #[test]
+    fn test_reports_fwhm() {
+        assert!(reports_fwhm("hocusfocus", "moffat4"));
+        assert!(reports_fwhm("HocusFocus", "gaussian"));
+        assert!(!reports_fwhm("hocusfocus", "none"));
+        assert!(!reports_fwhm("nina", "gaussian"));
+    }
+
+    #[test]
+    fn test_eccentricity_ignores_unfitted_stars() {
+        let mut stars = vec![
+            test_star(2.0, Some(3.8)),
+            test_star(2.4, Some(4.2)),
+            test_star(2.1, Some(4.0)),
+            test_star(2.2, None),
+            test_star(2.3, None),
+        ];
+        for (star, eccentricity) in stars.iter_mut().zip([0.2, 0.6, 0.3]) {
+            star.psf_model.as_mut().unwrap().eccentricity = eccentricity;
+        }
+        let detection = summarize_stars(&stars, DEFAULT_MIN_R_SQUARED);
+
+        // Unfitted stars have no eccentricity rather than a circular 0.0
+        let records: Vec<_> = detection.stars.iter().map(|s| s.eccentricity).collect();
+        assert_eq!(records, [Some(0.2), Some(0.6), Some(0.3), None, None]);
+        assert_eq!(detection.metrics.median_eccentricity, Some(0.3));
+        assert_eq!(detection.metrics.max_eccentricity, Some(0.6));
+
+        let unfitted = summarize_stars(&[test_star(2.2, None)], DEFAULT_MIN_R_SQUARED);
+        assert_eq!(unfitted.metrics.median_eccentricity, None);
+        assert_eq!(unfitted.metrics.max_eccentricity, None);
+    }
+
+    #[test]
+    fn test_poor_psf_fits_excluded_from_aggregates() {
+        // The second fit diverged on a star it could not model
+        let mut stars = vec![test_star(2.0, Some(4.0)), test_star(2.1, Some(25.0))];
+        let bad = stars[1].psf_model.as_mut().unwrap();
+        bad.r_squared = 0.12;
+        bad.eccentricity = 0.95;
+
+        let detection = summarize_stars(&stars, DEFAULT_MIN_R_SQUARED);
+        assert_eq!(detection.metrics.psf_fwhm, Some(4.0));
+        assert_eq!(detection.metrics.psf_fitted, 1);
+        assert_eq!(detection.metrics.average_fwhm, Some(4.0));
+        assert_eq!(detection.metrics.max_eccentricity, Some(0.1));
+        assert_eq!(detection.star_count, 2);
+        assert!(detection.notes[0].starts_with("Poor PSF fits: 1"));
+
+        // The bad fit is still listed per star, flagged
+        let mut out = Vec::new();
+        write_stars_csv(&mut out, "a.fits", &detection.stars, DEFAULT_MIN_R_SQUARED).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let rows: Vec<_> = text.lines().collect();
+        assert!(rows[0].ends_with(",0.950,false"), "{}", rows[0]);
+        assert!(
+            rows[1].ends_with(",25.000,5000.0,0.950,50.0,0.120,true"),
+            "{}",
+            rows[1]
+        );
+
+        // Without a threshold every fit counts
+        let ungated = summarize_stars(&stars, 0.0);
+        assert_eq!(ungated.metrics.average_fwhm, Some(14.5));
+        assert!(ungated.notes.is_empty());
+    }
+
+    #[test]
+    fn test_hfr_and_psf_fwhm_columns() {
+        // One star failed the fit and only contributes to HFR
+        let stars = vec![
+            test_star(2.0, Some(3.8)),
+            test_star(2.4, Some(4.2)),
+            test_star(2.2, None),
+        ];
+        let detection = summarize_stars(&stars, DEFAULT_MIN_R_SQUARED);
+        let psf_fwhm = detection.metrics.psf_fwhm.unwrap();
+
+        assert!(detection.reports_fwhm);
+        assert!((detection.avg_hfr - 2.2).abs() < 1e-9);
+        assert!((psf_fwhm - 4.0).abs() < 1e-9);
+        assert_eq!(detection.metrics.psf_fitted, 2);
+        // PSF FWHM is measured, not derived from HFR
+        assert!((psf_fwhm - detection.avg_hfr * HFR_TO_FWHM).abs() > 0.1);
+
+        let mut out = Vec::new();
+        write_csv(
+            &mut out,
+            true,
+            true,
+            "a.fits",
+            &test_stats(),
+            &detection,
+            None,
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        let header: Vec<_> = lines[0].split(',').collect();
+        let row: Vec<_> = lines[1].split(',').collect();
+        assert_eq!(header.len(), row.len());
+
+        let column = |name: &str| row[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(column("AvgHFR"), "2.200");
+        assert_eq!(column("AvgHFRFWHM"), format!("{:.3}", 2.2 * HFR_TO_FWHM));
+        assert_eq!(column("AvgPSFFWHM"), "4.000");
+        assert_eq!(column("PSFFittedStars"), "2");
+    }
+
+    #[test]
+    fn test_fwhm_columns_empty_without_fits() {
+        let detection = summarize_stars(&[test_star(2.0, None)], DEFAULT_MIN_R_SQUARED);
+        let mut out = Vec::new();
+        write_csv(
+            &mut out,
+            false,
+            true,
+            "a.fits",
+            &test_stats(),
+            &detection,
+            None,
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let header = csv_header(true);
+        let header: Vec<_> = header.split(',').collect();
+        let row: Vec<_> = text.trim_end().split(',').collect();
+        assert_eq!(row.len(), header.len());
+
+        let column = |name: &str| row[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(column("AvgPSFFWHM"), "");
+        assert_eq!(column("PSFFittedStars"), "0");
+    }
+
+    /// Synthetic frame with a grid of Gaussian stars on a flat background
+    fn star_grid() -> FitsImage {
+        let (width, height) = (200, 200);
+        let mut data = vec![1000u16; width * height];
+        for cy in [40, 100, 160] {
+            for cx in [40, 100, 160] {
+                for y in cy - 8..=cy + 8 {
+                    for x in cx - 8..=cx + 8 {
+                        let d2 = ((x - cx) * (x - cx) + (y - cy) * (y - cy)) as f64;
+                        data[y * width + x] += (20000.0 * (-d2 / 8.0).exp()) as u16;
+                    }
+                }
+            }
+        }
+        FitsImage {
+            width,
+            height,
+            data,
+            channel_data: Vec::new(),
+            raw_range: None,
+        }
+    }
+
+    #[test]
+    fn test_stars_csv_rows_match_star_count() {
+        let fits = star_grid();
+
+        for (detector, psf_type) in [("hocusfocus", "gaussian"), ("nina", "none")] {
+            let (_, detection) = detect_stars(
+                &fits,
+                &DetectionSettings {
+                    detector,
+                    psf_type,
+                    ..test_settings()
+                },
+                &FrameHeader::default(),
+            )
+            .unwrap();
+
+            let mut out = Vec::new();
+            writeln!(out, "{}", STARS_CSV_HEADER).unwrap();
+            write_stars_csv(
+                &mut out,
+                "grid.fits",
+                &detection.stars,
+                DEFAULT_MIN_R_SQUARED,
+            )
+            .unwrap();
+
+            let text = String::from_utf8(out).unwrap();
+            let lines: Vec<_> = text.lines().collect();
+            assert_eq!(lines[0], STARS_CSV_HEADER);
+            assert_eq!(lines.len() - 1, detection.star_count, "{}", detector);
+            for line in &lines[1..] {
+                assert!(line.starts_with("grid.fits,"));
+                assert_eq!(line.split(',').count(), 10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hocusfocus_detects_without_psf_fitting() {
+        let fits = star_grid();
+        let (_, detection) = detect_stars(
+            &fits,
+            &DetectionSettings {
+                detector: "hocusfocus",
+                psf_type: "none",
+                ..test_settings()
+            },
+            &FrameHeader::default(),
+        )
+        .unwrap();
+        assert!(detection.star_count > 0);
+        assert!(!detection.reports_fwhm);
+    }
+
+    #[test]
+    fn test_hocusfocus_star_metric_columns() {
+        let fits = star_grid();
+        let (stats, detection) = detect_stars(
+            &fits,
+            &DetectionSettings {
+                detector: "hocusfocus",
+                psf_type: "gaussian",
+                ..test_settings()
+            },
+            &FrameHeader::default(),
+        )
+        .unwrap();
+        assert!(detection.star_count > 0);
+
+        let mut out = Vec::new();
+        write_csv(&mut out, true, true, "grid.fits", &stats, &detection, None).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        let header: Vec<_> = lines[0].split(',').collect();
+        let row: Vec<_> = lines[1].split(',').collect();
+        assert_eq!(header.len(), row.len());
+        assert_eq!(
+            header[header.len() - 4..],
+            ["AvgFWHM", "AvgSNR", "MedianEccentricity", "MaxEccentricity"]
+        );
+        for value in &row[row.len() - 4..] {
+            assert!(value.parse::<f64>().is_ok(), "{:?}", row);
+        }
+
+        let json = analysis_json(&stats, &detection, None, "grid.fits");
+        let detection_json = &json["computed"]["detection"];
+        assert!(detection_json["average_fwhm"].as_f64().unwrap() > 0.0);
+        assert!(detection_json["average_snr"].as_f64().unwrap() > 0.0);
+        assert!(detection_json["median_eccentricity"].is_number());
+        assert!(detection_json["max_eccentricity"].is_number());
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_jobs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Star grids shifted per file so every file gives a different result
+        let (width, height) = (120usize, 100usize);
+        let files: Vec<PathBuf> = (0..6)
+            .map(|i| {
+                let mut data = vec![1000.0f32; width * height];
+                for cy in [30 + i, 70] {
+                    for cx in [25, 60 + 2 * i, 95] {
+                        for y in cy - 6..=cy + 6 {
+                            for x in cx - 6..=cx + 6 {
+                                let d2 = ((x - cx) * (x - cx) + (y - cy) * (y - cy)) as f32;
+                                data[y * width + x] += 20000.0 * (-d2 / (6.0 + i as f32)).exp();
+                            }
+                        }
+                    }
+                }
+                let path = dir.join(format!("frame_{}.fits", i));
+                fitrs::Fits::create(&path, fitrs::Hdu::new(&[width, height], data)).unwrap();
+                path
+            })
+            .collect();
+
+        let settings = test_settings();
+        let render = |jobs: usize| {
+            let mut out = Vec::new();
+            for result in analyze_files(&files, &settings, Some(jobs), false).unwrap() {
+                let analysis = result.unwrap();
+                write_csv(
+                    &mut out,
+                    false,
+                    false,
+                    &analysis.filename,
+                    &analysis.stats,
+                    &analysis.detection,
+                    None,
+                )
+                .unwrap();
+            }
+            String::from_utf8(out).unwrap()
+        };
+
+        let sequential = render(1);
+        let parallel = render(4);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(sequential, parallel);
+        let names: Vec<&str> = sequential
+            .lines()
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            (0..6)
+                .map(|i| format!("frame_{}.fits", i))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_compare_all_reports_each_detector_against_db() {
+        let path =
+            std::env::temp_dir().join(format!("psf_guard_compare_{}.fits", std::process::id()));
+        let (width, height) = (200usize, 160usize);
+        let mut data = vec![1000.0f32; width * height];
+        for cy in [30, 80, 130] {
+            for cx in [30, 80, 130, 170] {
+                for y in cy - 8..=cy + 8 {
+                    for x in cx - 8..=cx + 8 {
+                        let d2 = ((x - cx) * (x - cx) + (y - cy) * (y - cy)) as f32;
+                        data[y * width + x] += 20000.0 * (-d2 / 8.0).exp();
+                    }
+                }
+            }
+        }
+        fitrs::Fits::create(&path, fitrs::Hdu::new(&[width, height], data)).unwrap();
+
+        let settings = test_settings();
+        let comparison = compare_file(&path, &generate_detector_configs(), &settings).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let mut out = Vec::new();
+        write_comparison_csv(&mut out, &comparison, Some((12, 2.0))).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+
+        for detector in ["NINA-normal", "HocusFocus"] {
+            let row = rows
+                .iter()
+                .find(|row| row[1] == detector)
+                .unwrap_or_else(|| panic!("no row for {}: {}", detector, csv));
+            assert!(row[2].parse::<usize>().unwrap() > 0, "{}", csv);
+            assert_eq!(&row[5..7], &["12", "2.000"]);
+        }
+        assert_eq!(
+            rows.iter().filter(|row| row[7] == "true").count(),
+            1,
+            "{}",
+            csv
+        );
+        let closest = comparison.closest_to(2.0).unwrap();
+        assert_eq!(
+            rows.iter().find(|row| row[7] == "true").unwrap()[1],
+            comparison.results[closest].0
+        );
+    }
+
+    #[test]
+    fn test_truncated_file_does_not_stop_directory_run() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_truncated_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (width, height) = (60usize, 40usize);
+        let files: Vec<PathBuf> = (0..3)
+            .map(|i| {
+                let data: Vec<f32> = (0..width * height).map(|p| (p % 97) as f32).collect();
+                let path = dir.join(format!("frame_{}.fits", i));
+                fitrs::Fits::create(&path, fitrs::Hdu::new(&[width, height], data)).unwrap();
+                path
+            })
+            .collect();
+        // The middle file lost the second half of its pixels mid-transfer
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&files[1])
+            .unwrap();
+        file.set_len(2880 + (width * height * 4 / 2) as u64)
+            .unwrap();
+        drop(file);
+
+        let mut settings = test_settings();
+        let results = analyze_files(&files, &settings, Some(2), false).unwrap();
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1]
+                .as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<FitsReadError>()),
+            Some(&FitsReadError::SizeMismatch {
+                expected: width * height,
+                got: width * height / 2
+            })
+        );
+        assert!(results[2].is_ok());
+
+        settings.tolerant = true;
+        let results = analyze_files(&files, &settings, Some(2), false).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    #[test]
+    fn test_limit_analyzes_first_files_after_filter() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_limit_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (width, height) = (40usize, 30usize);
+        for i in 0..10 {
+            let data: Vec<f32> = (0..width * height).map(|p| (p % 89) as f32).collect();
+            let mut hdu = fitrs::Hdu::new(&[width, height], data);
+            hdu.insert("FILTER", if i < 8 { "Ha" } else { "OIII" });
+            fitrs::Fits::create(dir.join(format!("frame_{:02}.fits", i)), hdu).unwrap();
+        }
+
+        let traversal = TraversalOptions::default();
+        let files = find_fits_files(&dir, &traversal, None, Subset::First(5)).unwrap();
+        let settings = test_settings();
+        let results = analyze_files(&files, &settings, Some(2), false).unwrap();
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert!(files[4].ends_with("frame_04.fits"));
+
+        // Sampling picks from the filtered files only
+        let sample = Subset::Sample {
+            count: 5,
+            seed: Some(7),
+        };
+        let sampled = find_fits_files(&dir, &traversal, Some("OIII"), sample).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(sampled.len(), 2);
+    }
 }
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::field_analysis::{self, MIN_TILT_STARS};
+use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
+use crate::image_analysis::FitsImage;
+
+/// Detect stars and report the HFR tilt plane across the frame
+pub fn analyze_tilt(fits_path: &str, format: &str, verbose: bool) -> Result<()> {
+    crate::debug::init_debug(verbose);
+
+    let fits = FitsImage::from_file(Path::new(fits_path))?;
+    let params = HocusFocusParams {
+        verbose,
+        ..Default::default()
+    };
+    let result = detect_stars_hocus_focus(&fits.data, fits.width, fits.height, &params);
+
+    let tilt = field_analysis::analyze_tilt(&result.stars, fits.width, fits.height);
+
+    if format == "json" {
+        let output = serde_json::json!({
+            "file": fits_path,
+            "width": fits.width,
+            "height": fits.height,
+            "detected_stars": result.stars.len(),
+            "tilt": tilt,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    println!("Tilt analysis: {}", fits_path);
+    println!("  Detected stars: {}", result.stars.len());
+
+    let Some(tilt) = tilt else {
+        println!(
+            "  Not enough data for a tilt fit (need at least {} stars spread over 3+ regions)",
+            MIN_TILT_STARS
+        );
+        return Ok(());
+    };
+
+    println!();
+    println!(
+        "{:<14} {:>8} {:>8} {:>7} {:>10}",
+        "Region", "X", "Y", "Stars", "Median HFR"
+    );
+    println!("{}", "-".repeat(51));
+    for region in &tilt.regions {
+        println!(
+            "{:<14} {:>8.0} {:>8.0} {:>7} {:>10.3}",
+            region.name, region.x, region.y, region.star_count, region.median_hfr
+        );
+    }
+
+    println!();
+    println!("  Center HFR (fit):   {:.3}", tilt.center_hfr);
+    println!("  Slope X (to edge):  {:+.3}", tilt.slope_x);
+    println!("  Slope Y (to edge):  {:+.3}", tilt.slope_y);
+    println!(
+        "  Gradient:           {:.3} HFR towards {:.0} deg (0 = right, 90 = down)",
+        tilt.gradient_magnitude, tilt.gradient_direction
+    );
+    println!("  Tilt severity:      {:.1}%", tilt.tilt_severity);
+
+    Ok(())
+}
@@ -1,14 +1,18 @@
 use anyhow::{Context, Result};
 use image::codecs::png::{CompressionType, FilterType, PngEncoder};
 use image::{ColorType, ImageEncoder};
-use image::{ImageBuffer, Rgb};
+use image::{ImageBuffer, Rgb, RgbImage};
 use imageproc::drawing::{draw_filled_circle_mut, draw_hollow_circle_mut};
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::Path;
 
-use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
-use crate::image_analysis::FitsImage;
+use crate::commands::visualize_psf::text_render::draw_char;
+use crate::hocus_focus_star_detection::{
+    detect_stars_hocus_focus, HocusFocusParams, RejectedStar, StarRejectReason,
+};
+use crate::hotpixel_map::HotPixelMap;
+use crate::image_analysis::{saturation_adu, DebayerMode, FitsImage};
 use crate::mtf_stretch::{stretch_image, StretchParameters};
 use crate::nina_star_detection::{
     detect_stars_with_original, StarDetectionParams, StarSensitivity,
@@ -29,6 +33,21 @@ fn parse_color(color_name: &str) -> Rgb<u8> {
     }
 }
 
+/// Color of candidates that failed validation, with `--show-rejected`
+const REJECTED_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Every rejection reason, for the glyph legend
+const REJECT_REASONS: [StarRejectReason; 8] = [
+    StarRejectReason::TooSmall,
+    StarRejectReason::TouchingBorder,
+    StarRejectReason::TooDistorted,
+    StarRejectReason::Saturated,
+    StarRejectReason::LowSnr,
+    StarRejectReason::OffCenter,
+    StarRejectReason::TooFlat,
+    StarRejectReason::HfrBelowMin,
+];
+
 /// Create an annotated image with detected stars marked
 #[allow(clippy::too_many_arguments)]
 pub fn annotate_stars(
@@ -41,14 +60,38 @@ pub fn annotate_stars(
     shadow_clipping: f64,
     annotation_color: &str,
     psf_type: &str,
+    debayer: &str,
+    hotpixel_map: Option<&str>,
+    max_adu: Option<f64>,
+    show_rejected: bool,
     verbose: bool,
 ) -> Result<()> {
+    if show_rejected && !detector.eq_ignore_ascii_case("hocusfocus") {
+        anyhow::bail!("--show-rejected is only supported by the hocusfocus detector");
+    }
+    if let Some(max_adu) = max_adu {
+        if max_adu <= 0.0 {
+            anyhow::bail!("Maximum ADU must be greater than 0, got {}", max_adu);
+        }
+    }
+    let debayer: DebayerMode = debayer.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let hotpixel_map = hotpixel_map
+        .map(|path| HotPixelMap::load(Path::new(path)))
+        .transpose()?;
+
     if verbose {
         eprintln!("Loading FITS file: {}", fits_path);
     }
 
-    // Load the FITS file
-    let fits = FitsImage::from_file(Path::new(fits_path))?;
+    // Load the FITS file; hot pixels are mapped on the raw sensor frame, before debayering
+    let mut fits = FitsImage::from_file(Path::new(fits_path))?;
+    if let Some(map) = &hotpixel_map {
+        fits.apply_hotpixel_map(map)?;
+        if verbose {
+            eprintln!("Replaced {} hot pixels", map.len());
+        }
+    }
+    let fits = debayer.apply(fits, Path::new(fits_path))?;
     let width = fits.width;
     let height = fits.height;
 
@@ -70,6 +113,7 @@ pub fn annotate_stars(
     let stretch_params = StretchParameters {
         factor: midtone_factor,
         black_clipping: shadow_clipping,
+        ..Default::default()
     };
 
     let stretched = stretch_image(
@@ -87,6 +131,7 @@ pub fn annotate_stars(
     }
 
     // Detect stars using the selected algorithm
+    let mut rejected = Vec::new();
     let stars = match detector.to_lowercase().as_str() {
         "nina" => {
             // Parse sensitivity
@@ -104,6 +149,8 @@ pub fn annotate_stars(
                 sensitivity: star_sensitivity,
                 noise_reduction: crate::nina_star_detection::NoiseReduction::None,
                 use_roi: false,
+                verbose,
+                ..StarDetectionParams::default()
             };
             let result = detect_stars_with_original(&stretched, &fits.data, width, height, &params);
 
@@ -130,14 +177,21 @@ pub fn annotate_stars(
             // Parse PSF type
             let params = HocusFocusParams {
                 psf_type: psf_type.parse().unwrap_or(PSFType::None),
+                verbose,
+                keep_rejected: show_rejected,
                 ..Default::default()
-            };
+            }
+            .with_saturation_level(
+                saturation_adu(Path::new(fits_path), max_adu)
+                    .and_then(|adu| fits.scaled_level(adu)),
+            );
             if params.psf_type != PSFType::None && verbose {
                 eprintln!("  PSF Fitting: {:?}", params.psf_type);
             }
 
             let result = detect_stars_hocus_focus(&fits.data, width, height, &params);
             let stars = result.stars;
+            rejected = result.rejected;
 
             if verbose {
                 eprintln!("Detected {} stars", stars.len());
@@ -183,21 +237,7 @@ pub fn annotate_stars(
 
     // Parse annotation color
     let color = parse_color(annotation_color);
-
-    // Draw circles around detected stars
-    for (x, y, hfr) in &stars_to_annotate {
-        // Calculate circle radius based on HFR
-        // Use 2.5 * HFR for circle radius, with minimum of 5 pixels
-        let radius = (hfr * 2.5).max(5.0) as i32;
-
-        // Draw hollow circle
-        draw_hollow_circle_mut(&mut rgb_image, (*x as i32, *y as i32), radius, color);
-
-        // For very small stars, also draw a filled center point
-        if radius < 8 {
-            draw_filled_circle_mut(&mut rgb_image, (*x as i32, *y as i32), 1, color);
-        }
-    }
+    draw_annotations(&mut rgb_image, &stars_to_annotate, &rejected, color);
 
     // Generate output filename
     let output_path = output.unwrap_or_else(|| {
@@ -229,6 +269,17 @@ pub fn annotate_stars(
         stars_to_annotate.len(),
         total_stars
     );
+    if show_rejected {
+        let legend: Vec<String> = REJECT_REASONS
+            .iter()
+            .map(|reason| format!("{}={:?}", reason.glyph(), reason))
+            .collect();
+        println!(
+            "Marked {} rejected candidates in red ({})",
+            rejected.len(),
+            legend.join(", ")
+        );
+    }
 
     if verbose && !stars_to_annotate.is_empty() {
         println!("\nTop 10 stars by HFR:");
@@ -245,3 +296,112 @@ pub fn annotate_stars(
 
     Ok(())
 }
+
+/// Circle accepted stars in `color`, and rejected candidates in red with a
+/// letter for the rejection reason at their upper right
+fn draw_annotations(
+    image: &mut RgbImage,
+    stars: &[(f64, f64, f64)],
+    rejected: &[RejectedStar],
+    color: Rgb<u8>,
+) {
+    for (x, y, hfr) in stars {
+        draw_star(image, *x, *y, *hfr, color);
+    }
+
+    for star in rejected {
+        let (x, y) = star.position;
+        let radius = draw_star(image, x, y, star.hfr, REJECTED_COLOR) as f64;
+        let glyph_x = (x + radius * 0.7 + 2.0).max(0.0) as u32;
+        let glyph_y = (y - radius * 0.7 - 9.0).max(0.0) as u32;
+        draw_char(
+            image,
+            glyph_x,
+            glyph_y,
+            star.reason.glyph(),
+            REJECTED_COLOR,
+            1,
+        );
+    }
+}
+
+/// Circle one star, returning the radius drawn
+fn draw_star(image: &mut RgbImage, x: f64, y: f64, hfr: f64, color: Rgb<u8>) -> i32 {
+    // Use 2.5 * HFR for circle radius, with minimum of 5 pixels
+    let radius = (hfr * 2.5).max(5.0) as i32;
+    draw_hollow_circle_mut(image, (x as i32, y as i32), radius, color);
+
+    // For very small stars, also draw a filled center point
+    if radius < 8 {
+        draw_filled_circle_mut(image, (x as i32, y as i32), 1, color);
+    }
+    radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    /// Noisy background with a normal star on the left and a clipped one on the right
+    fn frame_with_saturated_star(width: usize, height: usize) -> Vec<u16> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let stars = [(40.0, 64.0, 20000.0, 2.5), (90.0, 64.0, 500000.0, 5.0)];
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f64, (i / width) as f64);
+                let signal: f64 = stars
+                    .iter()
+                    .map(|&(cx, cy, amplitude, sigma)| {
+                        let r2 = (x - cx).powi(2) + (y - cy).powi(2);
+                        amplitude * (-r2 / (2.0 * sigma * sigma)).exp()
+                    })
+                    .sum();
+                (1000.0 + rng.gen_range(-20.0..20.0) + signal).min(65535.0) as u16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_saturated_star_drawn_in_rejected_color() {
+        let (width, height) = (128, 128);
+        let data = frame_with_saturated_star(width, height);
+        let params = HocusFocusParams {
+            keep_rejected: true,
+            ..Default::default()
+        };
+        let result = detect_stars_hocus_focus(&data, width, height, &params);
+
+        let saturated = result
+            .rejected
+            .iter()
+            .find(|s| (s.position.0 - 90.0).abs() < 3.0 && (s.position.1 - 64.0).abs() < 3.0)
+            .expect("saturated star should be rejected");
+        assert_eq!(saturated.reason, StarRejectReason::Saturated);
+        assert!(result
+            .stars
+            .iter()
+            .all(|s| (s.position.0 - 90.0).abs() > 3.0));
+
+        let accepted: Vec<_> = result
+            .stars
+            .iter()
+            .map(|s| (s.position.0, s.position.1, s.hfr))
+            .collect();
+        assert!(!accepted.is_empty());
+
+        let green = Rgb([0, 255, 0]);
+        let mut image = RgbImage::new(width as u32, height as u32);
+        draw_annotations(&mut image, &accepted, &result.rejected, green);
+
+        // Rightmost point of each circle
+        let circle_edge = |x: f64, y: f64, hfr: f64| {
+            let radius = (hfr * 2.5).max(5.0) as i32;
+            *image.get_pixel((x as i32 + radius) as u32, y as u32)
+        };
+        let (x, y) = saturated.position;
+        assert_eq!(circle_edge(x, y, saturated.hfr), REJECTED_COLOR);
+        let (x, y, hfr) = accepted[0];
+        assert_eq!(circle_edge(x, y, hfr), green);
+    }
+}
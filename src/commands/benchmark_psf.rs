@@ -1,10 +1,16 @@
-use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
+use crate::hocus_focus_star_detection::{
+    detect_stars_hocus_focus, HocusFocusParams, HocusFocusStar,
+};
 use crate::image_analysis::FitsImage;
-use crate::psf_fitting::PSFType;
+use crate::psf_fitting::{PSFFitter, PSFType};
 use anyhow::Result;
+use rayon::prelude::*;
 use std::path::Path;
 use std::time::Instant;
 
+/// Stars fitted when comparing serial and parallel PSF fitting
+const FIT_COMPARISON_STARS: usize = 25;
+
 pub fn benchmark_psf(fits_path: &str, n_runs: usize, verbose: bool) -> Result<()> {
     if verbose {
         println!("Loading FITS file: {}", fits_path);
@@ -108,6 +114,8 @@ pub fn benchmark_psf(fits_path: &str, n_runs: usize, verbose: bool) -> Result<()
         }
     }
 
+    compare_parallel_fitting(&fits, n_runs);
+
     // Additional detailed analysis for PSF fitting
     if verbose {
         println!("\n=== Detailed PSF Analysis ===\n");
@@ -192,3 +200,77 @@ pub fn benchmark_psf(fits_path: &str, n_runs: usize, verbose: bool) -> Result<()
 
     Ok(())
 }
+
+/// Time fitting the brightest stars one after another against fitting them in parallel
+fn compare_parallel_fitting(fits: &FitsImage, n_runs: usize) {
+    let params = HocusFocusParams {
+        psf_type: PSFType::None,
+        ..Default::default()
+    };
+    let mut stars = detect_stars_hocus_focus(&fits.data, fits.width, fits.height, &params).stars;
+    stars.sort_by(|a, b| b.brightness.total_cmp(&a.brightness));
+    stars.truncate(FIT_COMPARISON_STARS);
+    if stars.is_empty() {
+        return;
+    }
+
+    println!(
+        "\nSerial vs parallel Moffat4 fitting ({} stars, {} runs each)...\n",
+        stars.len(),
+        n_runs
+    );
+
+    let fitter = PSFFitter::new(PSFType::Moffat4);
+    let fit = |star: &HocusFocusStar| {
+        // The candidate box is not kept, so size it from the star's pixel count
+        let side = (star.pixel_count as f64).sqrt().max(3.0);
+        fitter.fit_star(
+            &fits.data,
+            fits.width,
+            fits.height,
+            star.position.0,
+            star.position.1,
+            side,
+            side,
+            star.background,
+            star.brightness,
+        )
+    };
+
+    let mut serial_time = 0.0;
+    let mut parallel_time = 0.0;
+    let mut fitted = 0;
+    for _ in 0..n_runs {
+        let start = Instant::now();
+        let serial: Vec<_> = stars.iter().map(fit).collect();
+        serial_time += start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let parallel: Vec<_> = stars.par_iter().map(fit).collect();
+        parallel_time += start.elapsed().as_secs_f64();
+
+        fitted = parallel.iter().filter(|m| m.is_some()).count();
+        debug_assert_eq!(
+            serial
+                .iter()
+                .map(|m| m.as_ref().map(|m| m.fwhm))
+                .collect::<Vec<_>>(),
+            parallel
+                .iter()
+                .map(|m| m.as_ref().map(|m| m.fwhm))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    let serial_avg = serial_time / n_runs as f64 * 1000.0;
+    let parallel_avg = parallel_time / n_runs as f64 * 1000.0;
+    println!("{:<20} | {:>10.2}ms", "Serial", serial_avg);
+    println!(
+        "{:<20} | {:>10.2}ms ({:.1}x, {} threads)",
+        "Parallel",
+        parallel_avg,
+        serial_avg / parallel_avg.max(f64::EPSILON),
+        rayon::current_num_threads()
+    );
+    println!("{:<20} | {}/{} stars fitted", "", fitted, stars.len());
+}
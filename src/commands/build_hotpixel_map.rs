@@ -0,0 +1,26 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::hotpixel_map;
+
+/// Build a hot pixel map from a set of dark frames and save it as JSON
+pub fn build_hotpixel_map(darks: &[String], sigma: f64, output: &str) -> Result<()> {
+    let paths: Vec<&Path> = darks.iter().map(Path::new).collect();
+    println!("Stacking {} dark frames...", paths.len());
+
+    let map = hotpixel_map::build_hotpixel_map(&paths, sigma)?;
+    let total = map.width * map.height;
+    println!(
+        "Flagged {} hot pixels ({:.4}% of {}x{}) above median + {} sigma",
+        map.len(),
+        100.0 * map.len() as f64 / total.max(1) as f64,
+        map.width,
+        map.height,
+        sigma
+    );
+
+    map.save(Path::new(output))?;
+    println!("Saved hot pixel map to: {}", output);
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use crate::db::Database;
+use anyhow::{bail, Result};
+use rusqlite::Connection;
+
+/// Tables and columns read or written by psf-guard
+pub const EXPECTED_SCHEMA: &[(&str, &[&str])] = &[
+    ("project", &["Id", "profileId", "name", "description"]),
+    (
+        "target",
+        &["Id", "projectId", "name", "active", "ra", "dec"],
+    ),
+    (
+        "acquiredimage",
+        &[
+            "Id",
+            "projectId",
+            "targetId",
+            "acquireddate",
+            "filtername",
+            "gradingStatus",
+            "metadata",
+            "rejectreason",
+            "profileId",
+        ],
+    ),
+];
+
+#[derive(Debug)]
+pub struct TableReport {
+    pub name: String,
+    pub present: bool,
+    pub missing_columns: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct SchemaReport {
+    pub schema_version: i64,
+    pub tables: Vec<TableReport>,
+}
+
+impl SchemaReport {
+    pub fn is_compatible(&self) -> bool {
+        self.tables
+            .iter()
+            .all(|t| t.present && t.missing_columns.is_empty())
+    }
+}
+
+/// Compare the database schema against the tables and columns psf-guard uses
+pub fn inspect_schema(conn: &Connection) -> Result<SchemaReport> {
+    let db = Database::new(conn);
+    let schema_version = db.get_schema_version()?;
+
+    let mut tables = Vec::new();
+    for (table, expected_columns) in EXPECTED_SCHEMA {
+        let report = match db.get_table_columns(table)? {
+            Some(columns) => TableReport {
+                name: table.to_string(),
+                present: true,
+                // SQLite identifiers are case-insensitive
+                missing_columns: expected_columns
+                    .iter()
+                    .filter(|c| !columns.iter().any(|col| col.eq_ignore_ascii_case(c)))
+                    .map(|c| c.to_string())
+                    .collect(),
+            },
+            None => TableReport {
+                name: table.to_string(),
+                present: false,
+                missing_columns: expected_columns.iter().map(|c| c.to_string()).collect(),
+            },
+        };
+        tables.push(report);
+    }
+
+    Ok(SchemaReport {
+        schema_version,
+        tables,
+    })
+}
+
+pub fn check_db(conn: &Connection) -> Result<()> {
+    let report = inspect_schema(conn)?;
+
+    println!("Schema version (user_version): {}", report.schema_version);
+    println!();
+    println!("{:<16} {:<10} Missing Columns", "Table", "Status");
+    println!("{:-<60}", "");
+
+    for table in &report.tables {
+        let status = if !table.present {
+            "MISSING"
+        } else if table.missing_columns.is_empty() {
+            "OK"
+        } else {
+            "INCOMPLETE"
+        };
+        println!(
+            "{:<16} {:<10} {}",
+            table.name,
+            status,
+            table.missing_columns.join(", ")
+        );
+    }
+
+    if !report.is_compatible() {
+        bail!(
+            "Database schema is not compatible with psf-guard. \
+             Is this a N.I.N.A. Target Scheduler database?"
+        );
+    }
+
+    println!();
+    println!("Database schema is compatible.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_db() -> Connection {
+        let conn = crate::db::fixture_db();
+        conn.execute_batch("PRAGMA user_version = 12").unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_report_lists_expected_tables() {
+        let conn = fixture_db();
+        let report = inspect_schema(&conn).unwrap();
+
+        assert_eq!(report.schema_version, 12);
+        let names: Vec<_> = report.tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["project", "target", "acquiredimage"]);
+        assert!(report.tables.iter().all(|t| t.present));
+        assert!(report.is_compatible());
+    }
+
+    #[test]
+    fn test_report_missing_table_and_column() {
+        let conn = fixture_db();
+        conn.execute_batch(
+            "DROP TABLE project;
+             ALTER TABLE acquiredimage DROP COLUMN rejectreason;",
+        )
+        .unwrap();
+        let report = inspect_schema(&conn).unwrap();
+
+        assert!(!report.is_compatible());
+        assert!(!report.tables[0].present);
+        assert_eq!(report.tables[2].missing_columns, vec!["rejectreason"]);
+    }
+}
@@ -34,6 +34,7 @@ pub fn dump_grading_results(
         project_filter.as_deref(),
         target_filter.as_deref(),
         None,
+        None,
     )?;
 
     match format {
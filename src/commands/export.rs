@@ -0,0 +1,206 @@
+use crate::commands::analyze_fits::{measure_frames, FrameMetrics};
+use crate::commands::filter_rejected::find_fits_file;
+use crate::db::Database;
+use crate::models::{AcquiredImage, GradingStatus};
+use crate::utils::{escape_csv, extract_filename};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+pub const EXPORT_CSV_HEADER: &str = "id,project,target,filter,date,status,reason,db_hfr,db_stars,computed_hfr,computed_stars,eccentricity";
+
+/// Write one CSV row per image with its database metrics and, with `recompute`,
+/// freshly detected HFR, star count and median eccentricity
+pub fn export(
+    conn: &Connection,
+    project_filter: Option<String>,
+    target_filter: Option<String>,
+    output: Option<&str>,
+    base_dir: Option<&str>,
+    recompute: bool,
+    jobs: Option<usize>,
+) -> Result<()> {
+    let db = Database::new(conn);
+    let images = db.query_images(
+        None,
+        project_filter.as_deref(),
+        target_filter.as_deref(),
+        None,
+        None,
+    )?;
+
+    let computed = if recompute {
+        let base_dir = base_dir.ok_or_else(|| {
+            anyhow::anyhow!("--recompute needs --base-dir to find the image files")
+        })?;
+        recompute_metrics(&images, base_dir, jobs)?
+    } else {
+        vec![None; images.len()]
+    };
+
+    match output {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path, e))?;
+            let mut writer = BufWriter::new(file);
+            write_export_csv(&images, &computed, &mut writer)?;
+            writer.flush()?;
+            eprintln!("Exported {} images to {}", images.len(), path);
+        }
+        None => write_export_csv(&images, &computed, &mut std::io::stdout().lock())?,
+    }
+
+    Ok(())
+}
+
+/// Detection results for each image, None where the file is missing or fails to load
+fn recompute_metrics(
+    images: &[(AcquiredImage, String, String)],
+    base_dir: &str,
+    jobs: Option<usize>,
+) -> Result<Vec<Option<FrameMetrics>>> {
+    let paths: Vec<Option<PathBuf>> = images
+        .iter()
+        .map(|(image, _project_name, target_name)| locate_frame(base_dir, image, target_name))
+        .collect();
+    let found: Vec<PathBuf> = paths.iter().flatten().cloned().collect();
+    let missing = images.len() - found.len();
+    if missing > 0 {
+        eprintln!("{} image files not found under {}", missing, base_dir);
+    }
+
+    let mut results = measure_frames(&found, jobs)?.into_iter().zip(&found);
+    let mut computed = Vec::with_capacity(images.len());
+    for path in &paths {
+        if path.is_none() {
+            computed.push(None);
+            continue;
+        }
+        let (result, path) = results.next().expect("one result per found file");
+        match result {
+            Ok(metrics) => computed.push(Some(metrics)),
+            Err(e) => {
+                eprintln!("Error analyzing {}: {}", path.display(), e);
+                computed.push(None);
+            }
+        }
+    }
+    Ok(computed)
+}
+
+fn locate_frame(base_dir: &str, image: &AcquiredImage, target_name: &str) -> Option<PathBuf> {
+    let filename = extract_filename(&image.metadata)?;
+    let acquired = chrono::DateTime::from_timestamp(image.acquired_date?, 0)?;
+    let date_str = acquired.format("%Y-%m-%d").to_string();
+    find_fits_file(base_dir, &date_str, target_name, &filename, false)
+}
+
+fn write_export_csv(
+    images: &[(AcquiredImage, String, String)],
+    computed: &[Option<FrameMetrics>],
+    out: &mut dyn Write,
+) -> Result<()> {
+    writeln!(out, "{}", EXPORT_CSV_HEADER)?;
+    for ((image, project_name, target_name), &metrics) in images.iter().zip(computed) {
+        let metadata: serde_json::Value = serde_json::from_str(&image.metadata).unwrap_or_default();
+        let date_str = image
+            .acquired_date
+            .and_then(|d| chrono::DateTime::from_timestamp(d, 0))
+            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let format_value = |value: Option<f64>| value.map(|v| format!("{:.3}", v));
+
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            image.id,
+            escape_csv(project_name),
+            escape_csv(target_name),
+            escape_csv(&image.filter_name),
+            date_str,
+            GradingStatus::from_i32(image.grading_status),
+            escape_csv(image.reject_reason.as_deref().unwrap_or("")),
+            format_value(metadata["HFR"].as_f64()).unwrap_or_default(),
+            metadata["DetectedStars"]
+                .as_i64()
+                .map(|stars| stars.to_string())
+                .unwrap_or_default(),
+            format_value(metrics.map(|m| m.avg_hfr)).unwrap_or_default(),
+            metrics
+                .map(|m| m.star_count.to_string())
+                .unwrap_or_default(),
+            format_value(metrics.and_then(|m| m.median_eccentricity)).unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_db() -> Connection {
+        let conn = crate::db::fixture_db();
+        conn.execute_batch(
+            "INSERT INTO project VALUES (1, 'profile', 'Galaxies', NULL);
+             INSERT INTO project VALUES (2, 'profile', 'Nebulae', NULL);
+             INSERT INTO target VALUES (1, 1, 'M31', 1, 10.68, 41.27);
+             INSERT INTO target VALUES (2, 2, 'M42', 1, 83.82, -5.39);
+             INSERT INTO acquiredimage VALUES (1, 1, 1, 1700000000, 'L', 1,
+                 '{\"FileName\": \"M31_L_0001.fits\", \"HFR\": 2.5, \"DetectedStars\": 420}', NULL, 'profile');
+             INSERT INTO acquiredimage VALUES (2, 1, 1, 1700000300, 'L', 2,
+                 '{\"FileName\": \"M31_L_0002.fits\", \"HFR\": 4.1, \"DetectedStars\": 120}',
+                 'Clouds, thin', 'profile');
+             INSERT INTO acquiredimage VALUES (3, 2, 2, 1700000600, 'Ha', 0, '{}', NULL, 'profile');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_export_writes_header_and_one_row_per_image() {
+        let conn = fixture_db();
+        let path =
+            std::env::temp_dir().join(format!("psf_guard_export_{}.csv", std::process::id()));
+
+        export(
+            &conn,
+            None,
+            None,
+            Some(path.to_str().unwrap()),
+            None,
+            false,
+            None,
+        )
+        .unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], EXPORT_CSV_HEADER);
+        assert_eq!(lines.len(), 4);
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("2,Galaxies,M31,L,")
+                && line.contains(",Rejected,\"Clouds, thin\",4.100,120,,,")));
+
+        // Project filter
+        export(
+            &conn,
+            Some("Nebulae".to_string()),
+            None,
+            Some(path.to_str().unwrap()),
+            None,
+            false,
+            None,
+        )
+        .unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+
+        // Recomputing needs the image directory
+        assert!(export(&conn, None, None, None, None, true, None).is_err());
+
+        std::fs::remove_file(&path).ok();
+    }
+}
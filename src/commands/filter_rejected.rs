@@ -1,22 +1,58 @@
 use crate::db::Database;
 use crate::grading;
+use crate::image_analysis::FitsImage;
 use crate::models::{AcquiredImage, GradingStatus};
+use crate::trail_detection::{detect_trails_in_image, TrailDetectionParams};
+use crate::utils::image_extension;
 use anyhow::Result;
 use rusqlite::Connection;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// Extensions tried, in order, when a file is no longer stored under its original name
+const ALTERNATE_EXTENSIONS: &[&str] = &["fits", "fit", "fits.fz", "fits.gz", "xisf"];
+
+/// What filter-rejected did (or, in a dry run, would do) with one rejected image
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileActionKind {
+    /// Moved (or would be moved) to LIGHT_REJECT
+    Move,
+    /// Found, but already outside a LIGHT directory so there is nowhere to move it
+    Skip,
+    /// No file found for the image
+    Missing,
+    /// The move failed; `reason` holds the error
+    Error,
+}
+
+/// One entry of the `--format json` report
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FileAction {
+    pub image_id: i32,
+    pub filename: String,
+    pub source_path: Option<String>,
+    pub dest_path: Option<String>,
+    pub reason: String,
+    pub action: FileActionKind,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn filter_rejected_files(
     conn: &Connection,
     base_dir: &str,
     dry_run: bool,
     project_filter: Option<String>,
     target_filter: Option<String>,
+    filter_name: Option<String>,
     stat_config: Option<grading::StatisticalGradingConfig>,
+    format: &str,
     verbose: bool,
 ) -> Result<()> {
     let db = Database::new(conn);
+    // The JSON report replaces the human-readable output
+    let json = format == "json";
 
     // If statistical analysis is enabled, we need all images to analyze
     let perform_statistical = stat_config.is_some();
@@ -27,6 +63,7 @@ pub fn filter_rejected_files(
             None, // Get all statuses for statistical analysis
             project_filter.as_deref(),
             target_filter.as_deref(),
+            filter_name.as_deref(),
             None,
         )?
     } else {
@@ -34,6 +71,7 @@ pub fn filter_rejected_files(
             Some(GradingStatus::Rejected),
             project_filter.as_deref(),
             target_filter.as_deref(),
+            filter_name.as_deref(),
             None,
         )?
     };
@@ -41,7 +79,12 @@ pub fn filter_rejected_files(
     // Perform statistical analysis if enabled
     let mut statistical_rejections = HashMap::new();
     if let Some(config) = stat_config {
-        println!("Performing statistical analysis...");
+        if !json {
+            println!("Performing statistical analysis...");
+            if config.enable_gradient_analysis || config.enable_trail_detection {
+                println!("  Measuring image files...");
+            }
+        }
 
         // Convert to format expected by grader
         let mut image_stats = Vec::new();
@@ -54,10 +97,28 @@ pub fn filter_rejected_files(
                 &image.filter_name,
                 image.grading_status,
             ) {
-                Ok(stats) => image_stats.push(stats),
-                Err(e) => println!(
-                    "  Warning: Failed to parse metadata for image {}: {}",
-                    image.id, e
+                Ok(mut stats) => {
+                    if config.enable_gradient_analysis || config.enable_trail_detection {
+                        if let Err(e) =
+                            measure_image_file(image, target_name, base_dir, &config, &mut stats)
+                        {
+                            report_problem(
+                                json,
+                                format!(
+                                    "  Warning: Failed to measure image file for image {}: {}",
+                                    image.id, e
+                                ),
+                            );
+                        }
+                    }
+                    image_stats.push(stats)
+                }
+                Err(e) => report_problem(
+                    json,
+                    format!(
+                        "  Warning: Failed to parse metadata for image {}: {}",
+                        image.id, e
+                    ),
                 ),
             }
         }
@@ -66,29 +127,44 @@ pub fn filter_rejected_files(
         let grader = grading::StatisticalGrader::new(config);
         match grader.analyze_images(image_stats) {
             Ok(rejections) => {
-                println!("  Found {} statistical rejections", rejections.len());
+                if !json {
+                    println!("  Found {} statistical rejections", rejections.len());
+                }
                 for rejection in rejections {
-                    println!(
-                        "    Image {}: {} - {}",
-                        rejection.image_id, rejection.reason, rejection.details
-                    );
+                    if !json {
+                        println!(
+                            "    Image {}: {} - {} (score {:.2})",
+                            rejection.image_id,
+                            rejection.reason,
+                            rejection.details,
+                            rejection.score
+                        );
+                    }
                     statistical_rejections.insert(rejection.image_id, rejection);
                 }
             }
-            Err(e) => println!("  Warning: Statistical analysis failed: {}", e),
+            Err(e) => report_problem(
+                json,
+                format!("  Warning: Statistical analysis failed: {}", e),
+            ),
+        }
+        if !json {
+            println!();
         }
-        println!();
     }
 
     let mut moved_count = 0;
     let mut not_found_count = 0;
     let mut error_count = 0;
+    let mut actions = Vec::new();
 
-    println!(
-        "{}Filtering files...",
-        if dry_run { "[DRY RUN] " } else { "" }
-    );
-    println!();
+    if !json {
+        println!(
+            "{}Filtering files...",
+            if dry_run { "[DRY RUN] " } else { "" }
+        );
+        println!();
+    }
 
     for (image, _project_name, target_name) in all_images {
         // Check if this image should be moved
@@ -105,23 +181,50 @@ pub fn filter_rejected_files(
         }
 
         // Process the file movement
-        match process_file_movement(
+        let result = plan_file_movement(
             &image,
             &target_name,
             base_dir,
-            dry_run,
             &statistical_rejections,
-            verbose,
-        ) {
-            Ok(true) => moved_count += 1,
-            Ok(false) => not_found_count += 1,
+            verbose && !json,
+            json,
+        )
+        .map(|action| {
+            if !json {
+                print_file_action(&action, verbose, base_dir, &image, &target_name);
+            }
+            if action.action == FileActionKind::Move && !dry_run {
+                perform_move(action)
+            } else {
+                action
+            }
+        });
+
+        match result {
+            Ok(action) => {
+                match action.action {
+                    FileActionKind::Move => moved_count += 1,
+                    FileActionKind::Missing => not_found_count += 1,
+                    FileActionKind::Skip => {}
+                    FileActionKind::Error => {
+                        report_problem(json, format!("  ERROR: {}", action.reason));
+                        error_count += 1;
+                    }
+                }
+                actions.push(action);
+            }
             Err(e) => {
-                println!("  ERROR: {}", e);
+                report_problem(json, format!("  ERROR: {}", e));
                 error_count += 1;
             }
         }
     }
 
+    if json {
+        println!("{}", serde_json::to_string_pretty(&actions)?);
+        return Ok(());
+    }
+
     println!("\nSummary:");
     println!("  Files moved: {}", moved_count);
     println!("  Files not found: {}", not_found_count);
@@ -136,14 +239,73 @@ pub fn filter_rejected_files(
     Ok(())
 }
 
-fn process_file_movement(
+/// Warnings and errors go to stderr in JSON mode so stdout stays parseable
+fn report_problem(json: bool, message: String) {
+    if json {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Work out where a rejected image's file is and where it should go, without touching it
+fn plan_file_movement(
     image: &AcquiredImage,
     target_name: &str,
     base_dir: &str,
-    dry_run: bool,
     statistical_rejections: &HashMap<i32, grading::StatisticalRejection>,
     verbose: bool,
-) -> Result<bool> {
+    quiet: bool,
+) -> Result<FileAction> {
+    let (file_only, source_path) = locate_image_file(image, target_name, base_dir, verbose, quiet)?;
+
+    let reason = if let Some(stat_rejection) = statistical_rejections.get(&image.id) {
+        format!("{} - {}", stat_rejection.reason, stat_rejection.details)
+    } else {
+        image
+            .reject_reason
+            .clone()
+            .unwrap_or_else(|| "No reason".to_string())
+    };
+
+    let Some(source_path) = source_path else {
+        return Ok(FileAction {
+            image_id: image.id,
+            filename: file_only,
+            source_path: None,
+            dest_path: None,
+            reason,
+            action: FileActionKind::Missing,
+        });
+    };
+
+    // Create the reject path by replacing LIGHT with LIGHT_REJECT
+    let reject_path = get_reject_path(&source_path)?;
+    let action = if reject_path == source_path {
+        FileActionKind::Skip
+    } else {
+        FileActionKind::Move
+    };
+
+    Ok(FileAction {
+        image_id: image.id,
+        filename: file_only,
+        source_path: Some(source_path.display().to_string()),
+        dest_path: Some(reject_path.display().to_string()),
+        reason,
+        action,
+    })
+}
+
+/// Find the file for an image: the usual directory layouts first, then a
+/// recursive search. Returns the bare filename along with the path, if found.
+fn locate_image_file(
+    image: &AcquiredImage,
+    target_name: &str,
+    base_dir: &str,
+    verbose: bool,
+    quiet: bool,
+) -> Result<(String, Option<PathBuf>)> {
     let metadata = serde_json::from_str::<serde_json::Value>(&image.metadata)?;
 
     let filename = metadata["FileName"]
@@ -164,101 +326,171 @@ fn process_file_movement(
         .ok_or_else(|| anyhow::anyhow!("Invalid filename format"))?
         .to_string();
 
-    // Try to find the file in different possible locations
-    let possible_paths = get_possible_paths(base_dir, &date_str, target_name, &file_only);
-
     if verbose {
         println!("  Looking for: {}", file_only);
         println!("  Target: {}", target_name);
         println!("  Date: {}", date_str);
     }
 
-    let mut source_path = None;
-    for path in &possible_paths {
-        if verbose {
-            println!("  Checking: {}", path.display());
-        }
-        if path.exists() {
-            source_path = Some(path.clone());
-            break;
-        }
-    }
+    let source_path = match find_fits_file(base_dir, &date_str, target_name, &file_only, verbose) {
+        Some(path) => Some(path),
+        // Try recursive search as a fallback
+        None => find_file_recursive(base_dir, &file_only)?.inspect(|path| {
+            if !quiet {
+                println!("  Found via recursive search: {}", path.display());
+            }
+        }),
+    };
 
-    let source_path = match source_path {
-        Some(path) => path,
-        None => {
-            // Try recursive search as a fallback
-            let recursive_path = find_file_recursive(base_dir, &file_only)?;
+    Ok((file_only, source_path))
+}
 
-            match recursive_path {
-                Some(path) => {
-                    println!("  Found via recursive search: {}", path.display());
-                    path
-                }
-                None => {
-                    let rejection_reason =
-                        if let Some(stat_rejection) = statistical_rejections.get(&image.id) {
-                            format!("{} - {}", stat_rejection.reason, stat_rejection.details)
-                        } else {
-                            image
-                                .reject_reason
-                                .clone()
-                                .unwrap_or_else(|| "No reason".to_string())
-                        };
-
-                    println!(
-                        "  {:6} NOT FOUND: {} ({})",
-                        image.id, file_only, rejection_reason
-                    );
-
-                    // In verbose mode, show what paths were tried
-                    if verbose {
-                        println!("         Searched paths:");
-                        for path in &possible_paths {
-                            println!("           - {}", path.display());
-                        }
-                    }
+/// Fill in the file-based measurements enabled in `config`, leaving them unset
+/// when the file cannot be found
+fn measure_image_file(
+    image: &AcquiredImage,
+    target_name: &str,
+    base_dir: &str,
+    config: &grading::StatisticalGradingConfig,
+    stats: &mut grading::ImageStatistics,
+) -> Result<()> {
+    let (_, source_path) = locate_image_file(image, target_name, base_dir, false, true)?;
+    let Some(source_path) = source_path else {
+        return Ok(());
+    };
 
-                    return Ok(false);
+    let fits = FitsImage::from_file(&source_path)?;
+    if config.enable_gradient_analysis {
+        stats.background = Some(grading::BackgroundLevels::measure(&fits));
+    }
+    if config.enable_trail_detection {
+        let params = TrailDetectionParams {
+            min_length: config.min_trail_length,
+            ..TrailDetectionParams::default()
+        };
+        stats.trails = Some(detect_trails_in_image(&fits, &params).summary());
+    }
+    Ok(())
+}
+
+fn print_file_action(
+    action: &FileAction,
+    verbose: bool,
+    base_dir: &str,
+    image: &AcquiredImage,
+    target_name: &str,
+) {
+    match (&action.action, &action.source_path, &action.dest_path) {
+        (FileActionKind::Missing, _, _) => {
+            println!(
+                "  {:6} NOT FOUND: {} ({})",
+                action.image_id, action.filename, action.reason
+            );
+
+            // In verbose mode, show what paths were tried
+            if verbose {
+                let date_str = image
+                    .acquired_date
+                    .and_then(|d| chrono::DateTime::from_timestamp(d, 0))
+                    .map(|dt| dt.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                println!("         Searched paths:");
+                for path in get_possible_paths(base_dir, &date_str, target_name, &action.filename) {
+                    println!("           - {}", path.display());
                 }
             }
         }
-    };
+        (FileActionKind::Skip, Some(source), _) => {
+            println!(
+                "  {:6} SKIPPED (not in a LIGHT directory): {}",
+                action.image_id, source
+            );
+        }
+        (_, Some(source), Some(dest)) => {
+            println!("  {:6} {} -> {}", action.image_id, source, dest);
+            println!("         Reason: {}", action.reason);
+        }
+        _ => {}
+    }
+}
 
-    // Create the reject path by replacing LIGHT with LIGHT_REJECT
-    let reject_path = get_reject_path(&source_path)?;
+/// Move the file of a planned action, turning a failed move into an `Error` action
+fn perform_move(mut action: FileAction) -> FileAction {
+    if let Err(e) = move_file(&action) {
+        action.action = FileActionKind::Error;
+        action.reason = e.to_string();
+    }
+    action
+}
 
-    let rejection_reason = if let Some(stat_rejection) = statistical_rejections.get(&image.id) {
-        format!("{} - {}", stat_rejection.reason, stat_rejection.details)
-    } else {
-        image
-            .reject_reason
-            .clone()
-            .unwrap_or_else(|| "No reason".to_string())
+fn move_file(action: &FileAction) -> Result<()> {
+    let (Some(source), Some(dest)) = (&action.source_path, &action.dest_path) else {
+        return Ok(());
     };
+    let dest = Path::new(dest);
 
-    println!(
-        "  {:6} {} -> {}",
-        image.id,
-        source_path.display(),
-        reject_path.display()
-    );
-    println!("         Reason: {}", rejection_reason);
-
-    if !dry_run {
-        // Create the reject directory if it doesn't exist
-        if let Some(parent) = reject_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
+    // Create the reject directory if it doesn't exist
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
 
-        // Move the file
-        fs::rename(&source_path, &reject_path)?;
+    // Move the file
+    fs::rename(source, dest)?;
+    Ok(())
+}
+
+/// Look for a frame in the usual N.I.N.A. directory layouts. The exact filename is
+/// tried first, then the same stem with each of `ALTERNATE_EXTENSIONS` (frames are
+/// often compressed or renamed after capture).
+pub(crate) fn find_fits_file(
+    base_dir: &str,
+    date_str: &str,
+    target_name: &str,
+    filename: &str,
+    verbose: bool,
+) -> Option<PathBuf> {
+    for candidate in filename_variants(filename) {
+        for path in get_possible_paths(base_dir, date_str, target_name, &candidate) {
+            if verbose {
+                println!("  Checking: {}", path.display());
+            }
+            if path.exists() {
+                if verbose && candidate != filename {
+                    println!("  Matched alternate name: {}", candidate);
+                }
+                return Some(path);
+            }
+        }
     }
 
-    Ok(true)
+    None
+}
+
+/// The original filename followed by its stem with each alternate extension
+pub(crate) fn filename_variants(filename: &str) -> Vec<String> {
+    let lower = filename.to_lowercase();
+    let stem = image_extension(filename)
+        .or_else(|| {
+            ALTERNATE_EXTENSIONS
+                .iter()
+                .copied()
+                .find(|ext| lower.ends_with(&format!(".{}", ext)))
+        })
+        .map(|ext| &filename[..filename.len() - ext.len() - 1])
+        .or_else(|| filename.rsplit_once('.').map(|(stem, _)| stem))
+        .unwrap_or(filename);
+
+    let mut variants = vec![filename.to_string()];
+    for ext in ALTERNATE_EXTENSIONS {
+        let candidate = format!("{}.{}", stem, ext);
+        if !variants.contains(&candidate) {
+            variants.push(candidate);
+        }
+    }
+    variants
 }
 
-fn get_possible_paths(
+pub(crate) fn get_possible_paths(
     base_dir: &str,
     date_str: &str,
     target_name: &str,
@@ -333,7 +565,7 @@ fn find_file_recursive(base_dir: &str, filename: &str) -> Result<Option<PathBuf>
     search_dir(Path::new(base_dir), filename)
 }
 
-fn get_reject_path(source_path: &Path) -> Result<PathBuf> {
+pub(crate) fn get_reject_path(source_path: &Path) -> Result<PathBuf> {
     let path_str = source_path.to_string_lossy();
 
     // If the file is already in a rejected subdirectory, move it up to LIGHT_REJECT
@@ -352,3 +584,124 @@ fn get_reject_path(source_path: &Path) -> Result<PathBuf> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename_variants() {
+        let variants = filename_variants("M31_L_0001.fits");
+        assert_eq!(
+            variants,
+            vec![
+                "M31_L_0001.fits",
+                "M31_L_0001.fit",
+                "M31_L_0001.fits.fz",
+                "M31_L_0001.fits.gz",
+                "M31_L_0001.xisf",
+            ]
+        );
+
+        // Compound extensions are stripped as a whole
+        let variants = filename_variants("frame.FITS.fz");
+        assert_eq!(variants[0], "frame.FITS.fz");
+        assert!(variants.contains(&"frame.fits".to_string()));
+        assert!(!variants.iter().any(|v| v.starts_with("frame.FITS.fits")));
+    }
+
+    #[test]
+    fn test_find_fits_file_compressed_variant() {
+        let base = std::env::temp_dir().join(format!("psf_guard_find_fits_{}", std::process::id()));
+        let light = base.join("M31").join("2024-01-15").join("LIGHT");
+        fs::create_dir_all(&light).unwrap();
+        fs::write(light.join("M31_L_0001.fits.fz"), b"").unwrap();
+
+        let found = find_fits_file(
+            base.to_str().unwrap(),
+            "2024-01-15",
+            "M31",
+            "M31_L_0001.fits",
+            false,
+        );
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(found, Some(light.join("M31_L_0001.fits.fz")));
+    }
+
+    #[test]
+    fn test_json_report_marks_missing_files() {
+        let base = std::env::temp_dir().join(format!("psf_guard_missing_{}", std::process::id()));
+        let light = base.join("M31").join("2024-01-15").join("LIGHT");
+        fs::create_dir_all(&light).unwrap();
+        fs::write(light.join("M31_L_0001.fits"), b"").unwrap();
+
+        let image = |id: i32, filename: &str| AcquiredImage {
+            id,
+            project_id: 1,
+            target_id: 1,
+            // 2024-01-15 12:00 UTC
+            acquired_date: Some(1_705_320_000),
+            filter_name: "L".to_string(),
+            grading_status: 2,
+            metadata: serde_json::json!({ "FileName": format!("C:\\data\\{}", filename) })
+                .to_string(),
+            reject_reason: Some("HFR".to_string()),
+            profile_id: None,
+        };
+
+        let base_dir = base.to_str().unwrap();
+        let actions: Vec<FileAction> = [image(1, "M31_L_0001.fits"), image(2, "M31_L_0002.fits")]
+            .iter()
+            .map(|img| plan_file_movement(img, "M31", base_dir, &HashMap::new(), false, true))
+            .collect::<Result<_>>()
+            .unwrap();
+        fs::remove_dir_all(&base).unwrap();
+
+        let report = serde_json::to_value(&actions).unwrap();
+        assert_eq!(report[0]["action"], "move");
+        assert_eq!(
+            report[0]["dest_path"],
+            light.to_string_lossy().replace("/LIGHT", "/LIGHT_REJECT") + "/M31_L_0001.fits"
+        );
+        assert_eq!(report[1]["action"], "missing");
+        assert_eq!(report[1]["image_id"], 2);
+        assert_eq!(report[1]["filename"], "M31_L_0002.fits");
+        assert!(report[1]["source_path"].is_null());
+        assert_eq!(report[1]["reason"], "HFR");
+    }
+
+    #[test]
+    fn test_failed_move_is_reported() {
+        let base =
+            std::env::temp_dir().join(format!("psf_guard_failed_move_{}", std::process::id()));
+        let action = FileAction {
+            image_id: 7,
+            filename: "M31_L_0007.fits".to_string(),
+            source_path: Some(
+                base.join("LIGHT")
+                    .join("M31_L_0007.fits")
+                    .to_string_lossy()
+                    .into(),
+            ),
+            dest_path: Some(
+                base.join("LIGHT_REJECT")
+                    .join("M31_L_0007.fits")
+                    .to_string_lossy()
+                    .into(),
+            ),
+            reason: "HFR".to_string(),
+            action: FileActionKind::Move,
+        };
+
+        // The source does not exist, so the rename fails
+        let action = perform_move(action);
+        fs::remove_dir_all(&base).ok();
+
+        assert_eq!(action.action, FileActionKind::Error);
+        assert_ne!(action.reason, "HFR");
+        let report = serde_json::to_value(&action).unwrap();
+        assert_eq!(report["action"], "error");
+        assert_eq!(report["image_id"], 7);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::db::Database;
+use crate::grading::parse_image_metadata;
+use crate::utils::{extract_filename, find_files, TraversalOptions};
+use anyhow::Result;
+use rusqlite::Connection;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Profile id given to projects created by the import
+const IMPORT_PROFILE_ID: &str = "psf-guard-import";
+
+/// CSV columns holding numbers rather than text: every numeric field grading
+/// reads from the metadata, including aliases
+const NUMERIC_COLUMNS: &[&str] = &[
+    "HFR",
+    "DetectedStars",
+    "ExposureTime",
+    "ExposureDuration",
+    "Gain",
+    "GuidingRMSRAArcSec",
+    "GuidingRMSDECArcSec",
+    "GuidingRMSArcSec",
+    "RmsRA",
+    "RmsDec",
+    "Rms",
+];
+
+/// One image read from a metadata sidecar
+#[derive(Debug)]
+struct MetadataRecord {
+    filename: String,
+    filter_name: String,
+    target_name: String,
+    acquired_date: Option<i64>,
+    metadata_json: String,
+}
+
+/// Import N.I.N.A. image metadata (.json and .csv sidecars) into the database
+pub fn import_metadata(
+    conn: &Connection,
+    dir: &str,
+    project_name: &str,
+    target_override: Option<String>,
+    dry_run: bool,
+) -> Result<()> {
+    let db = Database::new(conn);
+
+    println!(
+        "{}Importing image metadata from {}",
+        if dry_run { "[DRY RUN] " } else { "" },
+        dir
+    );
+
+    let mut sidecars = find_files(Path::new(dir), &TraversalOptions::default(), |path| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("csv"))
+    })?;
+    sidecars.sort();
+
+    let mut records = Vec::new();
+    for sidecar in &sidecars {
+        let target_name = target_override.clone().unwrap_or_else(|| {
+            sidecar
+                .parent()
+                .and_then(|p| p.file_name())
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "Unknown".to_string())
+        });
+        match read_sidecar(sidecar, &target_name) {
+            Ok(found) => records.extend(found),
+            Err(e) => println!("  Warning: Skipping {}: {}", sidecar.display(), e),
+        }
+    }
+    println!(
+        "  Found {} images in {} metadata files",
+        records.len(),
+        sidecars.len()
+    );
+
+    // A fresh database has no tables yet; nothing in it can be a duplicate
+    let mut known = if db.get_table_columns("acquiredimage")?.is_some() {
+        db.get_image_filenames()?
+    } else {
+        HashSet::new()
+    };
+    let (new, duplicates): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|record| known.insert(record.filename.clone()));
+
+    if dry_run {
+        for record in &new {
+            println!(
+                "    Would import {} ({}, {})",
+                record.filename, record.target_name, record.filter_name
+            );
+        }
+        println!(
+            "  Would import {} images, skipping {} duplicates",
+            new.len(),
+            duplicates.len()
+        );
+        println!("\nThis was a dry run. Use without --dry-run to actually update the database.");
+        return Ok(());
+    }
+
+    db.create_schema_if_missing()?;
+    db.with_transaction(|_tx| {
+        let (project_id, profile_id) =
+            db.find_or_create_project(project_name, IMPORT_PROFILE_ID)?;
+        for record in &new {
+            let target_id = db.find_or_create_target(project_id, &record.target_name)?;
+            db.insert_acquired_image(
+                project_id,
+                target_id,
+                record.acquired_date,
+                &record.filter_name,
+                &record.metadata_json,
+                &profile_id,
+            )?;
+        }
+        Ok(())
+    })?;
+
+    println!(
+        "  Imported {} images into project '{}', skipped {} duplicates",
+        new.len(),
+        project_name,
+        duplicates.len()
+    );
+
+    Ok(())
+}
+
+/// Read every image record from a sidecar file
+fn read_sidecar(path: &Path, target_name: &str) -> Result<Vec<MetadataRecord>> {
+    let contents = std::fs::read_to_string(path)?;
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+
+    let entries = if is_csv {
+        csv_entries(&contents)
+    } else {
+        match serde_json::from_str(&contents)? {
+            Value::Array(entries) => entries,
+            entry => vec![entry],
+        }
+    };
+
+    let mut records = Vec::new();
+    for entry in entries {
+        match metadata_record(entry, target_name) {
+            Ok(record) => records.push(record),
+            Err(e) => println!("  Warning: Skipping entry in {}: {}", path.display(), e),
+        }
+    }
+    Ok(records)
+}
+
+fn metadata_record(entry: Value, target_name: &str) -> Result<MetadataRecord> {
+    let metadata_json = entry.to_string();
+    let filter_name = entry["FilterName"].as_str().unwrap_or_default();
+
+    // Validates the fields grading relies on
+    let stats = parse_image_metadata(0, 0, target_name, &metadata_json, filter_name, 0)?;
+    let filename = extract_filename(&metadata_json)
+        .filter(|f| !f.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("No FileName"))?;
+
+    Ok(MetadataRecord {
+        filename,
+        filter_name: stats.filter_name,
+        target_name: stats.target_name,
+        acquired_date: parse_exposure_time(&stats.exposure_time),
+        metadata_json,
+    })
+}
+
+/// Unix timestamp of an ExposureStartTime; times without an offset are taken as UTC
+pub(crate) fn parse_exposure_time(time: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(time)
+        .map(|t| t.timestamp())
+        .or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|t| t.and_utc().timestamp())
+        })
+        .ok()
+}
+
+/// Turn CSV rows into JSON objects keyed by the header row
+fn csv_entries(contents: &str) -> Vec<Value> {
+    let mut lines = contents.lines().filter(|l| !l.trim().is_empty());
+    let Some(header) = lines.next() else {
+        return Vec::new();
+    };
+    let columns = split_csv_line(header);
+
+    lines
+        .map(|line| {
+            let object: Map<String, Value> = columns
+                .iter()
+                .zip(split_csv_line(line))
+                .map(|(column, value)| {
+                    let value = if value.is_empty() {
+                        Value::Null
+                    } else if NUMERIC_COLUMNS.contains(&column.as_str()) {
+                        serde_json::from_str(&value).unwrap_or(Value::String(value))
+                    } else {
+                        Value::String(value)
+                    };
+                    (column.clone(), value)
+                })
+                .collect();
+            Value::Object(object)
+        })
+        .collect()
+}
+
+/// Split a CSV line, honouring double-quoted fields
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(filename: &str, filter: &str, hfr: f64) -> Value {
+        serde_json::json!({
+            "FileName": format!("C:\\data\\M31\\{}", filename),
+            "FilterName": filter,
+            "HFR": hfr,
+            "DetectedStars": 250,
+            "ExposureStartTime": "2024-01-15T22:30:00.123"
+        })
+    }
+
+    fn count(conn: &Connection, query: &str) -> i32 {
+        conn.query_row(query, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_import_json_set() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_import_{}", std::process::id()));
+        let target_dir = dir.join("M31");
+        std::fs::create_dir_all(&target_dir).unwrap();
+        std::fs::write(
+            target_dir.join("ImageMetaData.json"),
+            Value::Array(vec![
+                metadata("M31_L_0001.fits", "L", 2.1),
+                metadata("M31_L_0002.fits", "L", 2.3),
+                metadata("M31_Ha_0001.fits", "Ha", 2.8),
+            ])
+            .to_string(),
+        )
+        .unwrap();
+        // A second sidecar repeating one of the frames
+        std::fs::write(
+            target_dir.join("extra.json"),
+            metadata("M31_L_0002.fits", "L", 2.3).to_string(),
+        )
+        .unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        import_metadata(&conn, dir_str, "Imported", None, true).unwrap();
+        let db = Database::new(&conn);
+        assert!(db.get_table_columns("acquiredimage").unwrap().is_none());
+
+        import_metadata(&conn, dir_str, "Imported", None, false).unwrap();
+        // Importing again only finds duplicates
+        import_metadata(&conn, dir_str, "Imported", None, false).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM acquiredimage"), 3);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM project"), 1);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM acquiredimage WHERE filtername = 'L'"
+            ),
+            2
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM acquiredimage WHERE filtername = 'Ha'"
+            ),
+            1
+        );
+
+        let images = db
+            .query_images(None, Some("Imported"), None, None, None)
+            .unwrap();
+        assert_eq!(images.len(), 3);
+        assert!(images.iter().all(|(image, _, target)| {
+            target == "M31" && image.grading_status == 0 && image.acquired_date == Some(1705357800)
+        }));
+    }
+
+    #[test]
+    fn test_csv_entries() {
+        let csv = "FileName,FilterName,HFR,DetectedStars,ExposureStartTime\n\
+                   \"C:\\data\\a, b.fits\",OIII,3.25,120,2024-01-15T22:30:00\n\
+                   c.fits,SII,,,2024-01-15T22:35:00\n";
+
+        let entries = csv_entries(csv);
+        assert_eq!(entries.len(), 2);
+
+        let record = metadata_record(entries[0].clone(), "M42").unwrap();
+        assert_eq!(record.filename, "a, b.fits");
+        assert_eq!(record.filter_name, "OIII");
+        assert_eq!(entries[0]["HFR"], 3.25);
+        assert_eq!(entries[0]["DetectedStars"], 120);
+        assert_eq!(entries[1]["HFR"], Value::Null);
+    }
+
+    #[test]
+    fn test_csv_exposure_and_gain_columns() {
+        let csv = "FileName,FilterName,HFR,DetectedStars,ExposureStartTime,ExposureDuration,Gain,GuidingRMSArcSec\n\
+                   a.fits,Ha,2.5,300,2024-01-15T22:30:00,300,100,0.61\n";
+
+        let entries = csv_entries(csv);
+        assert_eq!(entries[0]["ExposureDuration"], 300);
+        assert_eq!(entries[0]["Gain"], 100);
+
+        let record = metadata_record(entries[0].clone(), "M42").unwrap();
+        let stats = parse_image_metadata(0, 0, "M42", &record.metadata_json, "Ha", 0).unwrap();
+        assert_eq!(stats.exposure_duration, Some(300.0));
+        assert_eq!(stats.gain, Some(100));
+        assert_eq!(stats.rms_total, Some(0.61));
+    }
+}
@@ -1,27 +1,45 @@
 pub mod analyze_fits;
+pub mod analyze_tilt;
 pub mod annotate_stars;
 pub mod benchmark_psf;
+pub mod build_hotpixel_map;
+pub mod check_db;
 pub mod dump_grading;
+pub mod export;
 pub mod filter_rejected;
+pub mod import_metadata;
 pub mod list_projects;
 pub mod list_targets;
+pub mod psf_summary;
 pub mod read_fits;
 pub mod regrade;
+pub mod restore_rejected;
 pub mod show_images;
+pub mod stack;
 pub mod stretch_to_png;
+pub mod trend;
 pub mod update_grade;
 pub mod visualize_psf;
 
 pub use analyze_fits::analyze_fits_and_compare;
+pub use analyze_tilt::analyze_tilt;
 pub use annotate_stars::annotate_stars;
 pub use benchmark_psf::benchmark_psf;
+pub use build_hotpixel_map::build_hotpixel_map;
+pub use check_db::check_db;
 pub use dump_grading::dump_grading_results;
+pub use export::export;
 pub use filter_rejected::filter_rejected_files;
+pub use import_metadata::import_metadata;
 pub use list_projects::list_projects;
 pub use list_targets::list_targets;
+pub use psf_summary::psf_summary;
 pub use read_fits::read_fits;
 pub use regrade::regrade_images;
+pub use restore_rejected::restore_rejected_files;
 pub use show_images::show_images;
+pub use stack::stack;
 pub use stretch_to_png::stretch_to_png;
+pub use trend::trend;
 pub use update_grade::update_grade;
 pub use visualize_psf::visualize_psf_residuals;
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::field_analysis::{ensemble_psf, fit_psf_sample, MIN_ENSEMBLE_FITS};
+use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
+use crate::image_analysis::FitsImage;
+use crate::psf_fitting::{PSFType, DEFAULT_MIN_R_SQUARED};
+
+/// Fit PSFs to the brightest stars and report the frame's median PSF
+pub fn psf_summary(
+    fits_path: &str,
+    psf_type: &str,
+    max_stars: usize,
+    format: &str,
+    verbose: bool,
+) -> Result<()> {
+    crate::debug::init_debug(verbose);
+
+    let psf: PSFType = psf_type.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    if psf == PSFType::None {
+        return Err(anyhow::anyhow!(
+            "psf-summary needs a PSF model (gaussian, moffat4, or moffat<beta>)"
+        ));
+    }
+
+    let fits = FitsImage::from_file(Path::new(fits_path))?;
+    let params = HocusFocusParams {
+        verbose,
+        ..Default::default()
+    };
+    let result = detect_stars_hocus_focus(&fits.data, fits.width, fits.height, &params);
+    let models = fit_psf_sample(
+        &fits.data,
+        fits.width,
+        fits.height,
+        &result.stars,
+        psf,
+        max_stars,
+        DEFAULT_MIN_R_SQUARED,
+    );
+    let summary = ensemble_psf(&models);
+
+    if format == "json" {
+        let output = serde_json::json!({
+            "file": fits_path,
+            "psf_type": psf_type,
+            "detected_stars": result.stars.len(),
+            "psf": summary,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    println!("PSF summary: {}", fits_path);
+    println!("  Detected stars: {}", result.stars.len());
+
+    let Some(summary) = summary else {
+        println!(
+            "  Not enough successful PSF fits ({}, need at least {})",
+            models.len(),
+            MIN_ENSEMBLE_FITS
+        );
+        return Ok(());
+    };
+
+    println!(
+        "  Fitted stars:     {} of the {} brightest",
+        summary.fitted_stars,
+        max_stars.min(result.stars.len())
+    );
+    println!("  Model:            {}", psf_type);
+    println!("  Sigma (major):    {:.3}", summary.sigma_x);
+    println!("  Sigma (minor):    {:.3}", summary.sigma_y);
+    println!("  Theta:            {:.1} deg", summary.theta.to_degrees());
+    println!(
+        "  FWHM:             {:.3} (IQR {:.3})",
+        summary.fwhm, summary.fwhm_iqr
+    );
+    println!(
+        "  Eccentricity:     {:.3} (IQR {:.3})",
+        summary.eccentricity, summary.eccentricity_iqr
+    );
+
+    Ok(())
+}
@@ -2,10 +2,20 @@ use anyhow::Result;
 use fitrs::Fits;
 use serde_json;
 use std::collections::HashMap;
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
-pub fn read_fits(path: &str, verbose: bool, format: &str) -> Result<()> {
+use crate::image_analysis::{FitsImage, ImageStatistics};
+use crate::utils::{escape_csv, find_files, is_image_file, TraversalOptions};
+
+/// Channel labels for RGB cubes
+const CHANNEL_NAMES: [&str; 3] = ["R", "G", "B"];
+
+pub fn read_fits(
+    path: &str,
+    verbose: bool,
+    format: &str,
+    traversal: &TraversalOptions,
+) -> Result<()> {
     let path = Path::new(path);
 
     if path.is_file() {
@@ -13,7 +23,7 @@ pub fn read_fits(path: &str, verbose: bool, format: &str) -> Result<()> {
         read_single_fits(path, verbose, format)?;
     } else if path.is_dir() {
         // Directory of files
-        read_fits_directory(path, verbose, format)?;
+        read_fits_directory(path, verbose, format, traversal)?;
     } else {
         return Err(anyhow::anyhow!(
             "Path does not exist or is not accessible: {}",
@@ -25,19 +35,13 @@ pub fn read_fits(path: &str, verbose: bool, format: &str) -> Result<()> {
 }
 
 fn read_single_fits(path: &Path, verbose: bool, format: &str) -> Result<()> {
+    if format.eq_ignore_ascii_case("json") {
+        println!("{}", serde_json::to_string_pretty(&fits_json(path)?)?);
+        return Ok(());
+    }
     let metadata = read_fits_metadata(path)?;
 
     match format.to_lowercase().as_str() {
-        "json" => {
-            let json_output = if verbose {
-                serde_json::to_string_pretty(&metadata)?
-            } else {
-                // For non-verbose JSON, create a simpler structure
-                let simplified = create_simplified_metadata(&metadata);
-                serde_json::to_string_pretty(&simplified)?
-            };
-            println!("{}", json_output);
-        }
         "csv" => {
             output_csv_single(&metadata, verbose)?;
         }
@@ -51,11 +55,14 @@ fn read_single_fits(path: &Path, verbose: bool, format: &str) -> Result<()> {
     Ok(())
 }
 
-fn read_fits_directory(dir: &Path, verbose: bool, format: &str) -> Result<()> {
-    let mut fits_files = Vec::new();
-
+fn read_fits_directory(
+    dir: &Path,
+    verbose: bool,
+    format: &str,
+    traversal: &TraversalOptions,
+) -> Result<()> {
     // Recursively find all FITS files
-    find_fits_files(dir, &mut fits_files)?;
+    let fits_files = find_files(dir, traversal, is_image_file)?;
 
     if fits_files.is_empty() {
         match format.to_lowercase().as_str() {
@@ -69,6 +76,15 @@ fn read_fits_directory(dir: &Path, verbose: bool, format: &str) -> Result<()> {
         return Ok(());
     }
 
+    if format.eq_ignore_ascii_case("json") {
+        let documents: Vec<serde_json::Value> = fits_files
+            .iter()
+            .filter_map(|file_path| fits_json(file_path).ok())
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&documents)?);
+        return Ok(());
+    }
+
     let mut successful_metadata = Vec::new();
     let mut error_count = 0;
 
@@ -82,18 +98,6 @@ fn read_fits_directory(dir: &Path, verbose: bool, format: &str) -> Result<()> {
 
     // Output based on format
     match format.to_lowercase().as_str() {
-        "json" => {
-            let json_output = if verbose {
-                serde_json::to_string_pretty(&successful_metadata)?
-            } else {
-                let simplified: Vec<_> = successful_metadata
-                    .iter()
-                    .map(create_simplified_metadata)
-                    .collect();
-                serde_json::to_string_pretty(&simplified)?
-            };
-            println!("{}", json_output);
-        }
         "csv" => {
             output_csv_directory(&successful_metadata, verbose)?;
         }
@@ -123,6 +127,46 @@ fn read_fits_directory(dir: &Path, verbose: bool, format: &str) -> Result<()> {
     Ok(())
 }
 
+/// JSON document for one file: `{ file, dimensions, bit_depth, header,
+/// statistics }`, with every valued primary header card under `header`
+pub fn fits_json(path: &Path) -> Result<serde_json::Value> {
+    let cards = FitsImage::read_header_map(path)?;
+    let header: serde_json::Map<String, serde_json::Value> = cards
+        .iter()
+        .map(|(keyword, value)| (keyword.clone(), header_value_json(value)))
+        .collect();
+
+    let axis_count = header.get("NAXIS").and_then(|v| v.as_u64()).unwrap_or(0);
+    let dimensions: Vec<u64> = (1..=axis_count)
+        .filter_map(|axis| header.get(&format!("NAXIS{}", axis))?.as_u64())
+        .collect();
+
+    let statistics = FitsImage::from_file(path)?.calculate_basic_statistics();
+
+    Ok(serde_json::json!({
+        "file": path.display().to_string(),
+        "dimensions": dimensions,
+        "bit_depth": header.get("BITPIX").and_then(|v| v.as_i64()),
+        "header": header,
+        "statistics": statistics,
+    }))
+}
+
+/// Header value with its FITS type kept: integers and reals as numbers,
+/// logicals as booleans, complex values as `[real, imaginary]`
+fn header_value_json(value: &fitrs::HeaderValue) -> serde_json::Value {
+    use fitrs::HeaderValue;
+
+    match value {
+        HeaderValue::CharacterString(text) => serde_json::json!(text.trim_end()),
+        HeaderValue::Logical(flag) => serde_json::json!(flag),
+        HeaderValue::IntegerNumber(n) => serde_json::json!(n),
+        HeaderValue::RealFloatingNumber(x) => serde_json::json!(x),
+        HeaderValue::ComplexIntegerNumber(re, im) => serde_json::json!([re, im]),
+        HeaderValue::ComplexFloatingNumber(re, im) => serde_json::json!([re, im]),
+    }
+}
+
 /// Metadata extracted from a FITS file
 #[derive(Debug, serde::Serialize)]
 pub struct FitsMetadata {
@@ -130,6 +174,9 @@ pub struct FitsMetadata {
     pub headers: Vec<HeaderInfo>,
     pub primary_header: HashMap<String, String>,
     pub image_info: Option<ImageInfo>,
+    /// Per-channel statistics, only filled for multi-plane (color) images
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub channel_statistics: Vec<ImageStatistics>,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -271,6 +318,19 @@ pub fn read_fits_metadata(path: &Path) -> Result<FitsMetadata> {
         _ => None,
     };
 
+    // Color cubes get per-channel statistics; this needs the pixel data so
+    // 2D images skip it to keep header reads cheap
+    let is_multi_plane = image_info
+        .as_ref()
+        .is_some_and(|info| info.dimensions.get(2).is_some_and(|&planes| planes > 1));
+    let channel_statistics = if is_multi_plane {
+        FitsImage::from_file(path)
+            .map(|image| image.calculate_statistics_per_channel())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
     let headers = vec![HeaderInfo {
         hdu_index: 0,
         hdu_name: primary_header.get("EXTNAME").cloned(),
@@ -282,6 +342,7 @@ pub fn read_fits_metadata(path: &Path) -> Result<FitsMetadata> {
         headers,
         primary_header,
         image_info,
+        channel_statistics,
     })
 }
 
@@ -298,6 +359,20 @@ pub fn format_fits_metadata(metadata: &FitsMetadata, verbose: bool) -> String {
         ));
     }
 
+    if !metadata.channel_statistics.is_empty() {
+        output.push_str("\nChannel Statistics:\n");
+        for (index, stats) in metadata.channel_statistics.iter().enumerate() {
+            let name = CHANNEL_NAMES
+                .get(index)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| index.to_string());
+            output.push_str(&format!(
+                "  {}: mean {:.1}, median {:.1}, std dev {:.1}\n",
+                name, stats.mean, stats.median, stats.std_dev
+            ));
+        }
+    }
+
     // Display key headers
     let key_headers = vec![
         ("OBJECT", "Object"),
@@ -355,35 +430,6 @@ pub fn format_fits_metadata(metadata: &FitsMetadata, verbose: bool) -> String {
     output
 }
 
-fn find_fits_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
-    let entries = fs::read_dir(dir)?;
-
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_dir() {
-            // Recurse into subdirectories
-            find_fits_files(&path, files)?;
-        } else if is_fits_file(&path) {
-            files.push(path);
-        }
-    }
-
-    Ok(())
-}
-
-fn is_fits_file(path: &Path) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| {
-            let ext_lower = ext.to_lowercase();
-            ext_lower == "fits" || ext_lower == "fit" || ext_lower == "fts"
-        })
-        .unwrap_or(false)
-}
-
-#[derive(serde::Serialize)]
 struct SimplifiedFitsMetadata {
     filename: String,
     width: Option<usize>,
@@ -567,10 +613,40 @@ fn output_csv_directory(metadata_list: &[FitsMetadata], verbose: bool) -> Result
     Ok(())
 }
 
-fn escape_csv(value: &str) -> String {
-    if value.contains(',') || value.contains('"') || value.contains('\n') {
-        format!("\"{}\"", value.replace('"', "\"\""))
-    } else {
-        value.to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fits_json_header_matches_image() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_read_fits_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frame.fits");
+
+        let image = FitsImage {
+            width: 12,
+            height: 7,
+            data: (0..12 * 7).map(|i| 1000 + i as u16).collect(),
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+        let headers = vec![
+            ("OBJECT".to_string(), fitrs::HeaderValue::from("M 31")),
+            ("EXPTIME".to_string(), fitrs::HeaderValue::from(300.0f64)),
+        ];
+        image.write_to_file_with_headers(&path, &headers).unwrap();
+
+        let text = serde_json::to_string(&fits_json(&path).unwrap()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(json["header"]["NAXIS1"], serde_json::json!(12));
+        assert_eq!(json["header"]["NAXIS2"], serde_json::json!(7));
+        assert_eq!(json["dimensions"], serde_json::json!([12, 7]));
+        assert_eq!(json["bit_depth"], serde_json::json!(16));
+        assert_eq!(json["header"]["OBJECT"], serde_json::json!("M 31"));
+        assert_eq!(json["header"]["EXPTIME"].as_f64(), Some(300.0));
+        assert_eq!(json["statistics"]["width"], serde_json::json!(12));
+        assert_eq!(json["statistics"]["height"], serde_json::json!(7));
     }
 }
@@ -1,17 +1,23 @@
 use crate::db::Database;
 use crate::grading;
-use crate::models::GradingStatus;
+use crate::models::{GradeChangeSource, GradingStatus};
+use crate::utils::Subset;
 use anyhow::Result;
 use rusqlite::Connection;
 
+#[allow(clippy::too_many_arguments)]
 pub fn regrade_images(
     conn: &Connection,
     dry_run: bool,
     target_filter: Option<String>,
     project_filter: Option<String>,
+    filter_name: Option<String>,
     days: u32,
     reset_mode: &str,
+    protect_reasons: &[String],
     stat_config: Option<grading::StatisticalGradingConfig>,
+    incremental: bool,
+    subset: Subset,
 ) -> Result<()> {
     // Validate reset mode
     match reset_mode {
@@ -24,6 +30,21 @@ pub fn regrade_images(
         }
     }
 
+    // Resetting every image but regrading a subset would drop the other rejections
+    if subset != Subset::All && reset_mode != "none" && !dry_run {
+        return Err(anyhow::anyhow!(
+            "--limit and --sample only regrade part of the images; use them with --dry-run or --reset none"
+        ));
+    }
+
+    // A blank entry (e.g. from "trail,") would match, and so protect, every reason
+    let protect_reasons: Vec<String> = protect_reasons
+        .iter()
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty())
+        .collect();
+    let protect_reasons = protect_reasons.as_slice();
+
     let db = Database::new(conn);
 
     println!(
@@ -37,6 +58,12 @@ pub fn regrade_images(
     let cutoff_timestamp = cutoff_date.timestamp();
 
     println!("  Date range: {} to now", cutoff_date.format("%Y-%m-%d"));
+    if let Some(filter) = &filter_name {
+        println!("  Filter: {}", filter);
+    }
+    if !protect_reasons.is_empty() {
+        println!("  Protected reasons: {}", protect_reasons.join(", "));
+    }
 
     // Wrap all operations in a transaction for consistency
     if !dry_run && (reset_mode != "none" || stat_config.is_some()) {
@@ -50,6 +77,8 @@ pub fn regrade_images(
                     cutoff_timestamp,
                     &project_filter,
                     &target_filter,
+                    &filter_name,
+                    protect_reasons,
                 )?;
             }
 
@@ -61,7 +90,11 @@ pub fn regrade_images(
                     cutoff_timestamp,
                     &project_filter,
                     &target_filter,
+                    &filter_name,
+                    protect_reasons,
                     config,
+                    incremental,
+                    subset,
                 )?;
             }
 
@@ -77,6 +110,8 @@ pub fn regrade_images(
                 cutoff_timestamp,
                 &project_filter,
                 &target_filter,
+                &filter_name,
+                protect_reasons,
             )?;
         }
 
@@ -87,7 +122,11 @@ pub fn regrade_images(
                 cutoff_timestamp,
                 &project_filter,
                 &target_filter,
+                &filter_name,
+                protect_reasons,
                 config,
+                incremental,
+                subset,
             )?;
         }
     }
@@ -101,6 +140,7 @@ pub fn regrade_images(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_reset(
     db: &Database,
     dry_run: bool,
@@ -108,6 +148,8 @@ fn handle_reset(
     cutoff_timestamp: i64,
     project_filter: &Option<String>,
     target_filter: &Option<String>,
+    filter_name: &Option<String>,
+    protect_reasons: &[String],
 ) -> Result<()> {
     println!("  Reset mode: {}", reset_mode);
 
@@ -117,6 +159,8 @@ fn handle_reset(
             cutoff_timestamp,
             project_filter.as_deref(),
             target_filter.as_deref(),
+            filter_name.as_deref(),
+            protect_reasons,
         )?;
         println!("  Would reset {} images to pending status", count);
     } else {
@@ -125,6 +169,8 @@ fn handle_reset(
             cutoff_timestamp,
             project_filter.as_deref(),
             target_filter.as_deref(),
+            filter_name.as_deref(),
+            protect_reasons,
         )?;
         println!("  Reset {} images to pending status", affected);
     }
@@ -132,24 +178,45 @@ fn handle_reset(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn perform_statistical_grading(
     db: &Database,
     dry_run: bool,
     cutoff_timestamp: i64,
     project_filter: &Option<String>,
     target_filter: &Option<String>,
+    filter_name: &Option<String>,
+    protect_reasons: &[String],
     config: grading::StatisticalGradingConfig,
+    incremental: bool,
+    subset: Subset,
 ) -> Result<()> {
     println!("\nPerforming statistical analysis...");
+    if config.enable_gradient_analysis {
+        println!("  Note: background gradient analysis needs the image files and is only available in filter-rejected");
+    }
+    if config.enable_trail_detection {
+        println!("  Note: trail detection needs the image files and is only available in filter-rejected");
+    }
 
-    // Get all images in date range
+    // Get all images in date range. Incremental runs need them too: the stored
+    // baselines only say which images are new, by id, and how many older ones
+    // each group had, which is how changed groups are spotted
     let all_images = db.query_images(
         None,
         project_filter.as_deref(),
         target_filter.as_deref(),
+        filter_name.as_deref(),
         Some(cutoff_timestamp),
     )?;
 
+    // Protected images are left out of the analysis entirely
+    let all_images: Vec<_> = all_images
+        .into_iter()
+        .filter(|(image, _, _)| !is_protected(image.reject_reason.as_deref(), protect_reasons))
+        .collect();
+    let all_images = subset.apply(all_images);
+
     println!("  Analyzing {} images", all_images.len());
 
     // Convert to format expected by grader
@@ -173,16 +240,35 @@ fn perform_statistical_grading(
 
     // Run statistical analysis
     let grader = grading::StatisticalGrader::new(config);
-    match grader.analyze_images(image_stats) {
-        Ok(rejections) => {
+    let now = chrono::Utc::now().timestamp();
+    let analysis = if incremental {
+        let stored = db.get_grading_baselines()?;
+        grader
+            .analyze_images_incremental(image_stats, &stored, now)
+            .map(|analysis| {
+                println!(
+                    "  Incremental: merged new images into {} stored baselines, recomputed {} groups",
+                    analysis.merged_groups, analysis.recomputed_groups
+                );
+                (analysis.rejections, analysis.baselines)
+            })
+    } else {
+        let baselines = grader.compute_baselines(&image_stats, now);
+        grader
+            .analyze_images(image_stats)
+            .map(|rejections| (rejections, baselines))
+    };
+
+    match analysis {
+        Ok((rejections, baselines)) => {
             println!("  Found {} statistical rejections", rejections.len());
 
             if dry_run {
                 // Just show what would be rejected
                 for rejection in &rejections {
                     println!(
-                        "    Would reject image {}: {} - {}",
-                        rejection.image_id, rejection.reason, rejection.details
+                        "    Would reject image {}: {} - {} (score {:.2})",
+                        rejection.image_id, rejection.reason, rejection.details, rejection.score
                     );
                 }
             } else {
@@ -199,8 +285,14 @@ fn perform_statistical_grading(
                     .collect();
 
                 // Apply updates
-                db.batch_update_grading_status(&updates)?;
+                db.batch_update_grading_status(&updates, GradeChangeSource::Regrade)?;
                 println!("  Applied {} rejections", updates.len());
+
+                // Baselines of a subset would mislead later incremental runs
+                if subset == Subset::All {
+                    db.save_grading_baselines(&baselines)?;
+                    println!("  Stored baselines for {} grading groups", baselines.len());
+                }
             }
         }
         Err(e) => println!("  Warning: Statistical analysis failed: {}", e),
@@ -208,3 +300,278 @@ fn perform_statistical_grading(
 
     Ok(())
 }
+
+/// Whether a reject reason matches any protected pattern (case-insensitive substring)
+fn is_protected(reject_reason: Option<&str>, protect_reasons: &[String]) -> bool {
+    let Some(reason) = reject_reason else {
+        return false;
+    };
+    let reason = reason.to_lowercase();
+    protect_reasons
+        .iter()
+        .any(|pattern| reason.contains(&pattern.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_db() -> Connection {
+        let conn = crate::db::fixture_db();
+        conn.execute_batch(
+            "INSERT INTO project VALUES (1, 'profile', 'Project', NULL);
+             INSERT INTO target VALUES (1, 1, 'M31', 1, 10.68, 41.27);",
+        )
+        .unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        let images = [
+            (1, 2, Some("Satellite trail")),
+            (2, 2, Some("[Auto] HFR outlier - too high")),
+            (3, 1, None),
+        ];
+        for (id, status, reason) in images {
+            conn.execute(
+                "INSERT INTO acquiredimage VALUES (?, 1, 1, ?, 'L', ?, '{}', ?, 'profile')",
+                rusqlite::params![id, now, status, reason],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn grade_of(conn: &Connection, id: i32) -> (i32, Option<String>) {
+        conn.query_row(
+            "SELECT gradingStatus, rejectreason FROM acquiredimage WHERE Id = ?",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_is_protected() {
+        let patterns = vec!["satellite".to_string(), "Clouds".to_string()];
+        assert!(is_protected(Some("Satellite trail"), &patterns));
+        assert!(is_protected(Some("thin clouds"), &patterns));
+        assert!(!is_protected(Some("[Auto] HFR outlier"), &patterns));
+        assert!(!is_protected(None, &patterns));
+        assert!(!is_protected(Some("Satellite trail"), &[]));
+    }
+
+    #[test]
+    fn test_protected_reason_survives_reset() {
+        let conn = fixture_db();
+        let protect = vec!["satellite".to_string()];
+
+        regrade_images(
+            &conn,
+            false,
+            None,
+            None,
+            None,
+            1,
+            "all",
+            &protect,
+            None,
+            false,
+            Subset::All,
+        )
+        .unwrap();
+
+        assert_eq!(grade_of(&conn, 1), (2, Some("Satellite trail".to_string())));
+        assert_eq!(grade_of(&conn, 2), (0, None));
+        assert_eq!(grade_of(&conn, 3), (0, None));
+    }
+
+    #[test]
+    fn test_blank_protect_reasons_are_ignored() {
+        let conn = fixture_db();
+        // As parsed from --protect-reasons " satellite , ,"
+        let protect = vec![" satellite ".to_string(), " ".to_string(), String::new()];
+
+        regrade_images(
+            &conn,
+            false,
+            None,
+            None,
+            None,
+            1,
+            "all",
+            &protect,
+            None,
+            false,
+            Subset::All,
+        )
+        .unwrap();
+
+        assert_eq!(grade_of(&conn, 1), (2, Some("Satellite trail".to_string())));
+        assert_eq!(grade_of(&conn, 2), (0, None));
+    }
+
+    #[test]
+    fn test_reset_logs_grade_history() {
+        let conn = fixture_db();
+        let protect = vec!["satellite".to_string()];
+
+        regrade_images(
+            &conn,
+            false,
+            None,
+            None,
+            None,
+            1,
+            "all",
+            &protect,
+            None,
+            false,
+            Subset::All,
+        )
+        .unwrap();
+
+        let db = Database::new(&conn);
+        assert!(db.get_grade_history(1).unwrap().is_empty());
+        let history = db.get_grade_history(2).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].old_status, Some(2));
+        assert_eq!(history[0].new_status, 0);
+        assert_eq!(history[0].source, "regrade");
+        assert_eq!(db.get_grade_history(3).unwrap()[0].old_status, Some(1));
+    }
+
+    #[test]
+    fn test_reset_without_protection_clears_rejection() {
+        let conn = fixture_db();
+
+        regrade_images(
+            &conn,
+            false,
+            None,
+            None,
+            None,
+            1,
+            "all",
+            &[],
+            None,
+            false,
+            Subset::All,
+        )
+        .unwrap();
+
+        assert_eq!(grade_of(&conn, 1), (0, None));
+    }
+
+    #[test]
+    fn test_filter_restricts_regrade() {
+        let conn = fixture_db();
+        let now = chrono::Utc::now().timestamp();
+        for (id, filter) in [(4, "Ha"), (5, "HA"), (6, "OIII")] {
+            conn.execute(
+                "INSERT INTO acquiredimage VALUES (?, 1, 1, ?, ?, 2, '{}', 'Clouds', 'profile')",
+                rusqlite::params![id, now, filter],
+            )
+            .unwrap();
+        }
+
+        let db = Database::new(&conn);
+        let ha: Vec<i32> = db
+            .query_images(None, None, Some("M31"), Some("ha"), None)
+            .unwrap()
+            .iter()
+            .map(|(image, _, _)| image.id)
+            .collect();
+        assert_eq!(ha.len(), 2);
+        assert!(ha.contains(&4) && ha.contains(&5));
+
+        regrade_images(
+            &conn,
+            false,
+            Some("M31".to_string()),
+            None,
+            Some("ha".to_string()),
+            1,
+            "all",
+            &[],
+            None,
+            false,
+            Subset::All,
+        )
+        .unwrap();
+
+        assert_eq!(grade_of(&conn, 4), (0, None));
+        assert_eq!(grade_of(&conn, 5), (0, None));
+        assert_eq!(grade_of(&conn, 6), (2, Some("Clouds".to_string())));
+        assert_eq!(grade_of(&conn, 1), (2, Some("Satellite trail".to_string())));
+    }
+
+    #[test]
+    fn test_incremental_regrade_updates_stored_baseline() {
+        let conn = fixture_db();
+        let metadata = r#"{"FileName": "M31_L.fits", "FilterName": "L", "HFR": 2.1,
+                           "DetectedStars": 300, "ExposureStartTime": "2023-08-27T10:00:00Z",
+                           "ExposureTime": 300.0, "Gain": 100}"#;
+        conn.execute("UPDATE acquiredimage SET metadata = ?", [metadata])
+            .unwrap();
+        let config = grading::StatisticalGradingConfig::default();
+        let baseline = |conn: &Connection| -> (i32, i32) {
+            conn.query_row(
+                "SELECT sampleCount, lastImageId FROM grading_baselines",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+
+        regrade_images(
+            &conn,
+            false,
+            None,
+            None,
+            None,
+            1,
+            "none",
+            &[],
+            Some(config.clone()),
+            false,
+            Subset::All,
+        )
+        .unwrap();
+        assert_eq!(baseline(&conn), (3, 3));
+        let group: (i64, i32) = conn
+            .query_row(
+                "SELECT exposureMs, gain FROM grading_baselines",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(group, (300_000, 100));
+
+        conn.execute(
+            "INSERT INTO acquiredimage VALUES (4, 1, 1, ?, 'L', 0, ?, NULL, 'profile')",
+            rusqlite::params![chrono::Utc::now().timestamp(), metadata],
+        )
+        .unwrap();
+        regrade_images(
+            &conn,
+            false,
+            None,
+            None,
+            None,
+            1,
+            "none",
+            &[],
+            Some(config),
+            true,
+            Subset::All,
+        )
+        .unwrap();
+
+        assert_eq!(baseline(&conn), (4, 4));
+        let rows: i32 = conn
+            .query_row("SELECT COUNT(*) FROM grading_baselines", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+}
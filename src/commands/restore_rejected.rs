@@ -0,0 +1,278 @@
+use crate::commands::filter_rejected::{filename_variants, get_possible_paths, get_reject_path};
+use crate::db::Database;
+use crate::models::{AcquiredImage, GradeChangeSource, GradingStatus};
+use crate::utils::{extract_filename, find_files, TraversalOptions};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory name filter-rejected moves files into
+const REJECT_DIR: &str = "LIGHT_REJECT";
+
+/// Move files from LIGHT_REJECT folders back to where filter-rejected found them
+pub fn restore_rejected_files(
+    conn: &Connection,
+    base_dir: &str,
+    dry_run: bool,
+    project_filter: Option<String>,
+    target_filter: Option<String>,
+    reset_status: bool,
+    verbose: bool,
+) -> Result<()> {
+    let db = Database::new(conn);
+
+    // Statistically rejected files may not be marked rejected in the database,
+    // so match against every image
+    let images = db.query_images(
+        None,
+        project_filter.as_deref(),
+        target_filter.as_deref(),
+        None,
+        None,
+    )?;
+
+    // Index images by each name their file may have been stored under
+    let mut by_filename: HashMap<String, (&AcquiredImage, &str)> = HashMap::new();
+    for (image, _project_name, target_name) in &images {
+        if let Some(filename) = extract_filename(&image.metadata) {
+            for variant in filename_variants(&filename) {
+                by_filename
+                    .entry(variant)
+                    .or_insert((image, target_name.as_str()));
+            }
+        }
+    }
+
+    // Every file directly inside a LIGHT_REJECT directory anywhere under base_dir
+    let mut rejected_files =
+        find_files(Path::new(base_dir), &TraversalOptions::default(), |path| {
+            path.parent()
+                .and_then(|parent| parent.file_name())
+                .is_some_and(|name| name == REJECT_DIR)
+        })?;
+    rejected_files.sort();
+
+    println!(
+        "{}Restoring {} file(s) from {} folders...",
+        if dry_run { "[DRY RUN] " } else { "" },
+        rejected_files.len(),
+        REJECT_DIR
+    );
+    println!();
+
+    let mut restored_count = 0;
+    let mut skipped_count = 0;
+    let mut unmatched_count = 0;
+    let mut error_count = 0;
+
+    for rejected_path in rejected_files {
+        let Some(file_name) = rejected_path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+
+        let Some(&(image, target_name)) = by_filename.get(file_name) else {
+            if verbose {
+                println!("  No database entry for {}", rejected_path.display());
+            }
+            unmatched_count += 1;
+            continue;
+        };
+
+        let original_path = original_path(base_dir, image, target_name, &rejected_path);
+
+        if original_path.exists() {
+            println!(
+                "  {:6} SKIPPED: {} already exists",
+                image.id,
+                original_path.display()
+            );
+            skipped_count += 1;
+            continue;
+        }
+
+        println!(
+            "  {:6} {} -> {}",
+            image.id,
+            rejected_path.display(),
+            original_path.display()
+        );
+
+        if !dry_run {
+            if let Err(e) = move_back(&rejected_path, &original_path) {
+                println!("  ERROR: {}", e);
+                error_count += 1;
+                continue;
+            }
+
+            if reset_status {
+                db.update_grading_status(
+                    image.id,
+                    GradingStatus::Pending,
+                    None,
+                    GradeChangeSource::Manual,
+                )?;
+            }
+        }
+
+        restored_count += 1;
+    }
+
+    println!("\nSummary:");
+    println!("  Files restored: {}", restored_count);
+    println!("  Skipped (original path occupied): {}", skipped_count);
+    println!("  Not matched to an image: {}", unmatched_count);
+    if error_count > 0 {
+        println!("  Errors: {}", error_count);
+    }
+    if reset_status && restored_count > 0 {
+        println!(
+            "  Grading status {}reset to pending",
+            if dry_run { "would be " } else { "" }
+        );
+    }
+
+    if dry_run {
+        println!("\nThis was a dry run. Use without --dry-run to actually move files.");
+    }
+
+    Ok(())
+}
+
+fn move_back(rejected_path: &Path, original_path: &Path) -> Result<()> {
+    if let Some(parent) = original_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(rejected_path, original_path)?;
+    Ok(())
+}
+
+/// Where filter-rejected found the file before moving it to `rejected_path`.
+///
+/// The candidate layouts from `get_possible_paths` are checked for the one that
+/// maps onto `rejected_path`; otherwise the LIGHT -> LIGHT_REJECT rename is undone.
+fn original_path(
+    base_dir: &str,
+    image: &AcquiredImage,
+    target_name: &str,
+    rejected_path: &Path,
+) -> PathBuf {
+    let date_str = image
+        .acquired_date
+        .and_then(|d| chrono::DateTime::from_timestamp(d, 0))
+        .map(|dt| dt.format("%Y-%m-%d").to_string());
+
+    if let (Some(date_str), Some(file_name)) =
+        (date_str, rejected_path.file_name().and_then(|n| n.to_str()))
+    {
+        let candidate = get_possible_paths(base_dir, &date_str, target_name, file_name)
+            .into_iter()
+            .find(|candidate| {
+                get_reject_path(candidate).is_ok_and(|reject| reject == rejected_path)
+            });
+        if let Some(candidate) = candidate {
+            return candidate;
+        }
+    }
+
+    let path_str = rejected_path.to_string_lossy();
+    PathBuf::from(
+        path_str
+            .replace("/LIGHT_REJECT/", "/LIGHT/")
+            .replace("\\LIGHT_REJECT\\", "\\LIGHT\\"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_db() -> Connection {
+        let conn = crate::db::fixture_db();
+        conn.execute_batch(
+            "INSERT INTO project VALUES (1, 'profile', 'Galaxies', NULL);
+             INSERT INTO target VALUES (1, 1, 'M31', 1, 10.68, 41.27);
+             -- 2024-01-15 12:00 UTC
+             INSERT INTO acquiredimage VALUES (7, 1, 1, 1705320000, 'L', 2,
+                 '{\"FileName\": \"C:\\\\data\\\\M31_L_0001.fits\"}', 'HFR', 'profile');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_moved_file_is_restored_to_original_path() {
+        let base = std::env::temp_dir().join(format!("psf_guard_restore_{}", std::process::id()));
+        let original = base
+            .join("M31")
+            .join("2024-01-15")
+            .join("LIGHT")
+            .join("M31_L_0001.fits");
+        fs::create_dir_all(original.parent().unwrap()).unwrap();
+        fs::write(&original, b"frame").unwrap();
+
+        // Move it the way filter-rejected does
+        let rejected = get_reject_path(&original).unwrap();
+        fs::create_dir_all(rejected.parent().unwrap()).unwrap();
+        fs::rename(&original, &rejected).unwrap();
+
+        let conn = fixture_db();
+        let base_dir = base.to_str().unwrap();
+
+        restore_rejected_files(&conn, base_dir, true, None, None, true, false).unwrap();
+        assert!(rejected.exists() && !original.exists());
+
+        restore_rejected_files(&conn, base_dir, false, None, None, true, false).unwrap();
+        let restored = original.exists() && !rejected.exists();
+        let contents = fs::read(&original).ok();
+        fs::remove_dir_all(&base).unwrap();
+
+        assert!(restored);
+        assert_eq!(contents.as_deref(), Some(&b"frame"[..]));
+        let status: i32 = conn
+            .query_row(
+                "SELECT gradingStatus FROM acquiredimage WHERE Id = 7",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(status, GradingStatus::Pending as i32);
+    }
+
+    #[test]
+    fn test_failed_move_keeps_going_and_leaves_status() {
+        let base =
+            std::env::temp_dir().join(format!("psf_guard_restore_error_{}", std::process::id()));
+        let night = base.join("M31").join("2024-01-15");
+        let rejected = night.join(REJECT_DIR).join("M31_L_0001.fits");
+        fs::create_dir_all(rejected.parent().unwrap()).unwrap();
+        fs::write(&rejected, b"frame").unwrap();
+        // A plain file where the LIGHT directory should be
+        fs::write(night.join("LIGHT"), b"").unwrap();
+
+        let conn = fixture_db();
+        let result = restore_rejected_files(
+            &conn,
+            base.to_str().unwrap(),
+            false,
+            None,
+            None,
+            true,
+            false,
+        );
+        let still_rejected = rejected.exists();
+        fs::remove_dir_all(&base).unwrap();
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(still_rejected);
+        let status: i32 = conn
+            .query_row(
+                "SELECT gradingStatus FROM acquiredimage WHERE Id = 7",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(status, GradingStatus::Rejected as i32);
+    }
+}
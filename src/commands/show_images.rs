@@ -76,6 +76,28 @@ pub fn show_images(conn: &Connection, ids_str: &str) -> Result<()> {
                 println!("  Offset: {}", offset);
             }
         }
+
+        let history = db.get_grade_history(image.id)?;
+        if !history.is_empty() {
+            println!("\nGrade History:");
+            for change in &history {
+                let when = chrono::DateTime::from_timestamp(change.timestamp, 0)
+                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!(
+                    "  {} [{}] {} -> {}{}",
+                    when,
+                    change.source,
+                    change.old_status.map_or("Unknown", GradingStatus::from_i32),
+                    GradingStatus::from_i32(change.new_status),
+                    change
+                        .reason
+                        .as_ref()
+                        .map(|r| format!(" ({})", r))
+                        .unwrap_or_default()
+                );
+            }
+        }
     }
 
     println!("\n{:-<60}", "");
@@ -0,0 +1,53 @@
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+
+use crate::stacking::{stack_frames, StackMethod};
+use crate::utils::{find_files, is_image_file, same_file, TraversalOptions};
+
+/// Combine calibration frames into a master frame and write it as FITS
+pub fn stack(inputs: &[String], method: &str, kappa: f64, output: &str) -> Result<()> {
+    let mut method: StackMethod = method.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    if let StackMethod::SigmaClip { kappa: k } = &mut method {
+        if kappa <= 0.0 {
+            bail!("Kappa must be greater than 0, got {}", kappa);
+        }
+        *k = kappa;
+    }
+
+    let mut frames = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            // Only the directory itself, so calibration subfolders are not mixed in
+            let options = TraversalOptions {
+                max_depth: Some(0),
+                ..TraversalOptions::default()
+            };
+            let mut found = find_files(path, &options, is_image_file)?;
+            found.sort();
+            frames.extend(found);
+        } else {
+            frames.push(path.to_path_buf());
+        }
+    }
+
+    let output_path = Path::new(output);
+    if frames.iter().any(|frame| same_file(frame, output_path)) {
+        bail!("Output {} is one of the input frames", output);
+    }
+    if frames.is_empty() {
+        bail!("No frames found to stack");
+    }
+
+    println!("Stacking {} frames ({:?})...", frames.len(), method);
+    let paths: Vec<&Path> = frames.iter().map(PathBuf::as_path).collect();
+    let master = stack_frames(&paths, method)?;
+
+    master.write_to_file(output_path)?;
+    println!(
+        "Saved {}x{} master frame to: {}",
+        master.width, master.height, output
+    );
+
+    Ok(())
+}
@@ -1,24 +1,41 @@
 use anyhow::{Context, Result};
 use image::codecs::png::{CompressionType, FilterType, PngEncoder};
-use image::{ColorType, ImageEncoder};
 use image::{ImageBuffer, Luma};
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 
-use crate::image_analysis::FitsImage;
-use crate::mtf_stretch::StretchParameters;
+use crate::image_analysis::{read_header_cards, FitsImage, ACQUISITION_KEYWORDS};
+use crate::mtf_stretch::{stretch_with_parameters, StretchAlgorithm, StretchParameters};
+use crate::utils::same_file;
 
+#[allow(clippy::too_many_arguments)]
 pub fn stretch_to_png(
     fits_path: &str,
     output: Option<String>,
     midtone_factor: f64,
     shadow_clipping: f64,
-    logarithmic: bool,
+    algorithm: StretchAlgorithm,
     invert: bool,
+    bit_depth: u8,
+    output_fits: Option<String>,
 ) -> Result<()> {
+    if bit_depth != 8 && bit_depth != 16 {
+        anyhow::bail!("Unsupported bit depth {} (expected 8 or 16)", bit_depth);
+    }
+    let max_level = if bit_depth == 16 {
+        u16::MAX
+    } else {
+        u8::MAX as u16
+    };
+
     // Load FITS file
     let fits_path = Path::new(fits_path);
+    if let Some(output_fits) = &output_fits {
+        if same_file(Path::new(output_fits), fits_path) {
+            anyhow::bail!("FITS output {} would overwrite the input", output_fits);
+        }
+    }
     println!("Loading FITS file: {}", fits_path.display());
 
     let image = FitsImage::from_file(fits_path)
@@ -47,20 +64,28 @@ pub fn stretch_to_png(
 
     println!("Processing image...");
 
-    // Apply stretch or logarithmic scaling
-    let processed_data = if logarithmic {
-        apply_logarithmic_stretch(&image, invert)
-    } else {
-        apply_mtf_stretch(&image, &stats, midtone_factor, shadow_clipping, invert)?
+    // Apply the selected stretch, quantized to 0..=max_level
+    let stretch_params = StretchParameters {
+        factor: midtone_factor,
+        black_clipping: shadow_clipping,
+        algorithm,
     };
+    let stretched = apply_stretch(&image, &stats, &stretch_params);
+    let processed_data = quantize(&stretched, invert, max_level);
 
-    // Create PNG image
-    let img_buffer = ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(
-        image.width as u32,
-        image.height as u32,
-        processed_data,
-    )
-    .context("Failed to create image buffer")?;
+    if let Some(output_fits) = &output_fits {
+        // Always the full 16-bit stretch, whatever the PNG depth
+        let stretched_image = FitsImage {
+            width: image.width,
+            height: image.height,
+            data: quantize(&stretched, invert, u16::MAX),
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+        let headers = read_header_cards(fits_path, ACQUISITION_KEYWORDS)?;
+        stretched_image.write_to_file_with_headers(Path::new(output_fits), &headers)?;
+        println!("Saved stretched FITS to: {}", output_fits);
+    }
 
     // Save PNG with compression
     let file = File::create(&output_path)
@@ -71,79 +96,214 @@ pub fn stretch_to_png(
     let encoder = PngEncoder::new_with_quality(writer, CompressionType::Best, FilterType::Adaptive);
 
     // Write the image data
-    encoder
-        .write_image(
-            &img_buffer,
+    let written = if bit_depth == 16 {
+        ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(
+            image.width as u32,
+            image.height as u32,
+            processed_data,
+        )
+        .context("Failed to create image buffer")?
+        .write_with_encoder(encoder)
+    } else {
+        ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(
             image.width as u32,
             image.height as u32,
-            ColorType::L8.into(),
+            processed_data.into_iter().map(|v| v as u8).collect(),
         )
-        .with_context(|| format!("Failed to write PNG image to {}", output_path.display()))?;
+        .context("Failed to create image buffer")?
+        .write_with_encoder(encoder)
+    };
+    written.with_context(|| format!("Failed to write PNG image to {}", output_path.display()))?;
 
     println!("Saved stretched image to: {}", output_path.display());
     Ok(())
 }
 
-fn apply_mtf_stretch(
+fn apply_stretch(
     image: &FitsImage,
     stats: &crate::image_analysis::ImageStatistics,
-    midtone_factor: f64,
-    shadow_clipping: f64,
-    invert: bool,
-) -> Result<Vec<u8>> {
-    use crate::mtf_stretch::stretch_image;
-
-    // Create stretch parameters
-    let stretch_params = StretchParameters {
-        factor: midtone_factor,
-        black_clipping: shadow_clipping,
-    };
-
-    println!(
-        "Applying MTF stretch (factor: {:.2}, shadow clipping: {:.2})",
-        midtone_factor, shadow_clipping
-    );
-
-    // Apply MTF stretch to get 16-bit data
-    let stretched_16bit = stretch_image(
-        &image.data,
-        stats,
-        stretch_params.factor,
-        stretch_params.black_clipping,
-    );
-
-    // Convert to 8-bit
-    let mut result = Vec::with_capacity(stretched_16bit.len());
-    for &pixel in &stretched_16bit {
-        let eight_bit = (pixel >> 8) as u8;
-        let final_pixel = if invert { 255 - eight_bit } else { eight_bit };
-        result.push(final_pixel);
+    stretch_params: &StretchParameters,
+) -> Vec<u16> {
+    match stretch_params.algorithm {
+        StretchAlgorithm::Mtf => println!(
+            "Applying MTF stretch (factor: {:.2}, shadow clipping: {:.2})",
+            stretch_params.factor, stretch_params.black_clipping
+        ),
+        StretchAlgorithm::Logarithmic => {
+            println!("Applying logarithmic stretch");
+            println!("Value range: {:.0} - {:.0}", stats.min, stats.max);
+        }
+        StretchAlgorithm::Asinh { softening } => println!(
+            "Applying asinh stretch (softening: {:.3}, shadow clipping: {:.2})",
+            softening, stretch_params.black_clipping
+        ),
     }
 
-    Ok(result)
+    // Stretch to 16-bit data
+    stretch_with_parameters(&image.data, stats, stretch_params)
 }
 
-fn apply_logarithmic_stretch(image: &FitsImage, invert: bool) -> Vec<u8> {
-    println!("Applying logarithmic stretch");
+/// Quantize stretched 16-bit data to 0..=max_level, optionally inverted
+fn quantize(stretched: &[u16], invert: bool, max_level: u16) -> Vec<u16> {
+    // Drop to 8-bit unless the full 16-bit range was requested
+    let shift = if max_level == u16::MAX { 0 } else { 8 };
+    stretched
+        .iter()
+        .map(|&pixel| {
+            let level = pixel >> shift;
+            if invert {
+                max_level - level
+            } else {
+                level
+            }
+        })
+        .collect()
+}
 
-    // Find min/max for scaling
-    let min_val = *image.data.iter().min().unwrap() as f64;
-    let max_val = *image.data.iter().max().unwrap() as f64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitrs::{Fits, Hdu};
+    use std::collections::HashSet;
 
-    println!("Value range: {:.0} - {:.0}", min_val, max_val);
+    #[test]
+    fn test_16_bit_output_keeps_more_levels() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_stretch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fits_path = dir.join("gradient.fits");
+        let (width, height) = (512, 512);
+        let gradient: Vec<f32> = (0..width * height).map(|i| i as f32).collect();
+        Fits::create(&fits_path, Hdu::new(&[width, height], gradient)).unwrap();
 
-    let mut result = Vec::with_capacity(image.data.len());
+        let mut levels = Vec::new();
+        for bit_depth in [8, 16] {
+            let png_path = dir.join(format!("gradient_{}.png", bit_depth));
+            stretch_to_png(
+                fits_path.to_str().unwrap(),
+                Some(png_path.to_string_lossy().into_owned()),
+                0.2,
+                -2.8,
+                StretchAlgorithm::Logarithmic,
+                false,
+                bit_depth,
+                None,
+            )
+            .unwrap();
 
-    // Apply logarithmic scaling: log(1 + x)
-    let log_max = (1.0 + max_val - min_val).ln();
+            let decoded = image::open(&png_path).unwrap();
+            let distinct: HashSet<u16> = match (bit_depth, decoded) {
+                (16, image::DynamicImage::ImageLuma16(img)) => img.into_raw().into_iter().collect(),
+                (8, image::DynamicImage::ImageLuma8(img)) => {
+                    img.into_raw().into_iter().map(u16::from).collect()
+                }
+                (_, other) => panic!("unexpected {}-bit decode: {:?}", bit_depth, other.color()),
+            };
+            levels.push(distinct.len());
+        }
+        std::fs::remove_dir_all(&dir).ok();
 
-    for &pixel in &image.data {
-        let normalized = (pixel as f64 - min_val).max(0.0);
-        let log_val = (1.0 + normalized).ln();
-        let scaled = (log_val / log_max * 255.0) as u8;
-        let final_pixel = if invert { 255 - scaled } else { scaled };
-        result.push(final_pixel);
+        assert!(levels[0] <= 256);
+        assert!(levels[1] > levels[0] * 10, "{:?}", levels);
     }
 
-    result
+    #[test]
+    fn test_output_fits_keeps_stretch_and_headers() {
+        use fitrs::HeaderValue;
+
+        let dir =
+            std::env::temp_dir().join(format!("psf_guard_stretch_fits_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fits_path = dir.join("frame.fits");
+
+        // Faint noisy background with a few saturated pixels
+        let (width, height) = (128, 96);
+        let mut state = 12345u32;
+        let mut data: Vec<f32> = (0..width * height)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                1000.0 + ((state >> 16) % 100) as f32
+            })
+            .collect();
+        for i in (0..data.len()).step_by(997) {
+            data[i] = 60000.0;
+        }
+        let mut hdu = Hdu::new(&[width, height], data);
+        hdu.insert("FILTER", "OIII");
+        hdu.insert("EXPOSURE", 180.0);
+        Fits::create(&fits_path, hdu).unwrap();
+
+        let input = FitsImage::from_file(&fits_path).unwrap();
+        let input_stats = input.calculate_basic_statistics();
+        let params = StretchParameters {
+            factor: 0.2,
+            black_clipping: -2.8,
+            algorithm: StretchAlgorithm::Mtf,
+        };
+        let expected = FitsImage {
+            width,
+            height,
+            data: stretch_with_parameters(&input.data, &input_stats, &params),
+            channel_data: Vec::new(),
+            raw_range: None,
+        }
+        .calculate_basic_statistics()
+        .median;
+        let fits_str = fits_path.to_str().unwrap();
+        let png_path = dir.join("frame.png").to_string_lossy().into_owned();
+        let output_fits = dir.join("stretched.fits");
+        stretch_to_png(
+            fits_str,
+            Some(png_path.clone()),
+            0.2,
+            -2.8,
+            StretchAlgorithm::Mtf,
+            false,
+            8,
+            Some(output_fits.to_string_lossy().into_owned()),
+        )
+        .unwrap();
+        let overwrite = stretch_to_png(
+            fits_str,
+            Some(png_path),
+            0.2,
+            -2.8,
+            StretchAlgorithm::Mtf,
+            false,
+            8,
+            Some(fits_str.to_string()),
+        );
+
+        let stretched = FitsImage::from_file(&output_fits).unwrap();
+        let written = Fits::open(&output_fits).unwrap();
+        let hdu = written.get(0).unwrap();
+        let filter = hdu.value("FILTER").cloned();
+        // Stored values, before the reader rescales them to the full range
+        let mut stored = match hdu.read_data() {
+            fitrs::FitsData::IntegersI32(array) => array
+                .data
+                .into_iter()
+                .map(|v| v.unwrap() + 32768)
+                .collect::<Vec<_>>(),
+            _ => panic!("expected 16-bit integer data"),
+        };
+        stored.sort_unstable();
+        let stored_median = stored[stored.len() / 2] as f64;
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!((stretched.width, stretched.height), (width, height));
+        // The faint background is lifted to the midtone target
+        assert!(input_stats.median < 1000.0, "{}", input_stats.median);
+        assert!(
+            (stored_median - expected).abs() <= 1.0,
+            "{} != {}",
+            stored_median,
+            expected
+        );
+        assert!((expected / 65535.0 - 0.2).abs() < 0.01, "{}", expected);
+        assert!(matches!(
+            filter,
+            Some(HeaderValue::CharacterString(filter)) if filter.trim() == "OIII"
+        ));
+        assert!(overwrite.is_err());
+    }
 }
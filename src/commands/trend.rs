@@ -0,0 +1,262 @@
+use crate::commands::import_metadata::parse_exposure_time;
+use crate::db::Database;
+use crate::grading::{parse_image_metadata, ImageStatistics};
+use crate::utils::escape_csv;
+use anyhow::{bail, Result};
+use rusqlite::Connection;
+use std::io::Write;
+
+/// Sparkline levels from lowest to highest value
+const SPARK_LEVELS: &[char] = &['_', '.', ',', '-', '~', '=', '+', '*', '#'];
+
+/// Longer series are averaged down to this many columns
+const SPARK_WIDTH: usize = 60;
+
+pub const TREND_CSV_HEADER: &str = "ExposureStartTime,ImageId,Target,Filter,HFR,Stars";
+
+/// One image in the time series
+#[derive(Debug, Clone)]
+pub struct TrendPoint {
+    pub id: i32,
+    pub exposure_time: String,
+    pub target_name: String,
+    pub filter_name: String,
+    pub hfr: f64,
+    pub star_count: Option<i32>,
+}
+
+/// Show how HFR and star count drift over time for the selected images
+pub fn trend(
+    conn: &Connection,
+    project_filter: Option<String>,
+    target_filter: Option<String>,
+    format: &str,
+) -> Result<()> {
+    if !matches!(format, "sparkline" | "csv") {
+        bail!("Unknown format: {} (expected sparkline or csv)", format);
+    }
+
+    let db = Database::new(conn);
+    let images = db.query_images(
+        None,
+        project_filter.as_deref(),
+        target_filter.as_deref(),
+        None,
+        None,
+    )?;
+
+    let stats: Vec<ImageStatistics> = images
+        .iter()
+        .filter_map(|(image, _project_name, target_name)| {
+            parse_image_metadata(
+                image.id,
+                image.target_id,
+                target_name,
+                &image.metadata,
+                &image.filter_name,
+                image.grading_status,
+            )
+            .ok()
+        })
+        .collect();
+
+    let total = images.len();
+    let points = trend_points(stats);
+
+    match format {
+        "csv" => write_trend_csv(&points, &mut std::io::stdout().lock())?,
+        _ => print_sparklines(&points, total),
+    }
+
+    Ok(())
+}
+
+/// Sort images chronologically, dropping those without an HFR
+pub fn trend_points(stats: Vec<ImageStatistics>) -> Vec<TrendPoint> {
+    let mut points: Vec<TrendPoint> = stats
+        .into_iter()
+        .filter_map(|s| {
+            Some(TrendPoint {
+                id: s.id,
+                hfr: s.hfr.filter(|hfr| hfr.is_finite() && *hfr > 0.0)?,
+                exposure_time: s.exposure_time,
+                target_name: s.target_name,
+                filter_name: s.filter_name,
+                star_count: s.star_count,
+            })
+        })
+        .collect();
+
+    // Compare instants, not strings, since timestamps may carry different UTC
+    // offsets. Times that do not parse go last
+    points.sort_by_cached_key(|p| {
+        let time = parse_exposure_time(&p.exposure_time);
+        (time.is_none(), time, p.id)
+    });
+    points
+}
+
+pub fn write_trend_csv(points: &[TrendPoint], out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "{}", TREND_CSV_HEADER)?;
+    for point in points {
+        writeln!(
+            out,
+            "{},{},{},{},{:.3},{}",
+            point.exposure_time,
+            point.id,
+            escape_csv(&point.target_name),
+            escape_csv(&point.filter_name),
+            point.hfr,
+            point
+                .star_count
+                .map(|count| count.to_string())
+                .unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+fn print_sparklines(points: &[TrendPoint], total: usize) {
+    println!(
+        "Trend: {} images ({} skipped without HFR)",
+        points.len(),
+        total - points.len()
+    );
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return;
+    };
+    println!("{} -> {}", first.exposure_time, last.exposure_time);
+
+    // HFR and star count depend heavily on the filter, so each gets its own series
+    let mut filters: Vec<&str> = points.iter().map(|p| p.filter_name.as_str()).collect();
+    filters.sort();
+    filters.dedup();
+
+    for filter in filters {
+        let series: Vec<&TrendPoint> = points.iter().filter(|p| p.filter_name == filter).collect();
+        let hfrs: Vec<f64> = series.iter().map(|p| p.hfr).collect();
+        let stars: Vec<f64> = series
+            .iter()
+            .filter_map(|p| p.star_count.map(f64::from))
+            .collect();
+
+        println!();
+        println!("{} ({} images)", filter, series.len());
+        print_series("HFR", &hfrs, 2);
+        print_series("Stars", &stars, 0);
+    }
+}
+
+fn print_series(label: &str, values: &[f64], precision: usize) {
+    if values.is_empty() {
+        return;
+    }
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    println!(
+        "  {:<6} {:>8.prec$} - {:<8.prec$} [{}]",
+        label,
+        min,
+        max,
+        sparkline(values, SPARK_WIDTH),
+        prec = precision
+    );
+}
+
+/// Render values as one character per column, averaging into at most `width` buckets
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+
+    let buckets = values.len().min(width);
+    let averaged: Vec<f64> = (0..buckets)
+        .map(|b| {
+            let start = b * values.len() / buckets;
+            let end = (b + 1) * values.len() / buckets;
+            values[start..end].iter().sum::<f64>() / (end - start) as f64
+        })
+        .collect();
+
+    let min = averaged.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = averaged.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let top = SPARK_LEVELS.len() - 1;
+
+    averaged
+        .iter()
+        .map(|&v| {
+            let level = if max > min {
+                ((v - min) / (max - min) * top as f64).round() as usize
+            } else {
+                top / 2
+            };
+            SPARK_LEVELS[level.min(top)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(id: i32, time: &str, hfr: Option<f64>) -> ImageStatistics {
+        let metadata = serde_json::json!({
+            "FileName": format!("image_{}.fits", id),
+            "FilterName": "Ha",
+            "HFR": hfr,
+            "DetectedStars": 100 + id,
+            "ExposureStartTime": time,
+        });
+        parse_image_metadata(id, 1, "M31", &metadata.to_string(), "Ha", 0).unwrap()
+    }
+
+    #[test]
+    fn test_csv_rows_are_chronological() {
+        let points = trend_points(vec![
+            stats(1, "2023-08-27T23:30:00Z", Some(2.4)),
+            stats(2, "2023-08-27T21:00:00Z", Some(2.1)),
+            stats(3, "2023-08-28T01:15:00Z", None),
+            stats(4, "2023-08-28T00:45:00Z", Some(3.0)),
+            stats(5, "2023-08-27T22:10:00Z", Some(2.2)),
+        ]);
+
+        let mut out = Vec::new();
+        write_trend_csv(&points, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], TREND_CSV_HEADER);
+        // Image 3 has no HFR and is skipped
+        assert_eq!(lines.len(), 5);
+        let times: Vec<&str> = lines[1..]
+            .iter()
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        let mut sorted = times.clone();
+        sorted.sort();
+        assert_eq!(times, sorted);
+        assert_eq!(lines[1], "2023-08-27T21:00:00Z,2,M31,Ha,2.100,102");
+    }
+
+    #[test]
+    fn test_points_sorted_by_instant_across_offsets() {
+        let points = trend_points(vec![
+            // 04:30 UTC on the 28th
+            stats(1, "2023-08-27T23:30:00-05:00", Some(2.4)),
+            stats(2, "2023-08-28T01:00:00Z", Some(2.1)),
+            stats(3, "not a time", Some(2.2)),
+            stats(4, "2023-08-28T02:00:00.5", Some(3.0)),
+        ]);
+
+        let ids: Vec<i32> = points.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn test_sparkline_levels() {
+        assert_eq!(sparkline(&[1.0, 2.0, 3.0], 10), "_~#");
+        assert_eq!(sparkline(&[5.0, 5.0], 10), "~~");
+        // Averaged down to the requested width
+        assert_eq!(sparkline(&[0.0, 0.0, 1.0, 1.0], 2), "_#");
+    }
+}
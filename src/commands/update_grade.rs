@@ -1,5 +1,5 @@
 use crate::db::Database;
-use crate::models::GradingStatus;
+use crate::models::{GradeChangeSource, GradingStatus};
 use anyhow::Result;
 use rusqlite::Connection;
 
@@ -32,7 +32,12 @@ pub fn update_grade(
     }
 
     // Update the grading status
-    db.update_grading_status(image_id, status, reason.as_deref())?;
+    db.update_grading_status(
+        image_id,
+        status,
+        reason.as_deref(),
+        GradeChangeSource::Manual,
+    )?;
 
     println!(
         "Successfully updated image {} to status: {}",
@@ -44,3 +49,31 @@ pub fn update_grade(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_successive_changes_recorded_in_order() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE acquiredimage (Id INTEGER PRIMARY KEY, gradingStatus INTEGER,
+                                         rejectreason TEXT);
+             INSERT INTO acquiredimage VALUES (1, 0, NULL);",
+        )
+        .unwrap();
+
+        update_grade(&conn, 1, "rejected", Some("Clouds".to_string())).unwrap();
+        update_grade(&conn, 1, "accepted", None).unwrap();
+
+        let history = Database::new(&conn).get_grade_history(1).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].old_status, Some(GradingStatus::Pending as i32));
+        assert_eq!(history[0].new_status, GradingStatus::Rejected as i32);
+        assert_eq!(history[0].reason.as_deref(), Some("Clouds"));
+        assert_eq!(history[1].old_status, Some(GradingStatus::Rejected as i32));
+        assert_eq!(history[1].new_status, GradingStatus::Accepted as i32);
+        assert!(history.iter().all(|c| c.source == "manual"));
+    }
+}
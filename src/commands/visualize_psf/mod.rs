@@ -1,9 +1,11 @@
 use anyhow::Result;
 
 mod star_selection;
-mod text_render;
+pub(crate) mod text_render;
+mod visualize_field;
 mod visualize_psf_multi;
 
+pub use self::visualize_field::visualize_field;
 pub use self::visualize_psf_multi::visualize_psf_multi;
 
 /// Wrapper for backwards compatibility
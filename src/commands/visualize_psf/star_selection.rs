@@ -11,6 +11,8 @@ pub enum SelectionStrategy {
     QualityRange { per_tier: usize },
     /// Stars from corners and edges (9 positions: 4 corners + 4 edges + center)
     Corners,
+    /// Best-fit star from each cell of a grid laid over the frame
+    Spread { n: usize },
     /// Custom selection based on criteria
     Custom {
         min_hfr: Option<f64>,
@@ -39,6 +41,7 @@ pub fn select_stars(
         }
         SelectionStrategy::QualityRange { per_tier } => select_quality_range(stars, *per_tier),
         SelectionStrategy::Corners => select_corners(stars, image_width, image_height),
+        SelectionStrategy::Spread { n } => select_spread(stars, *n, image_width, image_height),
         SelectionStrategy::Custom {
             min_hfr,
             max_hfr,
@@ -267,6 +270,56 @@ fn select_corners(
     selected
 }
 
+/// Fit quality used to pick between stars, higher is better
+fn fit_score(star: &HocusFocusStar) -> f64 {
+    star.psf_model.as_ref().map(|m| m.r_squared).unwrap_or(0.0)
+}
+
+fn select_spread(
+    stars: Vec<HocusFocusStar>,
+    n: usize,
+    image_width: usize,
+    image_height: usize,
+) -> Vec<HocusFocusStar> {
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Smallest near-square grid with at least n cells
+    let cols = (n as f64).sqrt().ceil() as usize;
+    let rows = n.div_ceil(cols);
+    let cell_width = image_width.max(1) as f64 / cols as f64;
+    let cell_height = image_height.max(1) as f64 / rows as f64;
+
+    let mut cells: Vec<Option<HocusFocusStar>> = vec![None; cols * rows];
+    for star in stars {
+        let col = ((star.position.0 / cell_width) as usize).min(cols - 1);
+        let row = ((star.position.1 / cell_height) as usize).min(rows - 1);
+        let cell = &mut cells[row * cols + col];
+        if cell
+            .as_ref()
+            .is_none_or(|best| fit_score(&star) > fit_score(best))
+        {
+            *cell = Some(star);
+        }
+    }
+
+    // Empty cells are skipped; with more filled cells than requested, drop the
+    // worst fits while keeping the cells in row-major order
+    let mut selected: Vec<(usize, HocusFocusStar)> = cells
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, cell)| cell.map(|star| (i, star)))
+        .collect();
+    if selected.len() > n {
+        selected.sort_by(|a, b| fit_score(&b.1).total_cmp(&fit_score(&a.1)));
+        selected.truncate(n);
+        selected.sort_by_key(|(i, _)| *i);
+    }
+
+    selected.into_iter().map(|(_, star)| star).collect()
+}
+
 fn select_custom(
     stars: Vec<HocusFocusStar>,
     min_hfr: Option<f64>,
@@ -303,3 +356,75 @@ fn select_custom(
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psf_fitting::{PSFModel, PSFType};
+
+    fn star(x: f64, y: f64, r_squared: f64) -> HocusFocusStar {
+        HocusFocusStar {
+            position: (x, y),
+            hfr: 2.0,
+            fwhm: 4.0,
+            brightness: 5000.0,
+            background: 1000.0,
+            snr: 50.0,
+            flux: 100000.0,
+            pixel_count: 40,
+            psf_model: Some(PSFModel {
+                psf_type: PSFType::Moffat4,
+                amplitude: 4000.0,
+                background: 1000.0,
+                x0: 0.0,
+                y0: 0.0,
+                sigma_x: 2.0,
+                sigma_y: 2.0,
+                theta: 0.0,
+                r_squared,
+                rmse: 10.0,
+                fwhm: 4.0,
+                eccentricity: 0.1,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_spread_samples_across_frame() {
+        // A tight cluster of excellent fits in the top-left, plus a few weaker
+        // stars elsewhere; the top-right quadrant has no stars at all
+        let mut stars: Vec<_> = (0..20)
+            .map(|i| star(100.0 + i as f64 * 5.0, 100.0 + i as f64 * 3.0, 0.99))
+            .collect();
+        stars.push(star(150.0, 900.0, 0.8));
+        stars.push(star(900.0, 900.0, 0.7));
+        stars.push(star(950.0, 950.0, 0.9));
+
+        let top = select_stars(
+            stars.clone(),
+            &SelectionStrategy::TopN {
+                n: 4,
+                metric: SortMetric::R2,
+            },
+            1000,
+            1000,
+        );
+        let spread = select_stars(stars, &SelectionStrategy::Spread { n: 4 }, 1000, 1000);
+
+        let quadrant = |s: &HocusFocusStar| (s.position.0 >= 500.0, s.position.1 >= 500.0);
+        let quadrants = |selected: &[HocusFocusStar]| {
+            let mut q: Vec<_> = selected.iter().map(quadrant).collect();
+            q.sort();
+            q.dedup();
+            q.len()
+        };
+        assert_eq!(quadrants(&top), 1);
+        assert_eq!(quadrants(&spread), 3);
+
+        // One star per filled cell, the best fit in each, in row-major order
+        let positions: Vec<_> = spread.iter().map(|s| s.position).collect();
+        assert_eq!(positions.len(), 3);
+        assert_eq!(positions[1], (150.0, 900.0));
+        assert_eq!(positions[2], (950.0, 950.0));
+    }
+}
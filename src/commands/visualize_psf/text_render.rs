@@ -1,6 +1,6 @@
 /// Simple text rendering for PSF visualization
 /// Uses a basic bitmap font approach
-use image::Rgba;
+use image::{GenericImage, Rgba};
 
 /// Simple 5x7 bitmap font patterns for digits and basic characters
 fn get_char_pattern(c: char) -> Option<[u8; 7]> {
@@ -139,7 +139,14 @@ fn get_char_pattern(c: char) -> Option<[u8; 7]> {
 }
 
 /// Draw a single character at the given position
-pub fn draw_char(img: &mut image::RgbaImage, x: u32, y: u32, c: char, color: Rgba<u8>, scale: u32) {
+pub fn draw_char<I: GenericImage>(
+    img: &mut I,
+    x: u32,
+    y: u32,
+    c: char,
+    color: I::Pixel,
+    scale: u32,
+) {
     if let Some(pattern) = get_char_pattern(c) {
         for (row_idx, &row) in pattern.iter().enumerate() {
             for col in 0..5 {
@@ -0,0 +1,366 @@
+use anyhow::{Context, Result};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ColorType, ImageBuffer, ImageEncoder, Rgba, RgbaImage};
+use imageproc::drawing::draw_hollow_rect_mut;
+use imageproc::rect::Rect;
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::hocus_focus_star_detection::{
+    detect_stars_hocus_focus, HocusFocusParams, HocusFocusStar,
+};
+use crate::image_analysis::FitsImage;
+use crate::psf_fitting::PSFType;
+
+use super::text_render::{draw_text, draw_text_with_bg};
+
+/// Size of one grid cell in the output image
+const CELL_SIZE: usize = 100;
+const MARGIN: usize = 20;
+const TITLE_HEIGHT: usize = 40;
+const LEGEND_HEIGHT: usize = 60;
+
+/// Per-star quantity mapped across the field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldMetric {
+    Hfr,
+    Eccentricity,
+    Fwhm,
+}
+
+impl std::str::FromStr for FieldMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hfr" => Ok(FieldMetric::Hfr),
+            "eccentricity" | "ecc" => Ok(FieldMetric::Eccentricity),
+            "fwhm" => Ok(FieldMetric::Fwhm),
+            _ => Err(format!("Unknown field metric: {}", s)),
+        }
+    }
+}
+
+impl FieldMetric {
+    fn label(&self) -> &'static str {
+        match self {
+            FieldMetric::Hfr => "HFR",
+            FieldMetric::Eccentricity => "ECCENTRICITY",
+            FieldMetric::Fwhm => "FWHM",
+        }
+    }
+
+    /// Eccentricity and FWHM come from the fitted PSF model
+    fn needs_psf(&self) -> bool {
+        *self != FieldMetric::Hfr
+    }
+
+    fn value(&self, star: &HocusFocusStar) -> Option<f64> {
+        match self {
+            FieldMetric::Hfr => Some(star.hfr),
+            FieldMetric::Eccentricity => star.psf_model.as_ref().map(|m| m.eccentricity),
+            FieldMetric::Fwhm => star.psf_model.as_ref().map(|m| m.fwhm),
+        }
+    }
+}
+
+/// Median of the metric for each cell of a `grid` x `grid` split of the frame,
+/// row-major from the top left; None for cells without stars
+fn field_grid(
+    stars: &[HocusFocusStar],
+    width: usize,
+    height: usize,
+    grid: usize,
+    metric: FieldMetric,
+) -> Vec<Option<f64>> {
+    let mut cells: Vec<Vec<f64>> = vec![Vec::new(); grid * grid];
+
+    for star in stars {
+        let Some(value) = metric.value(star) else {
+            continue;
+        };
+        let col = ((star.position.0 / width as f64 * grid as f64) as usize).min(grid - 1);
+        let row = ((star.position.1 / height as f64 * grid as f64) as usize).min(grid - 1);
+        cells[row * grid + col].push(value);
+    }
+
+    cells
+        .into_iter()
+        .map(|mut values| {
+            if values.is_empty() {
+                return None;
+            }
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let mid = values.len() / 2;
+            Some(if values.len() % 2 == 0 {
+                (values[mid - 1] + values[mid]) / 2.0
+            } else {
+                values[mid]
+            })
+        })
+        .collect()
+}
+
+/// Blue (low) through green to red (high)
+fn field_color(value: f64) -> Rgba<u8> {
+    let t = value.clamp(0.0, 1.0);
+    let (r, g, b) = if t < 0.5 {
+        let s = t * 2.0;
+        (0.0, s, 1.0 - s)
+    } else {
+        let s = (t - 0.5) * 2.0;
+        (s, 1.0 - s, 0.0)
+    };
+    Rgba([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255])
+}
+
+/// Render the cell medians as a labelled heatmap
+fn render_field_heatmap(cells: &[Option<f64>], grid: usize, metric: FieldMetric) -> RgbaImage {
+    let map_size = grid * CELL_SIZE;
+    let final_width = map_size + 2 * MARGIN;
+    let final_height = TITLE_HEIGHT + map_size + LEGEND_HEIGHT + 2 * MARGIN;
+
+    let mut img = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(final_width as u32, final_height as u32);
+    for pixel in img.pixels_mut() {
+        *pixel = Rgba([30, 30, 30, 255]); // Dark gray background
+    }
+
+    let values: Vec<f64> = cells.iter().flatten().copied().collect();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+
+    let map_x = MARGIN;
+    let map_y = MARGIN + TITLE_HEIGHT;
+
+    for (idx, cell) in cells.iter().enumerate() {
+        let x0 = map_x + (idx % grid) * CELL_SIZE;
+        let y0 = map_y + (idx / grid) * CELL_SIZE;
+
+        let color = match cell {
+            Some(value) if range > 0.0 => field_color((value - min) / range),
+            Some(_) => field_color(0.5),
+            None => Rgba([60, 60, 60, 255]),
+        };
+        for y in y0..y0 + CELL_SIZE {
+            for x in x0..x0 + CELL_SIZE {
+                img.put_pixel(x as u32, y as u32, color);
+            }
+        }
+
+        draw_hollow_rect_mut(
+            &mut img,
+            Rect::at(x0 as i32, y0 as i32).of_size(CELL_SIZE as u32, CELL_SIZE as u32),
+            Rgba([30, 30, 30, 255]),
+        );
+
+        if let Some(value) = cell {
+            draw_text_with_bg(
+                &mut img,
+                x0 as u32 + 10,
+                y0 as u32 + CELL_SIZE as u32 / 2 - 7,
+                &format!("{:.2}", value),
+                Rgba([255, 255, 255, 255]),
+                Rgba([0, 0, 0, 200]),
+                2,
+            );
+        }
+    }
+
+    draw_hollow_rect_mut(
+        &mut img,
+        Rect::at(map_x as i32 - 1, map_y as i32 - 1)
+            .of_size((map_size + 2) as u32, (map_size + 2) as u32),
+        Rgba([200, 200, 200, 255]),
+    );
+
+    draw_text_with_bg(
+        &mut img,
+        MARGIN as u32,
+        MARGIN as u32,
+        &format!("MEDIAN {} {}X{}", metric.label(), grid, grid),
+        Rgba([255, 255, 255, 255]),
+        Rgba([50, 50, 50, 255]),
+        2,
+    );
+
+    // Color scale from min to max
+    let legend_y = map_y + map_size + 20;
+    for x in 0..map_size {
+        let color = field_color(x as f64 / map_size as f64);
+        for y in legend_y..legend_y + 12 {
+            img.put_pixel((map_x + x) as u32, y as u32, color);
+        }
+    }
+    if !values.is_empty() {
+        let label_y = legend_y as u32 + 18;
+        draw_text(
+            &mut img,
+            map_x as u32,
+            label_y,
+            &format!("{:.2}", min),
+            Rgba([200, 200, 200, 255]),
+            2,
+        );
+        let max_text = format!("{:.2}", max);
+        draw_text(
+            &mut img,
+            (map_x + map_size) as u32 - max_text.len() as u32 * 12,
+            label_y,
+            &max_text,
+            Rgba([200, 200, 200, 255]),
+            2,
+        );
+    }
+
+    img
+}
+
+/// Render a heatmap of per-star HFR, eccentricity or FWHM across the frame
+pub fn visualize_field(
+    fits_path: &str,
+    output: Option<String>,
+    grid: usize,
+    metric: &str,
+    psf_type: &str,
+    verbose: bool,
+) -> Result<()> {
+    let metric: FieldMetric = metric.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+    if grid == 0 {
+        anyhow::bail!("Grid size must be at least 1");
+    }
+
+    if verbose {
+        eprintln!("Loading FITS file: {}", fits_path);
+    }
+    let fits = FitsImage::from_file(std::path::Path::new(fits_path))?;
+
+    let psf_type = if metric.needs_psf() {
+        let psf_type: PSFType = psf_type.parse().unwrap_or(PSFType::Moffat4);
+        if psf_type == PSFType::None {
+            anyhow::bail!(
+                "PSF type cannot be 'none' for the {} metric",
+                metric.label()
+            );
+        }
+        psf_type
+    } else {
+        PSFType::None
+    };
+
+    let params = HocusFocusParams {
+        psf_type,
+        verbose,
+        ..Default::default()
+    };
+    let result = detect_stars_hocus_focus(&fits.data, fits.width, fits.height, &params);
+    if result.stars.is_empty() {
+        anyhow::bail!("No stars detected in image");
+    }
+
+    let cells = field_grid(&result.stars, fits.width, fits.height, grid, metric);
+    let img = render_field_heatmap(&cells, grid, metric);
+
+    let output_path = output.unwrap_or_else(|| {
+        let base = fits_path.trim_end_matches(".fits").trim_end_matches(".fit");
+        format!("{}_field.png", base)
+    });
+
+    let file = File::create(&output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path))?;
+    let writer = BufWriter::new(file);
+    let encoder = PngEncoder::new_with_quality(writer, CompressionType::Best, FilterType::Adaptive);
+    encoder
+        .write_image(&img, img.width(), img.height(), ColorType::Rgba8.into())
+        .with_context(|| format!("Failed to write PNG image to {}", output_path))?;
+
+    println!("Created field heatmap: {}", output_path);
+    println!(
+        "{} stars across {} of {} cells",
+        result.stars.len(),
+        cells.iter().filter(|c| c.is_some()).count(),
+        grid * grid
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitrs::{Fits, Hdu};
+
+    #[test]
+    fn test_visualize_field_png_dimensions() {
+        // Stars spread over the frame, growing wider to the right
+        let (width, height) = (256, 256);
+        let mut data = vec![1000.0f32; width * height];
+        for (i, cx) in [32, 96, 160, 224].into_iter().enumerate() {
+            let sigma = 1.5 + i as f64 * 0.5;
+            for cy in [32, 96, 160, 224] {
+                for y in cy - 12..cy + 12 {
+                    for x in cx - 12..cx + 12 {
+                        let d2 = ((x - cx) * (x - cx) + (y - cy) * (y - cy)) as f64;
+                        data[y * width + x] +=
+                            (20000.0 * (-d2 / (2.0 * sigma * sigma)).exp()) as f32;
+                    }
+                }
+            }
+        }
+
+        let dir = std::env::temp_dir();
+        let fits_path = dir.join(format!("psf_guard_field_{}.fits", std::process::id()));
+        let png_path = dir.join(format!("psf_guard_field_{}.png", std::process::id()));
+        Fits::create(&fits_path, Hdu::new(&[width, height], data)).unwrap();
+
+        let grid = 4;
+        visualize_field(
+            fits_path.to_str().unwrap(),
+            Some(png_path.to_str().unwrap().to_string()),
+            grid,
+            "hfr",
+            "none",
+            false,
+        )
+        .unwrap();
+
+        let png = image::open(&png_path).unwrap();
+        assert_eq!(png.width() as usize, grid * CELL_SIZE + 2 * MARGIN);
+        assert_eq!(
+            png.height() as usize,
+            TITLE_HEIGHT + grid * CELL_SIZE + LEGEND_HEIGHT + 2 * MARGIN
+        );
+        assert!(std::fs::metadata(&png_path).unwrap().len() > 0);
+
+        std::fs::remove_file(&fits_path).ok();
+        std::fs::remove_file(&png_path).ok();
+    }
+
+    #[test]
+    fn test_field_grid_medians() {
+        let star = |x: f64, y: f64, hfr: f64| HocusFocusStar {
+            position: (x, y),
+            hfr,
+            fwhm: 0.0,
+            brightness: 0.0,
+            background: 0.0,
+            snr: 0.0,
+            flux: 0.0,
+            pixel_count: 0,
+            psf_model: None,
+        };
+        let stars = [
+            star(10.0, 10.0, 2.0),
+            star(20.0, 20.0, 3.0),
+            star(30.0, 10.0, 10.0),
+            star(90.0, 90.0, 4.0),
+        ];
+
+        let cells = field_grid(&stars, 100, 100, 2, FieldMetric::Hfr);
+        assert_eq!(cells, vec![Some(3.0), None, None, Some(4.0)]);
+
+        // No PSF fits, so nothing to map
+        let cells = field_grid(&stars, 100, 100, 2, FieldMetric::Eccentricity);
+        assert!(cells.iter().all(|c| c.is_none()));
+    }
+}
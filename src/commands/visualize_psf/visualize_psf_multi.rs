@@ -77,6 +77,7 @@ pub fn visualize_psf_multi(
     // Detect stars using HocusFocus
     let params = HocusFocusParams {
         psf_type: psf_type_enum,
+        verbose,
         ..Default::default()
     };
 
@@ -121,6 +122,7 @@ pub fn visualize_psf_multi(
             per_tier: num_stars.div_ceil(4),
         },
         "corners" => SelectionStrategy::Corners,
+        "spread" => SelectionStrategy::Spread { n: num_stars },
         _ => SelectionStrategy::TopN {
             n: num_stars,
             metric: sort_metric,
@@ -1,6 +1,11 @@
-use crate::models::{AcquiredImage, GradingStatus, Project, Target};
+use crate::grading::{GradingBaseline, MetricBaseline};
+use crate::models::{
+    AcquiredImage, GradeChange, GradeChangeSource, GradingStatus, Project, Target,
+};
+use crate::utils::extract_filename;
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
 
 /// Database access layer for PSF Guard
 pub struct Database<'a> {
@@ -81,6 +86,7 @@ impl<'a> Database<'a> {
         status_filter: Option<GradingStatus>,
         project_filter: Option<&str>,
         target_filter: Option<&str>,
+        filter_name: Option<&str>,
         date_cutoff: Option<i64>,
     ) -> Result<Vec<(AcquiredImage, String, String)>> {
         let mut query = String::from(
@@ -110,6 +116,11 @@ impl<'a> Database<'a> {
             params.push(Box::new(format!("%{}%", target)));
         }
 
+        if let Some(filter) = filter_name {
+            query.push_str(" AND ai.filtername = ? COLLATE NOCASE");
+            params.push(Box::new(filter.to_string()));
+        }
+
         if let Some(cutoff) = date_cutoff {
             query.push_str(" AND ai.acquireddate >= ?");
             params.push(Box::new(cutoff));
@@ -186,32 +197,51 @@ impl<'a> Database<'a> {
         image_id: i32,
         status: GradingStatus,
         reject_reason: Option<&str>,
+        source: GradeChangeSource,
     ) -> Result<()> {
-        self.conn.execute(
-            "UPDATE acquiredimage 
-             SET gradingStatus = ?, rejectreason = ? 
-             WHERE Id = ?",
-            params![status as i32, reject_reason, image_id],
-        )?;
-        Ok(())
+        self.batch_update_grading_status(
+            &[(image_id, status, reject_reason.map(str::to_string))],
+            source,
+        )
     }
 
     pub fn batch_update_grading_status(
         &self,
         updates: &[(i32, GradingStatus, Option<String>)],
+        source: GradeChangeSource,
     ) -> Result<()> {
-        let tx = self.conn.unchecked_transaction()?;
+        // Join the caller's transaction (e.g. from with_transaction) if there is one
+        let tx = if self.conn.is_autocommit() {
+            Some(self.conn.unchecked_transaction()?)
+        } else {
+            None
+        };
 
         for (id, status, reason) in updates {
-            tx.execute(
+            let old_status: Option<Option<i32>> = self
+                .conn
+                .query_row(
+                    "SELECT gradingStatus FROM acquiredimage WHERE Id = ?",
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(old_status) = old_status else {
+                continue;
+            };
+
+            self.conn.execute(
                 "UPDATE acquiredimage 
                  SET gradingStatus = ?, rejectreason = ? 
                  WHERE Id = ?",
                 params![*status as i32, reason.as_deref(), id],
             )?;
+            self.record_grade_change(*id, old_status, *status, reason.as_deref(), source)?;
         }
 
-        tx.commit()?;
+        if let Some(tx) = tx {
+            tx.commit()?;
+        }
         Ok(())
     }
 
@@ -221,12 +251,10 @@ impl<'a> Database<'a> {
         date_cutoff: i64,
         project_filter: Option<&str>,
         target_filter: Option<&str>,
+        filter_name: Option<&str>,
+        protect_reasons: &[String],
     ) -> Result<usize> {
-        let mut query = String::from(
-            "UPDATE acquiredimage 
-             SET gradingStatus = 0, rejectreason = NULL 
-             WHERE acquireddate >= ?",
-        );
+        let mut query = String::from(" WHERE acquireddate >= ?");
 
         let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(date_cutoff)];
 
@@ -240,14 +268,56 @@ impl<'a> Database<'a> {
             params.push(Box::new(format!("%{}%", target)));
         }
 
+        if let Some(filter) = filter_name {
+            query.push_str(" AND filtername = ? COLLATE NOCASE");
+            params.push(Box::new(filter.to_string()));
+        }
+
         // For automatic mode, only reset non-manual rejections
         if mode == "automatic" {
             query.push_str(" AND (gradingStatus != 2 OR rejectreason NOT LIKE '%Manual%')");
         }
 
+        // Never reset images whose reject reason matches a protected pattern
+        for reason in protect_reasons {
+            query.push_str(" AND (rejectreason IS NULL OR rejectreason NOT LIKE ? ESCAPE '\\')");
+            params.push(Box::new(like_substring(reason)));
+        }
+
         let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        let count = self.conn.execute(&query, param_refs.as_slice())?;
 
+        let tx = if self.conn.is_autocommit() {
+            Some(self.conn.unchecked_transaction()?)
+        } else {
+            None
+        };
+
+        // Log the images that actually change before they lose their old status
+        self.create_grade_history_table_if_missing()?;
+        let source = GradeChangeSource::Regrade.as_str();
+        let now = chrono::Utc::now().timestamp();
+        let mut history_params: Vec<&dyn rusqlite::ToSql> = vec![&source, &now];
+        history_params.extend(param_refs.iter().copied());
+        self.conn.execute(
+            &format!(
+                "INSERT INTO grade_history (imageId, oldStatus, newStatus, reason, source, timestamp)
+                 SELECT Id, gradingStatus, 0, NULL, ?, ? FROM acquiredimage{} AND gradingStatus != 0",
+                query
+            ),
+            history_params.as_slice(),
+        )?;
+
+        let count = self.conn.execute(
+            &format!(
+                "UPDATE acquiredimage SET gradingStatus = 0, rejectreason = NULL{}",
+                query
+            ),
+            param_refs.as_slice(),
+        )?;
+
+        if let Some(tx) = tx {
+            tx.commit()?;
+        }
         Ok(count)
     }
 
@@ -257,6 +327,8 @@ impl<'a> Database<'a> {
         date_cutoff: i64,
         project_filter: Option<&str>,
         target_filter: Option<&str>,
+        filter_name: Option<&str>,
+        protect_reasons: &[String],
     ) -> Result<usize> {
         let mut query = String::from(
             "SELECT COUNT(*) 
@@ -276,10 +348,21 @@ impl<'a> Database<'a> {
             params.push(Box::new(format!("%{}%", target)));
         }
 
+        if let Some(filter) = filter_name {
+            query.push_str(" AND filtername = ? COLLATE NOCASE");
+            params.push(Box::new(filter.to_string()));
+        }
+
         if mode == "automatic" {
             query.push_str(" AND (gradingStatus != 2 OR rejectreason NOT LIKE '%Manual%')");
         }
 
+        // Never reset images whose reject reason matches a protected pattern
+        for reason in protect_reasons {
+            query.push_str(" AND (rejectreason IS NULL OR rejectreason NOT LIKE ? ESCAPE '\\')");
+            params.push(Box::new(like_substring(reason)));
+        }
+
         query.push_str(" AND gradingStatus != 0");
 
         let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
@@ -290,6 +373,319 @@ impl<'a> Database<'a> {
         Ok(count)
     }
 
+    // Import helpers
+    /// Create the project, target and acquiredimage tables if they do not exist yet
+    pub fn create_schema_if_missing(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS project (Id INTEGER PRIMARY KEY, profileId TEXT,
+                                                 name TEXT, description TEXT);
+             CREATE TABLE IF NOT EXISTS target (Id INTEGER PRIMARY KEY, projectid INTEGER,
+                                                name TEXT, active INTEGER, ra REAL, dec REAL);
+             CREATE TABLE IF NOT EXISTS acquiredimage (Id INTEGER PRIMARY KEY, projectId INTEGER,
+                                                       targetId INTEGER, acquireddate INTEGER,
+                                                       filtername TEXT, gradingStatus INTEGER,
+                                                       metadata TEXT, rejectreason TEXT,
+                                                       profileId TEXT);",
+        )?;
+        Ok(())
+    }
+
+    /// Id and profile of the project with this name, creating it if needed
+    pub fn find_or_create_project(&self, name: &str, profile_id: &str) -> Result<(i32, String)> {
+        let existing = self
+            .conn
+            .query_row(
+                "SELECT Id, profileId FROM project WHERE name = ?",
+                [name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if let Some(project) = existing {
+            return Ok(project);
+        }
+
+        self.conn.execute(
+            "INSERT INTO project (profileId, name, description) VALUES (?, ?, ?)",
+            params![profile_id, name, "Imported from N.I.N.A. metadata"],
+        )?;
+        Ok((self.conn.last_insert_rowid() as i32, profile_id.to_string()))
+    }
+
+    /// Id of the target with this name in a project, creating it if needed
+    pub fn find_or_create_target(&self, project_id: i32, name: &str) -> Result<i32> {
+        let existing = self
+            .conn
+            .query_row(
+                "SELECT Id FROM target WHERE projectid = ? AND name = ?",
+                params![project_id, name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+
+        self.conn.execute(
+            "INSERT INTO target (projectid, name, active) VALUES (?, ?, 1)",
+            params![project_id, name],
+        )?;
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    /// Bare filenames of every image already in the database
+    pub fn get_image_filenames(&self) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare("SELECT metadata FROM acquiredimage")?;
+        let filenames = stmt
+            .query_map([], |row| row.get::<_, Option<String>>(0))?
+            .filter_map(|metadata| match metadata {
+                Ok(metadata) => metadata.as_deref().and_then(extract_filename).map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        Ok(filenames)
+    }
+
+    /// Insert a pending image and return its id
+    pub fn insert_acquired_image(
+        &self,
+        project_id: i32,
+        target_id: i32,
+        acquired_date: Option<i64>,
+        filter_name: &str,
+        metadata: &str,
+        profile_id: &str,
+    ) -> Result<i32> {
+        self.conn.execute(
+            "INSERT INTO acquiredimage
+                 (projectId, targetId, acquireddate, filtername, gradingStatus, metadata, profileId)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                project_id,
+                target_id,
+                acquired_date,
+                filter_name,
+                GradingStatus::Pending as i32,
+                metadata,
+                profile_id
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    // Grade history queries
+    /// Create the grade_history table that logs every grading status change
+    pub fn create_grade_history_table_if_missing(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS grade_history (
+                 Id INTEGER PRIMARY KEY,
+                 imageId INTEGER NOT NULL,
+                 oldStatus INTEGER,
+                 newStatus INTEGER NOT NULL,
+                 reason TEXT,
+                 source TEXT NOT NULL,
+                 timestamp INTEGER NOT NULL
+             );",
+        )?;
+        Ok(())
+    }
+
+    pub fn record_grade_change(
+        &self,
+        image_id: i32,
+        old_status: Option<i32>,
+        new_status: GradingStatus,
+        reason: Option<&str>,
+        source: GradeChangeSource,
+    ) -> Result<()> {
+        self.create_grade_history_table_if_missing()?;
+        self.conn.execute(
+            "INSERT INTO grade_history (imageId, oldStatus, newStatus, reason, source, timestamp)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                image_id,
+                old_status,
+                new_status as i32,
+                reason,
+                source.as_str(),
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Grade changes of an image, oldest first, or none if nothing was logged yet
+    pub fn get_grade_history(&self, image_id: i32) -> Result<Vec<GradeChange>> {
+        if self.get_table_columns("grade_history")?.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT imageId, oldStatus, newStatus, reason, source, timestamp
+             FROM grade_history
+             WHERE imageId = ?
+             ORDER BY Id",
+        )?;
+
+        let history = stmt
+            .query_map([image_id], |row| {
+                Ok(GradeChange {
+                    image_id: row.get(0)?,
+                    old_status: row.get(1)?,
+                    new_status: row.get(2)?,
+                    reason: row.get(3)?,
+                    source: row.get(4)?,
+                    timestamp: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(history)
+    }
+
+    // Grading baseline queries
+    /// Create the grading_baselines table used by incremental regrades
+    ///
+    /// One row per grading group. Groups without an exposure length or gain
+    /// store NULL there, so the unique index compares those columns through
+    /// IFNULL: a plain UNIQUE constraint treats every NULL as distinct.
+    pub fn create_baselines_table_if_missing(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS grading_baselines (
+                 targetId INTEGER NOT NULL,
+                 filterName TEXT NOT NULL,
+                 exposureMs INTEGER,
+                 gain INTEGER,
+                 hfrCount INTEGER, hfrMean REAL, hfrMedian REAL, hfrStddev REAL,
+                 starCount INTEGER, starMean REAL, starMedian REAL, starStddev REAL,
+                 sampleCount INTEGER NOT NULL,
+                 lastImageId INTEGER NOT NULL,
+                 updatedAt INTEGER NOT NULL
+             );
+             CREATE UNIQUE INDEX IF NOT EXISTS grading_baselines_group
+                 ON grading_baselines
+                 (targetId, filterName, IFNULL(exposureMs, ''), IFNULL(gain, ''));",
+        )?;
+        Ok(())
+    }
+
+    /// Stored grading baselines, or none if the table does not exist yet
+    pub fn get_grading_baselines(&self) -> Result<Vec<GradingBaseline>> {
+        if self.get_table_columns("grading_baselines")?.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT targetId, filterName, exposureMs, gain,
+                    hfrCount, hfrMean, hfrMedian, hfrStddev,
+                    starCount, starMean, starMedian, starStddev,
+                    sampleCount, lastImageId, updatedAt
+             FROM grading_baselines",
+        )?;
+
+        let baselines = stmt
+            .query_map([], |row| {
+                Ok(GradingBaseline {
+                    target_id: row.get(0)?,
+                    filter_name: row.get(1)?,
+                    exposure_ms: row.get(2)?,
+                    gain: row.get(3)?,
+                    hfr: MetricBaseline {
+                        count: row.get(4)?,
+                        mean: row.get(5)?,
+                        median: row.get(6)?,
+                        stddev: row.get(7)?,
+                    },
+                    star_count: MetricBaseline {
+                        count: row.get(8)?,
+                        mean: row.get(9)?,
+                        median: row.get(10)?,
+                        stddev: row.get(11)?,
+                    },
+                    sample_count: row.get(12)?,
+                    last_image_id: row.get(13)?,
+                    updated_at: row.get(14)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(baselines)
+    }
+
+    /// Insert or replace baselines, keyed by their group
+    pub fn save_grading_baselines(&self, baselines: &[GradingBaseline]) -> Result<()> {
+        self.create_baselines_table_if_missing()?;
+
+        // Join the caller's transaction (e.g. regrade's) so the upserts commit together
+        let tx = if self.conn.is_autocommit() {
+            Some(self.conn.unchecked_transaction()?)
+        } else {
+            None
+        };
+
+        for baseline in baselines {
+            // REPLACE removes the row the group's unique index conflicts with
+            self.conn.execute(
+                "INSERT OR REPLACE INTO grading_baselines
+                     (targetId, filterName, exposureMs, gain,
+                      hfrCount, hfrMean, hfrMedian, hfrStddev,
+                      starCount, starMean, starMedian, starStddev,
+                      sampleCount, lastImageId, updatedAt)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    baseline.target_id,
+                    baseline.filter_name,
+                    baseline.exposure_ms,
+                    baseline.gain,
+                    baseline.hfr.count,
+                    baseline.hfr.mean,
+                    baseline.hfr.median,
+                    baseline.hfr.stddev,
+                    baseline.star_count.count,
+                    baseline.star_count.mean,
+                    baseline.star_count.median,
+                    baseline.star_count.stddev,
+                    baseline.sample_count,
+                    baseline.last_image_id,
+                    baseline.updated_at
+                ],
+            )?;
+        }
+        if let Some(tx) = tx {
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    // Schema queries
+    pub fn get_schema_version(&self) -> Result<i64> {
+        let version = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(version)
+    }
+
+    /// Column names of a table, or None if the table does not exist
+    pub fn get_table_columns(&self, table: &str) -> Result<Option<Vec<String>>> {
+        let exists: i32 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ? COLLATE NOCASE",
+            [table],
+            |row| row.get(0),
+        )?;
+        if exists == 0 {
+            return Ok(None);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM pragma_table_info(?) ORDER BY cid")?;
+        let columns = stmt
+            .query_map([table], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(Some(columns))
+    }
+
     // Transaction helpers
     pub fn with_transaction<T, F>(&self, f: F) -> Result<T>
     where
@@ -301,3 +697,76 @@ impl<'a> Database<'a> {
         Ok(result)
     }
 }
+
+/// LIKE pattern matching `text` anywhere, with `%`, `_` and `\` taken literally
+/// (use with `ESCAPE '\'`), so protect reasons match like `regrade::is_protected`
+fn like_substring(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// In-memory database with the scheduler's tables, for command tests to seed
+#[cfg(test)]
+pub(crate) fn fixture_db() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    Database::new(&conn).create_schema_if_missing().unwrap();
+    conn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_grading_baselines_replaces_group() {
+        let conn = Connection::open_in_memory().unwrap();
+        let db = Database::new(&conn);
+        let baseline = |exposure_ms: Option<i64>, sample_count: usize| GradingBaseline {
+            target_id: 1,
+            filter_name: "L".to_string(),
+            exposure_ms,
+            gain: None,
+            hfr: MetricBaseline::default(),
+            star_count: MetricBaseline::default(),
+            sample_count,
+            last_image_id: sample_count as i32,
+            updated_at: 0,
+        };
+
+        db.save_grading_baselines(&[baseline(None, 3), baseline(Some(300_000), 5)])
+            .unwrap();
+        // A group without exposure or gain is replaced rather than duplicated
+        db.save_grading_baselines(&[baseline(None, 4)]).unwrap();
+
+        let mut counts: Vec<(Option<i64>, usize)> = db
+            .get_grading_baselines()
+            .unwrap()
+            .iter()
+            .map(|b| (b.exposure_ms, b.sample_count))
+            .collect();
+        counts.sort();
+        assert_eq!(counts, [(None, 4), (Some(300_000), 5)]);
+    }
+
+    #[test]
+    fn test_protect_reasons_are_literal() {
+        let conn = fixture_db();
+        conn.execute_batch(
+            "INSERT INTO acquiredimage (Id, acquireddate, gradingStatus, rejectreason)
+                 VALUES (1, 0, 2, 'HFR 100% over'), (2, 0, 2, 'Clouds');",
+        )
+        .unwrap();
+        let db = Database::new(&conn);
+
+        // '%' and '_' are matched literally instead of protecting every rejection
+        for (pattern, expected) in [("%", 1), ("100%", 1), ("_", 2), ("1_0", 2)] {
+            let count = db
+                .count_images_to_reset("all", 0, None, None, None, &[pattern.to_string()])
+                .unwrap();
+            assert_eq!(count, expected, "{}", pattern);
+        }
+    }
+}
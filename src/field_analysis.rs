@@ -0,0 +1,394 @@
+//! Field-dependent focus analysis: HFR across the frame and sensor tilt, and
+//! the ensemble PSF that stands for the whole frame.
+//!
+//! Tilt shows up as a roughly linear HFR gradient from one side of the frame
+//! to the other. The frame is split into a 3x3 grid and the four corner cells
+//! plus the center cell are reduced to median HFRs, then a plane is fitted
+//! through those five points.
+//!
+//! The ensemble PSF is the median of each fitted parameter over the brightest
+//! stars, so a few blended or poorly fitted stars do not move it.
+
+use crate::hocus_focus_star_detection::HocusFocusStar;
+use crate::psf_fitting::{PSFFitter, PSFModel, PSFType};
+use nalgebra::{Matrix3, Vector3};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::f64::consts::{FRAC_PI_2, PI};
+
+/// Fewer stars than this give per-region medians too noisy to fit
+pub const MIN_TILT_STARS: usize = 20;
+
+/// Region names and their cell in the 3x3 grid (column, row)
+const TILT_REGIONS: [(&str, (usize, usize)); 5] = [
+    ("top_left", (0, 0)),
+    ("top_right", (2, 0)),
+    ("center", (1, 1)),
+    ("bottom_left", (0, 2)),
+    ("bottom_right", (2, 2)),
+];
+
+/// Median HFR of one region of the frame
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionHfr {
+    pub name: &'static str,
+    /// Region center in pixels
+    pub x: f64,
+    pub y: f64,
+    pub star_count: usize,
+    pub median_hfr: f64,
+}
+
+/// Plane fit of HFR over the frame
+#[derive(Debug, Clone, Serialize)]
+pub struct TiltAnalysis {
+    pub star_count: usize,
+    pub regions: Vec<RegionHfr>,
+    /// Fitted HFR at the frame center
+    pub center_hfr: f64,
+    /// HFR change from the center to the right edge
+    pub slope_x: f64,
+    /// HFR change from the center to the bottom edge
+    pub slope_y: f64,
+    /// HFR change from the center to the edge along the steepest direction
+    pub gradient_magnitude: f64,
+    /// Direction of increasing HFR in degrees (0 = right, 90 = down)
+    pub gradient_direction: f64,
+    /// Gradient as a percentage of the center HFR
+    pub tilt_severity: f64,
+}
+
+/// Fit a tilt plane to the star HFRs.
+///
+/// Coordinates are normalized to -1..1 across the frame, so the slopes are in
+/// HFR units from the center to the edge. Returns None with fewer than
+/// `MIN_TILT_STARS` stars or fewer than three populated regions.
+pub fn analyze_tilt(stars: &[HocusFocusStar], width: usize, height: usize) -> Option<TiltAnalysis> {
+    if stars.len() < MIN_TILT_STARS || width == 0 || height == 0 {
+        return None;
+    }
+
+    let cell_width = width as f64 / 3.0;
+    let cell_height = height as f64 / 3.0;
+
+    let regions: Vec<RegionHfr> = TILT_REGIONS
+        .iter()
+        .filter_map(|&(name, (col, row))| {
+            let mut hfrs: Vec<f64> = stars
+                .iter()
+                .filter(|s| {
+                    (s.position.0 / cell_width) as usize == col
+                        && (s.position.1 / cell_height) as usize == row
+                })
+                .map(|s| s.hfr)
+                .collect();
+            if hfrs.is_empty() {
+                return None;
+            }
+
+            Some(RegionHfr {
+                name,
+                x: (col as f64 + 0.5) * cell_width,
+                y: (row as f64 + 0.5) * cell_height,
+                star_count: hfrs.len(),
+                median_hfr: median(&mut hfrs),
+            })
+        })
+        .collect();
+
+    if regions.len() < 3 {
+        return None;
+    }
+
+    // Least squares for hfr = c + a * x + b * y via the normal equations
+    let mut ata = Matrix3::<f64>::zeros();
+    let mut atb = Vector3::<f64>::zeros();
+    for region in &regions {
+        let x = region.x / width as f64 * 2.0 - 1.0;
+        let y = region.y / height as f64 * 2.0 - 1.0;
+        let row = Vector3::new(1.0, x, y);
+        ata += row * row.transpose();
+        atb += row * region.median_hfr;
+    }
+    // Three collinear regions (e.g. only one diagonal populated) leave the plane undetermined
+    let solution = ata.lu().solve(&atb)?;
+
+    let (center_hfr, slope_x, slope_y) = (solution[0], solution[1], solution[2]);
+    let gradient_magnitude = slope_x.hypot(slope_y);
+
+    Some(TiltAnalysis {
+        star_count: stars.len(),
+        regions,
+        center_hfr,
+        slope_x,
+        slope_y,
+        gradient_magnitude,
+        gradient_direction: slope_y.atan2(slope_x).to_degrees(),
+        tilt_severity: if center_hfr > 0.0 {
+            gradient_magnitude / center_hfr * 100.0
+        } else {
+            0.0
+        },
+    })
+}
+
+/// Stars fitted for the ensemble PSF by default, brightest first
+pub const DEFAULT_ENSEMBLE_STARS: usize = 50;
+
+/// Fewer successful fits than this are not summarized
+pub const MIN_ENSEMBLE_FITS: usize = 3;
+
+/// One representative PSF for a frame: robust medians of the per-star fits
+#[derive(Debug, Clone, Serialize)]
+pub struct EnsemblePSF {
+    #[serde(skip)]
+    pub psf_type: PSFType,
+    pub fitted_stars: usize,
+    /// Median sigma along each star's major axis
+    pub sigma_x: f64,
+    /// Median sigma along each star's minor axis
+    pub sigma_y: f64,
+    /// Median major-axis angle in radians, in -π/2..π/2
+    pub theta: f64,
+    pub fwhm: f64,
+    pub eccentricity: f64,
+    /// Interquartile range of the per-star FWHM
+    pub fwhm_iqr: f64,
+    /// Interquartile range of the per-star eccentricity
+    pub eccentricity_iqr: f64,
+}
+
+/// Fit `psf_type` to the `max_stars` brightest stars, keeping fits with an R²
+/// of at least `min_r_squared`
+pub fn fit_psf_sample(
+    data: &[u16],
+    width: usize,
+    height: usize,
+    stars: &[HocusFocusStar],
+    psf_type: PSFType,
+    max_stars: usize,
+    min_r_squared: f64,
+) -> Vec<PSFModel> {
+    let mut sample: Vec<&HocusFocusStar> = stars.iter().collect();
+    sample.sort_by(|a, b| b.flux.total_cmp(&a.flux));
+    sample.truncate(max_stars);
+
+    let fitter = PSFFitter::new(psf_type);
+    sample
+        .par_iter()
+        .filter_map(|star| {
+            // HocusFocus does not keep the bounding box; four HFRs span a
+            // Gaussian star out to about 3.5 sigma
+            let size = star.hfr * 4.0;
+            fitter.fit_star(
+                data,
+                width,
+                height,
+                star.position.0,
+                star.position.1,
+                size,
+                size,
+                star.background,
+                star.brightness,
+            )
+        })
+        .filter(|model| model.r_squared >= min_r_squared)
+        .collect()
+}
+
+/// Median parameters of the fitted models, None with fewer than
+/// `MIN_ENSEMBLE_FITS`.
+///
+/// Each model is first turned so `sigma_x` is its major axis, since a fit may
+/// land on either labelling of the same ellipse.
+pub fn ensemble_psf(models: &[PSFModel]) -> Option<EnsemblePSF> {
+    if models.len() < MIN_ENSEMBLE_FITS {
+        return None;
+    }
+
+    let mut major = Vec::with_capacity(models.len());
+    let mut minor = Vec::with_capacity(models.len());
+    let mut angles = Vec::with_capacity(models.len());
+    for model in models {
+        let (a, b, theta) = if model.sigma_x >= model.sigma_y {
+            (model.sigma_x, model.sigma_y, model.theta)
+        } else {
+            (model.sigma_y, model.sigma_x, model.theta + FRAC_PI_2)
+        };
+        major.push(a);
+        minor.push(b);
+        angles.push(theta);
+    }
+
+    // Orientation repeats every π, so take the median within π/2 of the mean
+    // axis (the mean of the doubled angles) rather than across the wrap
+    let (sin, cos) = angles.iter().fold((0.0, 0.0), |(sin, cos), theta| {
+        (sin + (2.0 * theta).sin(), cos + (2.0 * theta).cos())
+    });
+    let mean_axis = sin.atan2(cos) / 2.0;
+    for theta in &mut angles {
+        *theta = mean_axis + fold_angle(*theta - mean_axis);
+    }
+    let mut fwhms: Vec<f64> = models.iter().map(|m| m.fwhm).collect();
+    let mut eccentricities: Vec<f64> = models.iter().map(|m| m.eccentricity).collect();
+
+    Some(EnsemblePSF {
+        psf_type: models[0].psf_type,
+        fitted_stars: models.len(),
+        sigma_x: median(&mut major),
+        sigma_y: median(&mut minor),
+        theta: fold_angle(median(&mut angles)),
+        fwhm: median(&mut fwhms),
+        eccentricity: median(&mut eccentricities),
+        fwhm_iqr: quantile(&fwhms, 0.75) - quantile(&fwhms, 0.25),
+        eccentricity_iqr: quantile(&eccentricities, 0.75) - quantile(&eccentricities, 0.25),
+    })
+}
+
+/// The same orientation in -π/2..π/2
+fn fold_angle(theta: f64) -> f64 {
+    (theta + FRAC_PI_2).rem_euclid(PI) - FRAC_PI_2
+}
+
+/// Linearly interpolated quantile of sorted values
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn star(x: f64, y: f64, hfr: f64) -> HocusFocusStar {
+        HocusFocusStar {
+            position: (x, y),
+            hfr,
+            fwhm: 0.0,
+            brightness: 0.0,
+            background: 0.0,
+            snr: 0.0,
+            flux: 0.0,
+            pixel_count: 0,
+            psf_model: None,
+        }
+    }
+
+    /// Stars on a regular grid with HFR following a known plane
+    fn gradient_field(
+        width: usize,
+        height: usize,
+        center: f64,
+        slope_x: f64,
+        slope_y: f64,
+    ) -> Vec<HocusFocusStar> {
+        let mut stars = Vec::new();
+        for gy in 0..12 {
+            for gx in 0..12 {
+                let x = (gx as f64 + 0.5) * width as f64 / 12.0;
+                let y = (gy as f64 + 0.5) * height as f64 / 12.0;
+                let nx = x / width as f64 * 2.0 - 1.0;
+                let ny = y / height as f64 * 2.0 - 1.0;
+                stars.push(star(x, y, center + slope_x * nx + slope_y * ny));
+            }
+        }
+        stars
+    }
+
+    #[test]
+    fn test_recovers_linear_gradient() {
+        let stars = gradient_field(3000, 2000, 2.5, 0.6, -0.3);
+        let tilt = analyze_tilt(&stars, 3000, 2000).unwrap();
+
+        assert_eq!(tilt.regions.len(), 5);
+        assert!((tilt.center_hfr - 2.5).abs() < 1e-9);
+        assert!((tilt.slope_x - 0.6).abs() < 1e-9);
+        assert!((tilt.slope_y + 0.3).abs() < 1e-9);
+        assert!((tilt.gradient_magnitude - 0.6f64.hypot(0.3)).abs() < 1e-9);
+        // HFR grows to the right and towards the top
+        assert!(tilt.gradient_direction < 0.0 && tilt.gradient_direction > -90.0);
+        assert!((tilt.tilt_severity - 0.6f64.hypot(0.3) / 2.5 * 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_flat_field_has_no_tilt() {
+        let stars = gradient_field(1000, 1000, 3.0, 0.0, 0.0);
+        let tilt = analyze_tilt(&stars, 1000, 1000).unwrap();
+        assert!(tilt.gradient_magnitude < 1e-9);
+        assert!(tilt.tilt_severity < 1e-6);
+    }
+
+    #[test]
+    fn test_too_few_stars() {
+        let stars = gradient_field(1000, 1000, 3.0, 0.5, 0.0);
+        assert!(analyze_tilt(&stars[..MIN_TILT_STARS - 1], 1000, 1000).is_none());
+
+        // Plenty of stars, but all in the center region
+        let centered: Vec<_> = (0..50)
+            .map(|i| star(500.0, 480.0 + i as f64, 2.0))
+            .collect();
+        assert!(analyze_tilt(&centered, 1000, 1000).is_none());
+    }
+
+    #[test]
+    fn test_ensemble_psf_matches_uniform_stars() {
+        use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
+        use crate::psf_fitting::{GaussianPSF, PSFFunction, DEFAULT_MIN_R_SQUARED};
+
+        // Sixteen identical elongated Gaussian stars on a slightly noisy sky
+        let (width, height) = (256usize, 256usize);
+        let (sigma_x, sigma_y, theta) = (2.2, 1.6, 0.4);
+        let mut data: Vec<f64> = (0..width * height)
+            .map(|i| 1000.0 + ((i * 7919) % 23) as f64)
+            .collect();
+        for gy in 0..4 {
+            for gx in 0..4 {
+                let (cx, cy) = (32.0 + 64.0 * gx as f64, 32.0 + 64.0 * gy as f64);
+                let params = [20000.0, 0.0, cx, cy, sigma_x, sigma_y, theta];
+                for y in (cy as usize - 15)..(cy as usize + 15) {
+                    for x in (cx as usize - 15)..(cx as usize + 15) {
+                        data[y * width + x] += GaussianPSF.value(x as f64, y as f64, &params);
+                    }
+                }
+            }
+        }
+        let data: Vec<u16> = data.iter().map(|&v| v as u16).collect();
+
+        let detected =
+            detect_stars_hocus_focus(&data, width, height, &HocusFocusParams::default()).stars;
+        assert_eq!(detected.len(), 16);
+        let models = fit_psf_sample(
+            &data,
+            width,
+            height,
+            &detected,
+            PSFType::Gaussian,
+            DEFAULT_ENSEMBLE_STARS,
+            DEFAULT_MIN_R_SQUARED,
+        );
+        let ensemble = ensemble_psf(&models).unwrap();
+
+        assert!(ensemble.fitted_stars >= 12);
+        assert!((ensemble.sigma_x - sigma_x).abs() < 0.1 * sigma_x);
+        assert!((ensemble.sigma_y - sigma_y).abs() < 0.1 * sigma_y);
+        assert!((ensemble.theta - theta).abs() < 0.05);
+        let eccentricity = (1.0 - (sigma_y / sigma_x).powi(2)).sqrt();
+        assert!((ensemble.eccentricity - eccentricity).abs() < 0.05);
+        assert!(ensemble.eccentricity_iqr < 0.05);
+
+        // Too few fits to summarize
+        assert!(ensemble_psf(&models[..MIN_ENSEMBLE_FITS - 1]).is_none());
+    }
+}
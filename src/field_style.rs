@@ -0,0 +1,123 @@
+//! Key renaming for JSON output so results can match the conventions of external tools.
+//!
+//! Output is built with snake_case keys internally and converted on the way out.
+//! The `nina` style uses PascalCase with N.I.N.A.'s upper-case acronyms (`HFR`, `FWHM`, ...).
+
+use serde_json::{Map, Value};
+
+/// Word segments that N.I.N.A. writes fully upper-case
+const NINA_ACRONYMS: &[&str] = &["hfr", "fwhm", "psf", "mad", "snr", "adu", "id"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldStyle {
+    /// `average_hfr` (default, matches our own outputs)
+    #[default]
+    SnakeCase,
+    /// `averageHfr`
+    CamelCase,
+    /// `AverageHFR`, as used in N.I.N.A. metadata
+    Nina,
+}
+
+impl std::str::FromStr for FieldStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "snake_case" | "snake" => Ok(FieldStyle::SnakeCase),
+            "camelcase" | "camel" => Ok(FieldStyle::CamelCase),
+            "nina" | "pascalcase" => Ok(FieldStyle::Nina),
+            _ => Err(format!("Unknown field style: {}", s)),
+        }
+    }
+}
+
+impl FieldStyle {
+    /// Convert a single snake_case key to this style
+    pub fn rename(&self, key: &str) -> String {
+        match self {
+            FieldStyle::SnakeCase => key.to_string(),
+            FieldStyle::CamelCase => key
+                .split('_')
+                .enumerate()
+                .map(|(i, word)| {
+                    if i == 0 {
+                        word.to_string()
+                    } else {
+                        capitalize(word)
+                    }
+                })
+                .collect(),
+            FieldStyle::Nina => key
+                .split('_')
+                .map(|word| {
+                    if NINA_ACRONYMS.contains(&word) {
+                        word.to_uppercase()
+                    } else {
+                        capitalize(word)
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Recursively rename all object keys in a JSON value
+    pub fn apply(&self, value: Value) -> Value {
+        if *self == FieldStyle::SnakeCase {
+            return value;
+        }
+
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| (self.rename(&k), self.apply(v)))
+                    .collect::<Map<String, Value>>(),
+            ),
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.apply(v)).collect()),
+            other => other,
+        }
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_hfr() {
+        assert_eq!(FieldStyle::SnakeCase.rename("average_hfr"), "average_hfr");
+        assert_eq!(FieldStyle::CamelCase.rename("average_hfr"), "averageHfr");
+        assert_eq!(FieldStyle::Nina.rename("average_hfr"), "AverageHFR");
+        assert_eq!(FieldStyle::Nina.rename("hfr"), "HFR");
+        assert_eq!(FieldStyle::CamelCase.rename("hfr"), "hfr");
+    }
+
+    #[test]
+    fn test_apply_nested() {
+        let value = serde_json::json!({
+            "file": "a.fits",
+            "database": { "hfr": 2.5, "stars": 100 },
+            "detectors": [{ "avg_hfr": 2.1 }],
+        });
+
+        let renamed = FieldStyle::Nina.apply(value);
+        assert_eq!(renamed["File"], "a.fits");
+        assert_eq!(renamed["Database"]["HFR"], 2.5);
+        assert_eq!(renamed["Detectors"][0]["AvgHFR"], 2.1);
+    }
+
+    #[test]
+    fn test_parse_style() {
+        assert_eq!("camelCase".parse(), Ok(FieldStyle::CamelCase));
+        assert_eq!("nina".parse(), Ok(FieldStyle::Nina));
+        assert!("kebab".parse::<FieldStyle>().is_err());
+    }
+}
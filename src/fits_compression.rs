@@ -0,0 +1,1079 @@
+/// Tile-compressed FITS image support (the `fpack` / `.fits.fz` convention)
+///
+/// Compressed images are stored as a BINTABLE extension with `ZIMAGE = T`,
+/// one table row per tile and the compressed bytes in the table heap. fitrs
+/// cannot read the heap, so this module scans the raw file itself.
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const BLOCK_SIZE: u64 = 2880;
+const CARD_SIZE: usize = 80;
+const N_RANDOM: usize = 10000;
+
+/// Quantized value marking a null pixel with SUBTRACTIVE_DITHER_2
+const ZERO_VALUE: i64 = -2147483646;
+
+/// Header cards and data location of one HDU
+struct RawHdu {
+    cards: Vec<(String, String)>,
+    data_start: u64,
+    data_len: u64,
+}
+
+impl RawHdu {
+    fn value(&self, key: &str) -> Option<&str> {
+        self.cards
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn int(&self, key: &str) -> Option<i64> {
+        self.value(key).and_then(|v| v.parse().ok())
+    }
+
+    fn float(&self, key: &str) -> Option<f64> {
+        self.value(key)
+            .and_then(|v| v.replace(['D', 'd'], "E").parse().ok())
+    }
+
+    fn is_compressed_image(&self) -> bool {
+        self.value("XTENSION")
+            .is_some_and(|x| x.trim() == "BINTABLE")
+            && self.value("ZIMAGE") == Some("T")
+    }
+}
+
+/// Whether the file contains a tile-compressed image HDU
+pub fn is_compressed(path: &Path) -> Result<bool> {
+    Ok(compressed_hdu_index(path)?.is_some())
+}
+
+/// Index of the first tile-compressed image HDU, if any
+pub fn compressed_hdu_index(path: &Path) -> Result<Option<usize>> {
+    let hdus = scan_hdus(path)?;
+    Ok(hdus.iter().position(|hdu| hdu.is_compressed_image()))
+}
+
+/// Whether the HDU at `hdu_index` is a tile-compressed image
+pub fn is_compressed_hdu(path: &Path, hdu_index: usize) -> Result<bool> {
+    let hdus = scan_hdus(path)?;
+    Ok(hdus
+        .get(hdu_index)
+        .is_some_and(|hdu| hdu.is_compressed_image()))
+}
+
+/// EXTNAME of every HDU in the file, in order (None when the card is absent)
+pub fn hdu_extnames(path: &Path) -> Result<Vec<Option<String>>> {
+    let hdus = scan_hdus(path)?;
+    Ok(hdus
+        .iter()
+        .map(|hdu| hdu.value("EXTNAME").map(|name| name.trim().to_string()))
+        .collect())
+}
+
+/// Value of the first header card named `key` in any HDU (string quotes removed)
+pub fn find_header_value(path: &Path, key: &str) -> Result<Option<String>> {
+    let hdus = scan_hdus(path)?;
+    Ok(hdus
+        .iter()
+        .find_map(|hdu| hdu.value(key).map(|v| v.trim().to_string())))
+}
+
+/// Decompress the image stored in HDU `hdu_index`.
+/// Returns physical pixel values (BSCALE/BZERO applied) with width and height.
+pub fn read_compressed_image(path: &Path, hdu_index: usize) -> Result<(Vec<f64>, usize, usize)> {
+    let hdus = scan_hdus(path)?;
+    let hdu = hdus
+        .get(hdu_index)
+        .filter(|hdu| hdu.is_compressed_image())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "HDU {} in FITS file {} is not a tile-compressed image",
+                hdu_index,
+                path.display()
+            )
+        })?;
+
+    let mut file = open(path)?;
+    let mut data = vec![0u8; hdu.data_len as usize];
+    file.seek(SeekFrom::Start(hdu.data_start))?;
+    file.read_exact(&mut data)
+        .with_context(|| format!("Truncated compressed data in {}", path.display()))?;
+
+    decompress_hdu(hdu, &data)
+}
+
+/// Pixels of the uncompressed image in HDU `hdu_index` when the file ends before
+/// the data its header declares (e.g. an interrupted download), along with the
+/// declared pixel count. None when the data is complete. fitrs panics reading
+/// such files, so the available bytes are decoded here instead.
+pub fn read_truncated_image(path: &Path, hdu_index: usize) -> Result<Option<(Vec<f64>, usize)>> {
+    let hdus = scan_hdus(path)?;
+    let Some(hdu) = hdus.get(hdu_index) else {
+        return Ok(None);
+    };
+
+    let mut file = open(path)?;
+    let available = file.metadata()?.len().saturating_sub(hdu.data_start);
+    if available >= hdu.data_len {
+        return Ok(None);
+    }
+
+    let bitpix = hdu.int("BITPIX").unwrap_or(8);
+    let element_size = (bitpix.unsigned_abs() / 8).max(1) as usize;
+    let mut raw = vec![0u8; available as usize];
+    file.seek(SeekFrom::Start(hdu.data_start))?;
+    file.read_exact(&mut raw)?;
+
+    let n = raw.len() / element_size;
+    let values = if bitpix < 0 {
+        decode_floats(&raw, n, element_size)?
+    } else {
+        decode_ints(&raw, n, element_size)?
+            .into_iter()
+            .map(|v| v as f64)
+            .collect()
+    };
+    let bscale = hdu.float("BSCALE").unwrap_or(1.0);
+    let bzero = hdu.float("BZERO").unwrap_or(0.0);
+
+    Ok(Some((
+        values.into_iter().map(|v| bzero + bscale * v).collect(),
+        hdu.data_len as usize / element_size,
+    )))
+}
+
+fn open(path: &Path) -> Result<File> {
+    File::open(path).map_err(|e| {
+        let message = format!("Failed to open FITS file {}: {}", path.display(), e);
+        anyhow::Error::new(e).context(message)
+    })
+}
+
+/// Read all HDU headers without loading any data
+fn scan_hdus(path: &Path) -> Result<Vec<RawHdu>> {
+    let mut file = open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut hdus = Vec::new();
+    let mut position = 0u64;
+
+    while position + BLOCK_SIZE <= file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let mut cards = Vec::new();
+        let mut header_blocks = 0u64;
+        let mut found_end = false;
+
+        while !found_end {
+            let mut block = [0u8; BLOCK_SIZE as usize];
+            if file.read_exact(&mut block).is_err() {
+                bail!("Truncated FITS header in {}", path.display());
+            }
+            header_blocks += 1;
+
+            for card in block.chunks(CARD_SIZE) {
+                // Header cards should be ASCII (FITS standard 4.1.2.1). A card whose
+                // keyword is not cannot be matched, so it is skipped; stray bytes in
+                // a value (e.g. OBJECT = 'Mélotte') are decoded lossily
+                if !card[..10].is_ascii() {
+                    continue;
+                }
+                let key = String::from_utf8_lossy(&card[..8]).trim().to_string();
+                if key == "END" {
+                    found_end = true;
+                    break;
+                }
+                if &card[8..10] == b"= " {
+                    cards.push((key, parse_card_value(&String::from_utf8_lossy(&card[10..]))));
+                }
+            }
+        }
+
+        let mut hdu = RawHdu {
+            cards,
+            data_start: position + header_blocks * BLOCK_SIZE,
+            data_len: 0,
+        };
+        hdu.data_len = data_length(&hdu);
+        let padded = hdu.data_len.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        position = hdu.data_start + padded;
+        hdus.push(hdu);
+    }
+
+    Ok(hdus)
+}
+
+/// Extract the value of a header card, dropping quotes and comments
+fn parse_card_value(raw: &str) -> String {
+    let raw = raw.trim_start();
+    if let Some(rest) = raw.strip_prefix('\'') {
+        // Quoted string: '' is an escaped quote
+        let mut value = String::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\'' {
+                if chars.peek() == Some(&'\'') {
+                    value.push('\'');
+                    chars.next();
+                } else {
+                    break;
+                }
+            } else {
+                value.push(c);
+            }
+        }
+        value.trim_end().to_string()
+    } else {
+        raw.split('/').next().unwrap_or("").trim().to_string()
+    }
+}
+
+/// Data size in bytes per FITS standard 4.4.1, including the heap
+fn data_length(hdu: &RawHdu) -> u64 {
+    let naxis = hdu.int("NAXIS").unwrap_or(0);
+    if naxis == 0 {
+        return 0;
+    }
+    let bitpix = hdu.int("BITPIX").unwrap_or(8).unsigned_abs();
+    let elements: u64 = (1..=naxis)
+        .map(|i| hdu.int(&format!("NAXIS{}", i)).unwrap_or(0).max(0) as u64)
+        .product();
+    let pcount = hdu.int("PCOUNT").unwrap_or(0).max(0) as u64;
+    let gcount = hdu.int("GCOUNT").unwrap_or(1).max(1) as u64;
+    bitpix / 8 * gcount * (pcount + elements)
+}
+
+/// A binary table column: byte offset within the row and TFORM type
+struct Column {
+    offset: usize,
+    type_code: char,
+}
+
+/// Parse a TFORM value like "1PB(2048)", "1D" or "E" into (repeat, type, element size)
+fn parse_tform(tform: &str) -> Result<(usize, char, usize)> {
+    let digits: String = tform.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let repeat = if digits.is_empty() {
+        1
+    } else {
+        digits.parse()?
+    };
+    let type_code = tform[digits.len()..]
+        .chars()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Invalid TFORM '{}'", tform))?;
+
+    let size = match type_code {
+        'L' | 'B' | 'A' => 1,
+        'X' => return Ok((repeat, type_code, 0)), // bits, handled below
+        'I' => 2,
+        'J' | 'E' => 4,
+        'K' | 'D' | 'C' | 'P' => 8,
+        'M' | 'Q' => 16,
+        _ => bail!("Unsupported TFORM '{}'", tform),
+    };
+    Ok((repeat, type_code, size))
+}
+
+fn find_columns(hdu: &RawHdu) -> Result<Vec<(String, Column)>> {
+    let tfields = hdu.int("TFIELDS").unwrap_or(0);
+    let mut offset = 0;
+    let mut columns = Vec::new();
+
+    for i in 1..=tfields {
+        let tform = hdu
+            .value(&format!("TFORM{}", i))
+            .ok_or_else(|| anyhow::anyhow!("Missing TFORM{} in compressed HDU", i))?;
+        let (repeat, type_code, size) = parse_tform(tform)?;
+        let width = if type_code == 'X' {
+            repeat.div_ceil(8)
+        } else {
+            repeat * size
+        };
+        let name = hdu
+            .value(&format!("TTYPE{}", i))
+            .unwrap_or("")
+            .to_uppercase();
+        columns.push((name, Column { offset, type_code }));
+        offset += width;
+    }
+
+    Ok(columns)
+}
+
+/// Compressed image parameters from the ZIMAGE header keywords
+struct TileLayout {
+    zbitpix: i64,
+    width: usize,
+    tile_width: usize,
+    tile_height: usize,
+}
+
+fn decompress_hdu(hdu: &RawHdu, data: &[u8]) -> Result<(Vec<f64>, usize, usize)> {
+    let compression = hdu.value("ZCMPTYPE").unwrap_or("").to_uppercase();
+    if compression != "RICE_1" && compression != "GZIP_1" {
+        bail!(
+            "Unsupported tile compression ZCMPTYPE '{}' (supported: RICE_1, GZIP_1)",
+            compression
+        );
+    }
+
+    let znaxis = hdu.int("ZNAXIS").unwrap_or(0);
+    if znaxis < 2 {
+        bail!(
+            "Compressed image has ZNAXIS={}, expected 2D image data",
+            znaxis
+        );
+    }
+    let dim = |key: &str| -> Result<usize> {
+        hdu.int(key)
+            .map(|v| v as usize)
+            .ok_or_else(|| anyhow::anyhow!("Missing {} in compressed HDU", key))
+    };
+    let width = dim("ZNAXIS1")?;
+    let height = dim("ZNAXIS2")?;
+    let layout = TileLayout {
+        zbitpix: hdu
+            .int("ZBITPIX")
+            .ok_or_else(|| anyhow::anyhow!("Missing ZBITPIX in compressed HDU"))?,
+        width,
+        tile_width: hdu.int("ZTILE1").map_or(width, |v| v as usize),
+        tile_height: hdu.int("ZTILE2").map_or(1, |v| v as usize),
+    };
+    if layout.tile_width == 0 || layout.tile_height == 0 {
+        bail!("Invalid compressed tile size");
+    }
+
+    // Compression parameters are stored as ZNAMEn / ZVALn pairs
+    let mut block_size = 32usize;
+    let mut bytepix = 4usize;
+    for i in 1.. {
+        let Some(name) = hdu.value(&format!("ZNAME{}", i)) else {
+            break;
+        };
+        let value = hdu.int(&format!("ZVAL{}", i)).unwrap_or(0) as usize;
+        match name.to_uppercase().as_str() {
+            "BLOCKSIZE" => block_size = value,
+            "BYTEPIX" => bytepix = value,
+            _ => {}
+        }
+    }
+
+    let row_len = dim("NAXIS1")?;
+    let rows = dim("NAXIS2")?;
+    let heap_start = hdu.int("THEAP").map_or(row_len * rows, |v| v as usize);
+    let columns = find_columns(hdu)?;
+    let column = |name: &str| columns.iter().find(|(n, _)| n == name).map(|(_, c)| c);
+
+    let compressed = column("COMPRESSED_DATA")
+        .ok_or_else(|| anyhow::anyhow!("Compressed HDU has no COMPRESSED_DATA column"))?;
+    let gzip_fallback = column("GZIP_COMPRESSED_DATA");
+    let zscale_column = column("ZSCALE");
+    let zzero_column = column("ZZERO");
+    let zblank_column = column("ZBLANK");
+
+    let quantized =
+        layout.zbitpix < 0 && (zscale_column.is_some() || hdu.float("ZSCALE").is_some());
+    let dither = match hdu.value("ZQUANTIZ").map(|q| q.to_uppercase()) {
+        Some(q) if q == "SUBTRACTIVE_DITHER_1" => 1,
+        Some(q) if q == "SUBTRACTIVE_DITHER_2" => 2,
+        _ => 0,
+    };
+    let dither_seed = hdu.int("ZDITHER0").unwrap_or(1);
+    let random_values = if quantized && dither > 0 {
+        dither_random_values()
+    } else {
+        Vec::new()
+    };
+
+    let bscale = hdu.float("BSCALE").unwrap_or(1.0);
+    let bzero = hdu.float("BZERO").unwrap_or(0.0);
+
+    // Only the first image plane is read; with 2D tiles it is the first rows
+    let tiles_x = width.div_ceil(layout.tile_width);
+    let tiles_y = height.div_ceil(layout.tile_height);
+    if rows < tiles_x * tiles_y {
+        bail!(
+            "Compressed HDU has {} rows but {} tiles are expected",
+            rows,
+            tiles_x * tiles_y
+        );
+    }
+
+    let mut image = vec![0.0f64; width * height];
+
+    for tile in 0..tiles_x * tiles_y {
+        let row = data
+            .get(tile * row_len..(tile + 1) * row_len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated compressed table"))?;
+        let tx = tile % tiles_x;
+        let ty = tile / tiles_x;
+        let tw = layout.tile_width.min(width - tx * layout.tile_width);
+        let th = layout.tile_height.min(height - ty * layout.tile_height);
+        let n = tw * th;
+
+        let blank = read_i64(row, zblank_column)?.or(hdu.int("ZBLANK"));
+        let to_values = |ints: Vec<i64>| -> Result<Vec<f64>> {
+            if quantized {
+                let scale = read_f64(row, zscale_column)?
+                    .or(hdu.float("ZSCALE"))
+                    .unwrap_or(1.0);
+                let zero = read_f64(row, zzero_column)?
+                    .or(hdu.float("ZZERO"))
+                    .unwrap_or(0.0);
+                let iseed = (tile as i64 + dither_seed - 1).rem_euclid(N_RANDOM as i64) as usize;
+                Ok(unquantize(
+                    &ints,
+                    scale,
+                    zero,
+                    blank,
+                    dither,
+                    &random_values,
+                    iseed,
+                ))
+            } else {
+                Ok(ints
+                    .into_iter()
+                    .map(|q| if Some(q) == blank { f64::NAN } else { q as f64 })
+                    .collect())
+            }
+        };
+
+        let tile_bytes = heap_slice(row, compressed, data, heap_start)?;
+        let values = if !tile_bytes.is_empty() {
+            if compression == "RICE_1" {
+                to_values(rice_decompress(tile_bytes, n, block_size, bytepix)?)?
+            } else {
+                let raw = gunzip(tile_bytes)?;
+                if quantized {
+                    to_values(decode_ints(&raw, n, 4)?)?
+                } else if layout.zbitpix > 0 {
+                    to_values(decode_ints(&raw, n, layout.zbitpix as usize / 8)?)?
+                } else {
+                    // Losslessly compressed floating point
+                    decode_floats(&raw, n, layout.zbitpix.unsigned_abs() as usize / 8)?
+                }
+            }
+        } else if let Some(gzip_column) = gzip_fallback {
+            // Tiles that could not be quantized are stored losslessly
+            let raw = gunzip(heap_slice(row, gzip_column, data, heap_start)?)?;
+            decode_floats(&raw, n, layout.zbitpix.unsigned_abs() as usize / 8)?
+        } else {
+            bail!("Tile {} has no compressed data", tile);
+        };
+
+        let (scale, offset) = if quantized {
+            (1.0, 0.0)
+        } else {
+            (bscale, bzero)
+        };
+        place_tile(&mut image, &values, &layout, tx, ty, tw, th, scale, offset);
+    }
+
+    Ok((image, width, height))
+}
+
+/// Copy a decoded tile into the full image, applying a linear scaling
+#[allow(clippy::too_many_arguments)]
+fn place_tile(
+    image: &mut [f64],
+    values: &[f64],
+    layout: &TileLayout,
+    tx: usize,
+    ty: usize,
+    tw: usize,
+    th: usize,
+    scale: f64,
+    offset: f64,
+) {
+    for j in 0..th {
+        let y = ty * layout.tile_height + j;
+        for i in 0..tw {
+            let x = tx * layout.tile_width + i;
+            image[y * layout.width + x] = values[j * tw + i] * scale + offset;
+        }
+    }
+}
+
+/// Bytes referenced by a variable-length array descriptor (P or Q column)
+fn heap_slice<'a>(
+    row: &[u8],
+    column: &Column,
+    data: &'a [u8],
+    heap_start: usize,
+) -> Result<&'a [u8]> {
+    let (count, offset) = match column.type_code {
+        'P' => (
+            read_be(&row[column.offset..], 4) as usize,
+            read_be(&row[column.offset + 4..], 4) as usize,
+        ),
+        'Q' => (
+            read_be(&row[column.offset..], 8) as usize,
+            read_be(&row[column.offset + 8..], 8) as usize,
+        ),
+        other => bail!("Expected a variable-length column, found type '{}'", other),
+    };
+    // Compressed tiles are byte arrays (1PB); element counts are in bytes
+    let start = heap_start + offset;
+    data.get(start..start + count)
+        .ok_or_else(|| anyhow::anyhow!("Compressed tile extends past the heap"))
+}
+
+fn read_be(bytes: &[u8], len: usize) -> u64 {
+    bytes[..len]
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn read_f64(row: &[u8], column: Option<&Column>) -> Result<Option<f64>> {
+    let Some(column) = column else {
+        return Ok(None);
+    };
+    let bytes = &row[column.offset..];
+    Ok(Some(match column.type_code {
+        'D' => f64::from_bits(read_be(bytes, 8)),
+        'E' => f32::from_bits(read_be(bytes, 4) as u32) as f64,
+        other => bail!("Unexpected column type '{}' for scale factor", other),
+    }))
+}
+
+fn read_i64(row: &[u8], column: Option<&Column>) -> Result<Option<i64>> {
+    let Some(column) = column else {
+        return Ok(None);
+    };
+    let bytes = &row[column.offset..];
+    Ok(Some(match column.type_code {
+        'I' => read_be(bytes, 2) as u16 as i16 as i64,
+        'J' => read_be(bytes, 4) as u32 as i32 as i64,
+        'K' => read_be(bytes, 8) as i64,
+        other => bail!("Unexpected column type '{}' for ZBLANK", other),
+    }))
+}
+
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .read_to_end(&mut out)
+        .context("Failed to decompress GZIP_1 tile")?;
+    Ok(out)
+}
+
+fn decode_ints(raw: &[u8], n: usize, element_size: usize) -> Result<Vec<i64>> {
+    if raw.len() < n * element_size {
+        bail!("Decompressed tile is shorter than expected");
+    }
+    Ok(raw
+        .chunks_exact(element_size)
+        .take(n)
+        .map(|c| match element_size {
+            1 => c[0] as i64,
+            2 => i16::from_be_bytes([c[0], c[1]]) as i64,
+            4 => i32::from_be_bytes([c[0], c[1], c[2], c[3]]) as i64,
+            _ => read_be(c, 8) as i64,
+        })
+        .collect())
+}
+
+fn decode_floats(raw: &[u8], n: usize, element_size: usize) -> Result<Vec<f64>> {
+    if raw.len() < n * element_size {
+        bail!("Decompressed tile is shorter than expected");
+    }
+    Ok(raw
+        .chunks_exact(element_size)
+        .take(n)
+        .map(|c| match element_size {
+            4 => f32::from_be_bytes([c[0], c[1], c[2], c[3]]) as f64,
+            _ => f64::from_bits(read_be(c, 8)),
+        })
+        .collect())
+}
+
+/// Decode a RICE_1 compressed tile into `n` signed integers of `bytepix` bytes.
+/// Port of the decoder in CFITSIO's ricecomp.c.
+fn rice_decompress(input: &[u8], n: usize, block_size: usize, bytepix: usize) -> Result<Vec<i64>> {
+    let (fsbits, fsmax) = match bytepix {
+        1 => (3, 6),
+        2 => (4, 14),
+        4 => (5, 25),
+        _ => bail!("Unsupported RICE_1 BYTEPIX {}", bytepix),
+    };
+    let bbits = 1i32 << fsbits;
+    let width = bytepix * 8;
+    let mask = if width == 32 {
+        u32::MAX as u64
+    } else {
+        (1u64 << width) - 1
+    };
+    let truncated = || anyhow::anyhow!("RICE_1 tile data is truncated");
+
+    if input.len() < bytepix + 1 {
+        return Err(truncated());
+    }
+    let mut pos = bytepix;
+    let mut next = || -> Result<u64> {
+        let byte = *input.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        Ok(byte as u64)
+    };
+
+    // First pixel is stored verbatim
+    let mut lastpix = read_be(input, bytepix);
+    let mut b = next()?;
+    let mut nbits: i32 = 8;
+    let mut output = Vec::with_capacity(n);
+
+    let mut i = 0;
+    while i < n {
+        let imax = (i + block_size.max(1)).min(n);
+
+        // Read the FS code for this block
+        nbits -= fsbits;
+        while nbits < 0 {
+            b = (b << 8) | next()?;
+            nbits += 8;
+        }
+        let fs = (b >> nbits) as i32 - 1;
+        b &= (1u64 << nbits) - 1;
+
+        if fs < 0 {
+            // Low entropy: all differences are zero
+            for _ in i..imax {
+                output.push(lastpix);
+            }
+        } else if fs == fsmax {
+            // High entropy: differences stored verbatim in bbits bits
+            for _ in i..imax {
+                let mut k = bbits - nbits;
+                let mut diff = b << k;
+                k -= 8;
+                while k >= 0 {
+                    b = next()?;
+                    diff |= b << k;
+                    k -= 8;
+                }
+                if nbits > 0 {
+                    b = next()?;
+                    diff |= b >> (-k);
+                    b &= (1u64 << nbits) - 1;
+                } else {
+                    b = 0;
+                }
+                lastpix = undo_mapping(diff, lastpix, mask);
+                output.push(lastpix);
+            }
+        } else {
+            for _ in i..imax {
+                // Count leading zeros of the unary-coded high bits
+                while b == 0 {
+                    nbits += 8;
+                    b = next()?;
+                }
+                let nzero = nbits - (64 - b.leading_zeros() as i32);
+                nbits -= nzero + 1;
+                b ^= 1u64 << nbits;
+                nbits -= fs;
+                while nbits < 0 {
+                    b = (b << 8) | next()?;
+                    nbits += 8;
+                }
+                let diff = ((nzero as u64) << fs) | (b >> nbits);
+                b &= (1u64 << nbits) - 1;
+                lastpix = undo_mapping(diff, lastpix, mask);
+                output.push(lastpix);
+            }
+        }
+
+        i = imax;
+    }
+
+    let shift = 64 - width;
+    Ok(output
+        .into_iter()
+        .map(|v| ((v << shift) as i64) >> shift)
+        .collect())
+}
+
+/// Reverse the zig-zag mapping of a difference and add it to the last pixel
+fn undo_mapping(diff: u64, lastpix: u64, mask: u64) -> u64 {
+    let diff = if diff & 1 == 0 {
+        diff >> 1
+    } else {
+        !(diff >> 1)
+    };
+    diff.wrapping_add(lastpix) & mask
+}
+
+/// Random sequence used by the subtractive dithering quantization methods
+fn dither_random_values() -> Vec<f64> {
+    let a = 16807.0f64;
+    let m = 2147483647.0f64;
+    let mut seed = 1.0f64;
+    (0..N_RANDOM)
+        .map(|_| {
+            let temp = a * seed;
+            seed = temp - m * (temp / m).trunc();
+            (seed / m) as f32 as f64
+        })
+        .collect()
+}
+
+/// Convert quantized integers back to floating point values
+fn unquantize(
+    values: &[i64],
+    scale: f64,
+    zero: f64,
+    blank: Option<i64>,
+    dither: u8,
+    random_values: &[f64],
+    mut iseed: usize,
+) -> Vec<f64> {
+    if dither == 0 {
+        return values
+            .iter()
+            .map(|&q| {
+                if Some(q) == blank {
+                    f64::NAN
+                } else {
+                    q as f64 * scale + zero
+                }
+            })
+            .collect();
+    }
+
+    let mut nextrand = (random_values[iseed] * 500.0) as usize;
+    values
+        .iter()
+        .map(|&q| {
+            let value = if Some(q) == blank {
+                f64::NAN
+            } else if dither == 2 && q == ZERO_VALUE {
+                0.0
+            } else {
+                (q as f64 - random_values[nextrand] + 0.5) * scale + zero
+            };
+
+            nextrand += 1;
+            if nextrand == N_RANDOM {
+                iseed = (iseed + 1) % N_RANDOM;
+                nextrand = (random_values[iseed] * 500.0) as usize;
+            }
+            value
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Minimal bit writer for the test RICE_1 encoder
+    struct BitWriter {
+        bytes: Vec<u8>,
+        buffer: u64,
+        bits: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> Self {
+            Self {
+                bytes: Vec::new(),
+                buffer: 0,
+                bits: 0,
+            }
+        }
+
+        fn write(&mut self, value: u64, nbits: u32) {
+            for i in (0..nbits).rev() {
+                self.buffer = (self.buffer << 1) | ((value >> i) & 1);
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.bytes.push(self.buffer as u8);
+                    self.buffer = 0;
+                    self.bits = 0;
+                }
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.bits > 0 {
+                self.bytes.push((self.buffer << (8 - self.bits)) as u8);
+            }
+            self.bytes
+        }
+    }
+
+    /// RICE_1 encoder following CFITSIO's fits_rcomp, for 16-bit pixels
+    fn rice_compress_i16(values: &[i16], block_size: usize) -> Vec<u8> {
+        let (fsbits, fsmax, bbits) = (4u32, 14u32, 16u32);
+        let mut out = BitWriter::new();
+        out.write(values[0] as u16 as u64, 16);
+        let mut lastpix = values[0] as i32;
+
+        for block in values.chunks(block_size) {
+            let diffs: Vec<u64> = block
+                .iter()
+                .map(|&v| {
+                    let pdiff = (v as i32 - lastpix) as i16 as i32;
+                    lastpix = v as i32;
+                    (if pdiff < 0 { !(pdiff << 1) } else { pdiff << 1 }) as u16 as u64
+                })
+                .collect();
+            let pixel_sum: f64 = diffs.iter().map(|&d| d as f64).sum();
+            let n = block.len() as f64;
+            let dpsum = ((pixel_sum - (block.len() / 2) as f64 - 1.0) / n).max(0.0);
+            let mut psum = (dpsum as u64) >> 1;
+            let mut fs = 0u32;
+            while psum > 0 {
+                psum >>= 1;
+                fs += 1;
+            }
+
+            if fs >= fsmax {
+                out.write((fsmax + 1) as u64, fsbits);
+                for &d in &diffs {
+                    out.write(d, bbits);
+                }
+            } else if fs == 0 && pixel_sum == 0.0 {
+                out.write(0, fsbits);
+            } else {
+                out.write((fs + 1) as u64, fsbits);
+                for &d in &diffs {
+                    let top = d >> fs;
+                    out.write(0, top as u32);
+                    out.write(1, 1);
+                    out.write(d & ((1 << fs) - 1), fs);
+                }
+            }
+        }
+
+        out.finish()
+    }
+
+    fn card(key: &str, value: &str) -> String {
+        format!("{:<8}= {:<70}", key, value)
+    }
+
+    fn pad_block(bytes: &mut Vec<u8>, fill: u8) {
+        while bytes.len() % BLOCK_SIZE as usize != 0 {
+            bytes.push(fill);
+        }
+    }
+
+    /// Build a .fits.fz file with one compressed tile per image row
+    fn write_compressed_fits(
+        name: &str,
+        width: usize,
+        tiles: &[Vec<u8>],
+        cmptype: &str,
+    ) -> std::path::PathBuf {
+        let mut heap = Vec::new();
+        let mut table = Vec::new();
+        for tile in tiles {
+            table.extend_from_slice(&(tile.len() as i32).to_be_bytes());
+            table.extend_from_slice(&(heap.len() as i32).to_be_bytes());
+            heap.extend_from_slice(tile);
+        }
+        let max_len = tiles.iter().map(|t| t.len()).max().unwrap_or(0);
+
+        let mut header = String::new();
+        for (key, value) in [
+            ("XTENSION", "'BINTABLE'".to_string()),
+            ("BITPIX", "8".to_string()),
+            ("NAXIS", "2".to_string()),
+            ("NAXIS1", "8".to_string()),
+            ("NAXIS2", tiles.len().to_string()),
+            ("PCOUNT", heap.len().to_string()),
+            ("GCOUNT", "1".to_string()),
+            ("TFIELDS", "1".to_string()),
+            ("TTYPE1", "'COMPRESSED_DATA'".to_string()),
+            ("TFORM1", format!("'1PB({})'", max_len)),
+            ("ZIMAGE", "T".to_string()),
+            ("ZBITPIX", "16".to_string()),
+            ("ZNAXIS", "2".to_string()),
+            ("ZNAXIS1", width.to_string()),
+            ("ZNAXIS2", tiles.len().to_string()),
+            ("ZTILE1", width.to_string()),
+            ("ZTILE2", "1".to_string()),
+            ("ZCMPTYPE", format!("'{}'", cmptype)),
+            ("ZNAME1", "'BLOCKSIZE'".to_string()),
+            ("ZVAL1", "32".to_string()),
+            ("ZNAME2", "'BYTEPIX'".to_string()),
+            ("ZVAL2", "2".to_string()),
+            ("BZERO", "32768".to_string()),
+            ("BSCALE", "1".to_string()),
+        ] {
+            header.push_str(&card(key, &value));
+        }
+        header.push_str(&format!("{:<80}", "END"));
+
+        let mut bytes = Vec::new();
+        let primary = [
+            card("SIMPLE", "T"),
+            card("BITPIX", "8"),
+            card("NAXIS", "0"),
+            card("EXTEND", "T"),
+            format!("{:<80}", "END"),
+        ]
+        .concat();
+        bytes.extend_from_slice(primary.as_bytes());
+        pad_block(&mut bytes, b' ');
+        bytes.extend_from_slice(header.as_bytes());
+        pad_block(&mut bytes, b' ');
+        bytes.extend_from_slice(&table);
+        bytes.extend_from_slice(&heap);
+        pad_block(&mut bytes, 0);
+
+        let path =
+            std::env::temp_dir().join(format!("psf_guard_{}_{}.fits.fz", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// Physical 16-bit pixel grid with smooth, noisy and saturated regions
+    fn test_pixels(width: usize, height: usize) -> Vec<u16> {
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                match y % 3 {
+                    0 => 1000 + (x as u16) * 3,
+                    1 => ((x * 7919 + y * 104729) % 65536) as u16,
+                    _ => 500,
+                }
+            })
+            .collect()
+    }
+
+    /// Stored (BZERO-shifted) values for 16-bit unsigned data
+    fn stored(pixels: &[u16]) -> Vec<i16> {
+        pixels.iter().map(|&p| (p as i32 - 32768) as i16).collect()
+    }
+
+    #[test]
+    fn test_rice_round_trip_tile() {
+        let values = stored(&test_pixels(70, 3));
+        let compressed = rice_compress_i16(&values, 32);
+        let decoded = rice_decompress(&compressed, values.len(), 32, 2).unwrap();
+        let expected: Vec<i64> = values.iter().map(|&v| v as i64).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_rice_compressed_file_round_trip() {
+        let (width, height) = (70, 6);
+        let pixels = test_pixels(width, height);
+        let tiles: Vec<Vec<u8>> = stored(&pixels)
+            .chunks(width)
+            .map(|row| rice_compress_i16(row, 32))
+            .collect();
+        let path = write_compressed_fits("rice", width, &tiles, "RICE_1");
+
+        assert!(is_compressed(&path).unwrap());
+        let (data, w, h) = read_compressed_image(&path, 1).unwrap();
+        assert_eq!((w, h), (width, height));
+        let expected: Vec<f64> = pixels.iter().map(|&p| p as f64).collect();
+        assert_eq!(data, expected);
+
+        // FitsImage picks up the compressed HDU transparently
+        let image = crate::image_analysis::FitsImage::from_file(&path).unwrap();
+        assert_eq!((image.width, image.height), (width, height));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_gzip_compressed_file_round_trip() {
+        let (width, height) = (16, 4);
+        let pixels = test_pixels(width, height);
+        let tiles: Vec<Vec<u8>> = stored(&pixels)
+            .chunks(width)
+            .map(|row| {
+                let raw: Vec<u8> = row.iter().flat_map(|v| v.to_be_bytes()).collect();
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&raw).unwrap();
+                encoder.finish().unwrap()
+            })
+            .collect();
+        let path = write_compressed_fits("gzip", width, &tiles, "GZIP_1");
+
+        let (data, _, _) = read_compressed_image(&path, 1).unwrap();
+        let expected: Vec<f64> = pixels.iter().map(|&p| p as f64).collect();
+        assert_eq!(data, expected);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_non_ascii_header_cards_still_load() {
+        // 'é' is two bytes in UTF-8; one straddles the keyword/indicator boundary
+        let mut bytes = [
+            card("SIMPLE", "T"),
+            card("BITPIX", "16"),
+            card("NAXIS", "2"),
+            card("NAXIS1", "2"),
+            card("NAXIS2", "2"),
+        ]
+        .concat()
+        .into_bytes();
+        for raw in ["OBSERVé= 'x'", "OBJECT  = 'Mélotte 15'", "COMMENT Größe"] {
+            let mut bad_card = raw.as_bytes().to_vec();
+            bad_card.resize(CARD_SIZE, b' ');
+            bytes.extend_from_slice(&bad_card);
+        }
+        bytes.extend_from_slice(format!("{:<80}", "END").as_bytes());
+        pad_block(&mut bytes, b' ');
+        for value in [100i16, 200, 300, 400] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        pad_block(&mut bytes, 0);
+
+        let path =
+            std::env::temp_dir().join(format!("psf_guard_non_ascii_{}.fits", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let scanned = scan_hdus(&path);
+        let loaded = crate::image_analysis::FitsImage::from_file(&path);
+        std::fs::remove_file(&path).ok();
+
+        let hdus = scanned.unwrap();
+        assert_eq!(hdus.len(), 1);
+        assert!(!hdus[0]
+            .cards
+            .iter()
+            .any(|(key, _)| key.starts_with("OBSERV")));
+        let object = hdus[0].cards.iter().find(|(key, _)| key == "OBJECT");
+        assert!(object.unwrap().1.starts_with("M"), "{:?}", object);
+        let image = loaded.unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+    }
+
+    #[test]
+    fn test_unsupported_compression_type() {
+        let path = write_compressed_fits("hcomp", 4, &[vec![0u8; 4]], "HCOMPRESS_1");
+
+        let err = read_compressed_image(&path, 1).unwrap_err().to_string();
+        assert!(err.contains("HCOMPRESS_1"), "{}", err);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_uncompressed_file_is_not_compressed() {
+        let path =
+            std::env::temp_dir().join(format!("psf_guard_plain_{}.fits", std::process::id()));
+        let fits = fitrs::Fits::create(&path, fitrs::Hdu::new(&[2, 2], vec![1.0f32; 4])).unwrap();
+        drop(fits);
+
+        assert!(!is_compressed(&path).unwrap());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_parse_card_value() {
+        assert_eq!(parse_card_value("'RICE_1  '           / comment"), "RICE_1");
+        assert_eq!(parse_card_value("                  32 / block"), "32");
+        assert_eq!(parse_card_value("'it''s'"), "it's");
+    }
+}
@@ -1,8 +1,14 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::Path;
 
-#[derive(Debug, Clone)]
+use crate::image_analysis::FitsImage;
+use crate::trail_detection::TrailSummary;
+
+/// Statistical grading thresholds. Fields missing from a config file keep their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct StatisticalGradingConfig {
     /// Enable HFR outlier detection
     pub enable_hfr_analysis: bool,
@@ -13,6 +19,9 @@ pub struct StatisticalGradingConfig {
     pub enable_star_count_analysis: bool,
     /// Standard deviations for star count outlier detection
     pub star_count_stddev_threshold: f64,
+    /// How the HFR and star count outlier checks measure spread; the
+    /// thresholds above apply to whichever is chosen
+    pub outlier_method: RobustMethod,
 
     /// Enable median/mean shift detection
     pub enable_distribution_analysis: bool,
@@ -25,6 +34,53 @@ pub struct StatisticalGradingConfig {
     pub cloud_threshold: f64,
     /// Number of images to establish baseline after cloud event
     pub cloud_baseline_count: usize,
+
+    /// Enable background gradient detection (needs the image files, so only
+    /// images with measured `background` levels are checked)
+    pub enable_gradient_analysis: bool,
+    /// Max-min quadrant median difference, as a fraction of the global median
+    pub gradient_threshold: f64,
+
+    /// Enable satellite/airplane trail detection (needs the image files, so
+    /// only images with measured `trails` are checked)
+    pub enable_trail_detection: bool,
+    /// Shortest trail, in pixels, that rejects a frame
+    pub min_trail_length: f64,
+
+    /// Enable guiding RMS checks (images without a recorded RMS are skipped)
+    pub enable_guiding_analysis: bool,
+    /// Total guiding RMS, in arcseconds, above which a frame is always rejected
+    pub rms_threshold: f64,
+    /// Standard deviations above the group for a guiding RMS outlier
+    pub rms_stddev_threshold: f64,
+
+    /// Keep only the best N percent of each target/filter group (0-100]
+    pub keep_percentile: Option<f64>,
+    /// Metric used to rank images when `keep_percentile` is set
+    pub percentile_metric: PercentileMetric,
+}
+
+/// Center and spread used by the HFR and star count outlier checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RobustMethod {
+    /// Mean ± k·σ
+    #[default]
+    #[serde(alias = "stddev")]
+    StdDev,
+    /// Median ± k·MAD, which the outliers being looked for cannot inflate
+    Mad,
+}
+
+/// Metric used to rank images for percentile grading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PercentileMetric {
+    /// Lower HFR is better
+    Hfr,
+    /// Higher star count is better
+    #[serde(alias = "stars")]
+    StarCount,
 }
 
 impl Default for StatisticalGradingConfig {
@@ -34,15 +90,35 @@ impl Default for StatisticalGradingConfig {
             hfr_stddev_threshold: 2.0,
             enable_star_count_analysis: true,
             star_count_stddev_threshold: 2.0,
+            outlier_method: RobustMethod::StdDev,
             enable_distribution_analysis: true,
             median_shift_threshold: 0.10, // 10% shift
             enable_cloud_detection: true,
             cloud_threshold: 0.20,   // 20% increase indicates clouds
             cloud_baseline_count: 5, // Need 5 images to establish new baseline
+            enable_gradient_analysis: false,
+            gradient_threshold: 0.20, // 20% of the background level
+            enable_trail_detection: false,
+            min_trail_length: 100.0,
+            enable_guiding_analysis: false,
+            rms_threshold: 2.0,
+            rms_stddev_threshold: 2.0,
+            keep_percentile: None,
+            percentile_metric: PercentileMetric::Hfr,
         }
     }
 }
 
+impl StatisticalGradingConfig {
+    /// Load a config from a JSON file, using defaults for any field not present
+    pub fn from_json_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read grading config: {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid grading config: {}", path.display()))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ImageMetadata {
     #[serde(rename = "FileName")]
@@ -57,8 +133,48 @@ struct ImageMetadata {
     detected_stars: Option<i32>,
     #[serde(rename = "ExposureStartTime")]
     exposure_start_time: String,
+    /// Exposure length in seconds
+    #[serde(rename = "ExposureTime", alias = "ExposureDuration")]
+    exposure_duration: Option<f64>,
+    #[serde(rename = "Gain")]
+    gain: Option<f64>,
+    /// Guiding RMS in arcseconds, as Target Scheduler records it
+    #[serde(rename = "GuidingRMSRAArcSec", alias = "RmsRA")]
+    rms_ra: Option<f64>,
+    #[serde(rename = "GuidingRMSDECArcSec", alias = "RmsDec")]
+    rms_dec: Option<f64>,
+    #[serde(rename = "GuidingRMSArcSec", alias = "Rms")]
+    rms_total: Option<f64>,
+    /// N.I.N.A.'s formatted RMS, e.g. `RA: 0.41" DEC: 0.32" Tot: 0.52"`, read
+    /// when the numeric fields are missing
+    #[serde(rename = "RmsText")]
+    rms_text: Option<String>,
 }
 
+/// Per-image inputs to statistical grading.
+///
+/// Records read from the database come from [`parse_image_metadata`]. To grade
+/// in-memory data without a database, build them with [`ImageStatistics::new`]:
+/// `id` is any caller-assigned value, and is what rejections and scores refer
+/// back to. Images are grouped by [`GroupKey`] and ordered by `exposure_time`
+/// within a group.
+///
+/// ```
+/// use psf_guard::grading::{ImageStatistics, StatisticalGrader, StatisticalGradingConfig};
+///
+/// let images = vec![
+///     ImageStatistics::new(1, 1, "Ha").with_hfr(2.1).with_star_count(410),
+///     ImageStatistics::new(2, 1, "Ha").with_hfr(2.0).with_star_count(420),
+///     ImageStatistics::new(3, 1, "Ha").with_hfr(3.4).with_star_count(150),
+/// ];
+///
+/// let grader = StatisticalGrader::new(StatisticalGradingConfig::default());
+/// let scores = grader.analyze_images_scored(images).unwrap();
+///
+/// // Best first: the soft, sparse frame ranks last
+/// assert_eq!(scores[0].image_id, 2);
+/// assert_eq!(scores[2].image_id, 3);
+/// ```
 #[derive(Debug)]
 pub struct ImageStatistics {
     pub id: i32,
@@ -68,8 +184,127 @@ pub struct ImageStatistics {
     pub hfr: Option<f64>,
     pub star_count: Option<i32>,
     pub exposure_time: String,
+    /// Exposure length in seconds, when the metadata records it
+    pub exposure_duration: Option<f64>,
+    /// Camera gain, when the metadata records it
+    pub gain: Option<i32>,
+    /// Guiding RMS in arcseconds, when the metadata records it
+    pub rms_ra: Option<f64>,
+    pub rms_dec: Option<f64>,
+    pub rms_total: Option<f64>,
     pub original_status: i32,
     pub metadata_json: String,
+    /// Background levels measured from the image file, when available
+    pub background: Option<BackgroundLevels>,
+    /// Trails detected in the image file, when available
+    pub trails: Option<TrailSummary>,
+}
+
+impl ImageStatistics {
+    /// A record with no measurements; add them with the `with_*` methods
+    pub fn new(id: i32, target_id: i32, filter_name: &str) -> Self {
+        Self {
+            id,
+            target_id,
+            target_name: String::new(),
+            filter_name: filter_name.to_string(),
+            hfr: None,
+            star_count: None,
+            exposure_time: String::new(),
+            exposure_duration: None,
+            gain: None,
+            rms_ra: None,
+            rms_dec: None,
+            rms_total: None,
+            original_status: 0,
+            metadata_json: String::new(),
+            background: None,
+            trails: None,
+        }
+    }
+
+    pub fn with_target_name(mut self, target_name: &str) -> Self {
+        self.target_name = target_name.to_string();
+        self
+    }
+
+    pub fn with_hfr(mut self, hfr: f64) -> Self {
+        self.hfr = Some(hfr);
+        self
+    }
+
+    pub fn with_star_count(mut self, star_count: i32) -> Self {
+        self.star_count = Some(star_count);
+        self
+    }
+
+    /// Exposure start time; any format that sorts chronologically as a string,
+    /// such as ISO 8601
+    pub fn with_exposure_time(mut self, exposure_time: &str) -> Self {
+        self.exposure_time = exposure_time.to_string();
+        self
+    }
+
+    /// Exposure length in seconds
+    pub fn with_exposure_duration(mut self, seconds: f64) -> Self {
+        self.exposure_duration = Some(seconds);
+        self
+    }
+
+    pub fn with_gain(mut self, gain: i32) -> Self {
+        self.gain = Some(gain);
+        self
+    }
+
+    /// Guiding RMS in arcseconds per axis; the total is their quadrature sum
+    pub fn with_guiding_rms(mut self, ra: f64, dec: f64) -> Self {
+        self.rms_ra = Some(ra);
+        self.rms_dec = Some(dec);
+        self.rms_total = Some(ra.hypot(dec));
+        self
+    }
+
+    pub fn group_key(&self) -> GroupKey {
+        GroupKey {
+            target_id: self.target_id,
+            filter_name: self.filter_name.clone(),
+            exposure_ms: self.exposure_duration.map(exposure_ms),
+            gain: self.gain,
+        }
+    }
+}
+
+/// Images graded against each other: one target and filter, split further by
+/// exposure length and gain so 120s and 300s subs are not compared. Images
+/// whose metadata lacks them share a group per target and filter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GroupKey {
+    pub target_id: i32,
+    pub filter_name: String,
+    /// Exposure length in milliseconds
+    pub exposure_ms: Option<i64>,
+    pub gain: Option<i32>,
+}
+
+fn exposure_ms(seconds: f64) -> i64 {
+    (seconds * 1000.0).round() as i64
+}
+
+/// Background medians of a frame, used for gradient detection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundLevels {
+    pub median: f64,
+    /// Top-left, top-right, bottom-left, bottom-right
+    pub quadrant_medians: [f64; 4],
+}
+
+impl BackgroundLevels {
+    pub fn measure(image: &FitsImage) -> Self {
+        Self {
+            median: image.calculate_basic_statistics().median,
+            quadrant_medians: image.quadrant_medians(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -85,11 +320,126 @@ pub struct FilterStatistics {
     pub star_count_stddev: f64,
 }
 
+/// What outlier checks measure a value against
+struct Spread {
+    center: f64,
+    scale: f64,
+    center_name: &'static str,
+    unit: &'static str,
+}
+
+/// Count, mean, median and sample standard deviation of one metric
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MetricBaseline {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+}
+
+impl MetricBaseline {
+    /// Combine two baselines. Count, mean and standard deviation are exact
+    /// (pairwise variance update); the median is approximated by the
+    /// count-weighted mean of the two medians.
+    pub fn merge(&self, other: &MetricBaseline) -> MetricBaseline {
+        if other.count == 0 {
+            return *self;
+        }
+        if self.count == 0 {
+            return *other;
+        }
+
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let n = n_a + n_b;
+        let delta = other.mean - self.mean;
+        let m2 = self.stddev.powi(2) * (n_a - 1.0)
+            + other.stddev.powi(2) * (n_b - 1.0)
+            + delta * delta * n_a * n_b / n;
+
+        MetricBaseline {
+            count: self.count + other.count,
+            mean: self.mean + delta * n_b / n,
+            median: (self.median * n_a + other.median * n_b) / n,
+            stddev: (m2 / (n - 1.0)).sqrt(),
+        }
+    }
+}
+
+/// Stored HFR and star count statistics of a group (see [`GroupKey`]), so
+/// nightly regrades can merge in new images instead of rescanning the whole group
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradingBaseline {
+    pub target_id: i32,
+    pub filter_name: String,
+    pub exposure_ms: Option<i64>,
+    pub gain: Option<i32>,
+    pub hfr: MetricBaseline,
+    pub star_count: MetricBaseline,
+    /// Images in the group covered by the baseline
+    pub sample_count: usize,
+    /// Highest image id covered; images with larger ids are new
+    pub last_image_id: i32,
+    /// Unix time the baseline was written
+    pub updated_at: i64,
+}
+
+impl GradingBaseline {
+    pub fn group_key(&self) -> GroupKey {
+        GroupKey {
+            target_id: self.target_id,
+            filter_name: self.filter_name.clone(),
+            exposure_ms: self.exposure_ms,
+            gain: self.gain,
+        }
+    }
+
+    fn filter_statistics(&self) -> FilterStatistics {
+        FilterStatistics {
+            filter_name: self.filter_name.clone(),
+            hfr_values: Vec::new(),
+            star_counts: Vec::new(),
+            hfr_mean: self.hfr.mean,
+            hfr_median: self.hfr.median,
+            hfr_stddev: self.hfr.stddev,
+            star_count_mean: self.star_count.mean,
+            star_count_median: self.star_count.median,
+            star_count_stddev: self.star_count.stddev,
+        }
+    }
+}
+
+/// Result of an incremental analysis
+#[derive(Debug)]
+pub struct IncrementalAnalysis {
+    pub rejections: Vec<StatisticalRejection>,
+    /// Baselines for every analyzed group, to be stored for the next run
+    pub baselines: Vec<GradingBaseline>,
+    /// Groups whose new images were merged into the stored baseline
+    pub merged_groups: usize,
+    /// Groups analyzed from scratch (no baseline, or a stale one)
+    pub recomputed_groups: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct StatisticalRejection {
     pub image_id: i32,
     pub reason: String,
     pub details: String,
+    /// Combined quality score of the image (see [`ImageScore`]); 0 when its
+    /// target/filter group is too small to score
+    pub score: f64,
+}
+
+/// Quality score of one image, for ranking marginal frames
+#[derive(Debug, Clone)]
+pub struct ImageScore {
+    pub image_id: i32,
+    /// Mean of the image's HFR and star count z-scores within its target/filter
+    /// group, plus their median/MAD counterparts, signed so that higher is
+    /// worse: 0 is a typical frame, a clear outlier scores above the σ thresholds
+    pub score: f64,
+    /// Rejections found for the image, empty if it is kept
+    pub rejections: Vec<StatisticalRejection>,
 }
 
 pub struct StatisticalGrader {
@@ -106,56 +456,344 @@ impl StatisticalGrader {
         &self,
         mut images: Vec<ImageStatistics>,
     ) -> Result<Vec<StatisticalRejection>> {
-        let mut rejections = Vec::new();
-
-        // Sort images by target, filter, and time to ensure proper sequence
-        images.sort_by(|a, b| {
-            a.target_id
-                .cmp(&b.target_id)
-                .then_with(|| a.filter_name.cmp(&b.filter_name))
-                .then_with(|| a.exposure_time.cmp(&b.exposure_time))
-        });
+        self.analyze(&mut images)
+            .map(|(rejections, _scores)| rejections)
+    }
 
-        // Group images by target and filter
-        let mut target_filter_groups: HashMap<(i32, String), Vec<&ImageStatistics>> =
-            HashMap::new();
-        for image in &images {
-            target_filter_groups
-                .entry((image.target_id, image.filter_name.clone()))
+    /// Score every image, not just the rejected ones, so marginal frames can
+    /// be ranked. Results are sorted best (lowest score) first.
+    pub fn analyze_images_scored(
+        &self,
+        mut images: Vec<ImageStatistics>,
+    ) -> Result<Vec<ImageScore>> {
+        let ids: Vec<i32> = images.iter().map(|img| img.id).collect();
+        let (rejections, scores) = self.analyze(&mut images)?;
+
+        let mut by_image: HashMap<i32, Vec<StatisticalRejection>> = HashMap::new();
+        for rejection in rejections {
+            by_image
+                .entry(rejection.image_id)
                 .or_default()
-                .push(image);
+                .push(rejection);
         }
 
-        // Analyze each target/filter group
-        for ((_target_id, _filter_name), target_filter_images) in target_filter_groups {
-            if target_filter_images.len() < 3 {
-                // Not enough images for statistical analysis
+        let mut scored: Vec<ImageScore> = ids
+            .into_iter()
+            .map(|image_id| ImageScore {
+                image_id,
+                score: scores.get(&image_id).copied().unwrap_or(0.0),
+                rejections: by_image.remove(&image_id).unwrap_or_default(),
+            })
+            .collect();
+        scored.sort_by(|a, b| {
+            a.score
+                .total_cmp(&b.score)
+                .then_with(|| a.image_id.cmp(&b.image_id))
+        });
+
+        Ok(scored)
+    }
+
+    /// Like `analyze_images`, but for groups with a stored baseline only the
+    /// images newer than it are checked, against the baseline merged with them.
+    ///
+    /// Merging covers the HFR and star count outlier checks, which only need
+    /// the mean and standard deviation; distribution, cloud and percentile
+    /// checks need the whole group and run only when a group is recomputed.
+    /// A baseline is stale, and its group recomputed, when the number of images
+    /// it covers no longer matches `sample_count` (images were deleted or fell
+    /// out of the date range).
+    pub fn analyze_images_incremental(
+        &self,
+        mut images: Vec<ImageStatistics>,
+        baselines: &[GradingBaseline],
+        updated_at: i64,
+    ) -> Result<IncrementalAnalysis> {
+        self.validate()?;
+        let mut scores = HashMap::new();
+        let mut analysis = IncrementalAnalysis {
+            rejections: self.check_frames(&images),
+            baselines: Vec::new(),
+            merged_groups: 0,
+            recomputed_groups: 0,
+        };
+
+        let stored: HashMap<GroupKey, &GradingBaseline> =
+            baselines.iter().map(|b| (b.group_key(), b)).collect();
+
+        for (key, group) in group_images(&mut images) {
+            let baseline = stored.get(&key).filter(|b| {
+                group.iter().filter(|i| i.id <= b.last_image_id).count() == b.sample_count
+            });
+
+            let Some(baseline) = baseline else {
+                analysis
+                    .rejections
+                    .extend(self.analyze_group(&group, &mut scores));
+                analysis
+                    .baselines
+                    .push(self.group_baseline(&group, updated_at));
+                analysis.recomputed_groups += 1;
                 continue;
+            };
+
+            let new_images: Vec<&ImageStatistics> = group
+                .iter()
+                .filter(|i| i.id > baseline.last_image_id)
+                .copied()
+                .collect();
+            let added = self.group_baseline(&new_images, updated_at);
+            let merged = GradingBaseline {
+                target_id: key.target_id,
+                filter_name: key.filter_name,
+                exposure_ms: key.exposure_ms,
+                gain: key.gain,
+                hfr: baseline.hfr.merge(&added.hfr),
+                star_count: baseline.star_count.merge(&added.star_count),
+                sample_count: baseline.sample_count + new_images.len(),
+                last_image_id: baseline.last_image_id.max(added.last_image_id),
+                updated_at,
+            };
+
+            if merged.sample_count >= 3 {
+                let stats = merged.filter_statistics();
+                scores.extend(self.quality_scores(&new_images, &stats));
+                if self.config.enable_hfr_analysis {
+                    analysis
+                        .rejections
+                        .extend(self.check_hfr_outliers(&new_images, &stats));
+                }
+                if self.config.enable_star_count_analysis {
+                    analysis
+                        .rejections
+                        .extend(self.check_star_count_outliers(&new_images, &stats));
+                }
             }
 
-            // Calculate statistics for this target/filter combination
-            let stats = self.calculate_filter_statistics(&target_filter_images);
+            analysis.baselines.push(merged);
+            analysis.merged_groups += 1;
+        }
 
-            // Check for outliers
-            if self.config.enable_hfr_analysis {
-                rejections.extend(self.check_hfr_outliers(&target_filter_images, &stats));
-            }
+        apply_scores(&mut analysis.rejections, &scores);
+        Ok(analysis)
+    }
 
-            if self.config.enable_star_count_analysis {
-                rejections.extend(self.check_star_count_outliers(&target_filter_images, &stats));
-            }
+    /// Baselines of every group, computed from scratch
+    pub fn compute_baselines(
+        &self,
+        images: &[ImageStatistics],
+        updated_at: i64,
+    ) -> Vec<GradingBaseline> {
+        let mut groups: HashMap<GroupKey, Vec<&ImageStatistics>> = HashMap::new();
+        for image in images {
+            groups.entry(image.group_key()).or_default().push(image);
+        }
 
-            if self.config.enable_distribution_analysis {
-                rejections.extend(self.check_distribution_quality(&target_filter_images, &stats));
+        groups
+            .values()
+            .map(|group| self.group_baseline(group, updated_at))
+            .collect()
+    }
+
+    /// Rejections of every image, and the quality scores of images in groups
+    /// large enough to analyze
+    fn analyze(
+        &self,
+        images: &mut [ImageStatistics],
+    ) -> Result<(Vec<StatisticalRejection>, HashMap<i32, f64>)> {
+        self.validate()?;
+        let mut rejections = self.check_frames(images);
+        let mut scores = HashMap::new();
+
+        // Analyze each target/filter/exposure/gain group
+        for (_key, target_filter_images) in group_images(images) {
+            rejections.extend(self.analyze_group(&target_filter_images, &mut scores));
+        }
+
+        apply_scores(&mut rejections, &scores);
+        Ok((rejections, scores))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(percentile) = self.config.keep_percentile {
+            if !(percentile > 0.0 && percentile <= 100.0) {
+                bail!(
+                    "Keep percentile must be greater than 0 and at most 100, got {}",
+                    percentile
+                );
             }
+        }
+        Ok(())
+    }
 
-            // Check for cloud detection (sequence analysis)
-            if self.config.enable_cloud_detection {
-                rejections.extend(self.check_cloud_sequence(&target_filter_images));
+    /// Checks judged per frame, so no group minimum applies
+    fn check_frames(&self, images: &[ImageStatistics]) -> Vec<StatisticalRejection> {
+        let mut rejections = Vec::new();
+        if self.config.enable_gradient_analysis {
+            rejections.extend(self.check_background_gradient(images));
+        }
+        if self.config.enable_trail_detection {
+            rejections.extend(self.check_trails(images));
+        }
+        if self.config.enable_guiding_analysis {
+            rejections.extend(self.check_guiding_rms_threshold(images));
+        }
+        rejections
+    }
+
+    fn analyze_group(
+        &self,
+        target_filter_images: &[&ImageStatistics],
+        scores: &mut HashMap<i32, f64>,
+    ) -> Vec<StatisticalRejection> {
+        let mut rejections = Vec::new();
+
+        if target_filter_images.len() < 3 {
+            // Not enough images for statistical analysis
+            return rejections;
+        }
+
+        // Calculate statistics for this target/filter combination
+        let stats = self.calculate_filter_statistics(target_filter_images);
+        scores.extend(self.quality_scores(target_filter_images, &stats));
+
+        // Check for outliers
+        if self.config.enable_hfr_analysis {
+            rejections.extend(self.check_hfr_outliers(target_filter_images, &stats));
+        }
+
+        if self.config.enable_star_count_analysis {
+            rejections.extend(self.check_star_count_outliers(target_filter_images, &stats));
+        }
+
+        if self.config.enable_distribution_analysis {
+            rejections.extend(self.check_distribution_quality(target_filter_images, &stats));
+        }
+
+        if self.config.enable_guiding_analysis {
+            rejections.extend(self.check_guiding_rms(target_filter_images));
+        }
+
+        // Check for cloud detection (sequence analysis)
+        if self.config.enable_cloud_detection {
+            rejections.extend(self.check_cloud_sequence(target_filter_images));
+        }
+
+        if let Some(percentile) = self.config.keep_percentile {
+            rejections.extend(self.check_percentile(target_filter_images, percentile));
+        }
+
+        rejections
+    }
+
+    /// Combined quality score of each image, see [`ImageScore::score`]. The
+    /// median/MAD terms are left out when the group's values are not at hand
+    /// (merged baselines).
+    fn quality_scores(
+        &self,
+        images: &[&ImageStatistics],
+        stats: &FilterStatistics,
+    ) -> HashMap<i32, f64> {
+        let hfr_mad = self.mad(stats.hfr_values.iter().copied(), stats.hfr_median);
+        let star_mad = self.mad(
+            stats.star_counts.iter().map(|&c| c as f64),
+            stats.star_count_median,
+        );
+
+        images
+            .iter()
+            .map(|image| {
+                let mut terms = Vec::with_capacity(4);
+                if let Some(hfr) = image.hfr {
+                    // Larger HFR is worse
+                    if stats.hfr_stddev > 0.0 {
+                        terms.push((hfr - stats.hfr_mean) / stats.hfr_stddev);
+                    }
+                    if hfr_mad > 0.0 {
+                        terms.push((hfr - stats.hfr_median) / hfr_mad);
+                    }
+                }
+                if let Some(star_count) = image.star_count.map(|c| c as f64) {
+                    // Fewer stars is worse
+                    if stats.star_count_stddev > 0.0 {
+                        terms.push((stats.star_count_mean - star_count) / stats.star_count_stddev);
+                    }
+                    if star_mad > 0.0 {
+                        terms.push((stats.star_count_median - star_count) / star_mad);
+                    }
+                }
+
+                let score = if terms.is_empty() {
+                    0.0
+                } else {
+                    terms.iter().sum::<f64>() / terms.len() as f64
+                };
+                (image.id, score)
+            })
+            .collect()
+    }
+
+    /// Median absolute deviation, scaled to be comparable to a standard deviation
+    fn mad(&self, values: impl Iterator<Item = f64>, median: f64) -> f64 {
+        let mut deviations: Vec<f64> = values.map(|v| (v - median).abs()).collect();
+        self.calculate_median(&mut deviations) * 1.4826
+    }
+
+    /// Center and spread for the HFR and star count outlier checks, per
+    /// `outlier_method`. Merged baselines keep no per-image values, so MAD
+    /// falls back to the standard deviation there.
+    fn outlier_spread(&self, values: &[f64], mean: f64, stddev: f64, median: f64) -> Spread {
+        if self.config.outlier_method == RobustMethod::Mad && !values.is_empty() {
+            Spread {
+                center: median,
+                scale: self.mad(values.iter().copied(), median),
+                center_name: "median",
+                unit: " MAD",
+            }
+        } else {
+            Spread {
+                center: mean,
+                scale: stddev,
+                center_name: "mean",
+                unit: "σ",
             }
         }
+    }
 
-        Ok(rejections)
+    fn group_baseline(&self, images: &[&ImageStatistics], updated_at: i64) -> GradingBaseline {
+        let mut hfr_values: Vec<f64> = images.iter().filter_map(|img| img.hfr).collect();
+        let mut star_counts: Vec<f64> = images
+            .iter()
+            .filter_map(|img| img.star_count.map(|c| c as f64))
+            .collect();
+
+        let key = images.first().map(|img| img.group_key());
+        GradingBaseline {
+            target_id: key.as_ref().map_or(0, |k| k.target_id),
+            filter_name: key
+                .as_ref()
+                .map_or_else(String::new, |k| k.filter_name.clone()),
+            exposure_ms: key.as_ref().and_then(|k| k.exposure_ms),
+            gain: key.as_ref().and_then(|k| k.gain),
+            hfr: self.metric_baseline(&mut hfr_values),
+            star_count: self.metric_baseline(&mut star_counts),
+            sample_count: images.len(),
+            last_image_id: images.iter().map(|img| img.id).max().unwrap_or(0),
+            updated_at,
+        }
+    }
+
+    fn metric_baseline(&self, values: &mut [f64]) -> MetricBaseline {
+        if values.is_empty() {
+            return MetricBaseline::default();
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        MetricBaseline {
+            count: values.len(),
+            mean,
+            stddev: self.calculate_stddev(values, mean),
+            median: self.calculate_median(values),
+        }
     }
 
     fn calculate_filter_statistics(&self, images: &[&ImageStatistics]) -> FilterStatistics {
@@ -224,7 +862,7 @@ impl StatisticalGrader {
         if values.is_empty() {
             return 0.0;
         }
-        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        values.sort_by(|a, b| a.total_cmp(b));
         let mid = values.len() / 2;
         if values.len() % 2 == 0 {
             (values[mid - 1] + values[mid]) / 2.0
@@ -253,22 +891,35 @@ impl StatisticalGrader {
     ) -> Vec<StatisticalRejection> {
         let mut rejections = Vec::new();
 
-        if stats.hfr_stddev == 0.0 {
+        let spread = self.outlier_spread(
+            &stats.hfr_values,
+            stats.hfr_mean,
+            stats.hfr_stddev,
+            stats.hfr_median,
+        );
+        if spread.scale == 0.0 {
             return rejections;
         }
 
         for image in images {
             if let Some(hfr) = image.hfr {
-                let z_score = (hfr - stats.hfr_mean).abs() / stats.hfr_stddev;
+                let z_score = (hfr - spread.center).abs() / spread.scale;
 
                 if z_score > self.config.hfr_stddev_threshold {
                     rejections.push(StatisticalRejection {
                         image_id: image.id,
                         reason: "Statistical HFR".to_string(),
                         details: format!(
-                            "HFR {:.3} is {:.1}σ from mean {:.3} (threshold: {:.1}σ)",
-                            hfr, z_score, stats.hfr_mean, self.config.hfr_stddev_threshold
+                            "HFR {:.3} is {:.1}{} from {} {:.3} (threshold: {:.1}{})",
+                            hfr,
+                            z_score,
+                            spread.unit,
+                            spread.center_name,
+                            spread.center,
+                            self.config.hfr_stddev_threshold,
+                            spread.unit
                         ),
+                        score: 0.0,
                     });
                 }
             }
@@ -284,26 +935,36 @@ impl StatisticalGrader {
     ) -> Vec<StatisticalRejection> {
         let mut rejections = Vec::new();
 
-        if stats.star_count_stddev == 0.0 {
+        let star_counts: Vec<f64> = stats.star_counts.iter().map(|&c| c as f64).collect();
+        let spread = self.outlier_spread(
+            &star_counts,
+            stats.star_count_mean,
+            stats.star_count_stddev,
+            stats.star_count_median,
+        );
+        if spread.scale == 0.0 {
             return rejections;
         }
 
         for image in images {
             if let Some(star_count) = image.star_count {
-                let z_score =
-                    (star_count as f64 - stats.star_count_mean).abs() / stats.star_count_stddev;
+                let z_score = (star_count as f64 - spread.center).abs() / spread.scale;
 
                 if z_score > self.config.star_count_stddev_threshold {
                     rejections.push(StatisticalRejection {
                         image_id: image.id,
                         reason: "Statistical Stars".to_string(),
                         details: format!(
-                            "Star count {} is {:.1}σ from mean {:.0} (threshold: {:.1}σ)",
+                            "Star count {} is {:.1}{} from {} {:.0} (threshold: {:.1}{})",
                             star_count,
                             z_score,
-                            stats.star_count_mean,
-                            self.config.star_count_stddev_threshold
+                            spread.unit,
+                            spread.center_name,
+                            spread.center,
+                            self.config.star_count_stddev_threshold,
+                            spread.unit
                         ),
+                        score: 0.0,
                     });
                 }
             }
@@ -325,19 +986,11 @@ impl StatisticalGrader {
 
             if hfr_median_shift > self.config.median_shift_threshold {
                 // The distribution is skewed, use median for outlier detection
+                let mad = self.mad(stats.hfr_values.iter().copied(), stats.hfr_median);
                 for image in images {
                     if let Some(hfr) = image.hfr {
                         // Use median-based rejection for skewed distributions
                         let deviation_from_median = (hfr - stats.hfr_median).abs();
-                        let mad_multiplier = 1.4826; // Constant to make MAD comparable to stddev
-
-                        // Calculate Median Absolute Deviation (MAD)
-                        let mut deviations: Vec<f64> = stats
-                            .hfr_values
-                            .iter()
-                            .map(|&v| (v - stats.hfr_median).abs())
-                            .collect();
-                        let mad = self.calculate_median(&mut deviations) * mad_multiplier;
 
                         if mad > 0.0 {
                             let z_score = deviation_from_median / mad;
@@ -349,6 +1002,7 @@ impl StatisticalGrader {
                                         "HFR {:.3} deviates {:.1} MAD from median {:.3} (threshold: {:.1})",
                                         hfr, z_score, stats.hfr_median, self.config.hfr_stddev_threshold
                                     ),
+                                    score: 0.0,
                                 });
                             }
                         }
@@ -364,20 +1018,15 @@ impl StatisticalGrader {
 
             if star_median_shift > self.config.median_shift_threshold {
                 // The distribution is skewed, use median for outlier detection
+                let mad = self.mad(
+                    stats.star_counts.iter().map(|&c| c as f64),
+                    stats.star_count_median,
+                );
                 for image in images {
                     if let Some(star_count) = image.star_count {
                         // Use median-based rejection for skewed distributions
                         let deviation_from_median =
                             (star_count as f64 - stats.star_count_median).abs();
-                        let mad_multiplier = 1.4826; // Constant to make MAD comparable to stddev
-
-                        // Calculate Median Absolute Deviation (MAD)
-                        let mut deviations: Vec<f64> = stats
-                            .star_counts
-                            .iter()
-                            .map(|&v| (v as f64 - stats.star_count_median).abs())
-                            .collect();
-                        let mad = self.calculate_median(&mut deviations) * mad_multiplier;
 
                         if mad > 0.0 {
                             let z_score = deviation_from_median / mad;
@@ -389,6 +1038,7 @@ impl StatisticalGrader {
                                         "Star count {} deviates {:.1} MAD from median {:.0} (threshold: {:.1})",
                                         star_count, z_score, stats.star_count_median, self.config.star_count_stddev_threshold
                                     ),
+                                    score: 0.0,
                                 });
                             }
                         }
@@ -430,7 +1080,7 @@ impl StatisticalGrader {
 
                 // Calculate baseline median
                 let mut sorted_baseline = baseline_values.clone();
-                sorted_baseline.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                sorted_baseline.sort_by(|a, b| a.total_cmp(b));
                 let baseline_median = if sorted_baseline.len() % 2 == 0 {
                     let mid = sorted_baseline.len() / 2;
                     (sorted_baseline[mid - 1] + sorted_baseline[mid]) / 2.0
@@ -453,6 +1103,7 @@ impl StatisticalGrader {
                             baseline_median,
                             self.config.cloud_threshold * 100.0
                         ),
+                        score: 0.0,
                     });
 
                     // Reset baseline establishment
@@ -490,7 +1141,7 @@ impl StatisticalGrader {
 
                     // Calculate baseline median
                     let mut sorted_baseline = baseline_values.clone();
-                    sorted_baseline.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    sorted_baseline.sort_by(|a, b| a.total_cmp(b));
                     let baseline_median = if sorted_baseline.len() % 2 == 0 {
                         let mid = sorted_baseline.len() / 2;
                         (sorted_baseline[mid - 1] + sorted_baseline[mid]) / 2.0
@@ -512,6 +1163,7 @@ impl StatisticalGrader {
                                 baseline_median,
                                 self.config.cloud_threshold * 100.0
                             ),
+                            score: 0.0,
                         });
 
                         // Reset baseline
@@ -531,6 +1183,208 @@ impl StatisticalGrader {
 
         rejections
     }
+
+    /// Reject frames whose quadrant backgrounds differ by more than `gradient_threshold`
+    /// of the global median (light pollution ramps, twilight)
+    fn check_background_gradient(&self, images: &[ImageStatistics]) -> Vec<StatisticalRejection> {
+        images
+            .iter()
+            .filter_map(|image| {
+                let background = image.background?;
+                if background.median <= 0.0 {
+                    return None;
+                }
+
+                let quadrants = background.quadrant_medians;
+                let max = quadrants.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                let min = quadrants.iter().cloned().fold(f64::INFINITY, f64::min);
+                let gradient = (max - min) / background.median;
+
+                (gradient > self.config.gradient_threshold).then(|| StatisticalRejection {
+                    image_id: image.id,
+                    reason: "Background Gradient".to_string(),
+                    details: format!(
+                        "Quadrant medians span {:.0}-{:.0}, {:.0}% of median {:.0} (threshold: {:.0}%)",
+                        min,
+                        max,
+                        gradient * 100.0,
+                        background.median,
+                        self.config.gradient_threshold * 100.0
+                    ),
+                    score: 0.0,
+                })
+            })
+            .collect()
+    }
+
+    /// Reject frames crossed by a trail at least `min_trail_length` pixels long
+    fn check_trails(&self, images: &[ImageStatistics]) -> Vec<StatisticalRejection> {
+        images
+            .iter()
+            .filter_map(|image| {
+                let trails = image.trails?;
+                (trails.count > 0 && trails.longest_length >= self.config.min_trail_length).then(
+                    || StatisticalRejection {
+                        image_id: image.id,
+                        reason: "Satellite Trail".to_string(),
+                        details: format!(
+                            "{} trail(s), longest {:.0} px (minimum: {:.0} px)",
+                            trails.count, trails.longest_length, self.config.min_trail_length
+                        ),
+                        score: 0.0,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Reject frames whose total guiding RMS exceeds `rms_threshold`
+    fn check_guiding_rms_threshold(&self, images: &[ImageStatistics]) -> Vec<StatisticalRejection> {
+        images
+            .iter()
+            .filter_map(|image| {
+                let rms = image.rms_total?;
+                (rms > self.config.rms_threshold).then(|| StatisticalRejection {
+                    image_id: image.id,
+                    reason: "Guiding RMS".to_string(),
+                    details: format!(
+                        "Guiding RMS {:.2}\" exceeds {:.2}\"",
+                        rms, self.config.rms_threshold
+                    ),
+                    score: 0.0,
+                })
+            })
+            .collect()
+    }
+
+    /// Reject frames guided much worse than the rest of their group. Only high
+    /// RMS is an outlier. Frames over `rms_threshold` are left to the per-frame
+    /// check and out of the spread, so they cannot hide milder outliers.
+    fn check_guiding_rms(&self, images: &[&ImageStatistics]) -> Vec<StatisticalRejection> {
+        let within_threshold = |image: &ImageStatistics| {
+            image
+                .rms_total
+                .filter(|&rms| rms <= self.config.rms_threshold)
+        };
+        let mut rms_values: Vec<f64> = images
+            .iter()
+            .filter_map(|image| within_threshold(image))
+            .collect();
+        if rms_values.len() < 3 {
+            return Vec::new();
+        }
+        let mean = rms_values.iter().sum::<f64>() / rms_values.len() as f64;
+        let stddev = self.calculate_stddev(&rms_values, mean);
+        let median = self.calculate_median(&mut rms_values);
+        let spread = self.outlier_spread(&rms_values, mean, stddev, median);
+        if spread.scale == 0.0 {
+            return Vec::new();
+        }
+
+        images
+            .iter()
+            .filter_map(|image| {
+                let rms = within_threshold(image)?;
+                let z_score = (rms - spread.center) / spread.scale;
+                (z_score > self.config.rms_stddev_threshold).then(|| StatisticalRejection {
+                    image_id: image.id,
+                    reason: "Guiding RMS".to_string(),
+                    details: format!(
+                        "Guiding RMS {:.2}\" is {:.1}{} above {} {:.2}\" (threshold: {:.1}{})",
+                        rms,
+                        z_score,
+                        spread.unit,
+                        spread.center_name,
+                        spread.center,
+                        self.config.rms_stddev_threshold,
+                        spread.unit
+                    ),
+                    score: 0.0,
+                })
+            })
+            .collect()
+    }
+
+    /// Rank images by the configured metric and reject everything outside the best `percentile`%
+    fn check_percentile(
+        &self,
+        images: &[&ImageStatistics],
+        percentile: f64,
+    ) -> Vec<StatisticalRejection> {
+        let metric = self.config.percentile_metric;
+
+        let mut ranked: Vec<(&ImageStatistics, f64)> = images
+            .iter()
+            .filter_map(|img| {
+                let value = match metric {
+                    PercentileMetric::Hfr => img.hfr,
+                    PercentileMetric::StarCount => img.star_count.map(|c| c as f64),
+                };
+                value.filter(|v| v.is_finite()).map(|v| (*img, v))
+            })
+            .collect();
+
+        // Best first: ascending HFR, descending star count
+        ranked.sort_by(|a, b| match metric {
+            PercentileMetric::Hfr => a.1.total_cmp(&b.1),
+            PercentileMetric::StarCount => b.1.total_cmp(&a.1),
+        });
+
+        let total = ranked.len();
+        // Small epsilon so e.g. 10 * 70% keeps 7 rather than 8 due to float error
+        let keep = ((total as f64 * percentile / 100.0) - 1e-9).ceil().max(0.0) as usize;
+
+        let (reason, label) = match metric {
+            PercentileMetric::Hfr => ("Percentile HFR", "HFR"),
+            PercentileMetric::StarCount => ("Percentile Stars", "Star count"),
+        };
+
+        ranked
+            .iter()
+            .enumerate()
+            .skip(keep)
+            .map(|(rank, (image, value))| StatisticalRejection {
+                image_id: image.id,
+                reason: reason.to_string(),
+                details: format!(
+                    "{} {:.3} ranks {} of {}, outside best {}%",
+                    label,
+                    value,
+                    rank + 1,
+                    total,
+                    percentile
+                ),
+                score: 0.0,
+            })
+            .collect()
+    }
+}
+
+/// Copy each image's quality score onto its rejections
+fn apply_scores(rejections: &mut [StatisticalRejection], scores: &HashMap<i32, f64>) {
+    for rejection in rejections {
+        rejection.score = scores.get(&rejection.image_id).copied().unwrap_or(0.0);
+    }
+}
+
+/// Sort images by target, filter and time, and group them by [`GroupKey`]
+fn group_images(images: &mut [ImageStatistics]) -> HashMap<GroupKey, Vec<&ImageStatistics>> {
+    // Sort images by target, filter, and time to ensure proper sequence
+    images.sort_by(|a, b| {
+        a.target_id
+            .cmp(&b.target_id)
+            .then_with(|| a.filter_name.cmp(&b.filter_name))
+            .then_with(|| a.exposure_time.cmp(&b.exposure_time))
+    });
+
+    let mut target_filter_groups: HashMap<GroupKey, Vec<&ImageStatistics>> = HashMap::new();
+    for image in images.iter() {
+        target_filter_groups
+            .entry(image.group_key())
+            .or_default()
+            .push(image);
+    }
+    target_filter_groups
 }
 
 /// Parse image metadata from JSON to extract HFR and star count
@@ -543,6 +1397,13 @@ pub fn parse_image_metadata(
     original_status: i32,
 ) -> Result<ImageStatistics> {
     let metadata: ImageMetadata = serde_json::from_str(metadata_json)?;
+    let text_rms = metadata.rms_text.as_deref().map(parse_rms_text);
+    let rms_ra = metadata.rms_ra.or(text_rms.and_then(|rms| rms.0));
+    let rms_dec = metadata.rms_dec.or(text_rms.and_then(|rms| rms.1));
+    let rms_total = metadata
+        .rms_total
+        .or(text_rms.and_then(|rms| rms.2))
+        .or_else(|| Some(rms_ra?.hypot(rms_dec?)));
 
     Ok(ImageStatistics {
         id,
@@ -552,11 +1413,42 @@ pub fn parse_image_metadata(
         hfr: metadata.hfr,
         star_count: metadata.detected_stars,
         exposure_time: metadata.exposure_start_time,
+        exposure_duration: metadata.exposure_duration,
+        // N.I.N.A. records -1 when the camera does not report gain
+        gain: metadata
+            .gain
+            .filter(|gain| *gain >= 0.0)
+            .map(|gain| gain.round() as i32),
+        rms_ra,
+        rms_dec,
+        rms_total,
         original_status,
         metadata_json: metadata_json.to_string(),
+        background: None,
+        trails: None,
     })
 }
 
+/// RA, Dec and total RMS from N.I.N.A.'s formatted text; labels are matched
+/// case-insensitively and any that are missing come back as None
+fn parse_rms_text(text: &str) -> (Option<f64>, Option<f64>, Option<f64>) {
+    let lower = text.to_lowercase();
+    let value_after = |label: &str| {
+        let start = lower.find(label)? + label.len();
+        let number: String = lower[start..]
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+        number.parse().ok()
+    };
+    (
+        value_after("ra:"),
+        value_after("dec:"),
+        value_after("tot:").or_else(|| value_after("total:")),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -582,7 +1474,9 @@ mod tests {
             "FilterName": "Ha",
             "HFR": 2.5,
             "DetectedStars": 342,
-            "ExposureStartTime": "2023-08-27T10:00:00Z"
+            "ExposureStartTime": "2023-08-27T10:00:00Z",
+            "ExposureTime": 300.0,
+            "Gain": 100
         }"#;
 
         let result = parse_image_metadata(1, 2, "Test Target", metadata_json, "Ha", 0).unwrap();
@@ -594,6 +1488,8 @@ mod tests {
         assert_eq!(result.hfr, Some(2.5));
         assert_eq!(result.star_count, Some(342));
         assert_eq!(result.exposure_time, "2023-08-27T10:00:00Z");
+        assert_eq!(result.exposure_duration, Some(300.0));
+        assert_eq!(result.gain, Some(100));
         assert_eq!(result.original_status, 0);
     }
 
@@ -643,6 +1539,7 @@ mod tests {
             image_id: 123,
             reason: "Test Reason".to_string(),
             details: "Test Details".to_string(),
+            score: 0.0,
         };
 
         assert_eq!(rejection.image_id, 123);
@@ -788,11 +1685,21 @@ mod tests {
             hfr_stddev_threshold: 3.0,
             enable_star_count_analysis: true,
             star_count_stddev_threshold: 1.5,
+            outlier_method: RobustMethod::StdDev,
             enable_distribution_analysis: false,
             median_shift_threshold: 0.2,
             enable_cloud_detection: true,
             cloud_threshold: 0.15,
             cloud_baseline_count: 3,
+            enable_gradient_analysis: false,
+            gradient_threshold: 0.2,
+            enable_trail_detection: false,
+            min_trail_length: 100.0,
+            enable_guiding_analysis: false,
+            rms_threshold: 2.0,
+            rms_stddev_threshold: 2.0,
+            keep_percentile: None,
+            percentile_metric: PercentileMetric::Hfr,
         };
 
         let grader = StatisticalGrader::new(config.clone());
@@ -816,28 +1723,14 @@ mod tests {
         let config = StatisticalGradingConfig::default();
         let grader = StatisticalGrader::new(config);
         let images = vec![
-            ImageStatistics {
-                id: 1,
-                target_id: 1,
-                target_name: "Test Target".to_string(),
-                filter_name: "Ha".to_string(),
-                hfr: Some(2.5),
-                star_count: Some(100),
-                exposure_time: "2023-08-27T10:00:00Z".to_string(),
-                original_status: 0,
-                metadata_json: "{}".to_string(),
-            },
-            ImageStatistics {
-                id: 2,
-                target_id: 1,
-                target_name: "Test Target".to_string(),
-                filter_name: "Ha".to_string(),
-                hfr: Some(2.6),
-                star_count: Some(95),
-                exposure_time: "2023-08-27T10:05:00Z".to_string(),
-                original_status: 0,
-                metadata_json: "{}".to_string(),
-            },
+            ImageStatistics::new(1, 1, "Ha")
+                .with_hfr(2.5)
+                .with_star_count(100)
+                .with_exposure_time("2023-08-27T10:00:00Z"),
+            ImageStatistics::new(2, 1, "Ha")
+                .with_hfr(2.6)
+                .with_star_count(95)
+                .with_exposure_time("2023-08-27T10:05:00Z"),
         ];
         // Less than 3 images, should not perform analysis
         let result = grader.analyze_images(images).unwrap();
@@ -851,42 +1744,42 @@ mod tests {
             hfr_stddev_threshold: 2.0,
             enable_star_count_analysis: false,
             star_count_stddev_threshold: 2.0,
+            outlier_method: RobustMethod::StdDev,
             enable_distribution_analysis: false,
             median_shift_threshold: 0.1,
             enable_cloud_detection: true,
             cloud_threshold: 0.2,    // 20% threshold
             cloud_baseline_count: 3, // Need 3 images for baseline
+            enable_gradient_analysis: false,
+            gradient_threshold: 0.2,
+            enable_trail_detection: false,
+            min_trail_length: 100.0,
+            enable_guiding_analysis: false,
+            rms_threshold: 2.0,
+            rms_stddev_threshold: 2.0,
+            keep_percentile: None,
+            percentile_metric: PercentileMetric::Hfr,
         };
         let grader = StatisticalGrader::new(config);
         let mut images = vec![];
 
         // First 3 images establish baseline (HFR around 2.5)
         for i in 1..=3 {
-            images.push(ImageStatistics {
-                id: i,
-                target_id: 1,
-                target_name: "Test Target".to_string(),
-                filter_name: "Ha".to_string(),
-                hfr: Some(2.5),
-                star_count: Some(100),
-                exposure_time: format!("2023-08-27T10:{:02}:00Z", i * 5),
-                original_status: 0,
-                metadata_json: "{}".to_string(),
-            });
+            images.push(
+                ImageStatistics::new(i, 1, "Ha")
+                    .with_hfr(2.5)
+                    .with_star_count(100)
+                    .with_exposure_time(&format!("2023-08-27T10:{:02}:00Z", i * 5)),
+            );
         }
 
         // Image 4: Cloud event - HFR jumps by 30%
-        images.push(ImageStatistics {
-            id: 4,
-            target_id: 1,
-            target_name: "Test Target".to_string(),
-            filter_name: "Ha".to_string(),
-            hfr: Some(3.25), // 30% increase from 2.5
-            star_count: Some(100),
-            exposure_time: "2023-08-27T10:20:00Z".to_string(),
-            original_status: 0,
-            metadata_json: "{}".to_string(),
-        });
+        images.push(
+            ImageStatistics::new(4, 1, "Ha")
+                .with_hfr(3.25) // 30% increase from 2.5
+                .with_star_count(100)
+                .with_exposure_time("2023-08-27T10:20:00Z"),
+        );
 
         let result = grader.analyze_images(images).unwrap();
         assert_eq!(result.len(), 1);
@@ -894,4 +1787,481 @@ mod tests {
         assert_eq!(result[0].reason, "Cloud Detection");
         assert!(result[0].details.contains("30%"));
     }
+
+    fn percentile_test_images(count: i32) -> Vec<ImageStatistics> {
+        (1..=count)
+            .map(|i| {
+                ImageStatistics::new(i, 1, "Ha")
+                    .with_hfr(2.0 + i as f64 * 0.1)
+                    .with_star_count(200 - i * 10)
+                    .with_exposure_time(&format!("2023-08-27T10:{:02}:00Z", i))
+            })
+            .collect()
+    }
+
+    fn percentile_config(keep: f64, metric: PercentileMetric) -> StatisticalGradingConfig {
+        StatisticalGradingConfig {
+            enable_hfr_analysis: false,
+            enable_star_count_analysis: false,
+            enable_distribution_analysis: false,
+            enable_cloud_detection: false,
+            keep_percentile: Some(keep),
+            percentile_metric: metric,
+            ..StatisticalGradingConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_keep_percentile_rejects_worst_hfr() {
+        let grader = StatisticalGrader::new(percentile_config(70.0, PercentileMetric::Hfr));
+        let result = grader.analyze_images(percentile_test_images(10)).unwrap();
+
+        assert_eq!(result.len(), 3);
+        let mut ids: Vec<i32> = result.iter().map(|r| r.image_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![8, 9, 10]);
+        assert!(result.iter().all(|r| r.reason == "Percentile HFR"));
+    }
+
+    #[test]
+    fn test_keep_percentile_skips_non_finite_hfr() {
+        let mut images = percentile_test_images(10);
+        images[0].hfr = Some(f64::NAN);
+        images[1].hfr = Some(f64::INFINITY);
+
+        let grader = StatisticalGrader::new(percentile_config(75.0, PercentileMetric::Hfr));
+        let result = grader.analyze_images(images).unwrap();
+
+        // The 8 finite values are ranked; the worst 2 are rejected
+        let mut ids: Vec<i32> = result.iter().map(|r| r.image_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![9, 10]);
+    }
+
+    #[test]
+    fn test_bad_frame_scores_worse_than_good_frame() {
+        let mut images = percentile_test_images(10);
+        // Flatten the set, then make one frame clearly good and one clearly bad
+        for image in &mut images {
+            image.hfr = Some(2.5 + (image.id % 3) as f64 * 0.05);
+            image.star_count = Some(200 + (image.id % 4) * 5);
+        }
+        images[2].hfr = Some(2.3);
+        images[2].star_count = Some(230);
+        images[6].hfr = Some(4.5);
+        images[6].star_count = Some(60);
+
+        let grader = StatisticalGrader::new(StatisticalGradingConfig::default());
+        let scored = grader.analyze_images_scored(images).unwrap();
+
+        assert_eq!(scored.len(), 10);
+        let score = |id: i32| scored.iter().find(|s| s.image_id == id).unwrap();
+        assert!(score(3).score < 0.0, "{:?}", score(3));
+        assert!(score(7).score > 2.0, "{:?}", score(7));
+        // Best first, and only the bad frame is rejected
+        assert_eq!(scored[0].image_id, 3);
+        assert_eq!(scored[9].image_id, 7);
+        assert!(score(3).rejections.is_empty());
+        assert!(!score(7).rejections.is_empty());
+        assert!(score(7)
+            .rejections
+            .iter()
+            .all(|r| r.score == score(7).score));
+    }
+
+    #[test]
+    fn test_frames_judged_within_exposure_group() {
+        // Short subs are sharper; 2.6 is soft for a 120s frame but sits
+        // between the two groups when they are mixed
+        let short = [2.0, 2.05, 1.95, 2.0, 2.02, 1.98, 2.03, 2.6];
+        let long = [3.0, 3.1, 2.9, 3.05, 2.95, 3.0, 3.02, 2.98];
+        let images = |with_duration: bool| {
+            short
+                .iter()
+                .map(|&hfr| (hfr, 120.0))
+                .chain(long.iter().map(|&hfr| (hfr, 300.0)))
+                .zip(1..)
+                .map(|((hfr, seconds), id)| {
+                    let image = ImageStatistics::new(id, 1, "L")
+                        .with_hfr(hfr)
+                        .with_exposure_time(&format!("2023-08-27T10:{:02}:00Z", id));
+                    if with_duration {
+                        image.with_exposure_duration(seconds).with_gain(100)
+                    } else {
+                        image
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let grader = StatisticalGrader::new(StatisticalGradingConfig {
+            enable_star_count_analysis: false,
+            enable_distribution_analysis: false,
+            enable_cloud_detection: false,
+            ..StatisticalGradingConfig::default()
+        });
+
+        let grouped = grader.analyze_images(images(true)).unwrap();
+        let ids: Vec<i32> = grouped.iter().map(|r| r.image_id).collect();
+        assert_eq!(ids, vec![8]);
+        assert!(
+            grouped[0].details.contains("from mean 2.0"),
+            "{}",
+            grouped[0].details
+        );
+
+        // Without durations everything falls back to one target/filter group
+        assert!(grader.analyze_images(images(false)).unwrap().is_empty());
+        let baselines = grader.compute_baselines(&images(true), 0);
+        assert_eq!(baselines.len(), 2);
+        assert_eq!(grader.compute_baselines(&images(false), 0).len(), 1);
+    }
+
+    #[test]
+    fn test_mad_outliers_resist_skew() {
+        // A single very soft frame inflates σ enough to hide the milder one
+        let hfrs = [2.0, 2.1, 1.9, 2.2, 1.8, 2.0, 2.1, 1.9, 3.0, 6.0];
+        let images = || {
+            hfrs.iter()
+                .zip(1..)
+                .map(|(&hfr, id)| {
+                    ImageStatistics::new(id, 1, "Ha")
+                        .with_hfr(hfr)
+                        .with_star_count(200)
+                        .with_exposure_time(&format!("2023-08-27T10:{:02}:00Z", id))
+                })
+                .collect::<Vec<_>>()
+        };
+        let rejected_ids = |method: RobustMethod| {
+            let grader = StatisticalGrader::new(StatisticalGradingConfig {
+                enable_star_count_analysis: false,
+                enable_distribution_analysis: false,
+                enable_cloud_detection: false,
+                outlier_method: method,
+                ..StatisticalGradingConfig::default()
+            });
+            let rejections = grader.analyze_images(images()).unwrap();
+            assert!(rejections.iter().all(|r| r.reason == "Statistical HFR"));
+            let mut ids: Vec<i32> = rejections.iter().map(|r| r.image_id).collect();
+            ids.sort();
+            (ids, rejections)
+        };
+
+        let (stddev_ids, _) = rejected_ids(RobustMethod::StdDev);
+        let (mad_ids, mad_rejections) = rejected_ids(RobustMethod::Mad);
+
+        assert_eq!(stddev_ids, vec![10]);
+        assert_eq!(mad_ids, vec![9, 10]);
+        assert!(
+            mad_rejections[0].details.contains("MAD from median"),
+            "{}",
+            mad_rejections[0].details
+        );
+    }
+
+    #[test]
+    fn test_keep_percentile_star_count() {
+        let grader = StatisticalGrader::new(percentile_config(50.0, PercentileMetric::StarCount));
+        let result = grader.analyze_images(percentile_test_images(10)).unwrap();
+
+        let mut ids: Vec<i32> = result.iter().map(|r| r.image_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn test_keep_percentile_out_of_range() {
+        let grader = StatisticalGrader::new(percentile_config(0.0, PercentileMetric::Hfr));
+        assert!(grader.analyze_images(percentile_test_images(5)).is_err());
+
+        let grader = StatisticalGrader::new(percentile_config(100.0, PercentileMetric::Hfr));
+        assert!(grader
+            .analyze_images(percentile_test_images(5))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_partial_config_file_keeps_defaults() {
+        let path = std::env::temp_dir().join(format!(
+            "psf_guard_grading_config_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, r#"{ "cloud_threshold": 0.35 }"#).unwrap();
+
+        let config = StatisticalGradingConfig::from_json_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let defaults = StatisticalGradingConfig::default();
+        assert_eq!(config.cloud_threshold, 0.35);
+        assert_eq!(config.enable_hfr_analysis, defaults.enable_hfr_analysis);
+        assert_eq!(config.hfr_stddev_threshold, defaults.hfr_stddev_threshold);
+        assert_eq!(
+            config.enable_star_count_analysis,
+            defaults.enable_star_count_analysis
+        );
+        assert_eq!(
+            config.star_count_stddev_threshold,
+            defaults.star_count_stddev_threshold
+        );
+        assert_eq!(
+            config.enable_distribution_analysis,
+            defaults.enable_distribution_analysis
+        );
+        assert_eq!(
+            config.median_shift_threshold,
+            defaults.median_shift_threshold
+        );
+        assert_eq!(
+            config.enable_cloud_detection,
+            defaults.enable_cloud_detection
+        );
+        assert_eq!(config.cloud_baseline_count, defaults.cloud_baseline_count);
+        assert_eq!(config.keep_percentile, None);
+        assert_eq!(config.percentile_metric, PercentileMetric::Hfr);
+    }
+
+    #[test]
+    fn test_config_file_rejects_unknown_fields() {
+        let path = std::env::temp_dir().join(format!(
+            "psf_guard_grading_config_typo_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, r#"{ "cloud_treshold": 0.35 }"#).unwrap();
+
+        let result = StatisticalGradingConfig::from_json_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    fn background_test_image(id: i32, ramp: f64) -> ImageStatistics {
+        // 1000 ADU sky with a left-to-right ramp of `ramp` ADU across the frame
+        let (width, height) = (64, 48);
+        let data = (0..width * height)
+            .map(|i| (1000.0 + ramp * (i % width) as f64 / width as f64) as u16)
+            .collect();
+        let fits = FitsImage {
+            width,
+            height,
+            data,
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+
+        ImageStatistics {
+            background: Some(BackgroundLevels::measure(&fits)),
+            ..ImageStatistics::new(id, 1, "L")
+                .with_hfr(2.5)
+                .with_star_count(100)
+                .with_exposure_time(&format!("2023-08-27T10:{:02}:00Z", id))
+        }
+    }
+
+    #[test]
+    fn test_background_gradient_rejects_ramped_frame() {
+        let flat = background_test_image(1, 0.0);
+        let background = flat.background.unwrap();
+        assert_eq!(background.quadrant_medians, [1000.0; 4]);
+
+        let ramped = background_test_image(2, 800.0);
+        let quadrants = ramped.background.unwrap().quadrant_medians;
+        assert!(quadrants[1] > quadrants[0] + 300.0);
+        assert_eq!(quadrants[0], quadrants[2]);
+
+        let config = StatisticalGradingConfig {
+            enable_hfr_analysis: false,
+            enable_star_count_analysis: false,
+            enable_distribution_analysis: false,
+            enable_cloud_detection: false,
+            enable_gradient_analysis: true,
+            ..StatisticalGradingConfig::default()
+        };
+        let mut unmeasured = background_test_image(3, 800.0);
+        unmeasured.background = None;
+
+        let result = StatisticalGrader::new(config)
+            .analyze_images(vec![flat, ramped, unmeasured])
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].image_id, 2);
+        assert_eq!(result[0].reason, "Background Gradient");
+    }
+
+    #[test]
+    fn test_trails_reject_only_long_enough_trails() {
+        let with_trail = |id: i32, count: usize, longest_length: f64| ImageStatistics {
+            background: None,
+            trails: Some(TrailSummary {
+                count,
+                longest_length,
+            }),
+            ..background_test_image(id, 0.0)
+        };
+
+        let config = StatisticalGradingConfig {
+            enable_hfr_analysis: false,
+            enable_star_count_analysis: false,
+            enable_distribution_analysis: false,
+            enable_cloud_detection: false,
+            enable_trail_detection: true,
+            min_trail_length: 150.0,
+            ..StatisticalGradingConfig::default()
+        };
+
+        let result = StatisticalGrader::new(config)
+            .analyze_images(vec![
+                with_trail(1, 0, 0.0),
+                with_trail(2, 2, 420.0),
+                with_trail(3, 1, 120.0),
+                background_test_image(4, 0.0),
+            ])
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].image_id, 2);
+        assert_eq!(result[0].reason, "Satellite Trail");
+    }
+
+    /// Two target/filter groups of steady frames with a few bad ones late in the night
+    fn baseline_test_images() -> Vec<ImageStatistics> {
+        let mut images = Vec::new();
+        for (target_id, filter) in [(1, "Ha"), (2, "OIII")] {
+            for i in 1..=12 {
+                let id = target_id * 100 + i;
+                let (hfr, stars) = match i {
+                    10 => (4.5, 210),
+                    12 => (2.05, 40),
+                    _ => (2.0 + (i % 4) as f64 * 0.05, 200 + (i % 3) * 5),
+                };
+                images.push(
+                    ImageStatistics::new(id, target_id, filter)
+                        .with_target_name(&format!("Target {}", target_id))
+                        .with_hfr(hfr)
+                        .with_star_count(stars)
+                        .with_exposure_time(&format!("2023-08-27T10:{:02}:00Z", i)),
+                );
+            }
+        }
+        images
+    }
+
+    fn rejected_ids(rejections: &[StatisticalRejection]) -> Vec<(i32, String)> {
+        let mut ids: Vec<_> = rejections
+            .iter()
+            .map(|r| (r.image_id, r.reason.clone()))
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_guiding_rms_outlier_in_tight_group() {
+        let config = StatisticalGradingConfig {
+            enable_hfr_analysis: false,
+            enable_star_count_analysis: false,
+            enable_distribution_analysis: false,
+            enable_cloud_detection: false,
+            enable_guiding_analysis: true,
+            ..StatisticalGradingConfig::default()
+        };
+
+        // Eight frames guided at ~0.5", one at 1.4" (under the absolute
+        // threshold, but far outside the group), one over the threshold and
+        // one without a recorded RMS
+        let mut images: Vec<ImageStatistics> = [0.50, 0.52, 0.48, 0.55, 0.51, 0.49, 0.53, 0.50]
+            .iter()
+            .enumerate()
+            .map(|(i, &rms)| {
+                ImageStatistics::new(i as i32 + 1, 1, "L").with_guiding_rms(rms * 0.8, rms * 0.6)
+            })
+            .collect();
+        images.push(ImageStatistics::new(9, 1, "L").with_guiding_rms(1.12, 0.84));
+        images.push(ImageStatistics::new(10, 1, "L").with_guiding_rms(2.4, 1.8));
+        images.push(ImageStatistics::new(11, 1, "L"));
+
+        let mut result = StatisticalGrader::new(config)
+            .analyze_images(images)
+            .unwrap();
+        result.sort_by_key(|r| r.image_id);
+        let rejected: Vec<i32> = result.iter().map(|r| r.image_id).collect();
+        assert_eq!(rejected, vec![9, 10]);
+        assert!(result.iter().all(|r| r.reason == "Guiding RMS"));
+        assert!(result[1].details.contains("exceeds"));
+    }
+
+    #[test]
+    fn test_guiding_rms_from_metadata() {
+        let parse = |metadata: &str| parse_image_metadata(1, 1, "M31", metadata, "L", 0).unwrap();
+        let base = r#""FileName": "a.fits", "FilterName": "L", "ExposureStartTime": "2024-01-01T00:00:00""#;
+
+        let scheduler = parse(&format!(
+            r#"{{{}, "GuidingRMSRAArcSec": 0.3, "GuidingRMSDECArcSec": 0.4}}"#,
+            base
+        ));
+        assert_eq!(scheduler.rms_total, Some(0.5));
+
+        let text = parse(&format!(
+            r#"{{{}, "RmsText": "RA: 0.41\" DEC: 0.32\" Tot: 0.52\""}}"#,
+            base
+        ));
+        assert_eq!(text.rms_ra, Some(0.41));
+        assert_eq!(text.rms_dec, Some(0.32));
+        assert_eq!(text.rms_total, Some(0.52));
+
+        let missing = parse(&format!("{{{}}}", base));
+        assert_eq!(missing.rms_total, None);
+    }
+
+    #[test]
+    fn test_incremental_matches_full_analysis() {
+        let config = StatisticalGradingConfig {
+            enable_distribution_analysis: false,
+            enable_cloud_detection: false,
+            ..StatisticalGradingConfig::default()
+        };
+        let grader = StatisticalGrader::new(config);
+
+        let full = grader.analyze_images(baseline_test_images()).unwrap();
+
+        // Baselines from the first eight frames of each group, as a previous night left them
+        let earlier: Vec<_> = baseline_test_images()
+            .into_iter()
+            .filter(|img| img.id % 100 <= 8)
+            .collect();
+        let stored = grader.compute_baselines(&earlier, 1);
+        let incremental = grader
+            .analyze_images_incremental(baseline_test_images(), &stored, 2)
+            .unwrap();
+
+        assert_eq!(incremental.merged_groups, 2);
+        assert_eq!(incremental.recomputed_groups, 0);
+        assert!(!full.is_empty());
+        assert_eq!(rejected_ids(&incremental.rejections), rejected_ids(&full));
+
+        // The merged baselines equal ones computed over every frame
+        let mut recomputed = grader.compute_baselines(&baseline_test_images(), 2);
+        let mut merged = incremental.baselines;
+        recomputed.sort_by_key(|b| b.target_id);
+        merged.sort_by_key(|b| b.target_id);
+        for (merged, full) in merged.iter().zip(&recomputed) {
+            assert_eq!(merged.sample_count, full.sample_count);
+            assert_eq!(merged.last_image_id, full.last_image_id);
+            assert!((merged.hfr.mean - full.hfr.mean).abs() < 1e-9);
+            assert!((merged.hfr.stddev - full.hfr.stddev).abs() < 1e-9);
+            assert!((merged.star_count.stddev - full.star_count.stddev).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_stale_baseline_is_recomputed() {
+        let grader = StatisticalGrader::new(StatisticalGradingConfig::default());
+        let mut stored = grader.compute_baselines(&baseline_test_images(), 1);
+        // One group's baseline claims an image that no longer exists
+        stored[0].sample_count += 1;
+
+        let analysis = grader
+            .analyze_images_incremental(baseline_test_images(), &stored, 2)
+            .unwrap();
+
+        assert_eq!(analysis.merged_groups, 1);
+        assert_eq!(analysis.recomputed_groups, 1);
+    }
 }
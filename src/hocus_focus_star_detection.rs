@@ -7,9 +7,33 @@
 /// - Kappa-Sigma noise estimation for adaptive thresholding
 /// - Hot pixel filtering
 /// - Multi-criteria star validation
+use crate::accord_imaging::{
+    convolve_horizontal_f64, convolve_vertical_f64, create_gaussian_kernel, Median,
+};
+use crate::image_analysis::is_uniform;
 use crate::opencv_morphology::OpenCVMorphology;
 use crate::opencv_wavelets::WaveletStructureRemover;
-use crate::psf_fitting::{PSFModel, PSFType};
+use crate::psf_fitting::{PSFFitter, PSFModel, PSFType};
+use crate::utils::OnlineStats;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+/// Stars reaching this fraction of the saturation level count as saturated
+const SATURATION_FRACTION: f64 = 0.99;
+
+/// Range of wavelet layers picked from the frame size when none are set
+const MIN_AUTO_STRUCTURE_LAYERS: usize = 3;
+const MAX_AUTO_STRUCTURE_LAYERS: usize = 6;
+
+/// Noise reduction applied before structure detection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseReductionKind {
+    None,
+    /// Gaussian blur with a kernel of `noise_reduction_radius * 2 + 1`
+    Gaussian,
+    /// 3x3 median filter, as NINA's median option; suits hot-pixel-heavy data
+    Median,
+}
 
 /// Star detection parameters for HocusFocus algorithm
 #[derive(Debug, Clone)]
@@ -17,14 +41,15 @@ pub struct HocusFocusParams {
     // Preprocessing
     pub hotpixel_filtering: bool,
     pub hotpixel_threshold: f64, // Percent of max ADU for hot pixel threshold
+    pub noise_reduction: NoiseReductionKind,
     pub noise_reduction_radius: usize, // Half-size of Gaussian kernel
 
     // Note: OpenCV operations are always attempted first with automatic fallback
 
     // Structure detection
-    pub structure_layers: usize, // Number of wavelet layers for large structure removal
-    pub noise_clipping_multiplier: f64, // Sigma multiplier for noise threshold
-    pub star_clipping_multiplier: f64, // Sigma multiplier for star pixel filtering
+    pub structure_layers: Option<usize>, // Wavelet layers for large structure removal (None = from frame size)
+    pub noise_clipping_multiplier: f64,  // Sigma multiplier for noise threshold
+    pub star_clipping_multiplier: f64,   // Sigma multiplier for star pixel filtering
 
     // Star validation criteria
     pub min_star_size: usize,
@@ -36,9 +61,14 @@ pub struct HocusFocusParams {
     pub star_center_tolerance: f64, // Fraction of box size for center tolerance
     pub saturation_threshold: f64, // ADU value for saturation
     pub min_hfr: f64,        // Minimum HFR threshold
+    pub max_candidates: usize, // Keep only the brightest candidates beyond this (0 = unlimited)
 
     // PSF fitting
     pub psf_type: PSFType, // PSF model type to fit (None, Gaussian, Moffat4)
+
+    // Diagnostics
+    pub verbose: bool,       // Print per-image pipeline diagnostics to stderr
+    pub keep_rejected: bool, // Return candidates that failed validation, with the reason
 }
 
 impl Default for HocusFocusParams {
@@ -46,23 +76,51 @@ impl Default for HocusFocusParams {
         Self {
             hotpixel_filtering: true,
             hotpixel_threshold: 0.001, // 0.1% of max ADU
+            noise_reduction: NoiseReductionKind::Gaussian,
             noise_reduction_radius: 4, // Actual default from user
 
             // OpenCV operations always attempted with automatic fallback
-            structure_layers: 4,
+            structure_layers: None, // Sized to the frame, see resolved_structure_layers
             noise_clipping_multiplier: 4.0,
             star_clipping_multiplier: 2.0,
             min_star_size: 5, // Minimum bounding box size - actual default
             max_star_size: 150,
-            sensitivity: 10.0,                    // Brightness sensitivity
-            peak_response: 0.75,                  // 75% - actual default
-            max_distortion: 0.5,                  // Actual default
-            background_box_expansion: 3,          // Actual default
-            star_center_tolerance: 0.3,           // 30% - actual default
-            saturation_threshold: 65535.0 * 0.99, // 99% of max
-            min_hfr: 1.5,                         // Actual default
-            psf_type: PSFType::None,              // No PSF fitting by default
+            sensitivity: 10.0,           // Brightness sensitivity
+            peak_response: 0.75,         // 75% - actual default
+            max_distortion: 0.5,         // Actual default
+            background_box_expansion: 3, // Actual default
+            star_center_tolerance: 0.3,  // 30% - actual default
+            saturation_threshold: 65535.0 * SATURATION_FRACTION, // 99% of max
+            min_hfr: 1.5,                // Actual default
+            max_candidates: 0,           // Unlimited: star count is a grading metric
+            psf_type: PSFType::None,     // No PSF fitting by default
+            verbose: false,
+            keep_rejected: false,
+        }
+    }
+}
+
+impl HocusFocusParams {
+    /// Wavelet layers used for a `width` x `height` frame: the explicit
+    /// `structure_layers`, else one per octave of the shorter side beyond
+    /// 128 px, clamped to 3..=6: 3 below 2048 px, 4 up to 4095 px, 5 up to
+    /// 8191 px
+    pub fn resolved_structure_layers(&self, width: usize, height: usize) -> usize {
+        self.structure_layers.unwrap_or_else(|| {
+            let octaves = width.min(height).max(1).ilog2() as usize;
+            octaves
+                .saturating_sub(7)
+                .clamp(MIN_AUTO_STRUCTURE_LAYERS, MAX_AUTO_STRUCTURE_LAYERS)
+        })
+    }
+
+    /// Treat stars as saturated relative to `level`, on the 0-65535 scale of the
+    /// detection data; None keeps the current threshold
+    pub fn with_saturation_level(mut self, level: Option<f64>) -> Self {
+        if let Some(level) = level {
+            self.saturation_threshold = level * SATURATION_FRACTION;
         }
+        self
     }
 }
 
@@ -80,10 +138,49 @@ pub struct HocusFocusStar {
     pub psf_model: Option<PSFModel>, // PSF fitting results
 }
 
+/// Why a star candidate failed validation, in the order the checks run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StarRejectReason {
+    TooSmall,
+    TouchingBorder,
+    TooDistorted,
+    Saturated,
+    LowSnr,
+    OffCenter,
+    TooFlat,
+    HfrBelowMin,
+}
+
+impl StarRejectReason {
+    /// Single letter used to mark the reason on annotated images
+    pub fn glyph(&self) -> char {
+        match self {
+            StarRejectReason::TooSmall => 'T',
+            StarRejectReason::TouchingBorder => 'B',
+            StarRejectReason::TooDistorted => 'D',
+            StarRejectReason::Saturated => 'S',
+            StarRejectReason::LowSnr => 'N',
+            StarRejectReason::OffCenter => 'C',
+            StarRejectReason::TooFlat => 'F',
+            StarRejectReason::HfrBelowMin => 'H',
+        }
+    }
+}
+
+/// A candidate that failed validation, kept when `keep_rejected` is set
+#[derive(Debug, Clone)]
+pub struct RejectedStar {
+    pub position: (f64, f64),
+    pub hfr: f64,
+    pub reason: StarRejectReason,
+}
+
 /// Star detection result
 #[derive(Debug, Clone)]
 pub struct HocusFocusDetectionResult {
     pub stars: Vec<HocusFocusStar>,
+    /// Candidates that failed validation; empty unless `keep_rejected` is set
+    pub rejected: Vec<RejectedStar>,
     pub average_hfr: f64,
     pub average_fwhm: f64,
     pub noise_sigma: f64,
@@ -104,6 +201,18 @@ pub fn detect_stars_hocus_focus(
     height: usize,
     params: &HocusFocusParams,
 ) -> HocusFocusDetectionResult {
+    // A uniform frame (all-zero, saturated) has no structure to detect
+    if is_uniform(data) {
+        return HocusFocusDetectionResult {
+            stars: vec![],
+            rejected: vec![],
+            average_hfr: 0.0,
+            average_fwhm: 0.0,
+            noise_sigma: 0.0,
+            background_mean: data.first().map_or(0.0, |&v| v as f64),
+        };
+    }
+
     // Step 1: Apply hot pixel filtering if enabled
     let mut working_data = if params.hotpixel_filtering {
         apply_hotpixel_filter(data, width, height, params.hotpixel_threshold)
@@ -112,10 +221,16 @@ pub fn detect_stars_hocus_focus(
     };
 
     // Step 2: Apply noise reduction if configured
-    if params.noise_reduction_radius > 0 {
-        // HocusFocus uses kernel_size = radius * 2 + 1
-        let kernel_size = params.noise_reduction_radius * 2 + 1;
-        working_data = apply_gaussian_blur(&working_data, width, height, kernel_size);
+    match params.noise_reduction {
+        NoiseReductionKind::Gaussian if params.noise_reduction_radius > 0 => {
+            // HocusFocus uses kernel_size = radius * 2 + 1
+            let kernel_size = params.noise_reduction_radius * 2 + 1;
+            working_data = apply_gaussian_blur(&working_data, width, height, kernel_size);
+        }
+        NoiseReductionKind::Median => {
+            working_data = Median.apply(&working_data, width, height);
+        }
+        NoiseReductionKind::Gaussian | NoiseReductionKind::None => {}
     }
 
     // Step 3: Create structure map by removing large structures
@@ -125,6 +240,7 @@ pub fn detect_stars_hocus_focus(
             eprintln!("Error creating structure map: {}", e);
             return HocusFocusDetectionResult {
                 stars: vec![],
+                rejected: vec![],
                 average_hfr: 0.0,
                 average_fwhm: 0.0,
                 noise_sigma: 0.0,
@@ -141,29 +257,33 @@ pub fn detect_stars_hocus_focus(
         params.noise_clipping_multiplier,
     );
 
-    // Debug output
-    eprintln!(
-        "Debug HocusFocus: noise_sigma: {:.3}, background_mean: {:.3}",
-        noise_estimate.sigma, noise_estimate.background_mean
-    );
+    if params.verbose {
+        eprintln!(
+            "Debug HocusFocus: noise_sigma: {:.3}, background_mean: {:.3}",
+            noise_estimate.sigma, noise_estimate.background_mean
+        );
+    }
 
     // Step 5: Binarize structure map using noise threshold
     let median = calculate_median(&structure_map);
     let threshold = median + params.noise_clipping_multiplier * noise_estimate.sigma;
 
-    eprintln!(
-        "Debug HocusFocus: median: {:.3}, threshold: {:.3}",
-        median, threshold
-    );
+    if params.verbose {
+        eprintln!(
+            "Debug HocusFocus: median: {:.3}, threshold: {:.3}",
+            median, threshold
+        );
+    }
     let mut binary_map = binarize(&structure_map, threshold);
 
-    // Debug: Count non-zero pixels in binary map
     let non_zero = binary_map.iter().filter(|&&x| x).count();
-    eprintln!(
-        "Debug HocusFocus: Binary map has {} non-zero pixels ({:.2}%)",
-        non_zero,
-        non_zero as f64 / binary_map.len() as f64 * 100.0
-    );
+    if params.verbose {
+        eprintln!(
+            "Debug HocusFocus: Binary map has {} non-zero pixels ({:.2}%)",
+            non_zero,
+            non_zero as f64 / binary_map.len() as f64 * 100.0
+        );
+    }
 
     // Apply erosion to break up connected components
     if non_zero > structure_map.len() / 100 {
@@ -174,6 +294,7 @@ pub fn detect_stars_hocus_focus(
                 eprintln!("Error applying erosion: {}", e);
                 return HocusFocusDetectionResult {
                     stars: vec![],
+                    rejected: vec![],
                     average_hfr: 0.0,
                     average_fwhm: 0.0,
                     noise_sigma: 0.0,
@@ -181,23 +302,34 @@ pub fn detect_stars_hocus_focus(
                 };
             }
         };
-        let eroded_count = binary_map.iter().filter(|&&x| x).count();
-        eprintln!(
-            "Debug HocusFocus: After erosion: {} non-zero pixels ({:.2}%)",
-            eroded_count,
-            eroded_count as f64 / binary_map.len() as f64 * 100.0
-        );
+        if params.verbose {
+            let eroded_count = binary_map.iter().filter(|&&x| x).count();
+            eprintln!(
+                "Debug HocusFocus: After erosion: {} non-zero pixels ({:.2}%)",
+                eroded_count,
+                eroded_count as f64 / binary_map.len() as f64 * 100.0
+            );
+        }
     }
 
     // Step 6: Find star candidates
     let candidates = find_star_candidates(&binary_map, width, height, params);
-    eprintln!(
-        "Debug HocusFocus: Found {} star candidates",
-        candidates.len()
+    if params.verbose {
+        eprintln!(
+            "Debug HocusFocus: Found {} star candidates",
+            candidates.len()
+        );
+    }
+    let candidates = limit_candidates(
+        candidates,
+        &working_data,
+        width,
+        params.max_candidates,
+        params.verbose,
     );
 
     // Step 7: Measure and validate stars
-    let stars = measure_stars(
+    let (stars, rejected) = measure_stars(
         &working_data,
         width,
         height,
@@ -205,7 +337,9 @@ pub fn detect_stars_hocus_focus(
         params,
         &noise_estimate,
     );
-    eprintln!("Debug HocusFocus: {} stars passed validation", stars.len());
+    if params.verbose {
+        eprintln!("Debug HocusFocus: {} stars passed validation", stars.len());
+    }
 
     // Calculate statistics
     let average_hfr = if !stars.is_empty() {
@@ -222,6 +356,7 @@ pub fn detect_stars_hocus_focus(
 
     HocusFocusDetectionResult {
         stars,
+        rejected,
         average_hfr,
         average_fwhm,
         noise_sigma: noise_estimate.sigma,
@@ -270,42 +405,21 @@ fn apply_hotpixel_filter(
 }
 
 /// Apply Gaussian blur for noise reduction
+///
+/// Border pixels within the kernel radius are left at zero.
 fn apply_gaussian_blur(data: &[u16], width: usize, height: usize, kernel_size: usize) -> Vec<u16> {
-    // Generate Gaussian kernel
     let radius = kernel_size / 2;
-    let mut kernel = vec![0.0; kernel_size * kernel_size];
     let sigma = radius as f64 / 2.0;
-    let two_sigma_sq = 2.0 * sigma * sigma;
-    let mut sum = 0.0;
-
-    for y in 0..kernel_size {
-        for x in 0..kernel_size {
-            let dx = x as f64 - radius as f64;
-            let dy = y as f64 - radius as f64;
-            let value = (-((dx * dx + dy * dy) / two_sigma_sq)).exp();
-            kernel[y * kernel_size + x] = value;
-            sum += value;
-        }
-    }
+    let kernel = create_gaussian_kernel(kernel_size, sigma);
 
-    // Normalize kernel
-    for k in kernel.iter_mut() {
-        *k /= sum;
-    }
+    // The 2D Gaussian is separable, so two 1D passes replace the full 2D kernel
+    let horizontal = convolve_horizontal_f64(data, width, height, &kernel);
+    let blurred = convolve_vertical_f64(&horizontal, width, height, &kernel);
 
-    // Apply convolution
     let mut result = vec![0u16; width * height];
-    for y in radius..(height - radius) {
-        for x in radius..(width - radius) {
-            let mut sum = 0.0;
-            for ky in 0..kernel_size {
-                for kx in 0..kernel_size {
-                    let sy = y + ky - radius;
-                    let sx = x + kx - radius;
-                    sum += data[sy * width + sx] as f64 * kernel[ky * kernel_size + kx];
-                }
-            }
-            result[y * width + x] = sum as u16;
+    for y in radius..height.saturating_sub(radius) {
+        for x in radius..width.saturating_sub(radius) {
+            result[y * width + x] = blurred[y * width + x] as u16;
         }
     }
 
@@ -320,9 +434,10 @@ fn create_structure_map(
     params: &HocusFocusParams,
 ) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
     let float_data: Vec<f64> = data.iter().map(|&v| v as f64).collect();
+    let structure_layers = params.resolved_structure_layers(width, height);
 
     // Compute wavelet decomposition using OpenCV enhanced version
-    let wavelet_remover = WaveletStructureRemover::new(params.structure_layers);
+    let wavelet_remover = WaveletStructureRemover::new(structure_layers);
     let residual = wavelet_remover
         .remove_structures(&float_data, width, height)
         .map_err(|e| format!("OpenCV wavelet removal failed: {}", e))?;
@@ -333,76 +448,60 @@ fn create_structure_map(
         structure_map[i] = (structure_map[i] - residual[i]).max(0.0);
     }
 
-    // Debug: Check structure map statistics
-    let min = structure_map.iter().fold(f64::INFINITY, |a, &b| a.min(b));
-    let max = structure_map
-        .iter()
-        .fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-    let non_zero = structure_map.iter().filter(|&&v| v > 0.0).count();
-
-    // Check how many pixels are above various thresholds
-    let above_10 = structure_map.iter().filter(|&&v| v > 10.0).count();
-    let above_50 = structure_map.iter().filter(|&&v| v > 50.0).count();
-    let above_100 = structure_map.iter().filter(|&&v| v > 100.0).count();
-
-    eprintln!(
-        "Debug structure_map: min={:.1}, max={:.1}, non_zero={} ({:.1}%)",
-        min,
-        max,
-        non_zero,
-        non_zero as f64 / structure_map.len() as f64 * 100.0
-    );
-    eprintln!(
-        "  Above 10: {} ({:.1}%), Above 50: {} ({:.1}%), Above 100: {} ({:.1}%)",
-        above_10,
-        above_10 as f64 / structure_map.len() as f64 * 100.0,
-        above_50,
-        above_50 as f64 / structure_map.len() as f64 * 100.0,
-        above_100,
-        above_100 as f64 / structure_map.len() as f64 * 100.0
-    );
+    if params.verbose {
+        // Check structure map statistics
+        let min = structure_map.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let max = structure_map
+            .iter()
+            .fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let non_zero = structure_map.iter().filter(|&&v| v > 0.0).count();
+
+        // Check how many pixels are above various thresholds
+        let above_10 = structure_map.iter().filter(|&&v| v > 10.0).count();
+        let above_50 = structure_map.iter().filter(|&&v| v > 50.0).count();
+        let above_100 = structure_map.iter().filter(|&&v| v > 100.0).count();
+
+        eprintln!(
+            "Debug structure_map: min={:.1}, max={:.1}, non_zero={} ({:.1}%)",
+            min,
+            max,
+            non_zero,
+            non_zero as f64 / structure_map.len() as f64 * 100.0
+        );
+        eprintln!(
+            "  Above 10: {} ({:.1}%), Above 50: {} ({:.1}%), Above 100: {} ({:.1}%)",
+            above_10,
+            above_10 as f64 / structure_map.len() as f64 * 100.0,
+            above_50,
+            above_50 as f64 / structure_map.len() as f64 * 100.0,
+            above_100,
+            above_100 as f64 / structure_map.len() as f64 * 100.0
+        );
+    }
 
     // Apply smoothing to blend edges
-    let kernel_size = params.structure_layers * 2 + 1;
+    let kernel_size = structure_layers * 2 + 1;
     smooth_gaussian(&mut structure_map, width, height, kernel_size);
 
     Ok(structure_map)
 }
 
 /// Smooth with Gaussian kernel
+///
+/// Border pixels within the kernel radius keep their original values.
 fn smooth_gaussian(data: &mut [f64], width: usize, height: usize, kernel_size: usize) {
     let sigma = kernel_size as f64 / 3.0;
     let radius = kernel_size / 2;
+    let kernel = create_gaussian_kernel(kernel_size, sigma);
 
-    // Generate kernel
-    let mut kernel = vec![0.0; kernel_size * kernel_size];
-    let mut sum = 0.0;
-    for y in 0..kernel_size {
-        for x in 0..kernel_size {
-            let dx = x as f64 - radius as f64;
-            let dy = y as f64 - radius as f64;
-            let value = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
-            kernel[y * kernel_size + x] = value;
-            sum += value;
-        }
-    }
-    for k in kernel.iter_mut() {
-        *k /= sum;
-    }
+    let horizontal = convolve_horizontal_f64(data, width, height, &kernel);
+    let smoothed = convolve_vertical_f64(&horizontal, width, height, &kernel);
 
-    // Apply convolution
-    let original = data.to_vec();
-    for y in radius..(height - radius) {
-        for x in radius..(width - radius) {
-            let mut sum = 0.0;
-            for ky in 0..kernel_size {
-                for kx in 0..kernel_size {
-                    let sy = y + ky - radius;
-                    let sx = x + kx - radius;
-                    sum += original[sy * width + sx] * kernel[ky * kernel_size + kx];
-                }
-            }
-            data[y * width + x] = sum;
+    let (x_start, x_end) = (radius, width.saturating_sub(radius));
+    for y in radius..height.saturating_sub(radius) {
+        let row = y * width;
+        if x_start < x_end {
+            data[row + x_start..row + x_end].copy_from_slice(&smoothed[row + x_start..row + x_end]);
         }
     }
 }
@@ -421,29 +520,23 @@ fn kappa_sigma_noise_estimate(
     let mut last_mean = 1.0;
     let mut num_iterations = 0;
 
-    // Work with a copy of the data
-    let data_vec: Vec<f64> = data.to_vec();
-
     while num_iterations < max_iterations {
-        // Create mask for values below threshold
-        let mask: Vec<f64> = if num_iterations > 0 {
-            data_vec
-                .iter()
+        // Mean and standard deviation of the values below the threshold
+        let stats: OnlineStats = if num_iterations > 0 {
+            data.iter()
                 .filter(|&&x| x > f64::EPSILON && x < threshold - f64::EPSILON)
                 .copied()
                 .collect()
         } else {
-            data_vec.clone()
+            data.iter().copied().collect()
         };
 
-        if mask.is_empty() {
+        if stats.count() == 0 {
             break;
         }
 
-        // Calculate mean and standard deviation
-        let mean = mask.iter().sum::<f64>() / mask.len() as f64;
-        let variance = mask.iter().map(|&x| (x - mean).powi(2)).sum::<f64>() / mask.len() as f64;
-        let sigma = variance.sqrt();
+        let mean = stats.mean();
+        let sigma = stats.std_dev();
 
         num_iterations += 1;
 
@@ -610,10 +703,12 @@ fn find_star_candidates(
             // Check size constraints BEFORE clearing the map
             if star_bounds.2 < params.min_star_size || star_bounds.3 < params.min_star_size {
                 too_small += 1;
-                eprintln!(
-                    "  Structure too small: {}x{} at ({},{})",
-                    star_bounds.2, star_bounds.3, star_bounds.0, star_bounds.1
-                );
+                if params.verbose {
+                    eprintln!(
+                        "  Structure too small: {}x{} at ({},{})",
+                        star_bounds.2, star_bounds.3, star_bounds.0, star_bounds.1
+                    );
+                }
                 // Still need to clear to avoid re-processing
                 for sy in star_bounds.1..(star_bounds.1 + star_bounds.3).min(height) {
                     for sx in star_bounds.0..(star_bounds.0 + star_bounds.2).min(width) {
@@ -625,10 +720,12 @@ fn find_star_candidates(
 
             if star_bounds.2 > params.max_star_size || star_bounds.3 > params.max_star_size {
                 too_large += 1;
-                eprintln!(
-                    "  Structure too large: {}x{} at ({},{})",
-                    star_bounds.2, star_bounds.3, star_bounds.0, star_bounds.1
-                );
+                if params.verbose {
+                    eprintln!(
+                        "  Structure too large: {}x{} at ({},{})",
+                        star_bounds.2, star_bounds.3, star_bounds.0, star_bounds.1
+                    );
+                }
                 // Still need to clear to avoid re-processing
                 for sy in star_bounds.1..(star_bounds.1 + star_bounds.3).min(height) {
                     for sx in star_bounds.0..(star_bounds.0 + star_bounds.2).min(width) {
@@ -659,17 +756,58 @@ fn find_star_candidates(
         }
     }
 
-    eprintln!(
-        "Debug star scanning: total_structures={}, too_small={}, too_large={}, candidates={}",
-        total_structures,
-        too_small,
-        too_large,
-        candidates.len()
-    );
+    if params.verbose {
+        eprintln!(
+            "Debug star scanning: total_structures={}, too_small={}, too_large={}, candidates={}",
+            total_structures,
+            too_small,
+            too_large,
+            candidates.len()
+        );
+    }
 
     candidates
 }
 
+/// Keep only the `max_candidates` brightest candidates (by peak pixel) so that
+/// measurement and PSF fitting stay bounded on noisy, uncalibrated frames
+fn limit_candidates(
+    candidates: Vec<StarCandidate>,
+    data: &[u16],
+    width: usize,
+    max_candidates: usize,
+    verbose: bool,
+) -> Vec<StarCandidate> {
+    if max_candidates == 0 || candidates.len() <= max_candidates {
+        return candidates;
+    }
+
+    if verbose {
+        eprintln!(
+            "Warning: HocusFocus found {} star candidates, keeping the {} brightest",
+            candidates.len(),
+            max_candidates
+        );
+    }
+
+    let mut with_peak: Vec<(u16, StarCandidate)> = candidates
+        .into_iter()
+        .map(|c| {
+            let peak = c
+                .pixels
+                .iter()
+                .map(|&(x, y)| data[y * width + x])
+                .max()
+                .unwrap_or(0);
+            (peak, c)
+        })
+        .collect();
+    with_peak.sort_by_key(|(peak, _)| std::cmp::Reverse(*peak));
+    with_peak.truncate(max_candidates);
+
+    with_peak.into_iter().map(|(_, c)| c).collect()
+}
+
 #[derive(Debug, Clone)]
 struct StarCandidate {
     pixels: Vec<(usize, usize)>,
@@ -678,6 +816,11 @@ struct StarCandidate {
 }
 
 /// Measure and validate star candidates
+///
+/// PSF fits are independent per star and read-only on the image, so they run
+/// in parallel once the candidates have been validated; the stars keep the
+/// candidates' order either way. Rejected candidates are only collected when
+/// `keep_rejected` is set.
 fn measure_stars(
     data: &[u16],
     width: usize,
@@ -685,8 +828,10 @@ fn measure_stars(
     candidates: Vec<StarCandidate>,
     params: &HocusFocusParams,
     noise_estimate: &KappaSigmaResult,
-) -> Vec<HocusFocusStar> {
-    let mut stars = Vec::new();
+) -> (Vec<HocusFocusStar>, Vec<RejectedStar>) {
+    let mut measured = Vec::new();
+    let mut rejected = Vec::new();
+    let mut reject_counts: BTreeMap<StarRejectReason, usize> = BTreeMap::new();
 
     for candidate in candidates {
         // Measure star properties
@@ -703,52 +848,77 @@ fn measure_stars(
         let snr = signal / noise_estimate.sigma.max(0.001);
 
         // Validate star based on multiple criteria
-        if !validate_star(
+        if let Err(reason) = validate_star(
             &candidate, peak, median, background, hfr, snr, params, width, height,
         ) {
+            *reject_counts.entry(reason).or_default() += 1;
+            if params.keep_rejected {
+                rejected.push(RejectedStar {
+                    position: candidate.center,
+                    hfr,
+                    reason,
+                });
+            }
             continue;
         }
 
-        // PSF fitting if requested
-        let psf_model = if params.psf_type != PSFType::None {
-            use crate::psf_fitting::PSFFitter;
-            let fitter = PSFFitter::new(params.psf_type);
-            fitter.fit_star(
-                data,
-                width,
-                height,
-                candidate.center.0,
-                candidate.center.1,
-                candidate.bounding_box.2 as f64,
-                candidate.bounding_box.3 as f64,
-                background,
-                peak,
-            )
-        } else {
-            None
-        };
-
-        // Use PSF-derived FWHM if available
-        let final_fwhm = if let Some(ref psf) = psf_model {
-            psf.fwhm
-        } else {
-            fwhm
-        };
+        measured.push((candidate, hfr, fwhm, peak, background, snr, flux));
+    }
 
-        stars.push(HocusFocusStar {
-            position: candidate.center,
-            hfr,
-            fwhm: final_fwhm,
-            brightness: peak,
-            background,
-            snr,
-            flux,
-            pixel_count: candidate.pixels.len(),
-            psf_model,
-        });
+    if params.verbose && !reject_counts.is_empty() {
+        let tally: Vec<String> = reject_counts
+            .iter()
+            .map(|(reason, count)| format!("{:?}: {}", reason, count))
+            .collect();
+        eprintln!(
+            "Debug HocusFocus: Rejected candidates - {}",
+            tally.join(", ")
+        );
     }
 
-    stars
+    // PSF fitting if requested
+    let fitter = PSFFitter::new(params.psf_type);
+    let stars = measured
+        .into_par_iter()
+        .map(|(candidate, hfr, fwhm, peak, background, snr, flux)| {
+            let psf_model = if params.psf_type != PSFType::None {
+                fitter.fit_star(
+                    data,
+                    width,
+                    height,
+                    candidate.center.0,
+                    candidate.center.1,
+                    candidate.bounding_box.2 as f64,
+                    candidate.bounding_box.3 as f64,
+                    background,
+                    peak,
+                )
+            } else {
+                None
+            };
+
+            // Use PSF-derived FWHM if available
+            let final_fwhm = if let Some(ref psf) = psf_model {
+                psf.fwhm
+            } else {
+                fwhm
+            };
+
+            HocusFocusStar {
+                position: candidate.center,
+                hfr,
+                fwhm: final_fwhm,
+                brightness: peak,
+                background,
+                snr,
+                flux,
+                pixel_count: candidate.pixels.len(),
+                psf_model,
+            }
+        })
+        .collect();
+
+    (stars, rejected)
 }
 
 /// Measure star properties including median for flatness check
@@ -842,7 +1012,7 @@ fn measure_star_properties(
     (hfr, fwhm, peak, star_median - background, background, flux)
 }
 
-/// Validate star based on HocusFocus criteria
+/// Validate star based on HocusFocus criteria, returning the first check it fails
 #[allow(clippy::too_many_arguments)]
 fn validate_star(
     candidate: &StarCandidate,
@@ -854,34 +1024,34 @@ fn validate_star(
     params: &HocusFocusParams,
     src_width: usize,
     src_height: usize,
-) -> bool {
+) -> Result<(), StarRejectReason> {
     let (bx, by, bw, bh) = candidate.bounding_box;
 
     // Too small
     if bw < params.min_star_size || bh < params.min_star_size {
-        return false;
+        return Err(StarRejectReason::TooSmall);
     }
 
     // Touching the border
     if bx == 0 || by == 0 || bx + bw >= src_width || by + bh >= src_height {
-        return false;
+        return Err(StarRejectReason::TouchingBorder);
     }
 
     // Too distorted (pixel density check)
     let max_dim = bw.max(bh) as f64;
     let pixel_density = candidate.pixels.len() as f64 / (max_dim * max_dim);
     if pixel_density < params.max_distortion {
-        return false;
+        return Err(StarRejectReason::TooDistorted);
     }
 
     // Fully saturated
     if (background + peak) >= params.saturation_threshold {
-        return false;
+        return Err(StarRejectReason::Saturated);
     }
 
     // Not bright enough relative to noise (sensitivity check)
     if snr <= params.sensitivity {
-        return false;
+        return Err(StarRejectReason::LowSnr);
     }
 
     // Star center too far from bounding box center
@@ -893,18 +1063,344 @@ fn validate_star(
     if (candidate.center.0 - box_center_x).abs() > center_threshold_x
         || (candidate.center.1 - box_center_y).abs() > center_threshold_y
     {
-        return false;
+        return Err(StarRejectReason::OffCenter);
     }
 
     // Too flat (median too close to peak)
     if median >= params.peak_response * peak {
-        return false;
+        return Err(StarRejectReason::TooFlat);
     }
 
     // HFR below minimum threshold
     if hfr <= params.min_hfr {
-        return false;
+        return Err(StarRejectReason::HfrBelowMin);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_analysis::FitsImage;
+    use rand::prelude::*;
+
+    fn candidate_at(x: usize, y: usize) -> StarCandidate {
+        StarCandidate {
+            pixels: vec![(x, y)],
+            center: (x as f64, y as f64),
+            bounding_box: (x, y, 1, 1),
+        }
+    }
+
+    #[test]
+    fn test_auto_structure_layers_follow_frame_size() {
+        let auto = HocusFocusParams::default();
+        let small = auto.resolved_structure_layers(512, 512);
+        let large = auto.resolved_structure_layers(4096, 4096);
+        assert!(small < large, "{} vs {}", small, large);
+        assert_eq!(auto.resolved_structure_layers(3008, 3008), 4);
+        assert_eq!(auto.resolved_structure_layers(6000, 2047), 3);
+        assert_eq!(auto.resolved_structure_layers(2048, 2048), 4);
+        assert_eq!(auto.resolved_structure_layers(6000, 4096), 5);
+        assert_eq!(
+            auto.resolved_structure_layers(64, 64),
+            MIN_AUTO_STRUCTURE_LAYERS
+        );
+
+        let explicit = HocusFocusParams {
+            structure_layers: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(explicit.resolved_structure_layers(512, 512), 5);
+        assert_eq!(explicit.resolved_structure_layers(4096, 4096), 5);
+    }
+
+    #[test]
+    fn test_saturated_candidate_rejected_as_saturated() {
+        // A round, centered 9x9 blob well inside a 64x64 frame
+        let pixels: Vec<_> = (28..37)
+            .flat_map(|y| (28..37).map(move |x| (x, y)))
+            .collect();
+        let candidate = StarCandidate {
+            pixels,
+            center: (32.5, 32.5),
+            bounding_box: (28, 28, 9, 9),
+        };
+        let params = HocusFocusParams::default();
+        let validate = |peak: f64| {
+            validate_star(
+                &candidate, peak, 2000.0, 1000.0, 2.5, 100.0, &params, 64, 64,
+            )
+        };
+
+        assert_eq!(validate(20000.0), Ok(()));
+        assert_eq!(validate(65000.0), Err(StarRejectReason::Saturated));
+    }
+
+    #[test]
+    fn test_14_bit_saturation_with_max_adu() {
+        // A 14-bit frame in a 16-bit container, whose range a bright defect
+        // stretched to the full container: the clipped star sits at 16383
+        let (width, height) = (96, 96);
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut raw: Vec<f64> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f64 - 48.0, (i / width) as f64 - 48.0);
+                let star = 40000.0 * (-(x * x + y * y) / (2.0 * 3.0 * 3.0)).exp();
+                (1000.0 + rng.gen_range(-20.0..20.0) + star).min(16383.0)
+            })
+            .collect();
+        raw[0] = 65535.0;
+        let fits = FitsImage {
+            width,
+            height,
+            data: raw.iter().map(|&v| v as u16).collect(),
+            channel_data: Vec::new(),
+            raw_range: Some((0.0, 65535.0)),
+        };
+
+        let params = HocusFocusParams {
+            keep_rejected: true,
+            ..Default::default()
+        };
+        let default = detect_stars_hocus_focus(&fits.data, width, height, &params);
+        assert!(default
+            .rejected
+            .iter()
+            .all(|s| s.reason != StarRejectReason::Saturated));
+
+        let params = params.with_saturation_level(fits.scaled_level(16383.0));
+        let limited = detect_stars_hocus_focus(&fits.data, width, height, &params);
+        assert!(limited.stars.is_empty());
+        assert!(limited
+            .rejected
+            .iter()
+            .any(|s| s.reason == StarRejectReason::Saturated));
+    }
+
+    #[test]
+    fn test_median_noise_reduction_removes_hot_pixel() {
+        let (width, height) = (96, 96);
+        let mut rng = StdRng::seed_from_u64(11);
+        let clean: Vec<u16> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f64, (i / width) as f64);
+                let r2 = (x - 40.3).powi(2) + (y - 52.6).powi(2);
+                (1000.0 + rng.gen_range(-20.0..20.0) + 12000.0 * (-r2 / 18.0).exp()) as u16
+            })
+            .collect();
+        let mut data = clean.clone();
+        let hot = 20 * width + 70;
+        data[hot] = 60000;
+
+        let filtered = Median.apply(&data, width, height);
+        assert!(filtered[hot] < 1100, "hot pixel left at {}", filtered[hot]);
+
+        let detect = |data: &[u16], noise_reduction| {
+            let params = HocusFocusParams {
+                hotpixel_filtering: false,
+                noise_reduction,
+                ..Default::default()
+            };
+            detect_stars_hocus_focus(data, width, height, &params).stars
+        };
+        let reference = detect(&clean, NoiseReductionKind::None);
+        let median = detect(&data, NoiseReductionKind::Median);
+
+        assert_eq!(reference.len(), 1);
+        assert_eq!(median.len(), 1);
+        let (dx, dy) = (
+            median[0].position.0 - reference[0].position.0,
+            median[0].position.1 - reference[0].position.1,
+        );
+        assert!(
+            dx.abs() < 0.1 && dy.abs() < 0.1,
+            "shifted by ({}, {})",
+            dx,
+            dy
+        );
+    }
+
+    #[test]
+    fn test_limit_candidates_keeps_brightest() {
+        let width = 10;
+        let mut data = vec![100u16; width * width];
+        data[5] = 5000;
+        data[20] = 3000;
+        data[77] = 4000;
+
+        let candidates = vec![
+            candidate_at(5, 0),
+            candidate_at(0, 2),
+            candidate_at(7, 7),
+            candidate_at(3, 3),
+        ];
+        let kept = limit_candidates(candidates, &data, width, 2, false);
+
+        let centers: Vec<_> = kept.iter().map(|c| c.center).collect();
+        assert_eq!(centers, vec![(5.0, 0.0), (7.0, 7.0)]);
+    }
+
+    #[test]
+    fn test_candidates_unlimited_by_default() {
+        assert_eq!(HocusFocusParams::default().max_candidates, 0);
+    }
+
+    #[test]
+    fn test_limit_candidates_unlimited() {
+        let data = vec![0u16; 100];
+        let candidates = vec![candidate_at(1, 1), candidate_at(2, 2)];
+        assert_eq!(
+            limit_candidates(candidates.clone(), &data, 10, 0, false).len(),
+            2
+        );
+        assert_eq!(limit_candidates(candidates, &data, 10, 5, false).len(), 2);
+    }
+
+    #[test]
+    fn test_noisy_frame_respects_candidate_cap() {
+        // Uncalibrated-looking frame: heavy noise with many hot pixels
+        let (width, height) = (256, 256);
+        let mut rng = StdRng::seed_from_u64(42);
+        let data: Vec<u16> = (0..width * height)
+            .map(|_| {
+                if rng.gen_bool(0.02) {
+                    rng.gen_range(20000..60000)
+                } else {
+                    rng.gen_range(800..1600)
+                }
+            })
+            .collect();
+
+        let params = HocusFocusParams {
+            hotpixel_filtering: false,
+            noise_reduction_radius: 0,
+            min_star_size: 1,
+            max_candidates: 25,
+            ..Default::default()
+        };
+
+        let result = detect_stars_hocus_focus(&data, width, height, &params);
+        assert!(result.stars.len() <= 25);
+    }
+
+    /// The original direct 2D convolution, kept as a reference for the separable version
+    fn convolve_2d_reference(
+        data: &[f64],
+        width: usize,
+        height: usize,
+        kernel_size: usize,
+        sigma: f64,
+    ) -> Vec<f64> {
+        let radius = kernel_size / 2;
+        let mut kernel = vec![0.0; kernel_size * kernel_size];
+        for y in 0..kernel_size {
+            for x in 0..kernel_size {
+                let dx = x as f64 - radius as f64;
+                let dy = y as f64 - radius as f64;
+                kernel[y * kernel_size + x] = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+        let total: f64 = kernel.iter().sum();
+
+        let mut result = data.to_vec();
+        for y in radius..(height - radius) {
+            for x in radius..(width - radius) {
+                let mut sum = 0.0;
+                for ky in 0..kernel_size {
+                    for kx in 0..kernel_size {
+                        let sy = y + ky - radius;
+                        let sx = x + kx - radius;
+                        sum += data[sy * width + sx] * kernel[ky * kernel_size + kx];
+                    }
+                }
+                result[y * width + x] = sum / total;
+            }
+        }
+        result
     }
 
-    true
+    fn noisy_frame(width: usize, height: usize) -> Vec<u16> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..width * height)
+            .map(|_| rng.gen_range(500..30000))
+            .collect()
+    }
+
+    #[test]
+    fn test_separable_blur_matches_2d() {
+        let (width, height) = (64, 48);
+        let data = noisy_frame(width, height);
+        let float_data: Vec<f64> = data.iter().map(|&v| v as f64).collect();
+
+        for kernel_size in [3, 5, 9] {
+            let radius = kernel_size / 2;
+            let expected =
+                convolve_2d_reference(&float_data, width, height, kernel_size, radius as f64 / 2.0);
+            let blurred = apply_gaussian_blur(&data, width, height, kernel_size);
+            for y in 0..height {
+                for x in 0..width {
+                    let i = y * width + x;
+                    if x < radius || y < radius || x >= width - radius || y >= height - radius {
+                        assert_eq!(blurred[i], 0);
+                    } else {
+                        // Truncation to u16 can land either side of an integer
+                        assert!((blurred[i] as f64 - expected[i]).abs() < 1.0 + 1e-6);
+                    }
+                }
+            }
+
+            let expected = convolve_2d_reference(
+                &float_data,
+                width,
+                height,
+                kernel_size,
+                kernel_size as f64 / 3.0,
+            );
+            let mut smoothed = float_data.clone();
+            smooth_gaussian(&mut smoothed, width, height, kernel_size);
+            for (a, b) in smoothed.iter().zip(&expected) {
+                assert!((a - b).abs() < 1e-6, "{} vs {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --release --ignored --nocapture"]
+    fn bench_separable_blur() {
+        use std::time::Instant;
+
+        let (width, height) = (2048, 2048);
+        let kernel_size = 9;
+        let float_data: Vec<f64> = noisy_frame(width, height)
+            .iter()
+            .map(|&v| v as f64)
+            .collect();
+
+        let start = Instant::now();
+        convolve_2d_reference(
+            &float_data,
+            width,
+            height,
+            kernel_size,
+            kernel_size as f64 / 3.0,
+        );
+        let direct = start.elapsed();
+
+        let mut smoothed = float_data.clone();
+        let start = Instant::now();
+        smooth_gaussian(&mut smoothed, width, height, kernel_size);
+        let separable = start.elapsed();
+
+        println!(
+            "{}x{} kernel {}: 2D {:?}, separable {:?} ({:.1}x)",
+            width,
+            height,
+            kernel_size,
+            direct,
+            separable,
+            direct.as_secs_f64() / separable.as_secs_f64()
+        );
+    }
 }
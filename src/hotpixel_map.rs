@@ -0,0 +1,213 @@
+//! Persistent bad-pixel maps built from dark frames.
+//!
+//! A hot pixel shows up at the same position in every dark, while cosmic rays
+//! and read noise do not. The darks are median-stacked so only consistently
+//! bright pixels survive, and pixels of the stack above
+//! `median + sigma * stddev` are recorded. Applying the map replaces each
+//! flagged pixel with the median of its unflagged neighbours.
+
+use crate::stacking::{stack_frames, StackMethod};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Positions of hot pixels for one sensor geometry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotPixelMap {
+    pub width: usize,
+    pub height: usize,
+    /// Flagged pixel indices (`y * width + x`), sorted
+    pub pixels: Vec<usize>,
+}
+
+impl HotPixelMap {
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width
+            && y < self.height
+            && self.pixels.binary_search(&(y * self.width + x)).is_ok()
+    }
+
+    /// Load a map written by [`HotPixelMap::save`]
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read hot pixel map: {}", path.display()))?;
+        let mut map: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid hot pixel map: {}", path.display()))?;
+        if map.width == 0 || map.height == 0 {
+            bail!(
+                "Invalid hot pixel map {}: empty {}x{} geometry",
+                path.display(),
+                map.width,
+                map.height
+            );
+        }
+        let size = map.width.saturating_mul(map.height);
+        if let Some(&index) = map.pixels.iter().find(|&&i| i >= size) {
+            bail!(
+                "Invalid hot pixel map {}: pixel {} is outside the {}x{} frame",
+                path.display(),
+                index,
+                map.width,
+                map.height
+            );
+        }
+        map.pixels.sort_unstable();
+        map.pixels.dedup();
+        Ok(map)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write hot pixel map: {}", path.display()))
+    }
+
+    /// Replace flagged pixels of a `width` x `height` plane with the median of
+    /// their unflagged 3x3 neighbours
+    pub fn apply_to(&self, data: &mut [u16]) {
+        let original = data.to_vec();
+        let mut neighbors = Vec::with_capacity(8);
+
+        for &index in &self.pixels {
+            let (x, y) = (index % self.width, index / self.width);
+            neighbors.clear();
+            for ny in y.saturating_sub(1)..=(y + 1).min(self.height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(self.width - 1) {
+                    if (nx, ny) != (x, y) && !self.contains(nx, ny) {
+                        neighbors.push(original[ny * self.width + nx]);
+                    }
+                }
+            }
+
+            // A pixel surrounded by hot pixels is left alone
+            if !neighbors.is_empty() {
+                neighbors.sort_unstable();
+                data[index] = neighbors[neighbors.len() / 2];
+            }
+        }
+    }
+}
+
+/// Median-stack a set of darks and flag pixels above `median + sigma * stddev`
+pub fn build_hotpixel_map(dark_paths: &[&Path], sigma: f64) -> Result<HotPixelMap> {
+    if dark_paths.is_empty() {
+        bail!("At least one dark frame is needed to build a hot pixel map");
+    }
+
+    let stack = stack_frames(dark_paths, StackMethod::Median)?;
+    let (width, height) = (stack.width, stack.height);
+    let stats = stack.calculate_basic_statistics();
+    let threshold = stats.median + sigma * stats.std_dev;
+
+    let pixels = stack
+        .data
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v as f64 > threshold)
+        .map(|(i, _)| i)
+        .collect();
+
+    Ok(HotPixelMap {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_analysis::FitsImage;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 24;
+
+    /// Noisy dark with a hot pixel at (10, 5) and, optionally, a cosmic ray
+    fn write_dark(
+        dir: &Path,
+        index: u32,
+        cosmic_ray: Option<(usize, usize)>,
+    ) -> std::path::PathBuf {
+        let mut seed = 7919 * (index + 1);
+        let mut data: Vec<f32> = (0..WIDTH * HEIGHT)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                1000.0 + ((seed >> 16) % 40) as f32
+            })
+            .collect();
+        data[5 * WIDTH + 10] = 30000.0;
+        if let Some((x, y)) = cosmic_ray {
+            data[y * WIDTH + x] = 40000.0;
+        }
+
+        let path = dir.join(format!("dark_{}.fits", index));
+        fitrs::Fits::create(&path, fitrs::Hdu::new(&[WIDTH, HEIGHT], data)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_build_map_flags_consistent_hot_pixel() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_hotpixels_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let darks = [
+            write_dark(&dir, 0, Some((20, 12))),
+            write_dark(&dir, 1, None),
+            write_dark(&dir, 2, None),
+        ];
+        let paths: Vec<&Path> = darks.iter().map(|p| p.as_path()).collect();
+
+        let map = build_hotpixel_map(&paths, 5.0).unwrap();
+
+        // Round trip through the on-disk format
+        let map_path = dir.join("hotpixels.json");
+        map.save(&map_path).unwrap();
+        let loaded = HotPixelMap::load(&map_path).unwrap();
+        let mut light = FitsImage::from_file(&darks[1]).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(loaded, map);
+        assert_eq!(map.len(), 1, "{:?}", map.pixels);
+        assert!(map.contains(10, 5));
+        // The cosmic ray is in only one dark, so the median stack drops it
+        assert!(!map.contains(20, 12));
+
+        let hot = 5 * WIDTH + 10;
+        let before = light.data[hot];
+        light.apply_hotpixel_map(&map).unwrap();
+        let neighbor = light.data[hot + 1];
+        assert!(
+            light.data[hot] < before / 4,
+            "{} -> {}",
+            before,
+            light.data[hot]
+        );
+        assert!((light.data[hot] as i32 - neighbor as i32).abs() < 200);
+    }
+
+    #[test]
+    fn test_load_rejects_out_of_range_maps() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_bad_maps_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let load = |name: &str, json: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, json).unwrap();
+            HotPixelMap::load(&path)
+        };
+
+        let valid = load("valid.json", r#"{"width":4,"height":3,"pixels":[11,0,11]}"#);
+        let empty = load("empty.json", r#"{"width":0,"height":3,"pixels":[]}"#);
+        let outside = load("outside.json", r#"{"width":4,"height":3,"pixels":[2,12]}"#);
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(valid.unwrap().pixels, vec![0, 11]);
+        assert!(empty.unwrap_err().to_string().contains("0x3"));
+        assert!(outside.unwrap_err().to_string().contains("pixel 12"));
+    }
+}
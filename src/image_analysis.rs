@@ -1,6 +1,10 @@
+use crate::fits_compression;
+use crate::hotpixel_map::HotPixelMap;
+use crate::utils::OnlineStats;
+use crate::xisf;
 use anyhow::Result;
-use bumpalo::Bump;
 use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ImageStatistics {
@@ -17,26 +21,465 @@ pub struct ImageStatistics {
     pub mad: Option<f64>,
 }
 
+fn open_fits(path: &Path) -> Result<fitrs::Fits> {
+    fitrs::Fits::open(path).map_err(|e| {
+        // Keep the I/O error in the chain so retries can tell it apart
+        let message = format!("Failed to open FITS file {}: {}", path.display(), e);
+        anyhow::Error::new(e).context(message)
+    })
+}
+
+/// How image files are read from storage that fails intermittently, such as
+/// NAS or SMB mounts. Only transient I/O errors are retried; missing files and
+/// format errors fail at once.
+///
+/// fitrs reads pixel data without reporting I/O errors, so failures while
+/// opening a file and reading its headers are what can be retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FitsReadOptions {
+    /// Tries in total, including the first
+    pub attempts: u32,
+    /// Wait before the first retry, doubled before each further one
+    pub backoff: Duration,
+    /// Load files whose data ends early, padding the missing pixels with the
+    /// mean of those read, instead of failing with [`FitsReadError::SizeMismatch`]
+    pub tolerant: bool,
+}
+
+impl Default for FitsReadOptions {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(250),
+            tolerant: false,
+        }
+    }
+}
+
+/// Image data problems callers may want to tell apart, e.g. to skip a file and
+/// continue. Reach it with `error.downcast_ref::<FitsReadError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FitsReadError {
+    /// The data holds a different number of pixels than the header declares,
+    /// typically a download truncated mid-transfer
+    SizeMismatch { expected: usize, got: usize },
+}
+
+impl std::fmt::Display for FitsReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FitsReadError::SizeMismatch { expected, got } => write!(
+                f,
+                "Image data has {} pixels but the header declares {} (truncated file?)",
+                got, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FitsReadError {}
+
+impl FitsReadOptions {
+    /// Run `load`, retrying while it fails with a transient I/O error
+    pub fn retry<T>(&self, path: &Path, mut load: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut delay = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match load() {
+                Err(e) if attempt < self.attempts && is_transient_io_error(&e) => {
+                    attempt += 1;
+                    eprintln!(
+                        "Retrying {} after I/O error (attempt {} of {}): {}",
+                        path.display(),
+                        attempt,
+                        self.attempts,
+                        e
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error comes from an I/O failure worth retrying
+fn is_transient_io_error(error: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::TimedOut
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+            ) || is_remote_io_error(e)
+        })
+}
+
+/// EIO, and ESTALE for NFS handles invalidated on the server
+#[cfg(unix)]
+fn is_remote_io_error(error: &std::io::Error) -> bool {
+    const EIO: i32 = 5;
+    #[cfg(target_os = "linux")]
+    const ESTALE: i32 = 116;
+    #[cfg(not(target_os = "linux"))]
+    const ESTALE: i32 = 70;
+
+    matches!(error.raw_os_error(), Some(EIO) | Some(ESTALE))
+}
+
+#[cfg(not(unix))]
+fn is_remote_io_error(_error: &std::io::Error) -> bool {
+    false
+}
+
+/// Whether an HDU holds image data: NAXIS >= 2 and not a table extension
+fn is_image_hdu(hdu: &fitrs::Hdu) -> bool {
+    use fitrs::HeaderValue;
+
+    let naxis = match hdu.value("NAXIS") {
+        Some(HeaderValue::IntegerNumber(n)) => *n,
+        _ => 0,
+    };
+
+    let is_table = matches!(
+        hdu.value("XTENSION"),
+        Some(HeaderValue::CharacterString(ext)) if ext.trim() != "IMAGE"
+    );
+
+    naxis >= 2 && !is_table
+}
+
+/// Length of axis `n` (NAXISn) when present in the header
+fn hdu_axis_length(hdu: &fitrs::Hdu, n: usize) -> Option<usize> {
+    match hdu.value(&format!("NAXIS{}", n)) {
+        Some(fitrs::HeaderValue::IntegerNumber(len)) => usize::try_from(*len).ok(),
+        _ => None,
+    }
+}
+
+/// EXTNAME values recognised for each named plane
+const SCIENCE_EXTNAMES: &[&str] = &["SCI", "SCIENCE"];
+const ERROR_EXTNAMES: &[&str] = &["ERR", "ERROR", "SIGMA", "UNCERT"];
+
+/// Which HDU of a multi-extension FITS file to analyze
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitsPlane {
+    /// EXTNAME SCI when present, otherwise the first image HDU
+    Science,
+    /// The uncertainty plane (EXTNAME ERR, ERROR, SIGMA or UNCERT)
+    Error,
+    /// Explicit HDU index (0 = primary)
+    Index(usize),
+}
+
+impl std::str::FromStr for FitsPlane {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "science" | "sci" => Ok(FitsPlane::Science),
+            "error" | "err" => Ok(FitsPlane::Error),
+            other => other.parse().map(FitsPlane::Index).map_err(|_| {
+                format!(
+                    "Unknown plane: {} (expected science, error or an HDU index)",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// Index of the first HDU whose EXTNAME matches one of `names` (case-insensitive)
+fn find_extname(path: &Path, names: &[&str]) -> Result<Option<usize>> {
+    let extnames = fits_compression::hdu_extnames(path)?;
+    Ok(extnames.iter().position(|name| {
+        name.as_deref()
+            .is_some_and(|name| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    }))
+}
+
+/// Color filter array layout of a one-shot-color sensor, read left to right,
+/// top to bottom over the first 2x2 cell (the FITS `BAYERPAT` keyword)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl std::str::FromStr for BayerPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "rggb" => Ok(BayerPattern::Rggb),
+            "bggr" => Ok(BayerPattern::Bggr),
+            "grbg" => Ok(BayerPattern::Grbg),
+            "gbrg" => Ok(BayerPattern::Gbrg),
+            _ => Err(format!("Unknown Bayer pattern: {}", s)),
+        }
+    }
+}
+
+impl BayerPattern {
+    /// Offsets of the red and blue pixels within a 2x2 cell
+    fn red_blue_offsets(&self) -> ((usize, usize), (usize, usize)) {
+        match self {
+            BayerPattern::Rggb => ((0, 0), (1, 1)),
+            BayerPattern::Bggr => ((1, 1), (0, 0)),
+            BayerPattern::Grbg => ((1, 0), (0, 1)),
+            BayerPattern::Gbrg => ((0, 1), (1, 0)),
+        }
+    }
+}
+
+/// How to treat raw Bayer mosaics before analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebayerMode {
+    /// Analyze the data as stored
+    None,
+    /// Debayer when the file has a `BAYERPAT` header
+    Auto,
+    /// Debayer with an explicit pattern, ignoring the header
+    Pattern(BayerPattern),
+}
+
+impl std::str::FromStr for DebayerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(DebayerMode::None),
+            "auto" => Ok(DebayerMode::Auto),
+            other => other.parse().map(DebayerMode::Pattern).map_err(|_| {
+                format!(
+                    "Unknown debayer mode: {} (expected auto, none, rggb, bggr, grbg or gbrg)",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+impl DebayerMode {
+    /// Apply this mode to an image loaded from `path`
+    pub fn apply(self, fits: FitsImage, path: &Path) -> Result<FitsImage> {
+        let pattern = match self {
+            DebayerMode::None => return Ok(fits),
+            DebayerMode::Pattern(pattern) => pattern,
+            DebayerMode::Auto => match fits_compression::find_header_value(path, "BAYERPAT")? {
+                Some(value) => value.parse().map_err(|e| {
+                    anyhow::anyhow!("Unsupported BAYERPAT in {}: {}", path.display(), e)
+                })?,
+                None => return Ok(fits),
+            },
+        };
+
+        if fits.width < 2 || fits.height < 2 {
+            return Err(anyhow::anyhow!(
+                "Image {}x{} is too small to debayer",
+                fits.width,
+                fits.height
+            ));
+        }
+
+        Ok(fits.debayer(pattern))
+    }
+}
+
+/// Number of planes (NAXIS3) loaded as color channels
+const COLOR_CHANNELS: usize = 3;
+
 /// FITS image data structure
 pub struct FitsImage {
     pub width: usize,
     pub height: usize,
     pub data: Vec<u16>, // Keep as 16-bit unsigned integers
+    /// Individual planes of an RGB cube (NAXIS3 = 3); empty for 2D images,
+    /// in which case `data` is the image itself rather than the channel mean
+    pub channel_data: Vec<Vec<u16>>,
+    /// Physical pixel values mapped to 0 and 65535 when loading; None for
+    /// images not loaded from a file
+    pub raw_range: Option<(f64, f64)>,
 }
 
 impl FitsImage {
     /// Load FITS image data from file using fitrs
+    ///
+    /// Uses the primary HDU when it holds image data, otherwise the first
+    /// extension HDU with NAXIS >= 2 (e.g. a dataless primary header followed
+    /// by an IMAGE extension). Tile-compressed images (`.fits.fz`) are
+    /// decompressed transparently, and `.xisf` files are read as XISF.
+    /// Transient I/O errors are retried per [`FitsReadOptions::default`].
     pub fn from_file(path: &Path) -> Result<Self> {
-        use fitrs::Fits;
+        Self::from_file_with_options(path, &FitsReadOptions::default())
+    }
 
-        let fits = Fits::open(path)
-            .map_err(|e| anyhow::anyhow!("Failed to open FITS file {}: {}", path.display(), e))?;
+    /// Like [`from_file`](Self::from_file), with an explicit retry policy
+    pub fn from_file_with_options(path: &Path, options: &FitsReadOptions) -> Result<Self> {
+        options.retry(path, || Self::read_file(path, options.tolerant))
+    }
 
-        // Get the primary HDU
-        let hdu = fits
+    /// Every valued card of the primary header, in file order; COMMENT,
+    /// HISTORY and other cards without a value are left out. XISF files
+    /// yield no cards.
+    pub fn read_header_map(path: &Path) -> Result<Vec<(String, fitrs::HeaderValue)>> {
+        if xisf::is_xisf(path) {
+            return Ok(Vec::new());
+        }
+
+        let fits = open_fits(path)?;
+        let primary = fits
             .get(0)
-            .ok_or_else(|| anyhow::anyhow!("No HDU found in FITS file"))?;
+            .ok_or_else(|| anyhow::anyhow!("No primary HDU in {}", path.display()))?;
+        Ok(primary
+            .iter()
+            .filter_map(|(keyword, value)| value.map(|v| (keyword.to_string(), v.clone())))
+            .collect())
+    }
+
+    fn read_file(path: &Path, tolerant: bool) -> Result<Self> {
+        if xisf::is_xisf(path) {
+            let image = xisf::read_image(path)?;
+            return Self::from_planes(image.data, image.width, image.height, image.channels);
+        }
+
+        if let Some(index) = fits_compression::compressed_hdu_index(path)? {
+            let (data, width, height) = fits_compression::read_compressed_image(path, index)?;
+            return Self::from_pixels(data, width, height);
+        }
+
+        let fits = open_fits(path)?;
 
+        let mut hdu_count = 0;
+        for hdu in fits.iter() {
+            hdu_count += 1;
+            if is_image_hdu(&hdu) {
+                return Self::from_hdu(path, hdu_count - 1, &hdu, tolerant);
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "No HDU with 2D image data found in FITS file {} ({} HDU(s) present)",
+            path.display(),
+            hdu_count
+        ))
+    }
+
+    /// Load FITS image data from a specific HDU (0 = primary)
+    pub fn from_file_hdu(path: &Path, hdu_index: usize) -> Result<Self> {
+        Self::read_hdu(path, hdu_index, false)
+    }
+
+    fn read_hdu(path: &Path, hdu_index: usize, tolerant: bool) -> Result<Self> {
+        if fits_compression::is_compressed_hdu(path, hdu_index)? {
+            let (data, width, height) = fits_compression::read_compressed_image(path, hdu_index)?;
+            return Self::from_pixels(data, width, height);
+        }
+
+        let fits = open_fits(path)?;
+
+        let hdu = fits.get(hdu_index).ok_or_else(|| {
+            anyhow::anyhow!(
+                "HDU {} not found in FITS file {} ({} HDU(s) present)",
+                hdu_index,
+                path.display(),
+                fits.iter().count()
+            )
+        })?;
+
+        if !is_image_hdu(&hdu) {
+            return Err(anyhow::anyhow!(
+                "HDU {} in FITS file {} does not contain 2D image data",
+                hdu_index,
+                path.display()
+            ));
+        }
+
+        Self::from_hdu(path, hdu_index, &hdu, tolerant)
+    }
+
+    /// Load the requested plane of a multi-extension FITS file
+    pub fn from_file_plane(path: &Path, plane: FitsPlane) -> Result<Self> {
+        Self::from_file_plane_with_options(path, plane, &FitsReadOptions::default())
+    }
+
+    /// Like [`from_file_plane`](Self::from_file_plane), with explicit read options
+    pub fn from_file_plane_with_options(
+        path: &Path,
+        plane: FitsPlane,
+        options: &FitsReadOptions,
+    ) -> Result<Self> {
+        // XISF files are read as a single image; there are no HDUs to choose from
+        if xisf::is_xisf(path) {
+            return match plane {
+                FitsPlane::Science | FitsPlane::Index(0) => {
+                    Self::from_file_with_options(path, options)
+                }
+                _ => Err(anyhow::anyhow!(
+                    "Only the main image of XISF file {} can be read",
+                    path.display()
+                )),
+            };
+        }
+
+        match plane {
+            FitsPlane::Science => match find_extname(path, SCIENCE_EXTNAMES)? {
+                Some(index) => Self::read_hdu(path, index, options.tolerant),
+                None => Self::from_file_with_options(path, options),
+            },
+            FitsPlane::Error => match find_extname(path, ERROR_EXTNAMES)? {
+                Some(index) => Self::read_hdu(path, index, options.tolerant),
+                None => Err(anyhow::anyhow!(
+                    "No error plane (EXTNAME {}) found in FITS file {}",
+                    ERROR_EXTNAMES.join("/"),
+                    path.display()
+                )),
+            },
+            FitsPlane::Index(index) => Self::read_hdu(path, index, options.tolerant),
+        }
+    }
+
+    /// Load HDU `index` of `path`, checking first that the file holds all of its data
+    fn from_hdu(path: &Path, index: usize, hdu: &fitrs::Hdu, tolerant: bool) -> Result<Self> {
+        let (data_f64, width, height) = match fits_compression::read_truncated_image(path, index)? {
+            Some((mut data, expected)) => {
+                if !tolerant {
+                    return Err(FitsReadError::SizeMismatch {
+                        expected,
+                        got: data.len(),
+                    }
+                    .into());
+                }
+                // The mean neither adds structure nor stretches the 0-65535 scaling
+                let fill = data.iter().sum::<f64>() / data.len().max(1) as f64;
+                data.resize(expected, fill);
+                let width = hdu_axis_length(hdu, 1).unwrap_or(0);
+                let height = hdu_axis_length(hdu, 2).unwrap_or(0);
+                (data, width, height)
+            }
+            None => Self::read_hdu_data(hdu)?,
+        };
+
+        if hdu_axis_length(hdu, 3) == Some(COLOR_CHANNELS) {
+            return Self::from_planes(data_f64, width, height, COLOR_CHANNELS);
+        }
+
+        Self::from_pixels(data_f64, width, height)
+    }
+
+    fn read_hdu_data(hdu: &fitrs::Hdu) -> Result<(Vec<f64>, usize, usize)> {
         // Read the image data using pattern matching
         let (data_f64, width, height) = match hdu.read_data() {
             fitrs::FitsData::FloatingPoint32(array) => {
@@ -76,6 +519,8 @@ impl FitsImage {
                 }
             }
             fitrs::FitsData::IntegersU32(array) => {
+                // Values above 65535 (e.g. stacked masters) are not clamped here;
+                // from_pixels rescales the full range into u16
                 let shape = &array.shape;
                 if shape.len() >= 2 {
                     let width = shape[0];
@@ -95,6 +540,28 @@ impl FitsImage {
             }
         };
 
+        // fitrs returns stored values; BZERO/BSCALE give the physical ADU
+        let bzero = hdu.value("BZERO").and_then(header_number).unwrap_or(0.0);
+        let bscale = hdu.value("BSCALE").and_then(header_number).unwrap_or(1.0);
+        let data_f64 = if bzero == 0.0 && bscale == 1.0 {
+            data_f64
+        } else {
+            data_f64.into_iter().map(|v| bzero + bscale * v).collect()
+        };
+
+        Ok((data_f64, width, height))
+    }
+
+    /// Build an image from physical pixel values, scaling them to 0-65535
+    pub(crate) fn from_pixels(data_f64: Vec<f64>, width: usize, height: usize) -> Result<Self> {
+        Self::from_planes(data_f64, width, height, 1)
+    }
+
+    /// Build an image from `planes` consecutive width x height planes.
+    ///
+    /// All planes share one 0-65535 scaling so channel levels stay comparable.
+    /// With more than one plane, `data` is the per-pixel channel mean.
+    fn from_planes(data_f64: Vec<f64>, width: usize, height: usize, planes: usize) -> Result<Self> {
         // Get total pixels
         let total_pixels = data_f64.len();
         if total_pixels == 0 {
@@ -102,20 +569,19 @@ impl FitsImage {
         }
 
         // Verify dimensions match data length
-        if width * height != total_pixels {
-            return Err(anyhow::anyhow!(
-                "Image dimensions {}x{} don't match data length {}",
-                width,
-                height,
-                total_pixels
-            ));
+        if width * height * planes != total_pixels {
+            return Err(FitsReadError::SizeMismatch {
+                expected: width * height * planes,
+                got: total_pixels,
+            }
+            .into());
         }
 
         // Convert f64 data to u16, scaling to 0-65535 range
         let min = data_f64.iter().fold(f64::INFINITY, |a, &b| a.min(b));
         let max = data_f64.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
 
-        let data_u16 = if max > min {
+        let data_u16: Vec<u16> = if max > min {
             let scale = 65535.0 / (max - min);
             data_f64
                 .into_iter()
@@ -125,68 +591,254 @@ impl FitsImage {
             vec![0u16; total_pixels]
         };
 
+        if planes == 1 {
+            return Ok(FitsImage {
+                width,
+                height,
+                data: data_u16,
+                channel_data: Vec::new(),
+                raw_range: Some((min, max)),
+            });
+        }
+
+        let channel_data: Vec<Vec<u16>> = data_u16
+            .chunks_exact(width * height)
+            .map(|plane| plane.to_vec())
+            .collect();
+        let data = (0..width * height)
+            .map(|i| {
+                let sum: u32 = channel_data.iter().map(|plane| plane[i] as u32).sum();
+                (sum as f64 / planes as f64).round() as u16
+            })
+            .collect();
+
         Ok(FitsImage {
             width,
             height,
-            data: data_u16,
+            data,
+            channel_data,
+            raw_range: Some((min, max)),
         })
     }
 
-    /// Calculate basic statistics without star detection  
-    pub fn calculate_basic_statistics(&self) -> ImageStatistics {
-        self.calculate_statistics_with_mad()
+    /// Convert a physical pixel value to the 0-65535 scale of `data`, None for
+    /// images not loaded from a file or with a single value throughout
+    pub fn scaled_level(&self, raw: f64) -> Option<f64> {
+        let (min, max) = self.raw_range?;
+        (max > min).then(|| ((raw - min) * 65535.0 / (max - min)).clamp(0.0, 65535.0))
     }
 
-    /// Calculate statistics including MAD
-    pub fn calculate_statistics_with_mad(&self) -> ImageStatistics {
-        // Use arena for temporary allocation
-        let arena = Bump::new();
-        let mut sorted_data = bumpalo::vec![in &arena];
-        sorted_data.extend_from_slice(&self.data);
-        sorted_data.sort();
-
-        let sum: u64 = self.data.iter().map(|&x| x as u64).sum();
-        let mean = sum as f64 / self.data.len() as f64;
-
-        let median = if self.data.len() % 2 == 0 {
-            let mid = self.data.len() / 2;
-            (sorted_data[mid - 1] as f64 + sorted_data[mid] as f64) / 2.0
-        } else {
-            sorted_data[self.data.len() / 2] as f64
-        };
+    /// Convert a value of `data` back to the physical pixel value it was
+    /// scaled from; images not loaded from a file are taken as already physical
+    pub fn physical_level(&self, scaled: u16) -> f64 {
+        match self.raw_range {
+            Some((min, max)) => min + scaled as f64 * (max - min) / 65535.0,
+            None => scaled as f64,
+        }
+    }
+
+    /// Number of color channels (1 for a 2D image)
+    pub fn channel_count(&self) -> usize {
+        self.channel_data.len().max(1)
+    }
 
-        let variance: f64 = self
-            .data
+    /// Statistics for each color channel; a single entry for 2D images
+    pub fn calculate_statistics_per_channel(&self) -> Vec<ImageStatistics> {
+        if self.channel_data.is_empty() {
+            return vec![self.calculate_basic_statistics()];
+        }
+
+        self.channel_data
             .iter()
-            .map(|&x| {
-                let diff = x as f64 - mean;
-                diff * diff
+            .map(|plane| {
+                FitsImage {
+                    width: self.width,
+                    height: self.height,
+                    data: plane.clone(),
+                    channel_data: Vec::new(),
+                    raw_range: None,
+                }
+                .calculate_basic_statistics()
             })
-            .sum::<f64>()
-            / self.data.len() as f64;
-        let std_dev = variance.sqrt();
+            .collect()
+    }
 
-        let min = *sorted_data.first().unwrap_or(&0) as f64;
-        let max = *sorted_data.last().unwrap_or(&65535) as f64;
+    /// Collapse a raw Bayer mosaic into a half-resolution luminance frame
+    ///
+    /// Each 2x2 cell becomes one pixel weighted 0.299 R + 0.587 G + 0.114 B, with
+    /// G the mean of the two green pixels. A trailing odd row or column has no
+    /// complete cell and is dropped.
+    pub fn debayer(&self, pattern: BayerPattern) -> FitsImage {
+        let width = self.width / 2;
+        let height = self.height / 2;
+        let ((rx, ry), (bx, by)) = pattern.red_blue_offsets();
 
-        // Calculate MAD using N.I.N.A.'s histogram-based approach
-        let mad = self.calculate_mad_histogram(median);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel =
+                    |dx: usize, dy: usize| self.data[(2 * y + dy) * self.width + 2 * x + dx] as f64;
+                let cell_sum = pixel(0, 0) + pixel(1, 0) + pixel(0, 1) + pixel(1, 1);
+                let red = pixel(rx, ry);
+                let blue = pixel(bx, by);
+                let green = (cell_sum - red - blue) / 2.0;
 
-        ImageStatistics {
-            width: self.width,
-            height: self.height,
-            mean,
-            median,
-            std_dev,
-            min,
-            max,
-            star_count: None,
-            hfr: None,
-            fwhm: None,
-            mad: Some(mad),
+                let luminance = 0.299 * red + 0.587 * green + 0.114 * blue;
+                data.push(luminance.round().clamp(0.0, 65535.0) as u16);
+            }
+        }
+
+        FitsImage {
+            width,
+            height,
+            data,
+            channel_data: Vec::new(),
+            raw_range: self.raw_range,
         }
     }
 
+    /// Median of each image quadrant: top-left, top-right, bottom-left, bottom-right
+    ///
+    /// With an odd width or height the middle column/row goes to the right/bottom quadrants.
+    pub fn quadrant_medians(&self) -> [f64; 4] {
+        let half_x = self.width / 2;
+        let half_y = self.height / 2;
+        let bounds = [
+            (0, half_x, 0, half_y),
+            (half_x, self.width, 0, half_y),
+            (0, half_x, half_y, self.height),
+            (half_x, self.width, half_y, self.height),
+        ];
+
+        bounds.map(|(x0, x1, y0, y1)| {
+            let mut values: Vec<u16> = (y0..y1)
+                .flat_map(|y| {
+                    self.data[y * self.width + x0..y * self.width + x1]
+                        .iter()
+                        .copied()
+                })
+                .collect();
+            if values.is_empty() {
+                return 0.0;
+            }
+            let mid = values.len() / 2;
+            let (_, &mut median, _) = values.select_nth_unstable(mid);
+            median as f64
+        })
+    }
+
+    /// HFR of a star at a known position on the raw data, without detection or stretch.
+    ///
+    /// Uses N.I.N.A.'s background-subtracted, flux-weighted distance within `radius`.
+    /// Returns None when the measurement box falls off the image or holds no flux.
+    pub fn measure_hfr_at(&self, x: f64, y: f64, radius: f64) -> Option<f64> {
+        crate::nina_star_detection::measure_hfr(&self.data, self.width, self.height, (x, y), radius)
+    }
+
+    /// True when every pixel has the same value (or there are none), as in an
+    /// all-zero frame or a saturated calibration frame. Such frames have no stars.
+    pub fn is_blank(&self) -> bool {
+        is_uniform(&self.data)
+    }
+
+    /// Replace the pixels flagged in a hot pixel map with the median of their
+    /// unflagged neighbours. The map must match the raw (undebayered) frame size.
+    pub fn apply_hotpixel_map(&mut self, map: &HotPixelMap) -> Result<()> {
+        if (map.width, map.height) != (self.width, self.height) {
+            return Err(anyhow::anyhow!(
+                "Hot pixel map is {}x{} but the image is {}x{}",
+                map.width,
+                map.height,
+                self.width,
+                self.height
+            ));
+        }
+
+        map.apply_to(&mut self.data);
+        for channel in &mut self.channel_data {
+            map.apply_to(channel);
+        }
+        Ok(())
+    }
+
+    /// Write the image as a single-HDU FITS file: unsigned 16-bit pixels stored
+    /// as BITPIX = 16 with BZERO = 32768, big-endian, with the header and data
+    /// padded to 2880-byte blocks. RGB images are written as a 3-plane cube.
+    ///
+    /// Images with a `raw_range` get the BZERO and BSCALE that map the stored
+    /// values back to that physical range, so the file keeps real ADU levels.
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        self.write_to_file_with_headers(path, &[])
+    }
+
+    /// Like [`write_to_file`](Self::write_to_file), also writing `headers`
+    /// (e.g. from [`read_header_cards`]) after the structural keywords.
+    /// Structural keywords among them are skipped since the writer sets its own.
+    pub fn write_to_file_with_headers(
+        &self,
+        path: &Path,
+        headers: &[(String, fitrs::HeaderValue)],
+    ) -> Result<()> {
+        let planes: Vec<&[u16]> = if self.channel_data.is_empty() {
+            vec![&self.data]
+        } else {
+            self.channel_data.iter().map(Vec::as_slice).collect()
+        };
+
+        let mut cards = vec![
+            fits_card("SIMPLE", "T"),
+            fits_card("BITPIX", "16"),
+            fits_card("NAXIS", if planes.len() > 1 { "3" } else { "2" }),
+            fits_card("NAXIS1", &self.width.to_string()),
+            fits_card("NAXIS2", &self.height.to_string()),
+        ];
+        if planes.len() > 1 {
+            cards.push(fits_card("NAXIS3", &planes.len().to_string()));
+        }
+        let (bzero, bscale) = match self.raw_range {
+            Some((min, max)) => {
+                let bscale = if max > min {
+                    (max - min) / 65535.0
+                } else {
+                    1.0
+                };
+                (min + 32768.0 * bscale, bscale)
+            }
+            None => (32768.0, 1.0),
+        };
+        cards.push(fits_card("BZERO", &real_value(bzero)));
+        cards.push(fits_card("BSCALE", &real_value(bscale)));
+        cards.extend(
+            headers
+                .iter()
+                .filter(|(keyword, _)| !is_structural_keyword(keyword))
+                .filter_map(|(keyword, value)| value_card(keyword, value)),
+        );
+        cards.push(format!("{:<80}", "END"));
+
+        let mut bytes = cards.concat().into_bytes();
+        pad_to_fits_block(&mut bytes, b' ');
+        for plane in planes {
+            for &value in plane {
+                bytes.extend_from_slice(&((value as i32 - 32768) as i16).to_be_bytes());
+            }
+        }
+        pad_to_fits_block(&mut bytes, 0);
+
+        std::fs::write(path, bytes)
+            .map_err(|e| anyhow::anyhow!("Failed to write FITS file {}: {}", path.display(), e))
+    }
+
+    /// Calculate basic statistics without star detection  
+    pub fn calculate_basic_statistics(&self) -> ImageStatistics {
+        self.calculate_statistics_with_mad()
+    }
+
+    /// Calculate statistics including MAD
+    pub fn calculate_statistics_with_mad(&self) -> ImageStatistics {
+        pixel_statistics(&self.data, self.width, self.height)
+    }
+
     /// Calculate basic image statistics
     pub fn calculate_statistics(&self) -> ImageStatistics {
         let stats = self.calculate_basic_statistics();
@@ -207,65 +859,802 @@ impl FitsImage {
             mad: stats.mad,
         }
     }
+}
 
-    /// Calculate MAD using N.I.N.A.'s histogram-based approach
-    fn calculate_mad_histogram(&self, median: f64) -> f64 {
-        // Build histogram of pixel values
-        let mut pixel_counts = vec![0u32; 65536];
-        for &val in self.data.iter() {
-            pixel_counts[val as usize] += 1;
+/// Mean, median, spread, extremes and MAD of a 16-bit frame, for callers holding
+/// the pixels in their own buffer rather than a [`FitsImage`]
+pub fn pixel_statistics(data: &[u16], width: usize, height: usize) -> ImageStatistics {
+    // Spread is undefined below two pixels; report it as zero rather than NaN
+    if data.len() < 2 {
+        let value = data.first().map_or(0.0, |&v| v as f64);
+        return ImageStatistics {
+            width,
+            height,
+            mean: value,
+            median: value,
+            std_dev: 0.0,
+            min: value,
+            max: value,
+            star_count: None,
+            hfr: None,
+            fwhm: None,
+            mad: Some(0.0),
+        };
+    }
+
+    // One 16-bit histogram gives min, max, median and MAD without sorting a copy
+    let mut pixel_counts = vec![0u32; 65536];
+    for &val in data.iter() {
+        pixel_counts[val as usize] += 1;
+    }
+
+    let sum: u64 = data.iter().map(|&x| x as u64).sum();
+    let mean = sum as f64 / data.len() as f64;
+
+    let median = if data.len() % 2 == 0 {
+        let mid = data.len() / 2;
+        (histogram_rank(&pixel_counts, mid - 1) + histogram_rank(&pixel_counts, mid)) / 2.0
+    } else {
+        histogram_rank(&pixel_counts, data.len() / 2)
+    };
+
+    let std_dev = data
+        .iter()
+        .map(|&x| x as f64)
+        .collect::<OnlineStats>()
+        .std_dev();
+
+    let min = pixel_counts.iter().position(|&c| c > 0).unwrap_or(0) as f64;
+    let max = pixel_counts.iter().rposition(|&c| c > 0).unwrap_or(65535) as f64;
+
+    // Calculate MAD using N.I.N.A.'s histogram-based approach
+    let mad = mad_from_histogram(data, &pixel_counts, median);
+
+    ImageStatistics {
+        width,
+        height,
+        mean,
+        median,
+        std_dev,
+        min,
+        max,
+        star_count: None,
+        hfr: None,
+        fwhm: None,
+        mad: Some(mad),
+    }
+}
+
+/// Calculate MAD using N.I.N.A.'s histogram-based approach
+fn mad_from_histogram(data: &[u16], pixel_counts: &[u32], median: f64) -> f64 {
+    // Find median values (handling even vs odd length arrays)
+    let median1 = median.floor() as i32;
+    let median2 = median.ceil() as i32;
+
+    // Calculate MAD using N.I.N.A.'s algorithm
+    // MAD = median(|x_i - median|)
+    // Since we're looking for the median of absolute deviations,
+    // we start from the median and step outward symmetrically
+    let mut occurrences = 0u32;
+    let medianlength = data.len() as f64 / 2.0;
+    let mut idx_down = median1;
+    let mut idx_up = median2;
+
+    loop {
+        // Count pixels at current deviation distance
+        if idx_down >= 0 && idx_down != idx_up {
+            occurrences += pixel_counts[idx_down as usize] + pixel_counts[idx_up as usize];
+        } else if idx_up < 65536 {
+            occurrences += pixel_counts[idx_up as usize];
         }
 
-        // Find median values (handling even vs odd length arrays)
-        let median1 = median.floor() as i32;
-        let median2 = median.ceil() as i32;
+        // Check if we've found the median of deviations
+        if occurrences as f64 > medianlength {
+            // The median absolute deviation is the current distance from median
+            return (idx_up as f64 - median).abs();
+        }
 
-        // Calculate MAD using N.I.N.A.'s algorithm
-        // MAD = median(|x_i - median|)
-        // Since we're looking for the median of absolute deviations,
-        // we start from the median and step outward symmetrically
-        let mut occurrences = 0u32;
-        let medianlength = self.data.len() as f64 / 2.0;
-        let mut idx_down = median1;
-        let mut idx_up = median2;
+        // Step outward
+        idx_down -= 1;
+        idx_up += 1;
 
-        loop {
-            // Count pixels at current deviation distance
-            if idx_down >= 0 && idx_down != idx_up {
-                occurrences += pixel_counts[idx_down as usize] + pixel_counts[idx_up as usize];
-            } else if idx_up < 65536 {
-                occurrences += pixel_counts[idx_up as usize];
-            }
+        // Safety check
+        if idx_down < 0 && idx_up >= 65536 {
+            break;
+        }
+    }
 
-            // Check if we've found the median of deviations
-            if occurrences as f64 > medianlength {
-                // The median absolute deviation is the current distance from median
-                return (idx_up as f64 - median).abs();
-            }
+    // Fallback to simple MAD calculation
+    let mut deviations: Vec<f64> = data.iter().map(|&x| (x as f64 - median).abs()).collect();
+    deviations.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
-            // Step outward
-            idx_down -= 1;
-            idx_up += 1;
+    if deviations.len() % 2 == 0 {
+        let mid = deviations.len() / 2;
+        (deviations[mid - 1] + deviations[mid]) / 2.0
+    } else {
+        deviations[deviations.len() / 2]
+    }
+}
+
+/// Value at 0-based position `rank` of the sorted pixels, read from their histogram
+fn histogram_rank(pixel_counts: &[u32], rank: usize) -> f64 {
+    let mut seen = 0usize;
+    for (value, &count) in pixel_counts.iter().enumerate() {
+        seen += count as usize;
+        if seen > rank {
+            return value as f64;
+        }
+    }
+    (pixel_counts.len() - 1) as f64
+}
+
+/// One 80-character FITS header card with a right-aligned fixed-format value
+fn fits_card(keyword: &str, value: &str) -> String {
+    format!("{:<8}= {:>20}{:50}", keyword, value, "")
+}
+
+/// Fixed-format text of a real header value, at most 20 characters
+fn real_value(x: f64) -> String {
+    // Debug keeps the decimal point (300.0) so the value reads back as real
+    let text = format!("{:?}", x).to_uppercase();
+    if text.len() > 20 {
+        format!("{:.12E}", x)
+    } else {
+        text
+    }
+}
+
+/// Card for a copied header value; None for values the writer cannot represent
+fn value_card(keyword: &str, value: &fitrs::HeaderValue) -> Option<String> {
+    use fitrs::HeaderValue;
 
-            // Safety check
-            if idx_down < 0 && idx_up >= 65536 {
-                break;
+    if keyword.len() > 8 {
+        return None;
+    }
+    match value {
+        // Cards are ASCII; the padding below counts characters, not bytes
+        HeaderValue::CharacterString(text) if !text.is_ascii() => None,
+        HeaderValue::CharacterString(text) => {
+            // Quoted, at least 8 characters inside the quotes, '' escapes a quote
+            let quoted = format!("'{:<8}'", text.trim_end().replace('\'', "''"));
+            if quoted.len() > 70 {
+                return None;
             }
+            Some(format!("{:<8}= {:<70}", keyword, quoted))
         }
+        HeaderValue::Logical(flag) => Some(fits_card(keyword, if *flag { "T" } else { "F" })),
+        HeaderValue::IntegerNumber(n) => Some(fits_card(keyword, &n.to_string())),
+        HeaderValue::RealFloatingNumber(x) if x.is_finite() => {
+            Some(fits_card(keyword, &real_value(*x)))
+        }
+        _ => None,
+    }
+}
 
-        // Fallback to simple MAD calculation
-        let mut deviations: Vec<f64> = self
-            .data
-            .iter()
-            .map(|&x| (x as f64 - median).abs())
+/// Keywords describing the data layout, which the writer always sets itself
+fn is_structural_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "SIMPLE"
+            | "BITPIX"
+            | "BZERO"
+            | "BSCALE"
+            | "EXTEND"
+            | "XTENSION"
+            | "PCOUNT"
+            | "GCOUNT"
+            | "END"
+    ) || keyword.starts_with("NAXIS")
+}
+
+/// Acquisition keywords worth keeping on images derived from a frame
+pub const ACQUISITION_KEYWORDS: &[&str] = &[
+    "OBJECT", "IMAGETYP", "FILTER", "EXPOSURE", "EXPTIME", "DATE-OBS", "DATE-LOC", "GAIN",
+    "OFFSET", "CCD-TEMP", "SET-TEMP", "XBINNING", "YBINNING", "XPIXSZ", "YPIXSZ", "FOCALLEN",
+    "INSTRUME", "TELESCOP", "RA", "DEC", "OBJCTRA", "OBJCTDEC", "BAYERPAT",
+];
+
+/// Values of `keywords` from a FITS file, taking the first HDU that has each
+/// one, in the order given; keywords that are missing are left out. XISF
+/// files yield no values.
+pub fn read_header_cards(
+    path: &Path,
+    keywords: &[&str],
+) -> Result<Vec<(String, fitrs::HeaderValue)>> {
+    if xisf::is_xisf(path) {
+        return Ok(Vec::new());
+    }
+
+    let hdus: Vec<fitrs::Hdu> = open_fits(path)?.iter().collect();
+    Ok(keywords
+        .iter()
+        .filter_map(|keyword| {
+            hdus.iter()
+                .find_map(|hdu| hdu.value(keyword))
+                .map(|value| (keyword.to_string(), value.clone()))
+        })
+        .collect())
+}
+
+/// Physical pixel value at which the sensor saturates: `max_adu` when given,
+/// else the DATAMAX header, else None
+pub fn saturation_adu(path: &Path, max_adu: Option<f64>) -> Option<f64> {
+    if max_adu.is_some() {
+        return max_adu;
+    }
+    let cards = read_header_cards(path, &["DATAMAX"]).ok()?;
+    header_number(&cards.first()?.1).filter(|&value| value > 0.0)
+}
+
+/// Numeric value of a header card, None for strings and logicals
+pub fn header_number(value: &fitrs::HeaderValue) -> Option<f64> {
+    match *value {
+        fitrs::HeaderValue::IntegerNumber(value) => Some(value as f64),
+        fitrs::HeaderValue::RealFloatingNumber(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Pad FITS header or data bytes to a whole number of 2880-byte blocks
+fn pad_to_fits_block(bytes: &mut Vec<u8>, fill: u8) {
+    bytes.resize(bytes.len().div_ceil(2880) * 2880, fill);
+}
+
+/// True when all values are equal, including an empty slice
+pub fn is_uniform(data: &[u16]) -> bool {
+    data.first()
+        .is_none_or(|&first| data.iter().all(|&v| v == first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitrs::{Fits, Hdu};
+
+    fn temp_fits_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("psf_guard_{}_{}.fits", name, std::process::id()))
+    }
+
+    fn gradient_data(width: usize, height: usize) -> Vec<f32> {
+        (0..width * height).map(|i| i as f32).collect()
+    }
+
+    #[test]
+    fn test_from_file_skips_dataless_primary() {
+        let path = temp_fits_path("two_hdu");
+        let mut fits = Fits::create(&path, Hdu::empty()).unwrap();
+        fits.push(Hdu::new(&[4, 3], gradient_data(4, 3))).unwrap();
+        drop(fits);
+
+        let image = FitsImage::from_file(&path).unwrap();
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(image.data[0], 0);
+        assert_eq!(image.data[11], 65535);
+
+        let explicit = FitsImage::from_file_hdu(&path, 1).unwrap();
+        assert_eq!(explicit.data, image.data);
+
+        let primary = FitsImage::from_file_hdu(&path, 0);
+        assert!(primary.is_err());
+
+        let missing = FitsImage::from_file_hdu(&path, 5)
+            .err()
+            .expect("HDU 5 should not exist")
+            .to_string();
+        assert!(missing.contains("2 HDU(s) present"), "{}", missing);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_from_file_reports_hdu_count_without_image() {
+        let path = temp_fits_path("no_image");
+        let fits = Fits::create(&path, Hdu::empty()).unwrap();
+        drop(fits);
+
+        let err = FitsImage::from_file(&path)
+            .err()
+            .expect("no image HDU should be an error")
+            .to_string();
+        assert!(err.contains("No HDU with 2D image data"), "{}", err);
+        assert!(err.contains("1 HDU(s) present"), "{}", err);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_from_file_primary_image() {
+        let path = temp_fits_path("primary");
+        let fits = Fits::create(&path, Hdu::new(&[5, 2], gradient_data(5, 2))).unwrap();
+        drop(fits);
+
+        let image = FitsImage::from_file(&path).unwrap();
+        assert_eq!((image.width, image.height), (5, 2));
+        assert_eq!(image.data.len(), 10);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_from_file_plane_selects_error_extension() {
+        let path = temp_fits_path("planes");
+        let mut science = Hdu::new(&[4, 3], gradient_data(4, 3));
+        science.insert("EXTNAME", "SCI");
+        let mut fits = Fits::create(&path, science).unwrap();
+        // Error plane runs the opposite way so the two are distinguishable
+        let mut error = Hdu::new(&[4, 3], gradient_data(4, 3).into_iter().rev().collect());
+        error.insert("EXTNAME", "ERR");
+        fits.push(error).unwrap();
+        drop(fits);
+
+        let science = FitsImage::from_file_plane(&path, FitsPlane::Science).unwrap();
+        assert_eq!(science.data[0], 0);
+        assert_eq!(science.data[11], 65535);
+
+        let error = FitsImage::from_file_plane(&path, FitsPlane::Error).unwrap();
+        assert_eq!(error.data[0], 65535);
+        assert_eq!(error.data[11], 0);
+
+        let by_index = FitsImage::from_file_plane(&path, FitsPlane::Index(1)).unwrap();
+        assert_eq!(by_index.data, error.data);
+
+        assert!(FitsImage::from_file_plane(&path, FitsPlane::Index(2)).is_err());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_from_file_plane_missing_error_extension() {
+        let path = temp_fits_path("no_err_plane");
+        let fits = Fits::create(&path, Hdu::new(&[5, 2], gradient_data(5, 2))).unwrap();
+        drop(fits);
+
+        let err = FitsImage::from_file_plane(&path, FitsPlane::Error)
+            .err()
+            .expect("file has no error plane")
+            .to_string();
+        assert!(err.contains("No error plane"), "{}", err);
+
+        // Science falls back to the first image HDU
+        let science = FitsImage::from_file_plane(&path, FitsPlane::Science).unwrap();
+        assert_eq!((science.width, science.height), (5, 2));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_parse_fits_plane() {
+        assert_eq!("science".parse(), Ok(FitsPlane::Science));
+        assert_eq!("ERROR".parse(), Ok(FitsPlane::Error));
+        assert_eq!("2".parse(), Ok(FitsPlane::Index(2)));
+        assert!("weights".parse::<FitsPlane>().is_err());
+    }
+
+    #[test]
+    fn test_u32_data_above_u16_range_keeps_structure() {
+        let path = temp_fits_path("u32_master");
+        let data: Vec<u32> = vec![1_000, 70_000, 140_000, 280_000, 560_000, 1_120_000];
+        let fits = Fits::create(&path, Hdu::new(&[3, 2], data)).unwrap();
+        drop(fits);
+
+        let image = FitsImage::from_file(&path).unwrap();
+        assert_eq!(image.data[0], 0);
+        assert_eq!(image.data[5], 65535);
+        // Every step stays distinct and ordered rather than saturating at 65535
+        assert!(
+            image.data.windows(2).all(|w| w[0] < w[1]),
+            "{:?}",
+            image.data
+        );
+        // Doubling above the offset is preserved proportionally
+        let ratio = (image.data[4] as f64) / (image.data[3] as f64);
+        assert!((ratio - 2.0).abs() < 0.01, "ratio {}", ratio);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    /// 5x3 RGGB mosaic: every cell has R=1000, G=2000/3000, B=4000 except the
+    /// odd last column and row, which are filled with a marker value
+    fn rggb_mosaic() -> FitsImage {
+        let (width, height) = (5, 3);
+        let data = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                match (x == width - 1 || y == height - 1, x % 2, y % 2) {
+                    (true, _, _) => 60000,
+                    (_, 0, 0) => 1000,
+                    (_, 1, 0) => 2000,
+                    (_, 0, 1) => 3000,
+                    _ => 4000,
+                }
+            })
             .collect();
-        deviations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        FitsImage {
+            width,
+            height,
+            data,
+            channel_data: Vec::new(),
+            raw_range: None,
+        }
+    }
 
-        if deviations.len() % 2 == 0 {
-            let mid = deviations.len() / 2;
-            (deviations[mid - 1] + deviations[mid]) / 2.0
-        } else {
-            deviations[deviations.len() / 2]
+    #[test]
+    fn test_debayer_rggb() {
+        let luminance = rggb_mosaic().debayer(BayerPattern::Rggb);
+        assert_eq!((luminance.width, luminance.height), (2, 1));
+        // 0.299 * 1000 + 0.587 * 2500 + 0.114 * 4000
+        assert_eq!(luminance.data, vec![2223, 2223]);
+
+        // Reading the same cells as BGGR swaps the red and blue weights
+        let swapped = rggb_mosaic().debayer(BayerPattern::Bggr);
+        assert_eq!(swapped.data[0], 2778);
+    }
+
+    #[test]
+    fn test_debayer_mode_reads_bayerpat() {
+        let path = temp_fits_path("bayerpat");
+        let data: Vec<f32> = rggb_mosaic().data.iter().map(|&v| v as f32).collect();
+        let mut primary = Hdu::new(&[5, 3], data);
+        primary.insert("BAYERPAT", "RGGB");
+        Fits::create(&path, primary).unwrap();
+
+        let fits = FitsImage::from_file(&path).unwrap();
+        let auto = DebayerMode::Auto.apply(fits, &path).unwrap();
+        assert_eq!((auto.width, auto.height), (2, 1));
+
+        let fits = FitsImage::from_file(&path).unwrap();
+        let none = DebayerMode::None.apply(fits, &path).unwrap();
+        assert_eq!((none.width, none.height), (5, 3));
+
+        assert_eq!(
+            "gbrg".parse::<DebayerMode>(),
+            Ok(DebayerMode::Pattern(BayerPattern::Gbrg))
+        );
+        assert!("xtrans".parse::<DebayerMode>().is_err());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_rgb_cube_per_channel_statistics() {
+        let (width, height) = (8, 6);
+        let path = temp_fits_path("rgb_cube");
+        // Red plane dim, green brighter, blue brightest, each with a small ramp
+        let cube: Vec<f32> = (0..3)
+            .flat_map(|channel| (0..width * height).map(move |i| (channel * 1000 + i % 10) as f32))
+            .collect();
+        Fits::create(&path, Hdu::new(&[width, height, 3], cube)).unwrap();
+
+        let image = FitsImage::from_file(&path).unwrap();
+        assert_eq!((image.width, image.height), (width, height));
+        assert_eq!(image.channel_count(), 3);
+        assert_eq!(image.data.len(), width * height);
+
+        let stats = image.calculate_statistics_per_channel();
+        assert_eq!(stats.len(), 3);
+        assert!(stats[0].median < stats[1].median);
+        assert!(stats[1].median < stats[2].median);
+        // Shared scaling keeps the blue/red offset at ~2000/2009 of full range
+        assert!((stats[2].median - stats[0].median - 65535.0 * 2000.0 / 2009.0).abs() < 2.0);
+
+        // 2D images still report a single channel
+        let mono = FitsImage::from_pixels(vec![1.0, 2.0, 3.0, 4.0], 2, 2).unwrap();
+        assert_eq!(mono.channel_count(), 1);
+        assert_eq!(mono.calculate_statistics_per_channel().len(), 1);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_write_to_file_round_trip() {
+        use fitrs::HeaderValue;
+
+        let source = temp_fits_path("write_source");
+        let mut hdu = Hdu::new(&[4, 3], gradient_data(4, 3));
+        hdu.insert("FILTER", "Ha");
+        hdu.insert("EXPOSURE", 300.0);
+        hdu.insert("GAIN", 100);
+        Fits::create(&source, hdu).unwrap();
+
+        // The data spans 0..=65535 so the reader's range scaling is a no-op
+        let image = FitsImage {
+            width: 4,
+            height: 3,
+            data: vec![
+                0, 1, 2, 1000, 32767, 32768, 40000, 50000, 60000, 65000, 65534, 65535,
+            ],
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+        let headers =
+            read_header_cards(&source, &["FILTER", "EXPOSURE", "GAIN", "BITPIX", "OBJECT"])
+                .unwrap();
+        assert_eq!(headers.len(), 4);
+
+        let output = temp_fits_path("write_output");
+        image.write_to_file_with_headers(&output, &headers).unwrap();
+        let file_len = std::fs::metadata(&output).unwrap().len();
+        let reloaded = FitsImage::from_file(&output).unwrap();
+        let written = Fits::open(&output).unwrap();
+        let hdu = written.get(0).unwrap();
+
+        assert_eq!(file_len % 2880, 0);
+        assert_eq!((reloaded.width, reloaded.height), (4, 3));
+        assert_eq!(reloaded.data, image.data);
+        assert!(matches!(
+            hdu.value("FILTER"),
+            Some(HeaderValue::CharacterString(filter)) if filter.trim() == "Ha"
+        ));
+        assert_eq!(
+            hdu.value("EXPOSURE"),
+            Some(&HeaderValue::RealFloatingNumber(300.0))
+        );
+        assert_eq!(hdu.value("GAIN"), Some(&HeaderValue::IntegerNumber(100)));
+        // The copied BITPIX must not replace the writer's own
+        assert_eq!(hdu.value("BITPIX"), Some(&HeaderValue::IntegerNumber(16)));
+
+        std::fs::remove_file(&source).ok();
+        std::fs::remove_file(&output).ok();
+    }
+
+    #[test]
+    fn test_value_card_skips_non_ascii_strings() {
+        use fitrs::HeaderValue;
+
+        let card = value_card("OBJECT", &HeaderValue::CharacterString("M 45".to_string()));
+        assert_eq!(card.map(|c| c.len()), Some(80));
+        let text = HeaderValue::CharacterString("Mélotte 15".to_string());
+        assert_eq!(value_card("OBJECT", &text), None);
+    }
+
+    #[test]
+    fn test_short_data_is_size_mismatch() {
+        let err = FitsImage::from_planes(vec![1.0; 5], 3, 2, 1).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<FitsReadError>(),
+            Some(&FitsReadError::SizeMismatch {
+                expected: 6,
+                got: 5
+            })
+        );
+
+        // A download cut off halfway through the pixel data
+        let path = temp_fits_path("truncated");
+        Fits::create(&path, Hdu::new(&[40, 30], gradient_data(40, 30))).unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(2880 + 40 * 30 * 4 / 2).unwrap();
+        drop(file);
+
+        let err = FitsImage::from_file(&path).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<FitsReadError>(),
+            Some(&FitsReadError::SizeMismatch {
+                expected: 1200,
+                got: 600
+            })
+        );
+
+        let options = FitsReadOptions {
+            tolerant: true,
+            ..Default::default()
+        };
+        let image =
+            FitsImage::from_file_plane_with_options(&path, FitsPlane::Science, &options).unwrap();
+        assert_eq!((image.width, image.height), (40, 30));
+        assert_eq!(image.data.len(), 1200);
+        assert_eq!(image.data[0], 0);
+        assert_eq!(image.data[599], 65535);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_read_retries_transient_io_errors() {
+        let path = temp_fits_path("retry");
+        Fits::create(&path, Hdu::new(&[4, 3], gradient_data(4, 3))).unwrap();
+        let options = FitsReadOptions {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        // Fails twice like a flaky network mount, then reads the file
+        let mut calls = 0;
+        let image = options
+            .retry(&path, || {
+                calls += 1;
+                if calls <= 2 {
+                    let error = std::io::Error::from(std::io::ErrorKind::TimedOut);
+                    return Err(anyhow::Error::new(error).context("Failed to open FITS file"));
+                }
+                FitsImage::from_file(&path)
+            })
+            .unwrap();
+        assert_eq!(calls, 3);
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(image.data[11], 65535);
+
+        // Running out of attempts returns the last error
+        let mut calls = 0;
+        let result: Result<()> = options.retry(&path, || {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_read_fails_fast_on_permanent_errors() {
+        let options = FitsReadOptions {
+            attempts: 5,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let missing = temp_fits_path("retry_missing");
+        let mut calls = 0;
+        let result = options.retry(&missing, || {
+            calls += 1;
+            FitsImage::from_file(&missing)
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: Result<()> = options.retry(&missing, || {
+            calls += 1;
+            Err(anyhow::anyhow!("Unsupported FITS data type"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_histogram_statistics_match_sorted_copy() {
+        use rand::prelude::*;
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for (len, spread) in [(10_001, 65536), (10_000, 65536), (4_096, 300), (3, 2)] {
+            let data: Vec<u16> = (0..len)
+                .map(|_| (1000 + rng.gen_range(0..spread)).min(65535) as u16)
+                .collect();
+            let image = FitsImage {
+                width: len,
+                height: 1,
+                data: data.clone(),
+                channel_data: Vec::new(),
+                raw_range: None,
+            };
+            let stats = image.calculate_basic_statistics();
+
+            // The previous implementation: a sorted copy of the whole frame
+            let mut sorted = data.clone();
+            sorted.sort();
+            let median = if len % 2 == 0 {
+                (sorted[len / 2 - 1] as f64 + sorted[len / 2] as f64) / 2.0
+            } else {
+                sorted[len / 2] as f64
+            };
+            // N.I.N.A.'s MAD is the upper middle deviation when the length is even
+            let mut deviations: Vec<f64> =
+                data.iter().map(|&v| (v as f64 - median).abs()).collect();
+            deviations.sort_by(|a, b| a.total_cmp(b));
+
+            assert_eq!(stats.median, median);
+            assert_eq!(stats.min, sorted[0] as f64);
+            assert_eq!(stats.max, sorted[len - 1] as f64);
+            assert_eq!(stats.mad, Some(deviations[len / 2]), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_even_length_mad_against_sorted_deviations() {
+        // Median 3.5; sorted deviations 1.5, 1.5, 2.5, 4.5
+        let data = [1u16, 2, 5, 8];
+        let mut pixel_counts = vec![0u32; 65536];
+        for &v in &data {
+            pixel_counts[v as usize] += 1;
         }
+        let mut deviations: Vec<f64> = data.iter().map(|&v| (v as f64 - 3.5).abs()).collect();
+        deviations.sort_by(|a, b| a.total_cmp(b));
+
+        assert_eq!(
+            mad_from_histogram(&data, &pixel_counts, 3.5),
+            deviations[data.len() / 2]
+        );
+    }
+
+    #[test]
+    fn test_single_pixel_statistics() {
+        let image = FitsImage {
+            width: 1,
+            height: 1,
+            data: vec![1234],
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+
+        let stats = image.calculate_basic_statistics();
+        assert_eq!(stats.mean, 1234.0);
+        assert_eq!(stats.median, 1234.0);
+        assert_eq!((stats.min, stats.max), (1234.0, 1234.0));
+        assert_eq!(stats.std_dev, 0.0);
+        assert_eq!(stats.mad, Some(0.0));
+        assert!(image.is_blank());
+    }
+
+    #[test]
+    fn test_all_zero_frame_is_blank() {
+        let (width, height) = (64, 48);
+        let image = FitsImage {
+            width,
+            height,
+            data: vec![0; width * height],
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+
+        assert!(image.is_blank());
+        let stats = image.calculate_basic_statistics();
+        assert_eq!(
+            (stats.mean, stats.std_dev, stats.mad),
+            (0.0, 0.0, Some(0.0))
+        );
+
+        let hocus = crate::hocus_focus_star_detection::detect_stars_hocus_focus(
+            &image.data,
+            width,
+            height,
+            &Default::default(),
+        );
+        assert!(hocus.stars.is_empty());
+
+        let nina = crate::nina_star_detection::detect_stars_with_original(
+            &image.data,
+            &image.data,
+            width,
+            height,
+            &Default::default(),
+        );
+        assert!(nina.star_list.is_empty());
+
+        let mut lit = image;
+        lit.data[100] = 500;
+        assert!(!lit.is_blank());
+    }
+
+    #[test]
+    fn test_measure_hfr_at_gaussian_star() {
+        let (width, height) = (80, 80);
+        let (cx, cy, sigma) = (40.0, 38.0, 2.5);
+        let data = (0..width * height)
+            .map(|i| {
+                let d2 = ((i % width) as f64 - cx).powi(2) + ((i / width) as f64 - cy).powi(2);
+                (800.0 + 30000.0 * (-d2 / (2.0 * sigma * sigma)).exp()).round() as u16
+            })
+            .collect();
+        let image = FitsImage {
+            width,
+            height,
+            data,
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+
+        // The flux-weighted mean distance of a 2D Gaussian is sigma * sqrt(pi / 2)
+        let expected = sigma * (std::f64::consts::PI / 2.0).sqrt();
+        let hfr = image.measure_hfr_at(cx, cy, 4.0 * sigma).unwrap();
+        assert!(
+            (hfr - expected).abs() < expected * 0.05,
+            "hfr {} vs {}",
+            hfr,
+            expected
+        );
+
+        // Measurement box extends past the edge
+        assert!(image.measure_hfr_at(5.0, 40.0, 4.0).is_none());
+        // Flat sky has no flux above the background
+        assert!(image.measure_hfr_at(12.0, 12.0, 1.0).is_none());
     }
 }
@@ -1,10 +1,15 @@
 pub mod accord_imaging;
+pub mod analysis;
 pub mod cli;
 pub mod commands;
 pub mod db;
 pub mod debug;
+pub mod field_analysis;
+pub mod field_style;
+pub mod fits_compression;
 pub mod grading;
 pub mod hocus_focus_star_detection;
+pub mod hotpixel_map;
 pub mod image_analysis;
 pub mod models;
 pub mod mtf_stretch;
@@ -16,10 +21,14 @@ pub mod opencv_morphology;
 pub mod opencv_utils;
 pub mod opencv_wavelets;
 pub mod psf_fitting;
+pub mod stacking;
+pub mod trail_detection;
 pub mod utils;
+pub mod xisf;
 
 #[cfg(test)]
 mod test_star_detection;
 
 // Re-export commonly used items
+pub use analysis::{analyze_frame, AnalyzeOptions, FrameAnalysis};
 pub use image_analysis::{FitsImage, ImageStatistics};
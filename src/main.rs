@@ -1,13 +1,16 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags};
 
 use psf_guard::cli::{Cli, Commands};
 use psf_guard::commands::{
-    analyze_fits_and_compare, annotate_stars, benchmark_psf, dump_grading_results,
-    filter_rejected_files, list_projects, list_targets, read_fits, regrade_images, show_images,
-    stretch_to_png, update_grade,
+    analyze_fits_and_compare, analyze_tilt, annotate_stars, benchmark_psf, build_hotpixel_map,
+    check_db, dump_grading_results, export, filter_rejected_files, import_metadata, list_projects,
+    list_targets, psf_summary, read_fits, regrade_images, restore_rejected_files, show_images,
+    stack, stretch_to_png, trend, update_grade,
 };
+use psf_guard::mtf_stretch::StretchAlgorithm;
+use psf_guard::utils::{Subset, TraversalOptions};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -39,20 +42,45 @@ fn main() -> Result<()> {
             dry_run,
             project,
             target,
+            filter,
             verbose,
+            format,
             stat_options,
         } => {
             let conn = Connection::open(&database)
                 .with_context(|| format!("Failed to open database: {}", database))?;
 
-            let stat_config = stat_options.to_grading_config();
+            let stat_config = stat_options.to_grading_config()?;
             filter_rejected_files(
                 &conn,
                 &base_dir,
                 dry_run,
                 project,
                 target,
+                filter,
                 stat_config,
+                &format,
+                verbose,
+            )?;
+        }
+        Commands::RestoreRejected {
+            database,
+            base_dir,
+            dry_run,
+            project,
+            target,
+            reset_status,
+            verbose,
+        } => {
+            let conn = Connection::open(&database)
+                .with_context(|| format!("Failed to open database: {}", database))?;
+            restore_rejected_files(
+                &conn,
+                &base_dir,
+                dry_run,
+                project,
+                target,
+                reset_status,
                 verbose,
             )?;
         }
@@ -61,21 +89,85 @@ fn main() -> Result<()> {
             dry_run,
             target,
             project,
+            filter,
             days,
             reset,
+            protect_reasons,
+            incremental,
+            limit,
+            sample,
+            seed,
             stat_options,
         } => {
             let conn = Connection::open(&database)
                 .with_context(|| format!("Failed to open database: {}", database))?;
 
-            let stat_config = stat_options.to_grading_config();
-            regrade_images(&conn, dry_run, target, project, days, &reset, stat_config)?;
+            let stat_config = stat_options.to_grading_config()?;
+            regrade_images(
+                &conn,
+                dry_run,
+                target,
+                project,
+                filter,
+                days,
+                &reset,
+                &protect_reasons,
+                stat_config,
+                incremental,
+                Subset::from_args(limit, sample, seed),
+            )?;
+        }
+        Commands::CheckDb { database } => {
+            let database = database.as_deref().unwrap_or(&cli.database);
+            // Open read-only so a mistyped path is not silently created
+            let conn = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .with_context(|| format!("Failed to open database: {}", database))?;
+            check_db(&conn)?;
+        }
+        Commands::ImportMetadata {
+            dir,
+            project,
+            target,
+            dry_run,
+        } => {
+            let conn = Connection::open(&cli.database)
+                .with_context(|| format!("Failed to open database: {}", cli.database))?;
+            import_metadata(&conn, &dir, &project, target, dry_run)?;
         }
         Commands::ShowImages { ids } => {
             let conn = Connection::open(&cli.database)
                 .with_context(|| format!("Failed to open database: {}", cli.database))?;
             show_images(&conn, &ids)?;
         }
+        Commands::Trend {
+            project,
+            target,
+            format,
+        } => {
+            let conn = Connection::open(&cli.database)
+                .with_context(|| format!("Failed to open database: {}", cli.database))?;
+            trend(&conn, project, target, &format)?;
+        }
+        Commands::Export {
+            project,
+            target,
+            output,
+            base_dir,
+            recompute,
+            jobs,
+        } => {
+            let conn = Connection::open(&cli.database)
+                .with_context(|| format!("Failed to open database: {}", cli.database))?;
+            export(
+                &conn,
+                project,
+                target,
+                output.as_deref(),
+                base_dir.as_deref(),
+                recompute,
+                jobs,
+            )?;
+        }
         Commands::UpdateGrade { id, status, reason } => {
             let conn = Connection::open(&cli.database)
                 .with_context(|| format!("Failed to open database: {}", cli.database))?;
@@ -85,19 +177,42 @@ fn main() -> Result<()> {
             path,
             verbose,
             format,
+            follow_links,
+            max_depth,
         } => {
-            read_fits(&path, verbose, &format)?;
+            let traversal = TraversalOptions {
+                follow_links,
+                max_depth,
+            };
+            read_fits(&path, verbose, &format, &traversal)?;
         }
         Commands::AnalyzeFits {
             path,
             project,
             target,
+            filter,
             format,
             detector,
             sensitivity,
             apply_stretch,
             compare_all,
             psf_type,
+            no_header,
+            field_style,
+            plane,
+            debayer,
+            hotpixel_map,
+            downsample,
+            min_r_squared,
+            max_adu,
+            tolerant,
+            stars_csv,
+            jobs,
+            follow_links,
+            max_depth,
+            limit,
+            sample,
+            seed,
             verbose,
         } => {
             let conn = Connection::open(&cli.database)
@@ -107,12 +222,29 @@ fn main() -> Result<()> {
                 &path,
                 project,
                 target,
+                filter,
                 &format,
                 &detector,
                 &sensitivity,
                 apply_stretch,
                 compare_all,
                 &psf_type,
+                no_header,
+                &field_style,
+                &plane,
+                &debayer,
+                hotpixel_map.as_deref(),
+                downsample,
+                min_r_squared,
+                max_adu,
+                tolerant,
+                stars_csv,
+                jobs,
+                &TraversalOptions {
+                    follow_links,
+                    max_depth,
+                },
+                Subset::from_args(limit, sample, seed),
                 verbose,
             )?;
         }
@@ -122,15 +254,28 @@ fn main() -> Result<()> {
             midtone_factor,
             shadow_clipping,
             logarithmic,
+            algorithm,
+            softening,
             invert,
+            bit_depth,
+            output_fits,
         } => {
+            let algorithm = if logarithmic {
+                "logarithmic"
+            } else {
+                &algorithm
+            };
+            let algorithm = StretchAlgorithm::from_name(algorithm, softening)
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
             stretch_to_png(
                 &fits_path,
                 output,
                 midtone_factor,
                 shadow_clipping,
-                logarithmic,
+                algorithm,
                 invert,
+                bit_depth.parse()?,
+                output_fits,
             )?;
         }
         Commands::AnnotateStars {
@@ -143,6 +288,10 @@ fn main() -> Result<()> {
             shadow_clipping,
             annotation_color,
             psf_type,
+            debayer,
+            hotpixel_map,
+            max_adu,
+            show_rejected,
             verbose,
         } => {
             annotate_stars(
@@ -155,9 +304,28 @@ fn main() -> Result<()> {
                 shadow_clipping,
                 &annotation_color,
                 &psf_type,
+                &debayer,
+                hotpixel_map.as_deref(),
+                max_adu,
+                show_rejected,
                 verbose,
             )?;
         }
+        Commands::BuildHotpixelMap {
+            darks,
+            sigma,
+            output,
+        } => {
+            build_hotpixel_map(&darks, sigma, &output)?;
+        }
+        Commands::Stack {
+            inputs,
+            method,
+            kappa,
+            output,
+        } => {
+            stack(&inputs, &method, kappa, &output)?;
+        }
         Commands::VisualizePsf {
             fits_path,
             output,
@@ -207,6 +375,34 @@ fn main() -> Result<()> {
                 verbose,
             )?;
         }
+        Commands::AnalyzeTilt {
+            fits_path,
+            format,
+            verbose,
+        } => {
+            analyze_tilt(&fits_path, &format, verbose)?;
+        }
+        Commands::PsfSummary {
+            fits_path,
+            psf_type,
+            max_stars,
+            format,
+            verbose,
+        } => {
+            psf_summary(&fits_path, &psf_type, max_stars, &format, verbose)?;
+        }
+        Commands::VisualizeField {
+            fits_path,
+            output,
+            grid,
+            metric,
+            psf_type,
+            verbose,
+        } => {
+            use psf_guard::commands::visualize_psf::visualize_field;
+
+            visualize_field(&fits_path, output, grid, &metric, &psf_type, verbose)?;
+        }
         Commands::BenchmarkPsf {
             fits_path,
             runs,
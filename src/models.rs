@@ -49,6 +49,33 @@ impl GradingStatus {
     }
 }
 
+/// What made a grade change, as stored in the grade history
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GradeChangeSource {
+    Manual,
+    Regrade,
+}
+
+impl GradeChangeSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GradeChangeSource::Manual => "manual",
+            GradeChangeSource::Regrade => "regrade",
+        }
+    }
+}
+
+/// One row of an image's grade history
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GradeChange {
+    pub image_id: i32,
+    pub old_status: Option<i32>,
+    pub new_status: i32,
+    pub reason: Option<String>,
+    pub source: String,
+    pub timestamp: i64,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -12,6 +12,64 @@ pub fn stretch_image(
     stretch_image_with_bit_depth(data, statistics, factor, black_clipping, 16)
 }
 
+/// Apply whichever stretch `params.algorithm` selects
+pub fn stretch_with_parameters(
+    data: &[u16],
+    statistics: &ImageStatistics,
+    params: &StretchParameters,
+) -> Vec<u16> {
+    match params.algorithm {
+        StretchAlgorithm::Mtf => {
+            stretch_image(data, statistics, params.factor, params.black_clipping)
+        }
+        StretchAlgorithm::Logarithmic => logarithmic_stretch(data, statistics),
+        StretchAlgorithm::Asinh { softening } => {
+            asinh_stretch(data, statistics, softening, params.black_clipping)
+        }
+    }
+}
+
+/// Apply an asinh stretch: `asinh(x / softening) / asinh(1 / softening)`.
+///
+/// The black point is placed the same way as for MTF (median plus
+/// `black_clipping` MADs). Smaller softening values lift faint signal more
+/// while the log-like top end keeps star cores from saturating.
+pub fn asinh_stretch(
+    data: &[u16],
+    statistics: &ImageStatistics,
+    softening: f64,
+    black_clipping: f64,
+) -> Vec<u16> {
+    let normalized_median = normalize_u16(statistics.median as u16, 16);
+    let normalized_mad = calculate_mad(statistics) / 65535.0;
+    let shadows = (normalized_median + black_clipping * normalized_mad * 1.4826).clamp(0.0, 0.99);
+    let scale = (1.0 / softening).asinh();
+
+    let map: Vec<u16> = (0..=u16::MAX)
+        .map(|i| {
+            let x = ((normalize_u16(i, 16) - shadows) / (1.0 - shadows)).clamp(0.0, 1.0);
+            denormalize_u16((x / softening).asinh() / scale)
+        })
+        .collect();
+
+    data.iter().map(|&pixel| map[pixel as usize]).collect()
+}
+
+/// Apply `log(1 + x)` scaling between the image minimum and maximum
+pub fn logarithmic_stretch(data: &[u16], statistics: &ImageStatistics) -> Vec<u16> {
+    let log_max = (1.0 + statistics.max - statistics.min).ln();
+    if log_max <= 0.0 {
+        return vec![0; data.len()];
+    }
+
+    data.iter()
+        .map(|&pixel| {
+            let normalized = (pixel as f64 - statistics.min).max(0.0);
+            denormalize_u16((1.0 + normalized).ln() / log_max)
+        })
+        .collect()
+}
+
 /// Apply MTF stretch with explicit bit depth
 pub fn stretch_image_with_bit_depth(
     data: &[u16],
@@ -69,17 +127,27 @@ fn get_stretch_map_with_bit_depth(
             midtones_transfer_function(target_histogram_median_pct, normalized_median - shadows);
         let highlights = 1.0;
 
-        eprintln!("Debug MTF: normalized_median={:.4}, normalized_mad={:.4}, shadows={:.4}, midtones={:.4}", 
-                  normalized_median, normalized_mad, shadows, midtones);
-        eprintln!(
+        crate::debug_mtf!(
+            "normalized_median={:.4}, normalized_mad={:.4}, shadows={:.4}, midtones={:.4}",
+            normalized_median,
+            normalized_mad,
+            shadows,
+            midtones
+        );
+        crate::debug_mtf!(
             "  shadows_clipping={}, scale_factor={}",
-            shadows_clipping, scale_factor
+            shadows_clipping,
+            scale_factor
         );
-        eprintln!(
+        crate::debug_mtf!(
             "  shadows calculation: {} + {} * {} * {} = {}",
-            normalized_median, shadows_clipping, normalized_mad, scale_factor, shadows
+            normalized_median,
+            shadows_clipping,
+            normalized_mad,
+            scale_factor,
+            shadows
         );
-        eprintln!(
+        crate::debug_mtf!(
             "  midtones input: {} - {} = {}",
             normalized_median,
             shadows,
@@ -99,9 +167,13 @@ fn get_stretch_map_with_bit_depth(
 
         // Debug first few values
         if i < 5 || i == 398 || i == 204 || i == 340 {
-            eprintln!(
+            crate::debug_mtf!(
                 "  Stretch map[{}]: normalized={:.6}, input={:.6}, stretched={:.6} -> {}",
-                i, value, input_value, stretched, map[i]
+                i,
+                value,
+                input_value,
+                stretched,
+                map[i]
             );
         }
     }
@@ -152,10 +224,44 @@ fn midtones_transfer_function(midtone_balance: f64, x: f64) -> f64 {
     0.0
 }
 
+/// Stretch transfer curve
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StretchAlgorithm {
+    /// N.I.N.A.'s midtone transfer function (default)
+    #[default]
+    Mtf,
+    /// `log(1 + x)` between the image minimum and maximum
+    Logarithmic,
+    /// Inverse hyperbolic sine; smaller softening stretches faint signal harder
+    Asinh { softening: f64 },
+}
+
+impl StretchAlgorithm {
+    /// Parse an algorithm name; `softening` is only used by asinh
+    pub fn from_name(name: &str, softening: f64) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "mtf" => Ok(StretchAlgorithm::Mtf),
+            "logarithmic" | "log" => Ok(StretchAlgorithm::Logarithmic),
+            "asinh" => {
+                if softening > 0.0 {
+                    Ok(StretchAlgorithm::Asinh { softening })
+                } else {
+                    Err(format!(
+                        "Asinh softening must be positive, got {}",
+                        softening
+                    ))
+                }
+            }
+            _ => Err(format!("Unknown stretch algorithm: {}", name)),
+        }
+    }
+}
+
 /// Configuration for MTF stretching matching N.I.N.A. defaults
 pub struct StretchParameters {
     pub factor: f64,         // Target histogram median position (default: 0.15)
     pub black_clipping: f64, // Shadow clipping in MAD units (default: -2.8)
+    pub algorithm: StretchAlgorithm,
 }
 
 impl Default for StretchParameters {
@@ -163,6 +269,7 @@ impl Default for StretchParameters {
         Self {
             factor: 0.2,          // N.I.N.A. default AutoStretchFactor
             black_clipping: -2.8, // N.I.N.A. default BlackClipping
+            algorithm: StretchAlgorithm::Mtf,
         }
     }
 }
@@ -197,4 +304,55 @@ mod tests {
         // 0.5 * 65535 = 32767.5, rounds to 32768
         assert!((denormalize_u16(0.5) as i32 - 32768).abs() <= 1);
     }
+
+    fn test_statistics() -> ImageStatistics {
+        ImageStatistics {
+            width: 256,
+            height: 256,
+            mean: 1100.0,
+            median: 1000.0,
+            std_dev: 100.0,
+            min: 0.0,
+            max: 65535.0,
+            star_count: None,
+            hfr: None,
+            fwhm: None,
+            mad: Some(50.0),
+        }
+    }
+
+    #[test]
+    fn test_asinh_is_monotonic() {
+        let data: Vec<u16> = (0..=u16::MAX).step_by(7).collect();
+        let stretched = asinh_stretch(&data, &test_statistics(), 0.05, -2.8);
+
+        assert!(stretched.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(
+            asinh_stretch(&[u16::MAX], &test_statistics(), 0.05, -2.8)[0],
+            65535
+        );
+
+        // Above the black point, brighter input always gives brighter output
+        let faint = asinh_stretch(&[2000], &test_statistics(), 0.05, -2.8)[0];
+        let bright = asinh_stretch(&[20000], &test_statistics(), 0.05, -2.8)[0];
+        assert!(bright > faint && faint > 0);
+
+        // Harder softening lifts faint signal further
+        let harder = asinh_stretch(&[2000], &test_statistics(), 0.01, -2.8)[0];
+        assert!(harder > faint);
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!(
+            StretchAlgorithm::from_name("asinh", 0.1),
+            Ok(StretchAlgorithm::Asinh { softening: 0.1 })
+        );
+        assert_eq!(
+            StretchAlgorithm::from_name("MTF", 0.1),
+            Ok(StretchAlgorithm::Mtf)
+        );
+        assert!(StretchAlgorithm::from_name("asinh", 0.0).is_err());
+        assert!(StretchAlgorithm::from_name("gamma", 0.1).is_err());
+    }
 }
@@ -1,10 +1,13 @@
 /// Exact implementation of N.I.N.A.'s star detection algorithm
 /// Based on StarDetection.cs from N.I.N.A. source code
 use crate::accord_imaging::*;
+use crate::image_analysis::is_uniform;
 use crate::opencv_canny::{
     OpenCVBinaryMorphology, OpenCVCanny, OpenCVNoiseReduction, OpenCVThreshold,
 };
 use crate::opencv_contours::OpenCVBlobDetector;
+use crate::psf_fitting::{PSFFitter, PSFModel, PSFType};
+use crate::utils::OnlineStats;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// Convert 16-bit data to 8-bit using NINA's exact method
@@ -43,6 +46,26 @@ pub struct StarDetectionParams {
     pub sensitivity: StarSensitivity,
     pub noise_reduction: NoiseReduction,
     pub use_roi: bool,
+    /// Fraction of the frame, centered, whose stars are excluded when `use_roi` is set
+    /// and this is below 1.0 (N.I.N.A.'s InnerCropRatio; the hole of the ring)
+    pub inner_crop_ratio: f64,
+    /// Fraction of the frame, centered, that stars must fall inside when `use_roi` is set
+    /// (N.I.N.A.'s OuterCropRatio; 1.0 keeps the whole frame)
+    pub outer_crop_ratio: f64,
+    pub centroid_method: CentroidMethod,
+    /// Replicate N.I.N.A.'s missing right-edge centroid check so star counts match N.I.N.A.
+    pub nina_bug_compatible: bool,
+    /// Print per-image diagnostics to stderr
+    pub verbose: bool,
+    /// Fit a PSF model to each accepted star (not part of N.I.N.A., off by default)
+    pub fit_psf: Option<PSFType>,
+    /// Resize factor in (0, 1] for the detection image, applied regardless of
+    /// the frame width; HFR is still measured on the original data
+    pub force_downsample: Option<f64>,
+    /// Pixel size in µm (FITS XPIXSZ), used with `focal_length_mm` for the image scale
+    pub pixel_size_um: Option<f64>,
+    /// Focal length in mm (FITS FOCALLEN)
+    pub focal_length_mm: Option<f64>,
 }
 
 impl Default for StarDetectionParams {
@@ -51,10 +74,40 @@ impl Default for StarDetectionParams {
             sensitivity: StarSensitivity::Normal,
             noise_reduction: NoiseReduction::None,
             use_roi: false,
+            inner_crop_ratio: 1.0,
+            outer_crop_ratio: 1.0,
+            centroid_method: CentroidMethod::FluxWeighted,
+            nina_bug_compatible: true,
+            verbose: false,
+            fit_psf: None,
+            force_downsample: None,
+            pixel_size_um: None,
+            focal_length_mm: None,
         }
     }
 }
 
+impl StarDetectionParams {
+    /// Image scale in arcsec/pixel, when both the pixel size and focal length are known
+    pub fn image_scale(&self) -> Option<f64> {
+        match (self.pixel_size_um, self.focal_length_mm) {
+            (Some(pixel_size), Some(focal_length)) if pixel_size > 0.0 && focal_length > 0.0 => {
+                Some(206.265 * pixel_size / focal_length)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How the final star position is computed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CentroidMethod {
+    /// N.I.N.A.'s background-subtracted flux-weighted centroid
+    FluxWeighted,
+    /// 3-point parabolic fit in x and y around the peak pixel
+    Parabolic,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum StarSensitivity {
     Normal,
@@ -81,6 +134,7 @@ struct DetectionState<'a> {
     pub inverse_resize_factor: f64,
     pub min_star_size: usize,
     pub max_star_size: usize,
+    pub verbose: bool,
 }
 
 /// Star information during detection
@@ -112,6 +166,8 @@ pub struct DetectedStar {
     pub average_brightness: f64,
     pub max_brightness: f64,
     pub background: f64,
+    /// PSF fit on the original data, when `fit_psf` is set
+    pub psf_model: Option<PSFModel>,
 }
 
 /// Star detection result
@@ -124,6 +180,9 @@ pub struct StarDetectionResult {
 
 const MAX_WIDTH: usize = 1552;
 
+/// Image scale in arcsec/pixel that High sensitivity resizes the detection image towards
+const HIGH_SENSITIVITY_SCALE: f64 = 3.0;
+
 /// Star detection with separate detection and measurement data
 /// This matches N.I.N.A.'s behavior where detection uses stretched data
 /// but HFR measurement uses original raw data
@@ -134,6 +193,15 @@ pub fn detect_stars_with_original(
     height: usize,
     params: &StarDetectionParams,
 ) -> StarDetectionResult {
+    // A uniform frame (all-zero, saturated) has no edges to find
+    if is_uniform(original_data_16bit) {
+        return StarDetectionResult {
+            average_hfr: 0.0,
+            hfr_std_dev: 0.0,
+            star_list: Vec::new(),
+        };
+    }
+
     // Step 1: Get initial state using both detection and original data
     let state = get_initial_state(
         detection_data_16bit,
@@ -143,26 +211,28 @@ pub fn detect_stars_with_original(
         params,
     );
 
-    // Debug output for resize factor
-    eprintln!(
-        "Debug: Image {}x{}, resize_factor: {:.3}, min_star_size: {}, max_star_size: {}",
-        width, height, state.resize_factor, state.min_star_size, state.max_star_size
-    );
+    if params.verbose {
+        eprintln!(
+            "Debug: Image {}x{}, resize_factor: {:.3}, min_star_size: {}, max_star_size: {}",
+            width, height, state.resize_factor, state.min_star_size, state.max_star_size
+        );
+    }
 
     // Step 2: Convert 16bpp to 8bpp for edge detection using NINA's method (right shift by 8)
     let image_8bit = convert_16bpp_to_8bpp_nina(detection_data_16bit);
 
-    // Debug: Check 8-bit conversion
-    let min_8bit = *image_8bit.iter().min().unwrap_or(&0);
-    let max_8bit = *image_8bit.iter().max().unwrap_or(&0);
-    let non_zero_count = image_8bit.iter().filter(|&&x| x > 0).count();
-    eprintln!(
-        "Debug: 8-bit conversion - min: {}, max: {}, non-zero pixels: {} ({:.2}%)",
-        min_8bit,
-        max_8bit,
-        non_zero_count,
-        non_zero_count as f64 / image_8bit.len() as f64 * 100.0
-    );
+    if params.verbose {
+        let min_8bit = *image_8bit.iter().min().unwrap_or(&0);
+        let max_8bit = *image_8bit.iter().max().unwrap_or(&0);
+        let non_zero_count = image_8bit.iter().filter(|&&x| x > 0).count();
+        eprintln!(
+            "Debug: 8-bit conversion - min: {}, max: {}, non-zero pixels: {} ({:.2}%)",
+            min_8bit,
+            max_8bit,
+            non_zero_count,
+            non_zero_count as f64 / image_8bit.len() as f64 * 100.0
+        );
+    }
 
     // Step 3: Noise reduction (if enabled)
     let mut bitmap_to_analyze = if params.noise_reduction != NoiseReduction::None {
@@ -171,6 +241,7 @@ pub fn detect_stars_with_original(
             state.width,
             state.height,
             params.noise_reduction,
+            params.verbose,
         )
     } else {
         image_8bit
@@ -181,12 +252,13 @@ pub fn detect_stars_with_original(
         &bitmap_to_analyze,
         width,
         height,
-        MAX_WIDTH,
         state.resize_factor,
     );
     bitmap_to_analyze = resized_image;
 
-    eprintln!("Debug: Resized to {}x{}", resized_width, resized_height);
+    if params.verbose {
+        eprintln!("Debug: Resized to {}x{}", resized_width, resized_height);
+    }
 
     // Step 5: Prepare image for structure detection
     prepare_for_structure_detection(
@@ -199,13 +271,122 @@ pub fn detect_stars_with_original(
     // Step 6: Get structure info
     let blobs = detect_structures(&bitmap_to_analyze, resized_width, resized_height);
 
-    eprintln!("Debug: Detected {} blobs", blobs.len());
+    if params.verbose {
+        eprintln!("Debug: Detected {} blobs", blobs.len());
+    }
 
     // Step 7: Identify stars
     let (star_list, _detected_stars) =
         identify_stars(params, &state, blobs, resized_width, resized_height);
 
     // Step 8: Calculate statistics
+    summarize_stars(star_list)
+}
+
+/// Approximate star detection for quick triage of very large frames.
+///
+/// Runs only the Canny edge detection (blur and Sobel gradients), SIS
+/// threshold, dilation and blob count steps of [`detect_stars_with_original`],
+/// always in the pure Rust implementation. Noise reduction, contour quality checks,
+/// per-star pixel validation and the radius filter are skipped: every blob of
+/// plausible size and shape whose peak is 5σ above the frame background
+/// counts as a star, and its HFR is measured on the original data with
+/// [`measure_hfr`]. Hot pixel clusters, merged pairs and galaxy cores that the
+/// full pipeline rejects can be counted, and HFR is less precise than with the
+/// `nina` or `hocusfocus` detectors.
+pub fn detect_stars_fast(
+    detection_data_16bit: &[u16],
+    original_data_16bit: &[u16],
+    width: usize,
+    height: usize,
+    force_downsample: Option<f64>,
+) -> StarDetectionResult {
+    if is_uniform(original_data_16bit) {
+        return summarize_stars(Vec::new());
+    }
+
+    let state = get_initial_state(
+        detection_data_16bit,
+        original_data_16bit,
+        width,
+        height,
+        &StarDetectionParams {
+            force_downsample,
+            ..StarDetectionParams::default()
+        },
+    );
+
+    let (mut image, resized_width, resized_height) = DetectionUtility::resize_for_detection(
+        &convert_16bpp_to_8bpp_nina(detection_data_16bit),
+        width,
+        height,
+        state.resize_factor,
+    );
+    CannyEdgeDetector::new(10, 80).apply_in_place(&mut image, resized_width, resized_height);
+    SISThreshold.apply_in_place(&mut image, resized_width, resized_height);
+    BinaryDilation3x3.apply_in_place(&mut image, resized_width, resized_height);
+
+    let mut blob_counter = BlobCounter::new();
+    blob_counter.process_image(&image, resized_width, resized_height);
+
+    // Robust background level and noise from a subsample of the frame
+    let mut samples: Vec<f64> = original_data_16bit
+        .iter()
+        .step_by(16)
+        .map(|&v| v as f64)
+        .collect();
+    samples.sort_unstable_by(f64::total_cmp);
+    let background = samples[samples.len() / 2];
+    let mut deviations: Vec<f64> = samples.iter().map(|v| (v - background).abs()).collect();
+    deviations.sort_unstable_by(f64::total_cmp);
+    let peak_threshold = background + 5.0 * 1.4826 * deviations[deviations.len() / 2];
+
+    let star_list = blob_counter
+        .get_objects_information()
+        .iter()
+        .map(|blob| blob.rectangle)
+        .filter(|rect| {
+            let size_range = state.min_star_size as i32..=state.max_star_size as i32;
+            size_range.contains(&rect.width)
+                && size_range.contains(&rect.height)
+                && calculate_eccentricity(rect.width as f64, rect.height as f64) <= 0.8
+        })
+        .filter_map(|rect| {
+            // Blob box in original coordinates, centered on its brightest pixel
+            let x0 = (rect.x as f64 * state.inverse_resize_factor) as usize;
+            let y0 = (rect.y as f64 * state.inverse_resize_factor) as usize;
+            let x1 = (((rect.x + rect.width) as f64 * state.inverse_resize_factor).ceil() as usize)
+                .min(width);
+            let y1 = (((rect.y + rect.height) as f64 * state.inverse_resize_factor).ceil()
+                as usize)
+                .min(height);
+            let (peak, peak_index) = (y0..y1)
+                .flat_map(|y| (x0..x1).map(move |x| y * width + x))
+                .map(|i| (original_data_16bit[i], i))
+                .max()?;
+            if peak as f64 <= peak_threshold {
+                return None;
+            }
+
+            let position = ((peak_index % width) as f64, (peak_index / width) as f64);
+            let radius = rect.width.max(rect.height) as f64 / 2.0 * state.inverse_resize_factor;
+            let hfr = measure_hfr(original_data_16bit, width, height, position, radius)?;
+            Some(DetectedStar {
+                hfr,
+                position,
+                average_brightness: 0.0,
+                max_brightness: peak as f64,
+                background,
+                psf_model: None,
+            })
+        })
+        .collect();
+
+    summarize_stars(star_list)
+}
+
+/// Average HFR and its sample standard deviation over the stars
+fn summarize_stars(star_list: Vec<DetectedStar>) -> StarDetectionResult {
     let mut result = StarDetectionResult {
         average_hfr: 0.0,
         hfr_std_dev: 0.0,
@@ -240,18 +421,21 @@ fn get_initial_state<'a>(
 ) -> DetectionState<'a> {
     let mut resize_factor = 1.0;
 
-    if width > MAX_WIDTH {
+    if let Some(factor) = params.force_downsample {
+        resize_factor = factor;
+    } else if width > MAX_WIDTH {
         resize_factor = match params.sensitivity {
             StarSensitivity::Highest => f64::max(2.0 / 3.0, MAX_WIDTH as f64 / width as f64),
             StarSensitivity::High => {
-                // N.I.N.A. uses image scale for High sensitivity
-                // For now, we'll simulate the common case of 1.0-1.5 arcsec/pixel
-                // which uses 1/3 resize factor
-                // TODO: Calculate from FITS headers XPIXSZ/FOCALLEN
-                let simulated_resize = 1.0 / 3.0;
+                // N.I.N.A. uses image scale for High sensitivity, shrinking
+                // finely sampled frames more; without XPIXSZ/FOCALLEN assume the
+                // common 1 arcsec/pixel
+                let scale_resize = params
+                    .image_scale()
+                    .map_or(1.0 / 3.0, |scale| (scale / HIGH_SENSITIVITY_SCALE).min(1.0));
 
                 // But still respect the MAX_WIDTH limit
-                f64::max(simulated_resize, MAX_WIDTH as f64 / width as f64)
+                f64::max(scale_resize, MAX_WIDTH as f64 / width as f64)
             }
             StarSensitivity::Normal => MAX_WIDTH as f64 / width as f64,
         };
@@ -270,6 +454,7 @@ fn get_initial_state<'a>(
         inverse_resize_factor,
         min_star_size,
         max_star_size,
+        verbose: params.verbose,
     }
 }
 
@@ -278,6 +463,7 @@ fn reduce_noise(
     width: usize,
     height: usize,
     noise_reduction: NoiseReduction,
+    verbose: bool,
 ) -> Vec<u8> {
     match noise_reduction {
         NoiseReduction::None => image.to_vec(),
@@ -286,7 +472,9 @@ fn reduce_noise(
             match OpenCVNoiseReduction::gaussian_blur(image, width, height, 1.0) {
                 Ok(blurred) => blurred,
                 Err(e) => {
-                    eprintln!("OpenCV Gaussian blur failed: {}, using fallback", e);
+                    if verbose {
+                        eprintln!("OpenCV Gaussian blur failed: {}, using fallback", e);
+                    }
                     let blur = FastGaussianBlur::new();
                     blur.process(image, width, height, 1)
                 }
@@ -297,7 +485,9 @@ fn reduce_noise(
             match OpenCVNoiseReduction::gaussian_blur(image, width, height, 2.0) {
                 Ok(blurred) => blurred,
                 Err(e) => {
-                    eprintln!("OpenCV Gaussian blur failed: {}, using fallback", e);
+                    if verbose {
+                        eprintln!("OpenCV Gaussian blur failed: {}, using fallback", e);
+                    }
                     let blur = FastGaussianBlur::new();
                     blur.process(image, width, height, 2)
                 }
@@ -308,7 +498,9 @@ fn reduce_noise(
             match OpenCVNoiseReduction::gaussian_blur(image, width, height, 3.0) {
                 Ok(blurred) => blurred,
                 Err(e) => {
-                    eprintln!("OpenCV Gaussian blur failed: {}, using fallback", e);
+                    if verbose {
+                        eprintln!("OpenCV Gaussian blur failed: {}, using fallback", e);
+                    }
                     let blur = FastGaussianBlur::new();
                     blur.process(image, width, height, 3)
                 }
@@ -319,7 +511,9 @@ fn reduce_noise(
             match OpenCVNoiseReduction::median_blur(image, width, height, 3) {
                 Ok(blurred) => blurred,
                 Err(e) => {
-                    eprintln!("OpenCV median blur failed: {}, using fallback", e);
+                    if verbose {
+                        eprintln!("OpenCV median blur failed: {}, using fallback", e);
+                    }
                     let median = Median;
                     median.apply(image, width, height)
                 }
@@ -353,7 +547,9 @@ fn prepare_for_structure_detection(
             image.copy_from_slice(&edges);
         }
         Err(e) => {
-            eprintln!("OpenCV Canny failed: {}, using fallback", e);
+            if params.verbose {
+                eprintln!("OpenCV Canny failed: {}, using fallback", e);
+            }
             // Fallback to original implementation
             match params.sensitivity {
                 StarSensitivity::Normal => {
@@ -368,12 +564,13 @@ fn prepare_for_structure_detection(
         }
     }
 
-    // Debug: Check edge detection results
-    let edge_pixels = image.iter().filter(|&&p| p > 0).count();
-    eprintln!(
-        "Debug: After Canny edge detection - {} non-zero pixels",
-        edge_pixels
-    );
+    if params.verbose {
+        let edge_pixels = image.iter().filter(|&&p| p > 0).count();
+        eprintln!(
+            "Debug: After Canny edge detection - {} non-zero pixels",
+            edge_pixels
+        );
+    }
 
     // Apply SIS threshold using OpenCV
     match OpenCVThreshold::apply_sis(image, width, height) {
@@ -381,16 +578,19 @@ fn prepare_for_structure_detection(
             image.copy_from_slice(&thresholded);
         }
         Err(e) => {
-            eprintln!("OpenCV threshold failed: {}, using fallback", e);
+            if params.verbose {
+                eprintln!("OpenCV threshold failed: {}, using fallback", e);
+            }
             // Fallback to original implementation
             let sis = SISThreshold;
             sis.apply_in_place(image, width, height);
         }
     }
 
-    // Debug: Count non-zero pixels after SIS
-    let non_zero = image.iter().filter(|&&p| p > 0).count();
-    eprintln!("Debug: After SIS threshold - {} non-zero pixels", non_zero);
+    if params.verbose {
+        let non_zero = image.iter().filter(|&&p| p > 0).count();
+        eprintln!("Debug: After SIS threshold - {} non-zero pixels", non_zero);
+    }
 
     // Apply binary dilation using OpenCV
     match OpenCVBinaryMorphology::dilate_3x3(image, width, height) {
@@ -398,7 +598,9 @@ fn prepare_for_structure_detection(
             image.copy_from_slice(&dilated);
         }
         Err(e) => {
-            eprintln!("OpenCV dilation failed: {}, using fallback", e);
+            if params.verbose {
+                eprintln!("OpenCV dilation failed: {}, using fallback", e);
+            }
             // Fallback to original implementation
             let dilation = BinaryDilation3x3;
             dilation.apply_in_place(image, width, height);
@@ -441,7 +643,7 @@ fn identify_stars(
     let mut sum_squares = 0.0;
 
     let mut size_filtered = 0;
-    let roi_filtered = 0;
+    let mut roi_filtered = 0;
     let mut failed_detection = 0;
     let mut edge_filtered = 0;
 
@@ -458,10 +660,18 @@ fn identify_stars(
 
         // ROI filtering - skip if outside ROI when enabled
         if params.use_roi {
-            // TODO: Implement proper InROI check based on inner/outer crop ratios
-            // For now, don't filter since we don't have ROI bounds
-            // roi_filtered += 1;
-            // continue;
+            let center_x = blob.rectangle.x as f64 + blob.rectangle.width as f64 / 2.0;
+            let center_y = blob.rectangle.y as f64 + blob.rectangle.height as f64 / 2.0;
+            if !in_roi(
+                params,
+                center_x * state.inverse_resize_factor,
+                center_y * state.inverse_resize_factor,
+                state.width,
+                state.height,
+            ) {
+                roi_filtered += 1;
+                continue;
+            }
         }
 
         // Scale rectangle back to original coordinates
@@ -546,13 +756,28 @@ fn identify_stars(
             star = calculate_star_hfr(state, star);
 
             // Check if centroid is not touching rectangle edges
-            // NOTE: N.I.N.A. has a bug in line 344 where it compares Position.X < Position.X + Width
-            // We replicate this bug for compatibility
+            // NOTE: N.I.N.A. has a bug in line 344 where it compares Position.X < Position.X + Width,
+            // so the right edge is never checked. We replicate it unless compatibility is disabled
+            let right_edge = if params.nina_bug_compatible {
+                star.position.0 + star.rectangle.width as f64 - 2.0
+            } else {
+                (star.rectangle.x + star.rectangle.width - 2) as f64
+            };
             if star.position.0 > (star.rectangle.x + 1) as f64
                 && star.position.1 > (star.rectangle.y + 1) as f64
-                && star.position.0 < (star.position.0 + star.rectangle.width as f64 - 2.0)  // N.I.N.A. bug
+                && star.position.0 < right_edge
                 && star.position.1 < (star.rectangle.y + star.rectangle.height - 2) as f64
             {
+                if params.centroid_method == CentroidMethod::Parabolic {
+                    if let Some(position) = parabolic_peak_centroid(
+                        state.original_data,
+                        state.width,
+                        state.height,
+                        &star.rectangle,
+                    ) {
+                        star.position = position;
+                    }
+                }
                 star_list.push(star);
             } else {
                 edge_filtered += 1;
@@ -575,12 +800,14 @@ fn identify_stars(
         let stdev =
             ((sum_squares - star_list.len() as f64 * avg * avg) / star_list.len() as f64).sqrt();
 
-        eprintln!(
-            "Debug: Before radius filter: {} stars, avg radius: {:.2}, stdev: {:.2}",
-            star_list.len(),
-            avg,
-            stdev
-        );
+        if params.verbose {
+            eprintln!(
+                "Debug: Before radius filter: {} stars, avg radius: {:.2}, stdev: {:.2}",
+                star_list.len(),
+                avg,
+                stdev
+            );
+        }
 
         star_list.retain(|s| match params.sensitivity {
             StarSensitivity::Highest => {
@@ -590,18 +817,29 @@ fn identify_stars(
             _ => s.radius <= avg + 1.5 * stdev && s.radius >= avg - 1.5 * stdev,
         });
 
-        eprintln!("Debug: After radius filter: {} stars", star_list.len());
+        if params.verbose {
+            eprintln!("Debug: After radius filter: {} stars", star_list.len());
+        }
     }
 
-    eprintln!(
-        "Debug: Blob filtering - Size: {}, ROI: {}, Failed detection: {}, Edge: {}",
-        size_filtered, roi_filtered, failed_detection, edge_filtered
-    );
+    if params.verbose {
+        eprintln!(
+            "Debug: Blob filtering - Size: {}, ROI: {}, Failed detection: {}, Edge: {}",
+            size_filtered, roi_filtered, failed_detection, edge_filtered
+        );
+    }
 
     // Convert to DetectedStar
+    let fitter = params
+        .fit_psf
+        .filter(|&psf_type| psf_type != PSFType::None)
+        .map(PSFFitter::new);
     let detected: Vec<DetectedStar> = star_list
         .into_iter()
         .map(|s| DetectedStar {
+            psf_model: fitter
+                .as_ref()
+                .and_then(|fitter| fit_star_psf(fitter, state, &s)),
             hfr: s.hfr,
             position: s.position,
             average_brightness: s.average,
@@ -613,6 +851,52 @@ fn identify_stars(
     (detected, detected_stars)
 }
 
+/// Whether a star center lies in the ring outside the inner crop and inside the
+/// outer crop; a ratio of 1 or more disables that edge of the ring
+fn in_roi(params: &StarDetectionParams, x: f64, y: f64, width: usize, height: usize) -> bool {
+    let inside = |ratio: f64| {
+        let margin_x = width as f64 * (1.0 - ratio) / 2.0;
+        let margin_y = height as f64 * (1.0 - ratio) / 2.0;
+        x >= margin_x
+            && x < width as f64 - margin_x
+            && y >= margin_y
+            && y < height as f64 - margin_y
+    };
+
+    if params.inner_crop_ratio < 1.0 && inside(params.inner_crop_ratio) {
+        return false;
+    }
+    if params.outer_crop_ratio < 1.0 && !inside(params.outer_crop_ratio) {
+        return false;
+    }
+    true
+}
+
+/// Fit a PSF to a star on the original (unstretched) data
+fn fit_star_psf(fitter: &PSFFitter, state: &DetectionState, star: &Star) -> Option<PSFModel> {
+    // max_pixel_value comes from the stretched detection data, so take the
+    // peak again from the original pixels the model is fitted to
+    let rect = &star.rectangle;
+    let mut peak = star.surrounding_mean;
+    for y in rect.y.max(0)..(rect.y + rect.height).min(state.height as i32) {
+        for x in rect.x.max(0)..(rect.x + rect.width).min(state.width as i32) {
+            peak = peak.max(state.original_data[y as usize * state.width + x as usize] as f64);
+        }
+    }
+
+    fitter.fit_star(
+        state.original_data,
+        state.width,
+        state.height,
+        star.position.0,
+        star.position.1,
+        rect.width as f64,
+        rect.height as f64,
+        star.surrounding_mean,
+        peak,
+    )
+}
+
 fn analyze_star_pixels(
     state: &DetectionState,
     mut star: Star,
@@ -621,7 +905,7 @@ fn analyze_star_pixels(
     let mut star_pixel_sum = 0.0;
     let mut star_pixel_count = 0;
     let mut large_rect_pixel_sum = 0.0;
-    let mut large_rect_pixel_sum_squares = 0.0;
+    let mut large_rect_stats = OnlineStats::new();
     let mut inner_star_bright_pixels = 0;
 
     // Also track original data for HFR background calculation
@@ -657,7 +941,7 @@ fn analyze_star_pixels(
                 } else {
                     // Background pixel
                     large_rect_pixel_sum += pixel_value;
-                    large_rect_pixel_sum_squares += pixel_value * pixel_value;
+                    large_rect_stats.push(pixel_value);
                     // Track original background for HFR
                     original_background_sum +=
                         state.original_data[(y as usize) * state.width + (x as usize)] as f64;
@@ -682,10 +966,9 @@ fn analyze_star_pixels(
     } else {
         large_rect_mean // Fallback
     };
-    let large_rect_stdev = ((large_rect_pixel_sum_squares
-        - large_rect_pixel_count * large_rect_mean * large_rect_mean)
-        / large_rect_pixel_count)
-        .sqrt();
+    // N.I.N.A. takes sum_squares - n * mean² here, which cancels to a negative
+    // variance (and a NaN threshold) on flat backgrounds
+    let large_rect_stdev = large_rect_stats.std_dev();
 
     // Minimum bright pixels threshold
     let minimum_bright_pixels = (state.width.max(state.height) as f64 / 1000.0).ceil() as usize;
@@ -722,7 +1005,7 @@ fn analyze_star_pixels(
         && inner_star_bright_pixels > minimum_bright_pixels;
 
     // Debug why stars fail
-    if !is_star && star_pixel_count > 0 {
+    if state.verbose && !is_star && star_pixel_count > 0 {
         static FAIL_COUNT: AtomicUsize = AtomicUsize::new(0);
         let count = FAIL_COUNT.fetch_add(1, Ordering::Relaxed);
         if count < 5 {
@@ -751,12 +1034,7 @@ fn calculate_star_hfr(state: &DetectionState, mut star: Star) -> Star {
                 let pixel_value =
                     state.original_data[(y as usize) * state.width + (x as usize)] as f64;
 
-                // N.I.N.A.'s exact background subtraction: Math.Round(value - SurroundingMean)
-                // Uses banker's rounding (round half to even)
-                let mut value = round_half_to_even(pixel_value - star.surrounding_mean);
-                if value < 0.0 {
-                    value = 0.0;
-                }
+                let value = subtract_background(pixel_value, star.surrounding_mean);
 
                 all_sum += value;
                 pixel_count += 1;
@@ -805,6 +1083,118 @@ fn calculate_star_hfr(state: &DetectionState, mut star: Star) -> Star {
     star
 }
 
+/// N.I.N.A.'s exact background subtraction: Math.Round(value - SurroundingMean),
+/// using banker's rounding, clamped at zero
+fn subtract_background(pixel_value: f64, background: f64) -> f64 {
+    round_half_to_even(pixel_value - background).max(0.0)
+}
+
+/// HFR of a star at a known position, measured on raw data the way
+/// `calculate_star_hfr` does after detection.
+///
+/// The star box is the square of half-size `radius` around the position, and
+/// the background is the mean of the box three times that size, excluding the
+/// star box. Pixels within `radius` are weighted by their background-subtracted
+/// value. Returns None if the background box does not fit inside the image or
+/// there is no flux above the background.
+pub fn measure_hfr(
+    data: &[u16],
+    width: usize,
+    height: usize,
+    position: (f64, f64),
+    radius: f64,
+) -> Option<f64> {
+    if radius.is_nan() || radius <= 0.0 || data.len() != width * height {
+        return None;
+    }
+
+    let (cx, cy) = position;
+    let half = radius.ceil() as i64;
+    let (px, py) = (cx.round() as i64, cy.round() as i64);
+    let outer = 3 * half + 1;
+    if px - outer < 0 || py - outer < 0 || px + outer >= width as i64 || py + outer >= height as i64
+    {
+        return None;
+    }
+
+    let at = |x: i64, y: i64| data[y as usize * width + x as usize] as f64;
+    let in_star_box = |x: i64, y: i64| (x - px).abs() <= half && (y - py).abs() <= half;
+
+    let mut background_sum = 0.0;
+    let mut background_count = 0usize;
+    for y in py - outer..=py + outer {
+        for x in px - outer..=px + outer {
+            if !in_star_box(x, y) {
+                background_sum += at(x, y);
+                background_count += 1;
+            }
+        }
+    }
+    let background = background_sum / background_count as f64;
+
+    let mut sum = 0.0;
+    let mut sum_dist = 0.0;
+    for y in py - half..=py + half {
+        for x in px - half..=px + half {
+            if inside_circle(x as f64, y as f64, cx, cy, radius) {
+                let value = subtract_background(at(x, y), background);
+                sum += value;
+                sum_dist += value * (x as f64 - cx).hypot(y as f64 - cy);
+            }
+        }
+    }
+
+    (sum > 0.0).then(|| sum_dist / sum)
+}
+
+/// Sub-pixel peak position from a 3-point parabolic fit in x and y around
+/// the brightest pixel of the rectangle. A constant background cancels out of
+/// the fit, so the result does not depend on the background estimate.
+/// Returns None if the peak sits on the image border or is not a local maximum.
+fn parabolic_peak_centroid(
+    data: &[u16],
+    width: usize,
+    height: usize,
+    rect: &Rectangle,
+) -> Option<(f64, f64)> {
+    let x_start = rect.x.max(0) as usize;
+    let y_start = rect.y.max(0) as usize;
+    let x_end = ((rect.x + rect.width).max(0) as usize).min(width);
+    let y_end = ((rect.y + rect.height).max(0) as usize).min(height);
+
+    let mut peak: Option<(usize, usize, u16)> = None;
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            let value = data[y * width + x];
+            if peak.is_none_or(|(_, _, best)| value > best) {
+                peak = Some((x, y, value));
+            }
+        }
+    }
+
+    let (px, py, _) = peak?;
+    if px == 0 || py == 0 || px + 1 >= width || py + 1 >= height {
+        return None;
+    }
+
+    let at = |x: usize, y: usize| data[y * width + x] as f64;
+    let offset_x = parabolic_offset(at(px - 1, py), at(px, py), at(px + 1, py))?;
+    let offset_y = parabolic_offset(at(px, py - 1), at(px, py), at(px, py + 1))?;
+
+    Some((px as f64 + offset_x, py as f64 + offset_y))
+}
+
+/// Vertex offset of the parabola through three equally spaced samples,
+/// relative to the center sample
+fn parabolic_offset(left: f64, center: f64, right: f64) -> Option<f64> {
+    let denominator = left - 2.0 * center + right;
+    if denominator >= 0.0 {
+        // Flat or not a maximum
+        return None;
+    }
+    Some((0.5 * (left - right) / denominator).clamp(-0.5, 0.5))
+}
+
 fn inside_circle(x: f64, y: f64, center_x: f64, center_y: f64, radius: f64) -> bool {
     (x - center_x).powi(2) + (y - center_y).powi(2) <= radius.powi(2)
 }
@@ -820,6 +1210,69 @@ fn calculate_eccentricity(width: f64, height: f64) -> f64 {
 mod tests {
     use super::*;
 
+    /// `width` x `height` frame of 1000 ADU sky with a Gaussian star of peak
+    /// 20000 ADU at each of `centers`
+    fn gaussian_frame(width: usize, height: usize, centers: &[(f64, f64)], sigma: f64) -> Vec<u16> {
+        (0..width * height)
+            .map(|i| {
+                let (px, py) = ((i % width) as f64, (i / width) as f64);
+                let flux: f64 = centers
+                    .iter()
+                    .map(|&(x, y)| {
+                        let d2 = (px - x).powi(2) + (py - y).powi(2);
+                        20000.0 * (-d2 / (2.0 * sigma * sigma)).exp()
+                    })
+                    .sum();
+                (1000.0 + flux) as u16
+            })
+            .collect()
+    }
+
+    /// Unresized detection state over `data`, which serves as both detection and original data
+    fn test_state(data: &[u16], width: usize, height: usize) -> DetectionState<'_> {
+        DetectionState {
+            detection_data: data,
+            original_data: data,
+            width,
+            height,
+            resize_factor: 1.0,
+            inverse_resize_factor: 1.0,
+            min_star_size: 2,
+            max_star_size: 150,
+            verbose: false,
+        }
+    }
+
+    #[test]
+    fn test_high_sensitivity_resize_from_image_scale() {
+        let data = vec![0u16; 4];
+        let resize = |pixel_size_um, focal_length_mm, width| {
+            let params = StarDetectionParams {
+                sensitivity: StarSensitivity::High,
+                pixel_size_um,
+                focal_length_mm,
+                ..StarDetectionParams::default()
+            };
+            get_initial_state(&data, &data, width, 4000, &params).resize_factor
+        };
+
+        // 3.76 µm at 530 mm is 1.463"/px, resized towards 3"/px
+        let params = StarDetectionParams {
+            pixel_size_um: Some(3.76),
+            focal_length_mm: Some(530.0),
+            ..StarDetectionParams::default()
+        };
+        assert!((params.image_scale().unwrap() - 1.4633).abs() < 1e-4);
+        assert!((resize(Some(3.76), Some(530.0), 6248) - 1.4633 / 3.0).abs() < 1e-4);
+
+        // Coarse scales are not upsampled, and MAX_WIDTH still caps the width
+        assert_eq!(resize(Some(9.0), Some(300.0), 6248), 1.0);
+        assert_eq!(resize(Some(2.4), Some(2000.0), 6248), 1552.0 / 6248.0);
+
+        // Without the headers the 1/3 default remains
+        assert_eq!(resize(None, Some(530.0), 6248), 1.0 / 3.0);
+    }
+
     #[test]
     fn test_inside_circle() {
         assert!(inside_circle(0.0, 0.0, 0.0, 0.0, 1.0));
@@ -836,4 +1289,345 @@ mod tests {
         let e = calculate_eccentricity(10.0, 5.0);
         assert!(e > 0.8);
     }
+
+    #[test]
+    fn test_fast_detection_on_large_frame() {
+        use crate::image_analysis::FitsImage;
+        use crate::mtf_stretch::{stretch_image, StretchParameters};
+
+        // 3000x2000 frame, wide enough to be resized, with a 10x6 grid of stars
+        let (width, height) = (3000, 2000);
+        let mut seed = 12345u32;
+        let mut data: Vec<u16> = (0..width * height)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                1000 + ((seed >> 16) % 60) as u16
+            })
+            .collect();
+        let sigma: f64 = 2.5;
+        for row in 0..6 {
+            for col in 0..10 {
+                let (cx, cy) = (150.0 + col as f64 * 300.0, 150.0 + row as f64 * 330.0);
+                for y in (cy as usize - 12)..=(cy as usize + 12) {
+                    for x in (cx as usize - 12)..=(cx as usize + 12) {
+                        let r2 = (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2);
+                        let value = 20000.0 * (-r2 / (2.0 * sigma * sigma)).exp();
+                        data[y * width + x] = data[y * width + x].saturating_add(value as u16);
+                    }
+                }
+            }
+        }
+
+        let fits = FitsImage {
+            data,
+            width,
+            height,
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+        let stats = fits.calculate_basic_statistics();
+        let stretch = StretchParameters::default();
+        let stretched = stretch_image(&fits.data, &stats, stretch.factor, stretch.black_clipping);
+
+        let start = std::time::Instant::now();
+        let result = detect_stars_fast(&stretched, &fits.data, width, height, None);
+        let elapsed = start.elapsed();
+
+        assert!(
+            (54..=66).contains(&result.star_list.len()),
+            "{} stars",
+            result.star_list.len()
+        );
+        // A Gaussian with sigma 2.5 has an HFR of about 2.9
+        assert!(
+            (2.5..3.5).contains(&result.average_hfr),
+            "HFR {}",
+            result.average_hfr
+        );
+        assert!(elapsed.as_secs_f64() < 5.0, "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_force_downsample_keeps_hfr() {
+        use crate::image_analysis::FitsImage;
+        use crate::mtf_stretch::{stretch_image, StretchParameters};
+
+        // Oversampled stars (sigma 3) on a frame narrower than MAX_WIDTH
+        let (width, height) = (1200, 900);
+        let mut seed = 777u32;
+        let mut data: Vec<u16> = (0..width * height)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                1000 + ((seed >> 16) % 20) as u16
+            })
+            .collect();
+        let sigma: f64 = 3.0;
+        for row in 0..5 {
+            for col in 0..7 {
+                let (cx, cy) = (100.0 + col as f64 * 165.0, 100.0 + row as f64 * 170.0);
+                for y in (cy as usize - 15)..=(cy as usize + 15) {
+                    for x in (cx as usize - 15)..=(cx as usize + 15) {
+                        let r2 = (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2);
+                        let value = 30000.0 * (-r2 / (2.0 * sigma * sigma)).exp();
+                        data[y * width + x] = data[y * width + x].saturating_add(value as u16);
+                    }
+                }
+            }
+        }
+
+        let fits = FitsImage {
+            data,
+            width,
+            height,
+            channel_data: Vec::new(),
+            raw_range: None,
+        };
+        let stats = fits.calculate_basic_statistics();
+        let stretch = StretchParameters::default();
+        let stretched = stretch_image(&fits.data, &stats, stretch.factor, stretch.black_clipping);
+        let data = fits.data;
+
+        let run = |force_downsample: Option<f64>| {
+            let params = StarDetectionParams {
+                force_downsample,
+                ..StarDetectionParams::default()
+            };
+            let state = get_initial_state(&stretched, &data, width, height, &params);
+            let (mut image, w, h) = DetectionUtility::resize_for_detection(
+                &convert_16bpp_to_8bpp_nina(&stretched),
+                width,
+                height,
+                state.resize_factor,
+            );
+            prepare_for_structure_detection(&mut image, w, h, &params);
+            let blobs = detect_structures(&image, w, h).len();
+            let result = detect_stars_with_original(&stretched, &data, width, height, &params);
+            (blobs, w * h, result)
+        };
+
+        let (full_blobs, full_pixels, full) = run(None);
+        let (half_blobs, half_pixels, half) = run(Some(0.5));
+
+        assert_eq!(half_pixels * 4, full_pixels);
+        assert!(
+            half_blobs < full_blobs,
+            "{} blobs downsampled vs {} full",
+            half_blobs,
+            full_blobs
+        );
+        assert!(!full.star_list.is_empty() && !half.star_list.is_empty());
+        // HFR is measured on the original data either way
+        assert!(
+            (half.average_hfr - full.average_hfr).abs() < 0.15 * full.average_hfr,
+            "HFR {} downsampled vs {} full",
+            half.average_hfr,
+            full.average_hfr
+        );
+    }
+
+    #[test]
+    fn test_parabolic_offset() {
+        // Symmetric samples put the vertex on the center pixel
+        assert_eq!(parabolic_offset(5.0, 10.0, 5.0), Some(0.0));
+        // Brighter right neighbour pulls the vertex right
+        assert!(parabolic_offset(4.0, 10.0, 8.0).unwrap() > 0.0);
+        // Not a maximum
+        assert_eq!(parabolic_offset(10.0, 5.0, 10.0), None);
+    }
+
+    #[test]
+    fn test_parabolic_centroid_beats_flux_weighted() {
+        let width = 41;
+        let height = 41;
+        let (true_x, true_y) = (20.3, 19.6);
+        let background = 1000.0;
+        let data = gaussian_frame(width, height, &[(true_x, true_y)], 1.5);
+
+        // Star rectangle off-center from the star, as blob bounds often are
+        let rect = Rectangle {
+            x: 13,
+            y: 14,
+            width: 12,
+            height: 12,
+        };
+
+        let state = test_state(&data, width, height);
+
+        // Underestimated background biases the flux-weighted centroid
+        let star = Star {
+            position: (19.0, 20.0),
+            radius: 6.0,
+            rectangle: rect,
+            mean_brightness: 0.0,
+            surrounding_mean: background * 0.5,
+            max_pixel_value: 0.0,
+            hfr: 0.0,
+            average: 0.0,
+        };
+        let flux = calculate_star_hfr(&state, star).position;
+        let parabolic = parabolic_peak_centroid(&data, width, height, &rect).unwrap();
+
+        let error = |p: (f64, f64)| ((p.0 - true_x).powi(2) + (p.1 - true_y).powi(2)).sqrt();
+        assert!(
+            error(parabolic) < 0.15,
+            "parabolic error {}",
+            error(parabolic)
+        );
+        assert!(
+            error(parabolic) < error(flux),
+            "parabolic {:?} vs flux {:?}",
+            parabolic,
+            flux
+        );
+    }
+
+    #[test]
+    fn test_fit_star_psf_round_star() {
+        let width = 41;
+        let height = 41;
+        let (true_x, true_y) = (20.0, 20.0);
+        let sigma = 2.0;
+        let background = 1000.0;
+        let data = gaussian_frame(width, height, &[(true_x, true_y)], sigma);
+
+        let state = test_state(&data, width, height);
+
+        // Stretched units, which the fit must not rely on
+        let star = Star {
+            position: (true_x, true_y),
+            radius: 6.0,
+            rectangle: Rectangle {
+                x: 12,
+                y: 12,
+                width: 17,
+                height: 17,
+            },
+            mean_brightness: 0.0,
+            surrounding_mean: background,
+            max_pixel_value: 65535.0,
+            hfr: 0.0,
+            average: 0.0,
+        };
+
+        let fitter = PSFFitter::new(PSFType::Gaussian);
+        let model = fit_star_psf(&fitter, &state, &star).expect("fit should succeed");
+        assert!(
+            model.eccentricity < 0.1,
+            "eccentricity {}",
+            model.eccentricity
+        );
+        assert!(
+            (model.fwhm - 2.355 * sigma).abs() < 0.2,
+            "fwhm {}",
+            model.fwhm
+        );
+    }
+
+    /// 200x200 frame with one Gaussian star per position and a matching blob for each
+    fn roi_test_frame(centers: &[(f64, f64)]) -> (Vec<u16>, Vec<Blob>) {
+        let data = gaussian_frame(200, 200, centers, 2.0);
+        let blobs = centers
+            .iter()
+            .map(|&(x, y)| Blob {
+                rectangle: crate::accord_imaging::Rectangle {
+                    x: x as i32 - 7,
+                    y: y as i32 - 7,
+                    width: 15,
+                    height: 15,
+                },
+            })
+            .collect();
+        (data, blobs)
+    }
+
+    #[test]
+    fn test_in_roi() {
+        let centers = [(100.0, 100.0), (60.0, 120.0), (20.0, 20.0), (180.0, 100.0)];
+        let surviving = |params: &StarDetectionParams| -> Vec<(f64, f64)> {
+            centers
+                .iter()
+                .copied()
+                .filter(|&(x, y)| in_roi(params, x, y, 200, 200))
+                .collect()
+        };
+
+        // Default ratios keep the whole frame
+        assert_eq!(surviving(&StarDetectionParams::default()).len(), 4);
+
+        let center_crop = StarDetectionParams {
+            outer_crop_ratio: 0.5,
+            ..StarDetectionParams::default()
+        };
+        assert_eq!(surviving(&center_crop), vec![(100.0, 100.0), (60.0, 120.0)]);
+
+        let ring = StarDetectionParams {
+            inner_crop_ratio: 0.3,
+            outer_crop_ratio: 0.5,
+            ..StarDetectionParams::default()
+        };
+        assert_eq!(surviving(&ring), vec![(60.0, 120.0)]);
+    }
+
+    #[test]
+    fn test_identify_stars_roi_filter() {
+        let centers = [(100.0, 100.0), (60.0, 120.0), (20.0, 20.0), (180.0, 100.0)];
+        let (data, blobs) = roi_test_frame(&centers);
+        let state = test_state(&data, 200, 200);
+        let positions = |params: &StarDetectionParams| -> Vec<(i64, i64)> {
+            let (stars, _) = identify_stars(params, &state, blobs.clone(), 200, 200);
+            stars
+                .iter()
+                .map(|s| (s.position.0.round() as i64, s.position.1.round() as i64))
+                .collect()
+        };
+
+        // Crop ratios are ignored unless use_roi is set
+        let disabled = StarDetectionParams {
+            outer_crop_ratio: 0.5,
+            ..StarDetectionParams::default()
+        };
+        assert_eq!(positions(&disabled).len(), 4);
+
+        let center_crop = StarDetectionParams {
+            use_roi: true,
+            outer_crop_ratio: 0.5,
+            ..StarDetectionParams::default()
+        };
+        assert_eq!(positions(&center_crop), vec![(100, 100), (60, 120)]);
+
+        let ring = StarDetectionParams {
+            use_roi: true,
+            inner_crop_ratio: 0.3,
+            outer_crop_ratio: 0.5,
+            ..StarDetectionParams::default()
+        };
+        assert_eq!(positions(&ring), vec![(60, 120)]);
+    }
+
+    #[test]
+    fn test_right_edge_centroid_rejection() {
+        let (data, _) = roi_test_frame(&[(101.5, 100.0)]);
+        let state = test_state(&data, 200, 200);
+        // Blob bounds clipped on the right, leaving the centroid against that edge
+        let blobs = vec![Blob {
+            rectangle: crate::accord_imaging::Rectangle {
+                x: 86,
+                y: 93,
+                width: 15,
+                height: 15,
+            },
+        }];
+
+        let compatible = StarDetectionParams::default();
+        let (stars, _) = identify_stars(&compatible, &state, blobs.clone(), 200, 200);
+        assert_eq!(stars.len(), 1);
+        assert!(stars[0].position.0 > 99.0, "{:?}", stars[0].position);
+
+        let correct = StarDetectionParams {
+            nina_bug_compatible: false,
+            ..StarDetectionParams::default()
+        };
+        let (stars, _) = identify_stars(&correct, &state, blobs, 200, 200);
+        assert!(stars.is_empty());
+    }
 }
@@ -4,6 +4,9 @@
 use nalgebra::{DMatrix, DVector};
 use std::f64::consts::PI;
 
+/// Fits with an R² below this are too poor to report FWHM or eccentricity from
+pub const DEFAULT_MIN_R_SQUARED: f64 = 0.5;
+
 /// PSF fitting type
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PSFType {
@@ -13,6 +16,8 @@ pub enum PSFType {
     Gaussian,
     /// Moffat PSF with beta=4.0
     Moffat4,
+    /// Moffat PSF with a fixed, user-chosen beta
+    MoffatBeta(f64),
 }
 
 impl std::str::FromStr for PSFType {
@@ -23,7 +28,18 @@ impl std::str::FromStr for PSFType {
             "none" => Ok(PSFType::None),
             "gaussian" => Ok(PSFType::Gaussian),
             "moffat" | "moffat4" | "moffat_4" => Ok(PSFType::Moffat4),
-            _ => Err(format!("Unknown PSF type: {}", s)),
+            other => {
+                // "moffat2.5" / "moffat_2.5" select an arbitrary beta
+                let beta = other
+                    .strip_prefix("moffat")
+                    .map(|b| b.trim_start_matches('_'))
+                    .and_then(|b| b.parse::<f64>().ok())
+                    .ok_or_else(|| format!("Unknown PSF type: {}", s))?;
+                if !beta.is_finite() || beta <= 0.0 {
+                    return Err(format!("Moffat beta must be positive: {}", s));
+                }
+                Ok(PSFType::MoffatBeta(beta))
+            }
         }
     }
 }
@@ -31,7 +47,7 @@ impl std::str::FromStr for PSFType {
 /// PSF model parameters after fitting
 #[derive(Debug, Clone)]
 pub struct PSFModel {
-    /// PSF type used
+    /// PSF type used (`MoffatBeta` with the fitted value when beta was a free parameter)
     pub psf_type: PSFType,
     /// Amplitude (peak brightness above background)
     pub amplitude: f64,
@@ -67,6 +83,7 @@ impl PSFModel {
                 // For Moffat with beta=4: FWHM = sigma * 2 * sqrt(2^(1/4) - 1)
                 avg_sigma * 2.0 * (2.0_f64.powf(0.25) - 1.0).sqrt() // ≈ 1.1895
             }
+            PSFType::MoffatBeta(beta) => MoffatBetaPSF { beta }.sigma_to_fwhm(avg_sigma),
             PSFType::None => 0.0,
         }
     }
@@ -161,7 +178,7 @@ impl PSFFunction for GaussianPSF {
         grad[5] = a * exp_arg * yp * yp / (sy2 * sigma_y);
 
         // d/dtheta
-        grad[6] = a * exp_arg * xp * yp * (1.0 / sx2 - 1.0 / sy2);
+        grad[6] = -a * exp_arg * xp * yp * (1.0 / sx2 - 1.0 / sy2);
     }
 
     fn sigma_to_fwhm(&self, sigma: f64) -> f64 {
@@ -251,6 +268,119 @@ impl PSFFunction for Moffat4PSF {
     }
 }
 
+/// Moffat PSF model with an arbitrary fixed beta
+pub struct MoffatBetaPSF {
+    pub beta: f64,
+}
+
+impl PSFFunction for MoffatBetaPSF {
+    fn value(&self, x: f64, y: f64, params: &[f64]) -> f64 {
+        let a = params[0]; // Amplitude
+        let b = params[1]; // Background
+        let x0 = params[2]; // X offset
+        let y0 = params[3]; // Y offset
+        let u = params[4]; // Sigma X
+        let v = params[5]; // Sigma Y
+        let theta = params[6]; // Rotation angle
+
+        // Rotate coordinates
+        let cos_t = theta.cos();
+        let sin_t = theta.sin();
+        let dx = x - x0;
+        let dy = y - y0;
+        let xp = dx * cos_t + dy * sin_t;
+        let yp = -dx * sin_t + dy * cos_t;
+
+        let d = 1.0 + (xp * xp) / (u * u) + (yp * yp) / (v * v);
+        b + a * d.powf(-self.beta)
+    }
+
+    fn gradient(&self, x: f64, y: f64, params: &[f64], grad: &mut [f64]) {
+        let a = params[0];
+        let x0 = params[2];
+        let y0 = params[3];
+        let u = params[4];
+        let v = params[5];
+        let theta = params[6];
+
+        let cos_t = theta.cos();
+        let sin_t = theta.sin();
+        let dx = x - x0;
+        let dy = y - y0;
+        let xp = dx * cos_t + dy * sin_t;
+        let yp = -dx * sin_t + dy * cos_t;
+
+        let u2 = u * u;
+        let v2 = v * v;
+        let xp2 = xp * xp;
+        let yp2 = yp * yp;
+
+        let d = 1.0 + xp2 / u2 + yp2 / v2;
+        let beta = self.beta;
+
+        // d/dA
+        grad[0] = d.powf(-beta);
+
+        // d/dB
+        grad[1] = 1.0;
+
+        // Common terms
+        let factor = -a * beta * d.powf(-beta - 1.0);
+
+        // d/dx0
+        grad[2] = factor * ((2.0 * sin_t * yp / v2) - (2.0 * cos_t * xp / u2));
+
+        // d/dy0
+        grad[3] = factor * ((-2.0 * sin_t * xp / u2) - (2.0 * cos_t * yp / v2));
+
+        // d/du (sigma_x)
+        grad[4] = (2.0 * a * beta / (u2 * u)) * xp2 * d.powf(-beta - 1.0);
+
+        // d/dv (sigma_y)
+        grad[5] = (2.0 * a * beta / (v2 * v)) * yp2 * d.powf(-beta - 1.0);
+
+        // d/dtheta
+        grad[6] = factor * (2.0 * yp * xp * (1.0 / u2 - 1.0 / v2));
+    }
+
+    fn sigma_to_fwhm(&self, sigma: f64) -> f64 {
+        // FWHM = sigma * 2 * sqrt(2^(1/beta) - 1)
+        sigma * 2.0 * (2.0_f64.powf(1.0 / self.beta) - 1.0).sqrt()
+    }
+}
+
+/// Moffat PSF model with beta as an 8th free parameter
+/// parameters: [A, B, x0, y0, sigma_x, sigma_y, theta, beta]
+pub struct MoffatFreeBetaPSF;
+
+impl PSFFunction for MoffatFreeBetaPSF {
+    fn value(&self, x: f64, y: f64, params: &[f64]) -> f64 {
+        MoffatBetaPSF { beta: params[7] }.value(x, y, params)
+    }
+
+    fn gradient(&self, x: f64, y: f64, params: &[f64], grad: &mut [f64]) {
+        let beta = params[7];
+        MoffatBetaPSF { beta }.gradient(x, y, params, grad);
+
+        let a = params[0];
+        let cos_t = params[6].cos();
+        let sin_t = params[6].sin();
+        let dx = x - params[2];
+        let dy = y - params[3];
+        let xp = dx * cos_t + dy * sin_t;
+        let yp = -dx * sin_t + dy * cos_t;
+        let d = 1.0 + (xp * xp) / (params[4] * params[4]) + (yp * yp) / (params[5] * params[5]);
+
+        // d/dbeta
+        grad[7] = -a * d.ln() * d.powf(-beta);
+    }
+
+    fn sigma_to_fwhm(&self, _sigma: f64) -> f64 {
+        // Depends on the fitted beta; use MoffatBetaPSF once it is known
+        0.0
+    }
+}
+
 /// Bilinear interpolation for sub-pixel sampling
 pub fn bilinear_sample(data: &[u16], width: usize, height: usize, x: f64, y: f64) -> f64 {
     // Clamp to image bounds
@@ -338,8 +468,11 @@ impl Default for LevenbergMarquardt {
 
 impl LevenbergMarquardt {
     /// Fit PSF model to data
+    ///
+    /// Damping always starts from the configured lambda, so one optimizer can be
+    /// reused across stars without a hard fit slowing down the next one.
     pub fn fit(
-        &mut self,
+        &self,
         psf: &dyn PSFFunction,
         positions: &[(f64, f64)],
         values: &[f64],
@@ -354,6 +487,7 @@ impl LevenbergMarquardt {
             return Err("Not enough data points for fitting".to_string());
         }
 
+        let mut lambda = self.lambda;
         let mut params = initial_params.to_vec();
         let mut best_params = params.clone();
         let mut best_error = f64::MAX;
@@ -373,7 +507,7 @@ impl LevenbergMarquardt {
 
                 psf.gradient(*x, *y, &params, &mut gradient);
                 for (j, &grad) in gradient.iter().enumerate() {
-                    jacobian[(i, j)] = -grad; // Negative because residual = observed - predicted
+                    jacobian[(i, j)] = grad;
                 }
             }
 
@@ -396,7 +530,7 @@ impl LevenbergMarquardt {
                 // Add lambda to diagonal (LM modification)
                 let mut h = jtj.clone();
                 for i in 0..n_params {
-                    h[(i, i)] += self.lambda;
+                    h[(i, i)] += lambda;
                 }
 
                 // Solve for delta
@@ -421,20 +555,20 @@ impl LevenbergMarquardt {
                         if new_error < current_error {
                             // Accept update
                             params = new_params;
-                            self.lambda /= self.lambda_factor;
+                            lambda /= self.lambda_factor;
                             break;
                         } else {
                             // Reject update, increase lambda
-                            self.lambda *= self.lambda_factor;
-                            if self.lambda > 1e10 {
+                            lambda *= self.lambda_factor;
+                            if lambda > 1e10 {
                                 return Ok(best_params);
                             }
                         }
                     }
                     None => {
                         // Singular matrix, increase lambda
-                        self.lambda *= self.lambda_factor;
-                        if self.lambda > 1e10 {
+                        lambda *= self.lambda_factor;
+                        if lambda > 1e10 {
                             return Ok(best_params);
                         }
                     }
@@ -449,11 +583,16 @@ impl LevenbergMarquardt {
 /// Type alias for residual map data (observed, fitted, residuals)
 pub type ResidualMaps = (Vec<Vec<f64>>, Vec<Vec<f64>>, Vec<Vec<f64>>);
 
+/// Bounds for beta when it is fitted as a free parameter
+const FIT_BETA_MIN: f64 = 1.0;
+const FIT_BETA_MAX: f64 = 10.0;
+
 /// PSF Fitter
 pub struct PSFFitter {
     psf_type: PSFType,
     roi_size: usize,
     sample_spacing: f64,
+    fit_beta: bool,
 }
 
 impl PSFFitter {
@@ -462,6 +601,26 @@ impl PSFFitter {
             psf_type,
             roi_size: 32,        // Default ROI size
             sample_spacing: 0.5, // Sub-pixel sampling
+            fit_beta: false,
+        }
+    }
+
+    /// Fit Moffat beta as a free parameter, starting from the configured beta.
+    /// Has no effect for Gaussian fits.
+    pub fn with_fit_beta(mut self, fit_beta: bool) -> Self {
+        self.fit_beta = fit_beta;
+        self
+    }
+
+    /// Starting beta when beta is a free parameter, None if it stays fixed
+    fn free_beta_start(&self) -> Option<f64> {
+        if !self.fit_beta {
+            return None;
+        }
+        match self.psf_type {
+            PSFType::Moffat4 => Some(4.0),
+            PSFType::MoffatBeta(beta) => Some(beta.clamp(FIT_BETA_MIN, FIT_BETA_MAX)),
+            PSFType::Gaussian | PSFType::None => None,
         }
     }
 
@@ -498,15 +657,19 @@ impl PSFFitter {
             return None; // Not enough points
         }
 
+        let free_beta = self.free_beta_start();
+
         // Set up PSF model
-        let psf: Box<dyn PSFFunction> = match self.psf_type {
-            PSFType::Gaussian => Box::new(GaussianPSF),
-            PSFType::Moffat4 => Box::new(Moffat4PSF),
-            PSFType::None => unreachable!(),
+        let psf: Box<dyn PSFFunction> = match (self.psf_type, free_beta) {
+            (_, Some(_)) => Box::new(MoffatFreeBetaPSF),
+            (PSFType::Gaussian, _) => Box::new(GaussianPSF),
+            (PSFType::Moffat4, _) => Box::new(Moffat4PSF),
+            (PSFType::MoffatBeta(beta), _) => Box::new(MoffatBetaPSF { beta }),
+            (PSFType::None, _) => unreachable!(),
         };
 
         // Initial parameters: [A, B, x0, y0, sigma_x, sigma_y, theta]
-        let initial_params = vec![
+        let mut initial_params = vec![
             peak_brightness - background, // Amplitude
             background,                   // Background
             0.0,                          // x0 (centered)
@@ -521,7 +684,7 @@ impl PSFFitter {
         let dy_limit = bbox_height / 8.0;
         let sigma_max = ((bbox_width * bbox_width + bbox_height * bbox_height).sqrt()) / 2.0;
 
-        let lower_bounds = vec![
+        let mut lower_bounds = vec![
             0.0,       // A must be positive
             0.0,       // B must be positive
             -dx_limit, // x0
@@ -531,7 +694,7 @@ impl PSFFitter {
             -PI / 2.0, // theta
         ];
 
-        let upper_bounds = vec![
+        let mut upper_bounds = vec![
             2.0 * (peak_brightness - background), // A max
             peak_brightness,                      // B max
             dx_limit,                             // x0
@@ -541,8 +704,14 @@ impl PSFFitter {
             PI / 2.0,                             // theta
         ];
 
+        if let Some(beta) = free_beta {
+            initial_params.push(beta);
+            lower_bounds.push(FIT_BETA_MIN);
+            upper_bounds.push(FIT_BETA_MAX);
+        }
+
         // Fit the model
-        let mut optimizer = LevenbergMarquardt::default();
+        let optimizer = LevenbergMarquardt::default();
         match optimizer.fit(
             &*psf,
             &positions,
@@ -571,8 +740,13 @@ impl PSFFitter {
 
                 let rmse = (sum_squared_residuals / positions.len() as f64).sqrt();
 
+                let psf_type = match free_beta {
+                    Some(_) => PSFType::MoffatBeta(fitted_params[7]),
+                    None => self.psf_type,
+                };
+
                 let mut model = PSFModel {
-                    psf_type: self.psf_type,
+                    psf_type,
                     amplitude: fitted_params[0],
                     background: fitted_params[1],
                     x0: fitted_params[2],
@@ -610,9 +784,11 @@ impl PSFFitter {
         let mut fitted = vec![vec![0.0; self.roi_size]; self.roi_size];
         let mut residuals = vec![vec![0.0; self.roi_size]; self.roi_size];
 
-        let psf: Box<dyn PSFFunction> = match self.psf_type {
+        // The model's type carries the fitted beta when beta was a free parameter
+        let psf: Box<dyn PSFFunction> = match model.psf_type {
             PSFType::Gaussian => Box::new(GaussianPSF),
             PSFType::Moffat4 => Box::new(Moffat4PSF),
+            PSFType::MoffatBeta(beta) => Box::new(MoffatBetaPSF { beta }),
             PSFType::None => return None,
         };
 
@@ -650,3 +826,167 @@ impl PSFFitter {
         Some((observed, fitted, residuals))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render a noiseless, centered Moffat star
+    fn synthetic_moffat(size: usize, params: &[f64], beta: f64) -> Vec<u16> {
+        let psf = MoffatBetaPSF { beta };
+        let center = size as f64 / 2.0;
+        (0..size * size)
+            .map(|i| {
+                let x = (i % size) as f64 - center;
+                let y = (i / size) as f64 - center;
+                psf.value(x, y, params).round() as u16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_moffat_beta() {
+        assert_eq!("moffat2.5".parse(), Ok(PSFType::MoffatBeta(2.5)));
+        assert_eq!("moffat_3".parse(), Ok(PSFType::MoffatBeta(3.0)));
+        assert_eq!("moffat4".parse(), Ok(PSFType::Moffat4));
+        assert!("moffat0".parse::<PSFType>().is_err());
+        assert!("moffatx".parse::<PSFType>().is_err());
+    }
+
+    #[test]
+    fn test_moffat_beta_fwhm() {
+        // beta=4 must agree with the dedicated Moffat4 model
+        assert!(
+            (MoffatBetaPSF { beta: 4.0 }.sigma_to_fwhm(3.0) - Moffat4PSF.sigma_to_fwhm(3.0)).abs()
+                < 1e-12
+        );
+
+        // At the FWHM radius the profile drops to half its peak
+        let beta = 2.5;
+        let psf = MoffatBetaPSF { beta };
+        let params = [1000.0, 0.0, 0.0, 0.0, 3.0, 3.0, 0.0];
+        let half_width = psf.sigma_to_fwhm(3.0) / 2.0;
+        assert!((psf.value(half_width, 0.0, &params) - 500.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fit_moffat_beta_recovers_parameters() {
+        let beta = 2.5;
+        let truth = [20000.0, 1000.0, 0.0, 0.0, 5.0, 5.0, 0.0];
+        let size = 64;
+        let data = synthetic_moffat(size, &truth, beta);
+        let center = size as f64 / 2.0;
+
+        let fitter = PSFFitter::new(PSFType::MoffatBeta(beta));
+        let model = fitter
+            .fit_star(
+                &data, size, size, center, center, 24.0, 24.0, 1000.0, 21000.0,
+            )
+            .expect("fit should succeed");
+
+        // Bilinear resampling flattens the peak slightly, hence the few-percent tolerances
+        assert_eq!(model.psf_type, PSFType::MoffatBeta(beta));
+        assert!(
+            (model.amplitude / 20000.0 - 1.0).abs() < 0.03,
+            "{:?}",
+            model
+        );
+        assert!((model.background - 1000.0).abs() < 20.0, "{:?}", model);
+        assert!((model.sigma_x / 5.0 - 1.0).abs() < 0.03, "{:?}", model);
+        assert!((model.sigma_y / 5.0 - 1.0).abs() < 0.03, "{:?}", model);
+        assert!(
+            model.x0.abs() < 0.05 && model.y0.abs() < 0.05,
+            "{:?}",
+            model
+        );
+        assert!(model.r_squared > 0.99, "{:?}", model);
+
+        let expected_fwhm = MoffatBetaPSF { beta }.sigma_to_fwhm(5.0);
+        assert!(
+            (model.fwhm / expected_fwhm - 1.0).abs() < 0.03,
+            "{:?}",
+            model
+        );
+    }
+
+    #[test]
+    fn test_fit_beta_converges_and_improves_fit() {
+        let beta = 2.5;
+        let truth = [20000.0, 1000.0, 0.0, 0.0, 5.0, 5.0, 0.0];
+        let size = 64;
+        let data = synthetic_moffat(size, &truth, beta);
+        let center = size as f64 / 2.0;
+
+        let fit = |fitter: PSFFitter| {
+            fitter
+                .fit_star(
+                    &data, size, size, center, center, 24.0, 24.0, 1000.0, 21000.0,
+                )
+                .expect("fit should succeed")
+        };
+
+        let fixed = fit(PSFFitter::new(PSFType::Moffat4));
+        let free = fit(PSFFitter::new(PSFType::Moffat4).with_fit_beta(true));
+
+        let fitted_beta = match free.psf_type {
+            PSFType::MoffatBeta(b) => b,
+            other => panic!("expected fitted beta, got {:?}", other),
+        };
+        assert!(
+            (fitted_beta - beta).abs() < 0.15,
+            "fitted beta {}",
+            fitted_beta
+        );
+        assert!(
+            free.r_squared > fixed.r_squared,
+            "free {} vs fixed {}",
+            free.r_squared,
+            fixed.r_squared
+        );
+    }
+
+    #[test]
+    fn test_fit_beta_ignored_for_gaussian() {
+        let fitter = PSFFitter::new(PSFType::Gaussian).with_fit_beta(true);
+        assert_eq!(fitter.free_beta_start(), None);
+
+        let fitter = PSFFitter::new(PSFType::MoffatBeta(20.0)).with_fit_beta(true);
+        assert_eq!(fitter.free_beta_start(), Some(FIT_BETA_MAX));
+    }
+
+    #[test]
+    fn test_levenberg_marquardt_reuse_is_independent() {
+        let psf = GaussianPSF;
+        let truth = [1000.0, 100.0, 0.0, 0.0, 2.0, 2.0, 0.0];
+        let positions: Vec<(f64, f64)> = (-8..=8)
+            .flat_map(|y| (-8..=8).map(move |x| (x as f64, y as f64)))
+            .collect();
+        let values: Vec<f64> = positions
+            .iter()
+            .map(|&(x, y)| psf.value(x, y, &truth))
+            .collect();
+
+        let initial = [800.0, 80.0, 0.5, -0.5, 3.0, 3.0, 0.0];
+        let lower = [0.0, 0.0, -2.0, -2.0, 0.1, 0.1, -PI / 2.0];
+        let upper = [2000.0, 1000.0, 2.0, 2.0, 10.0, 10.0, PI / 2.0];
+
+        let optimizer = LevenbergMarquardt::default();
+        let first = optimizer
+            .fit(&psf, &positions, &values, &initial, &lower, &upper)
+            .unwrap();
+
+        // A flat frame has nothing to fit, so every step is rejected and the
+        // damping climbs to its limit before the optimizer gives up
+        let flat = vec![100.0; positions.len()];
+        optimizer
+            .fit(&psf, &positions, &flat, &initial, &lower, &upper)
+            .unwrap();
+
+        let second = optimizer
+            .fit(&psf, &positions, &values, &initial, &lower, &upper)
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert!((first[4] - 2.0).abs() < 1e-3, "{:?}", first);
+    }
+}
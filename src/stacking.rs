@@ -0,0 +1,290 @@
+//! Per-pixel combination of calibration frames into a master frame.
+//!
+//! Frames are combined in horizontal bands sized so that one band of every
+//! frame fits in [`MEMORY_BUDGET`]. When everything fits, each frame is read
+//! once; otherwise each frame is read again for every band, trading I/O for a
+//! bounded memory footprint with large frame counts.
+
+use crate::image_analysis::FitsImage;
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Bytes of frame data held at once while stacking
+const MEMORY_BUDGET: usize = 1 << 30;
+
+/// Sigma clipping stops after this many passes even if values are still rejected
+const MAX_CLIP_ITERATIONS: usize = 10;
+
+/// How the values of one pixel across frames are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackMethod {
+    Median,
+    Mean,
+    /// Mean of the values within `kappa` standard deviations of the median,
+    /// rejecting outliers repeatedly until none remain
+    SigmaClip {
+        kappa: f64,
+    },
+}
+
+impl FromStr for StackMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "median" => Ok(StackMethod::Median),
+            "mean" | "average" => Ok(StackMethod::Mean),
+            "sigma-clip" | "sigma_clip" | "sigmaclip" => Ok(StackMethod::SigmaClip { kappa: 3.0 }),
+            _ => Err(format!(
+                "Invalid stack method '{}': expected median, mean or sigma-clip",
+                s
+            )),
+        }
+    }
+}
+
+impl StackMethod {
+    /// Combine one pixel's values; `values` is reordered
+    pub fn combine(&self, values: &mut [f64]) -> f64 {
+        match *self {
+            StackMethod::Median => median(values),
+            StackMethod::Mean => mean(values),
+            StackMethod::SigmaClip { kappa } => sigma_clipped_mean(values, kappa),
+        }
+    }
+}
+
+/// Combine frames of identical dimensions pixel by pixel
+///
+/// Frames are combined in physical units (ADU), undoing the per-frame 0-65535
+/// scaling of the reader, so frames with different pixel ranges line up. The
+/// master's `raw_range` keeps its ADU levels for the FITS writer.
+pub fn stack_frames(paths: &[&Path], method: StackMethod) -> Result<FitsImage> {
+    stack_in_bands(paths, method, MEMORY_BUDGET)
+}
+
+fn stack_in_bands(paths: &[&Path], method: StackMethod, memory_budget: usize) -> Result<FitsImage> {
+    let Some(first_path) = paths.first() else {
+        bail!("At least one frame is needed to stack");
+    };
+
+    let mut first = Some(FitsImage::from_file(first_path)?);
+    let (width, height) = first.as_ref().map_or((0, 0), |f| (f.width, f.height));
+
+    let row_bytes = paths.len() * width * std::mem::size_of::<f64>();
+    let band_rows = (memory_budget / row_bytes.max(1)).clamp(1, height.max(1));
+
+    let mut data = Vec::with_capacity(width * height);
+    let mut samples = vec![0.0; paths.len()];
+    for band_start in (0..height).step_by(band_rows) {
+        let band = band_start * width..(band_start + band_rows).min(height) * width;
+
+        let mut bands = Vec::with_capacity(paths.len());
+        for path in paths {
+            let frame = match first.take() {
+                Some(frame) => frame,
+                None => load_frame(path, width, height)?,
+            };
+            let physical: Vec<f64> = frame.data[band.clone()]
+                .iter()
+                .map(|&value| frame.physical_level(value))
+                .collect();
+            bands.push(physical);
+        }
+
+        for i in 0..band.len() {
+            for (sample, frame) in samples.iter_mut().zip(&bands) {
+                *sample = frame[i];
+            }
+            data.push(method.combine(&mut samples));
+        }
+    }
+
+    FitsImage::from_pixels(data, width, height)
+}
+
+/// Load a frame, checking it matches the first frame's dimensions
+fn load_frame(path: &Path, width: usize, height: usize) -> Result<FitsImage> {
+    let frame = FitsImage::from_file(path)?;
+    if (frame.width, frame.height) != (width, height) {
+        bail!(
+            "Frame {} is {}x{}, expected {}x{}",
+            path.display(),
+            frame.width,
+            frame.height,
+            width,
+            height
+        );
+    }
+    Ok(frame)
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn sigma_clipped_mean(values: &mut [f64], kappa: f64) -> f64 {
+    let mut kept = values.len();
+    for _ in 0..MAX_CLIP_ITERATIONS {
+        let current = &mut values[..kept];
+        let center = median(current);
+        let mean_value = mean(current);
+        let stddev = (current
+            .iter()
+            .map(|&v| (v - mean_value).powi(2))
+            .sum::<f64>()
+            / kept as f64)
+            .sqrt();
+
+        // Move survivors to the front; median() left the values sorted
+        let (low, high) = (center - kappa * stddev, center + kappa * stddev);
+        let survivors: Vec<f64> = current
+            .iter()
+            .copied()
+            .filter(|v| (low..=high).contains(v))
+            .collect();
+        if survivors.len() == kept || survivors.is_empty() {
+            break;
+        }
+        kept = survivors.len();
+        values[..kept].copy_from_slice(&survivors);
+    }
+    mean(&values[..kept])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a 4x3 frame of ADU values
+    fn write_frame(dir: &Path, name: &str, data: [u16; 12]) -> std::path::PathBuf {
+        let path = dir.join(name);
+        FitsImage {
+            width: 4,
+            height: 3,
+            data: data.to_vec(),
+            channel_data: Vec::new(),
+            raw_range: None,
+        }
+        .write_to_file(&path)
+        .unwrap();
+        path
+    }
+
+    /// Physical values of an image, rounded to whole ADU
+    fn adu(image: &FitsImage) -> Vec<f64> {
+        image
+            .data
+            .iter()
+            .map(|&value| image.physical_level(value).round())
+            .collect()
+    }
+
+    #[test]
+    fn test_stack_three_frames() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_stack_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Dark-like frames, none spanning the full 16-bit range and each with
+        // its own minimum and maximum
+        let frames = [
+            write_frame(
+                &dir,
+                "a.fits",
+                [
+                    500, 600, 700, 800, 900, 1000, 1100, 1200, 1300, 1400, 1500, 1600,
+                ],
+            ),
+            write_frame(
+                &dir,
+                "b.fits",
+                [
+                    510, 590, 730, 800, 880, 1050, 1100, 1190, 1350, 1400, 1480, 9000,
+                ],
+            ),
+            write_frame(
+                &dir,
+                "c.fits",
+                [
+                    490, 610, 710, 820, 920, 1010, 1120, 1210, 1310, 1420, 1520, 1620,
+                ],
+            ),
+        ];
+        let paths: Vec<&Path> = frames.iter().map(|p| p.as_path()).collect();
+
+        let median = stack_frames(&paths, StackMethod::Median).unwrap();
+        let mean = stack_frames(&paths, StackMethod::Mean).unwrap();
+        // A budget of one row per band forces every frame to be re-read per band
+        let banded = stack_in_bands(
+            &paths,
+            StackMethod::Median,
+            3 * 4 * std::mem::size_of::<f64>(),
+        )
+        .unwrap();
+
+        // Round trip the master through the writer
+        let output = dir.join("master.fits");
+        median.write_to_file(&output).unwrap();
+        let reloaded = FitsImage::from_file(&output).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let expected = vec![
+            500.0, 600.0, 710.0, 800.0, 900.0, 1010.0, 1100.0, 1200.0, 1310.0, 1400.0, 1500.0,
+            1620.0,
+        ];
+        assert_eq!((median.width, median.height), (4, 3));
+        assert_eq!(adu(&median), expected);
+        assert_eq!(adu(&banded), expected);
+        // The written master keeps ADU levels rather than a 0-65535 stretch
+        assert_eq!(adu(&reloaded), expected);
+        // The outlier in frame b pulls the mean but not the median
+        assert_eq!(adu(&mean)[11], 4073.0);
+    }
+
+    #[test]
+    fn test_sigma_clip_rejects_outlier() {
+        let mut values = [
+            1000.0, 1010.0, 990.0, 1005.0, 995.0, 1002.0, 998.0, 1001.0, 999.0, 40000.0,
+        ];
+        let clipped = StackMethod::SigmaClip { kappa: 2.0 }.combine(&mut values);
+        assert_eq!(clipped, 1000.0);
+
+        let mut values = [
+            1000.0, 1010.0, 990.0, 1005.0, 995.0, 1002.0, 998.0, 1001.0, 999.0, 40000.0,
+        ];
+        assert_eq!(StackMethod::Mean.combine(&mut values), 4900.0);
+    }
+
+    #[test]
+    fn test_stack_rejects_mismatched_frames() {
+        let dir = std::env::temp_dir().join(format!("psf_guard_stack_dims_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = write_frame(&dir, "a.fits", [1; 12]);
+        let other = dir.join("b.fits");
+        FitsImage {
+            width: 2,
+            height: 2,
+            data: vec![0, 1, 2, 65535],
+            channel_data: Vec::new(),
+            raw_range: None,
+        }
+        .write_to_file(&other)
+        .unwrap();
+
+        let result = stack_frames(&[first.as_path(), other.as_path()], StackMethod::Median);
+        std::fs::remove_dir_all(&dir).ok();
+
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("is 2x2, expected 4x3"), "{}", error);
+    }
+}
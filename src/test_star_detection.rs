@@ -50,6 +50,8 @@ mod tests {
             data: image.data.clone(),
             width: image.width,
             height: image.height,
+            channel_data: Vec::new(),
+            raw_range: None,
         };
         let stats = fits.calculate_basic_statistics();
         let stretch_params = StretchParameters::default();
@@ -451,4 +453,65 @@ mod tests {
             );
         }
     }
+
+    /// Environment variable that turns the stderr test into its child-process half
+    const STDERR_CHILD_ENV: &str = "PSF_GUARD_STDERR_CHILD";
+
+    /// Run both detectors on a small synthetic frame with the requested verbosity
+    fn run_detectors(verbose: bool) {
+        use crate::hocus_focus_star_detection::{detect_stars_hocus_focus, HocusFocusParams};
+
+        crate::debug::init_debug(verbose);
+
+        let mut image = SyntheticImage::new(256, 256, 1000, 50);
+        image.add_gaussian_star(64.0, 64.0, 4.0, 20000);
+        image.add_gaussian_star(180.0, 120.0, 5.0, 30000);
+
+        let params = StarDetectionParams {
+            verbose,
+            ..StarDetectionParams::default()
+        };
+        detect_stars_with_stretching(&image, &params);
+
+        let params = HocusFocusParams {
+            verbose,
+            ..Default::default()
+        };
+        detect_stars_hocus_focus(&image.data, image.width, image.height, &params);
+    }
+
+    /// Re-run `test_name` in a child process with output capture disabled and return its stderr
+    fn child_stderr(test_name: &str, mode: &str) -> String {
+        let module = module_path!().split_once("::").unwrap().1;
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                &format!("{}::{}", module, test_name),
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(STDERR_CHILD_ENV, mode)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "child test run failed");
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    }
+
+    #[test]
+    fn test_detection_stderr_respects_verbose() {
+        match std::env::var(STDERR_CHILD_ENV).as_deref() {
+            Ok("quiet") => return run_detectors(false),
+            Ok("verbose") => return run_detectors(true),
+            _ => {}
+        }
+
+        let test_name = "test_detection_stderr_respects_verbose";
+
+        let quiet = child_stderr(test_name, "quiet");
+        assert!(quiet.is_empty(), "unexpected stderr output:\n{}", quiet);
+
+        // Sanity check that the capture actually sees diagnostics when requested
+        let verbose = child_stderr(test_name, "verbose");
+        assert!(verbose.contains("Debug"));
+    }
 }
@@ -1,3 +1,112 @@
+use anyhow::{Context, Result};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// How recursive directory scans treat symlinks and nesting
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraversalOptions {
+    /// Descend into symlinked directories (symlinked files are always included)
+    pub follow_links: bool,
+    /// Levels of subdirectories to descend into; 0 scans only the given directory
+    pub max_depth: Option<usize>,
+}
+
+/// Recursively collect the files below `dir` accepted by `include`.
+///
+/// Each directory is entered at most once by canonical path, so symlink cycles
+/// terminate. Subdirectories that cannot be read are skipped with a warning;
+/// only an unreadable `dir` itself is an error.
+pub fn find_files(
+    dir: &Path,
+    options: &TraversalOptions,
+    include: impl Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    let root = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    visited.insert(root);
+
+    let mut pending = vec![(dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if depth == 0 => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read directory: {}", dir.display()))
+            }
+            Err(e) => {
+                eprintln!("Warning: Skipping {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("Warning: Skipping entry in {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let is_link = entry.file_type().is_ok_and(|t| t.is_symlink());
+
+            if path.is_dir() {
+                if (is_link && !options.follow_links)
+                    || options.max_depth.is_some_and(|max| depth >= max)
+                {
+                    continue;
+                }
+                match path.canonicalize() {
+                    Ok(canonical) => {
+                        if visited.insert(canonical) {
+                            pending.push((path, depth + 1));
+                        }
+                    }
+                    Err(e) => eprintln!("Warning: Skipping {}: {}", path.display(), e),
+                }
+            } else if path.is_file() && include(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Extensions of the image formats psf-guard reads; compound ones come first so
+/// `.fits.fz` is matched as a whole rather than as `.fz`
+pub const IMAGE_EXTENSIONS: &[&str] = &["fits.fz", "fits", "fit", "fts", "fz", "xisf"];
+
+/// The entry of `IMAGE_EXTENSIONS` that `filename` ends with, ignoring case
+pub fn image_extension(filename: &str) -> Option<&'static str> {
+    let lower = filename.to_lowercase();
+    IMAGE_EXTENSIONS
+        .iter()
+        .copied()
+        .find(|ext| lower.ends_with(&format!(".{}", ext)))
+}
+
+/// Whether `path` names a FITS, tile-compressed FITS or XISF image
+pub fn is_image_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(image_extension)
+        .is_some()
+}
+
+/// Whether two paths name the same file, comparing canonical paths when both exist
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 pub fn truncate_string(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
         s.to_string()
@@ -6,6 +115,62 @@ pub fn truncate_string(s: &str, max_len: usize) -> String {
     }
 }
 
+/// Quote a CSV field when it contains a comma, quote or newline
+pub fn escape_csv(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Part of a file or image list to process, for quick runs while tuning thresholds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Subset {
+    #[default]
+    All,
+    /// The first N in list order
+    First(usize),
+    /// N picked at random, the same N for the same seed; kept in list order
+    Sample { count: usize, seed: Option<u64> },
+}
+
+impl Subset {
+    /// From `--limit`, `--sample` and `--seed`; clap keeps limit and sample exclusive
+    pub fn from_args(limit: Option<usize>, sample: Option<usize>, seed: Option<u64>) -> Self {
+        match (limit, sample) {
+            (Some(count), _) => Subset::First(count),
+            (None, Some(count)) => Subset::Sample { count, seed },
+            (None, None) => Subset::All,
+        }
+    }
+
+    pub fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        match *self {
+            Subset::All => items,
+            Subset::First(count) => items.into_iter().take(count).collect(),
+            Subset::Sample { count, seed } => {
+                if count >= items.len() {
+                    return items;
+                }
+                let mut rng = match seed {
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                    None => StdRng::from_entropy(),
+                };
+                let mut keep = vec![false; items.len()];
+                for index in rand::seq::index::sample(&mut rng, items.len(), count) {
+                    keep[index] = true;
+                }
+                items
+                    .into_iter()
+                    .zip(keep)
+                    .filter_map(|(item, keep)| keep.then_some(item))
+                    .collect()
+            }
+        }
+    }
+}
+
 pub fn extract_filename(metadata: &str) -> Option<String> {
     let json: serde_json::Value = serde_json::from_str(metadata).ok()?;
     json.get("FileName").and_then(|f| f.as_str()).map(|path| {
@@ -17,10 +182,125 @@ pub fn extract_filename(metadata: &str) -> Option<String> {
     })
 }
 
+/// Running mean and variance by Welford's method. Unlike `sum_squares / n - mean²`
+/// it cannot cancel to a negative variance when the spread is tiny next to the
+/// values themselves, such as a flat background of 16-bit pixels.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OnlineStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl OnlineStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Mean of the values pushed so far, 0 when there are none
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population variance (divided by n), 0 for fewer than two values
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+impl FromIterator<f64> for OnlineStats {
+    fn from_iter<I: IntoIterator<Item = f64>>(iter: I) -> Self {
+        let mut stats = Self::new();
+        for value in iter {
+            stats.push(value);
+        }
+        stats
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_escape_csv() {
+        assert_eq!(escape_csv("M31"), "M31");
+        assert_eq!(escape_csv("M31, core"), "\"M31, core\"");
+        assert_eq!(escape_csv("5\" field"), "\"5\"\" field\"");
+    }
+
+    #[test]
+    fn test_subset_limit_and_seeded_sample() {
+        let items: Vec<usize> = (0..10).collect();
+        assert_eq!(Subset::All.apply(items.clone()), items);
+        assert_eq!(Subset::First(5).apply(items.clone()), vec![0, 1, 2, 3, 4]);
+        assert_eq!(Subset::First(20).apply(items.clone()).len(), 10);
+
+        let sample = Subset::Sample {
+            count: 4,
+            seed: Some(42),
+        };
+        let picked = sample.apply(items.clone());
+        assert_eq!(picked.len(), 4);
+        assert!(picked.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(sample.apply(items.clone()), picked);
+    }
+
+    #[test]
+    fn test_online_stats_near_constant_frame() {
+        // A flat 16-bit background where one pixel in three is a hair brighter
+        let values: Vec<f64> = (0..1000)
+            .map(|i| 65000.0 + if i % 3 == 0 { 1e-3 } else { 0.0 })
+            .collect();
+
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let sum_squares: f64 = values.iter().map(|v| v * v).sum();
+        let naive_variance = (sum_squares - n * mean * mean) / n;
+        assert!(naive_variance < 0.0, "{}", naive_variance);
+
+        let stats: OnlineStats = values.iter().copied().collect();
+        assert_eq!(stats.count(), 1000);
+        assert!(stats.variance() >= 0.0);
+        // 334 of 1000 values are 1e-3 above the rest
+        let p = 334.0 / 1000.0;
+        let expected = p * (1.0 - p) * 1e-6;
+        assert!(
+            (stats.variance() - expected).abs() < 1e-9,
+            "{}",
+            stats.variance()
+        );
+        assert!(stats.std_dev().is_finite());
+    }
+
+    #[test]
+    fn test_online_stats_matches_two_pass() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let stats: OnlineStats = values.iter().copied().collect();
+        assert!((stats.mean() - 5.0).abs() < 1e-12);
+        assert!((stats.std_dev() - 2.0).abs() < 1e-12);
+        assert_eq!(OnlineStats::new().variance(), 0.0);
+    }
+
     #[test]
     fn test_truncate_string_short() {
         assert_eq!(truncate_string("hello", 10), "hello");
@@ -96,4 +376,84 @@ mod tests {
         let metadata = r#"{"FileName": null}"#;
         assert_eq!(extract_filename(metadata), None);
     }
+
+    /// Temp tree: root/a.fits, root/sub/b.fits, root/sub/deeper/c.fits, root/notes.txt
+    fn fits_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("psf_guard_{}_{}", name, std::process::id()));
+        let deeper = root.join("sub").join("deeper");
+        std::fs::create_dir_all(&deeper).unwrap();
+        for file in [
+            root.join("a.fits"),
+            root.join("notes.txt"),
+            root.join("sub").join("b.fits"),
+            deeper.join("c.fits"),
+        ] {
+            std::fs::write(file, b"").unwrap();
+        }
+        root
+    }
+
+    fn is_fits(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "fits")
+    }
+
+    fn names(mut files: Vec<PathBuf>) -> Vec<String> {
+        files.sort();
+        files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_is_image_file() {
+        for name in [
+            "a.fits",
+            "a.FIT",
+            "a.fts",
+            "a.fits.fz",
+            "a.FITS.FZ",
+            "a.xisf",
+        ] {
+            assert!(is_image_file(Path::new(name)), "{}", name);
+        }
+        for name in ["a.txt", "a.fits.gz", "fits", "a.jpg"] {
+            assert!(!is_image_file(Path::new(name)), "{}", name);
+        }
+        assert_eq!(image_extension("frame.Fits.fz"), Some("fits.fz"));
+    }
+
+    #[test]
+    fn test_find_files_max_depth() {
+        let root = fits_tree("walk_depth");
+        let all = find_files(&root, &TraversalOptions::default(), is_fits).unwrap();
+        let shallow = TraversalOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let limited = find_files(&root, &shallow, is_fits).unwrap();
+        std::fs::remove_dir_all(&root).ok();
+
+        assert_eq!(names(all), vec!["a.fits", "b.fits", "c.fits"]);
+        assert_eq!(names(limited), vec!["a.fits", "b.fits"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_symlink_loop_terminates() {
+        let root = fits_tree("walk_loop");
+        // sub/deeper/loop -> root
+        std::os::unix::fs::symlink(&root, root.join("sub").join("deeper").join("loop")).unwrap();
+
+        let skipped = find_files(&root, &TraversalOptions::default(), is_fits).unwrap();
+        let follow = TraversalOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        let followed = find_files(&root, &follow, is_fits).unwrap();
+        std::fs::remove_dir_all(&root).ok();
+
+        assert_eq!(names(skipped), vec!["a.fits", "b.fits", "c.fits"]);
+        assert_eq!(names(followed), vec!["a.fits", "b.fits", "c.fits"]);
+    }
 }
@@ -0,0 +1,298 @@
+/// PixInsight XISF image support
+///
+/// A monolithic XISF file starts with the `XISF0100` signature, a little-endian
+/// header length and a reserved word, followed by an XML header. Each `<Image>`
+/// element describes its geometry and sample format, and points at a block of
+/// pixel data attached elsewhere in the file (`location="attachment:pos:size"`).
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SIGNATURE: &[u8; 8] = b"XISF0100";
+
+/// Signature, header length and reserved word
+const PREAMBLE_LEN: u64 = 16;
+
+/// Pixel data of the first image in an XISF file
+#[derive(Debug)]
+pub struct XisfImage {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    /// Sample values, one width x height plane per channel
+    pub data: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleFormat {
+    UInt8,
+    UInt16,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl SampleFormat {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "UInt8" => Self::UInt8,
+            "UInt16" => Self::UInt16,
+            "UInt32" => Self::UInt32,
+            "Float32" => Self::Float32,
+            "Float64" => Self::Float64,
+            other => bail!("Unsupported XISF sample format: {}", other),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::UInt8 => 1,
+            Self::UInt16 => 2,
+            Self::UInt32 | Self::Float32 => 4,
+            Self::Float64 => 8,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! read {
+            ($ty:ty) => {{
+                let array = bytes.try_into().unwrap();
+                if big_endian {
+                    <$ty>::from_be_bytes(array) as f64
+                } else {
+                    <$ty>::from_le_bytes(array) as f64
+                }
+            }};
+        }
+        match self {
+            Self::UInt8 => bytes[0] as f64,
+            Self::UInt16 => read!(u16),
+            Self::UInt32 => read!(u32),
+            Self::Float32 => read!(f32),
+            Self::Float64 => read!(f64),
+        }
+    }
+}
+
+/// Whether the path has an `.xisf` extension
+pub fn is_xisf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xisf"))
+}
+
+/// Read the first image of a monolithic, uncompressed XISF file
+pub fn read_image(path: &Path) -> Result<XisfImage> {
+    let mut file = File::open(path).map_err(|e| {
+        let message = format!("Failed to open XISF file {}: {}", path.display(), e);
+        anyhow::Error::new(e).context(message)
+    })?;
+
+    let mut preamble = [0u8; PREAMBLE_LEN as usize];
+    file.read_exact(&mut preamble)
+        .with_context(|| format!("Truncated XISF file {}", path.display()))?;
+    if &preamble[..8] != SIGNATURE {
+        bail!("{} is not a monolithic XISF file", path.display());
+    }
+    let header_len = u32::from_le_bytes(preamble[8..12].try_into().unwrap()) as usize;
+
+    let mut header = vec![0u8; header_len];
+    file.read_exact(&mut header)
+        .with_context(|| format!("Truncated XISF header in {}", path.display()))?;
+    let header = String::from_utf8_lossy(&header);
+
+    let attributes = image_attributes(&header)
+        .ok_or_else(|| anyhow::anyhow!("No Image element in XISF file {}", path.display()))?;
+    let attribute = |name: &str| attributes.get(name).map(String::as_str);
+
+    if let Some(compression) = attribute("compression") {
+        let codec = compression.split(':').next().unwrap_or(compression);
+        bail!(
+            "Compressed XISF images ({}) are not supported yet: {}",
+            codec,
+            path.display()
+        );
+    }
+
+    let geometry: Vec<usize> = attribute("geometry")
+        .ok_or_else(|| anyhow::anyhow!("XISF image has no geometry"))?
+        .split(':')
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()
+        .context("Invalid XISF image geometry")?;
+    let (width, height, channels) = match geometry[..] {
+        [width, height, channels] => (width, height, channels),
+        [width, height] => (width, height, 1),
+        _ => bail!(
+            "Only 2D XISF images are supported, got geometry {:?}",
+            geometry
+        ),
+    };
+
+    let format = SampleFormat::parse(attribute("sampleFormat").unwrap_or("UInt16"))?;
+    let big_endian = attribute("byteOrder") == Some("big");
+    let interleaved = attribute("pixelStorage") == Some("Normal");
+
+    let location = attribute("location").unwrap_or_default();
+    let (position, size) = match location.split(':').collect::<Vec<_>>()[..] {
+        ["attachment", position, size] => (
+            position
+                .parse::<u64>()
+                .context("Invalid XISF attachment position")?,
+            size.parse::<usize>()
+                .context("Invalid XISF attachment size")?,
+        ),
+        _ => bail!(
+            "Unsupported XISF data location '{}' in {} (only attached blocks are supported)",
+            location,
+            path.display()
+        ),
+    };
+
+    let samples = width * height * channels;
+    if size != samples * format.size() {
+        bail!(
+            "XISF data block is {} bytes, expected {} for geometry {}x{}x{}",
+            size,
+            samples * format.size(),
+            width,
+            height,
+            channels
+        );
+    }
+
+    let mut block = vec![0u8; size];
+    file.seek(SeekFrom::Start(position))?;
+    file.read_exact(&mut block)
+        .with_context(|| format!("Truncated XISF data block in {}", path.display()))?;
+
+    let values: Vec<f64> = block
+        .chunks_exact(format.size())
+        .map(|bytes| format.decode(bytes, big_endian))
+        .collect();
+
+    // Normal storage interleaves channels per pixel; convert to planes
+    let data = if interleaved && channels > 1 {
+        (0..channels)
+            .flat_map(|c| values.iter().skip(c).step_by(channels).copied())
+            .collect()
+    } else {
+        values
+    };
+
+    Ok(XisfImage {
+        width,
+        height,
+        channels,
+        data,
+    })
+}
+
+/// Attributes of the first `<Image>` element in the XML header
+fn image_attributes(header: &str) -> Option<HashMap<String, String>> {
+    let element = Regex::new(r"<Image\b([^>]*)>").unwrap();
+    let attribute = Regex::new(r#"([\w:]+)\s*=\s*"([^"]*)""#).unwrap();
+
+    let attributes = element.captures(header)?.get(1)?.as_str();
+    Some(
+        attribute
+            .captures_iter(attributes)
+            .map(|c| (c[1].to_string(), c[2].to_string()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset of the data block in test files, past the header
+    const DATA_OFFSET: usize = 1024;
+
+    /// Monolithic XISF file with one image whose data block is `data`
+    fn write_xisf(name: &str, image_attributes: &str, data: &[u8]) -> std::path::PathBuf {
+        let header = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <xisf version=\"1.0\" xmlns=\"http://www.pixinsight.com/xisf\">\
+             <Image {} location=\"attachment:{}:{}\"/></xisf>",
+            image_attributes,
+            DATA_OFFSET,
+            data.len()
+        );
+
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.resize(DATA_OFFSET, 0);
+        bytes.extend_from_slice(data);
+
+        let path =
+            std::env::temp_dir().join(format!("psf_guard_{}_{}.xisf", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_uint16_image() {
+        let (width, height) = (4, 3);
+        // Full 0-65535 range, so FitsImage's scaling leaves the pixels unchanged
+        let pixels: Vec<u16> = vec![0, 100, 200, 300, 400, 500, 600, 700, 800, 900, 1000, 65535];
+        let data: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let path = write_xisf(
+            "uint16",
+            "geometry=\"4:3:1\" sampleFormat=\"UInt16\" colorSpace=\"Gray\"",
+            &data,
+        );
+
+        let raw = read_image(&path).unwrap();
+        assert_eq!((raw.width, raw.height, raw.channels), (width, height, 1));
+
+        let image = crate::image_analysis::FitsImage::from_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!((image.width, image.height), (width, height));
+        assert_eq!(image.data, pixels);
+        assert!(image.channel_data.is_empty());
+    }
+
+    #[test]
+    fn test_read_interleaved_float32_rgb() {
+        // Two pixels, RGB interleaved: (0.0, 0.5, 1.0), (1.0, 0.5, 0.0)
+        let samples = [0.0f32, 0.5, 1.0, 1.0, 0.5, 0.0];
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let path = write_xisf(
+            "float_rgb",
+            "geometry=\"2:1:3\" sampleFormat=\"Float32\" bounds=\"0:1\" \
+             colorSpace=\"RGB\" pixelStorage=\"Normal\"",
+            &data,
+        );
+
+        let raw = read_image(&path).unwrap();
+        let image = crate::image_analysis::FitsImage::from_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(raw.channels, 3);
+        assert_eq!(raw.data, vec![0.0, 1.0, 0.5, 0.5, 1.0, 0.0]);
+        assert_eq!(image.channel_count(), 3);
+        assert_eq!(image.channel_data[0], vec![0, 65535]);
+        assert_eq!(image.channel_data[2], vec![65535, 0]);
+    }
+
+    #[test]
+    fn test_compressed_image_is_rejected() {
+        let path = write_xisf(
+            "compressed",
+            "geometry=\"4:3:1\" sampleFormat=\"UInt16\" compression=\"zlib:24\"",
+            &[0; 10],
+        );
+
+        let err = read_image(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).ok();
+
+        assert!(err.contains("zlib"), "{}", err);
+        assert!(err.contains("not supported"), "{}", err);
+    }
+}
@@ -0,0 +1,91 @@
+use psf_guard::db::Database;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory with three small frames holding one Gaussian star each, and a
+/// database with the scheduler's tables
+fn fixture(name: &str) -> (PathBuf, PathBuf) {
+    let root = std::env::temp_dir().join(format!("psf_guard_cli_{}_{}", name, std::process::id()));
+    let frames = root.join("frames");
+    std::fs::create_dir_all(&frames).unwrap();
+
+    let (width, height) = (64usize, 64usize);
+    for file in ["a.fits", "b.fits", "c.fits"] {
+        let mut data = vec![1000.0f32; width * height];
+        for y in 26..=38 {
+            for x in 26..=38 {
+                let d2 = ((x - 32) * (x - 32) + (y - 32) * (y - 32)) as f32;
+                data[y * width + x] += 20000.0 * (-d2 / 6.0).exp();
+            }
+        }
+        fitrs::Fits::create(frames.join(file), fitrs::Hdu::new(&[width, height], data)).unwrap();
+    }
+
+    let database = root.join("schedulerdb.sqlite");
+    let conn = Connection::open(&database).unwrap();
+    Database::new(&conn).create_schema_if_missing().unwrap();
+    (root, database)
+}
+
+/// Stdout of `psf-guard -d <database> analyze-fits <path> --format csv <extra>`
+fn analyze_csv(database: &Path, path: &Path, extra: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_psf-guard"))
+        .arg("-d")
+        .arg(database)
+        .arg("analyze-fits")
+        .arg(path)
+        .args(["--format", "csv"])
+        .args(extra)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_csv_stdout_is_only_csv() {
+    let (root, database) = fixture("csv_stdout");
+    let frames = root.join("frames");
+
+    let directory = analyze_csv(&database, &frames, &[]);
+    let single = analyze_csv(&database, &frames.join("a.fits"), &[]);
+    std::fs::remove_dir_all(&root).ok();
+
+    // Directory mode: one header, then one row per file
+    let lines: Vec<&str> = directory.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", directory);
+    assert!(lines[0].starts_with("Filename,"), "{}", directory);
+    assert_eq!(lines.iter().filter(|l| **l == lines[0]).count(), 1);
+    for (line, file) in lines[1..].iter().zip(["a.fits", "b.fits", "c.fits"]) {
+        assert!(line.starts_with(&format!("{},", file)), "{}", directory);
+    }
+
+    // A single file gets the same header and a row, with no progress text
+    let lines: Vec<&str> = single.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", single);
+    assert_eq!(lines[0], directory.lines().next().unwrap());
+    assert!(lines[1].starts_with("a.fits,"), "{}", single);
+}
+
+#[test]
+fn test_stars_csv_note_stays_off_stdout() {
+    let (root, database) = fixture("stars_csv");
+    let stars = root.join("stars.csv");
+
+    let stdout = analyze_csv(
+        &database,
+        &root.join("frames").join("a.fits"),
+        &["--stars-csv", stars.to_str().unwrap()],
+    );
+    let written = std::fs::read_to_string(&stars).unwrap();
+    std::fs::remove_dir_all(&root).ok();
+
+    assert_eq!(stdout.lines().count(), 2, "{}", stdout);
+    assert!(!stdout.contains("Per-star"), "{}", stdout);
+    assert!(written.lines().count() > 1, "{}", written);
+}